@@ -0,0 +1,55 @@
+//! Microbenchmarks of parsing and formatting amounts, which is done for every row
+
+use std::{fmt::Write, hint::black_box};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use transactor::Amount;
+
+const INPUTS: [&str; 6] = ["5", "1.5", "0.0001", "12345.6789", "-42.1", "3.141592653"];
+
+fn parse(c: &mut Criterion) {
+    c.bench_function("parse", |b| {
+        b.iter(|| {
+            for input in INPUTS {
+                black_box(black_box(input).parse::<Amount>().unwrap());
+            }
+        })
+    });
+}
+
+fn format(c: &mut Criterion) {
+    let amounts = INPUTS.map(|input| input.parse::<Amount>().unwrap());
+    let mut out = String::new();
+    c.bench_function("format", |b| {
+        b.iter(|| {
+            for amount in amounts {
+                out.clear();
+                write!(out, "{}", black_box(amount)).unwrap();
+            }
+            black_box(&out);
+        })
+    });
+    c.bench_function("format_precision", |b| {
+        b.iter(|| {
+            for amount in amounts {
+                out.clear();
+                write!(out, "{:.2}", black_box(amount)).unwrap();
+            }
+            black_box(&out);
+        })
+    });
+}
+
+fn convert(c: &mut Criterion) {
+    let amounts = INPUTS.map(|input| input.parse::<Amount>().unwrap());
+    c.bench_function("f64_round_trip", |b| {
+        b.iter(|| {
+            for amount in amounts {
+                black_box(Amount::from_f64(black_box(amount).as_f64()));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, parse, format, convert);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+//! Generates the gRPC service of the `grpc` feature
+//!
+//! The messages are defined by hand in `src/grpc.rs`, so this does not need `protoc`. They must
+//! be kept in sync with `proto/transactor.proto`.
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+#[cfg(feature = "grpc")]
+fn grpc() {
+    use tonic_build::manual::{Builder, Method, Service};
+    let method = |name: &str, route_name: &str, input_type: &str, output_type: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(format!("crate::grpc::proto::{}", input_type))
+            .output_type(format!("crate::grpc::proto::{}", output_type))
+            .codec_path("tonic_prost::ProstCodec")
+            .build()
+    };
+    let service = Service::builder()
+        .name("Transactor")
+        .package("transactor")
+        .method(method(
+            "submit_transaction",
+            "SubmitTransaction",
+            "SubmitTransactionRequest",
+            "SubmitTransactionResponse",
+        ))
+        .method(method(
+            "get_account",
+            "GetAccount",
+            "GetAccountRequest",
+            "Account",
+        ))
+        .method(method(
+            "list_transactions",
+            "ListTransactions",
+            "ListTransactionsRequest",
+            "TransactionPage",
+        ))
+        .build();
+    Builder::new().compile(&[service]);
+}
@@ -1,22 +1,268 @@
 //! Types for working with client accounts
 
 use std::{
-    collections::{HashMap, HashSet},
-    error::Error,
+    cmp::{Ordering, Reverse},
+    collections::{btree_map::Entry, BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
     fmt,
-    ops::Index,
+    io::{self, BufRead, Read, Write},
+    ops::{Bound, Index},
+    str::FromStr,
+    sync::{mpsc::Sender, Arc},
 };
 
-use crate::{amount::Amount, transaction::*};
+use thiserror::Error;
+
+use crate::{
+    accrual::AccrualSchedule,
+    amount::{Amount, Precision},
+    clock::{Clock, SystemClock},
+    events::{self, BalanceEvent, BalanceEventKind, Balances},
+    history::HistorySpill,
+    transaction::*,
+};
+
+/// The range of transactions in which an account was active
+///
+/// Only deposits, withdrawals, fees, and transfers count as activity. Disputes, resolutions, and
+/// chargebacks refer to earlier transactions rather than being new activity. The timestamps of
+/// the first and most recent transactions are kept too, if they had them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Activity {
+    /// The id of the first deposit, withdrawal, fee, or transfer applied to the account
+    pub first_tx: TransactionId,
+    /// The id of the most recent deposit, withdrawal, fee, or transfer applied to the account
+    pub last_tx: TransactionId,
+    /// The timestamp of the first transaction
+    pub first_timestamp: Option<Timestamp>,
+    /// The timestamp of the most recent transaction
+    pub last_timestamp: Option<Timestamp>,
+}
+
+/// A free-text note attached to an account by an admin operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Note {
+    /// When the note was added, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The operator who added the note
+    pub operator: String,
+    /// The reference of the operation that added the note, such as a ticket id
+    pub reference: String,
+    /// The text of the note
+    pub text: String,
+}
+
+/// Limits on the disputes a client may initiate, to defend against dispute spam
+///
+/// Disputes beyond a limit are rejected. No limits are set by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisputeLimits {
+    /// The most disputes a client may have open at once
+    pub max_open: Option<usize>,
+    /// The most disputes a client may initiate during a run
+    pub max_per_run: Option<usize>,
+    /// The most seconds a dispute's timestamp may be past the timestamp of the transaction
+    /// it disputes, as with card networks' dispute windows
+    ///
+    /// Disputes of transactions without timestamps, and disputes without timestamps, are
+    /// not limited.
+    pub window: Option<Timestamp>,
+}
+
+/// Limits on how far withdrawals may take accounts' available funds below zero
+///
+/// Each client has the default limit unless it has its own. Withdrawals need the funds to be
+/// available by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverdraftLimits {
+    /// The limit of clients without their own
+    pub default: Amount,
+    /// The limits of particular clients
+    pub clients: HashMap<ClientId, Amount>,
+}
+
+impl OverdraftLimits {
+    /// Get the limit of a client
+    pub fn limit(&self, client: ClientId) -> Amount {
+        self.clients.get(&client).copied().unwrap_or(self.default)
+    }
+    /// Read the limits of particular clients from a CSV source with `client` and `limit`
+    /// columns
+    ///
+    /// Limits must not be negative, and each client may only appear once.
+    pub fn read_clients<R: Read>(&mut self, source: R) -> Result<(), String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(source);
+        let headers = reader
+            .headers()
+            .map_err(|e| format!("Error reading overdraft limits: {}", e))?
+            .clone();
+        let required = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("Overdraft limits are missing a {:?} column", name))
+        };
+        let client_col = required("client")?;
+        let limit_col = required("limit")?;
+        for (i, record) in reader.records().enumerate() {
+            let row = i + 1;
+            let record =
+                record.map_err(|e| format!("Error reading overdraft limits row {}: {}", row, e))?;
+            let field = |col: usize| record.get(col).unwrap_or_default();
+            let client: ClientId = field(client_col).parse().map_err(|_| {
+                format!(
+                    "Invalid client {:?} on overdraft limits row {}",
+                    field(client_col),
+                    row
+                )
+            })?;
+            let limit: Amount = field(limit_col)
+                .parse()
+                .ok()
+                .filter(|limit| *limit >= Amount::default())
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid limit {:?} on overdraft limits row {}",
+                        field(limit_col),
+                        row
+                    )
+                })?;
+            if self.clients.insert(client, limit).is_some() {
+                return Err(format!(
+                    "Client {} appears more than once in the overdraft limits",
+                    client
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How far below zero debits may take an account's available funds
+#[derive(Debug, Clone, Copy, Default)]
+struct Overdraft {
+    withdrawals: Amount,
+    fees: Amount,
+}
+
+/// How long deposits take to settle, see [`Accounts::set_settlement_period`]
+///
+/// Deposits settle immediately by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SettlementPeriod {
+    /// How many seconds past its timestamp a deposit with a timestamp is pending
+    pub time: Option<Timestamp>,
+    /// How far past a deposit's id the id of a transaction must be to settle it, for deposits
+    /// without timestamps or when there is no time
+    pub transactions: Option<TransactionId>,
+}
+
+impl SettlementPeriod {
+    /// Check whether a deposit with a timestamp, or without one, is pending until it settles
+    pub fn pends(&self, timestamp: Option<Timestamp>) -> bool {
+        (self.time.is_some() && timestamp.is_some()) || self.transactions.is_some()
+    }
+}
+
+/// A policy for retrying withdrawals rejected for insufficient funds
+///
+/// A withdrawal rejected for insufficient funds is parked, and retried after each later
+/// deposit to the same account until it succeeds or runs out of retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WithdrawalRetries {
+    /// The most times a parked withdrawal is retried
+    pub max_retries: u32,
+    /// How far past a parked withdrawal's id a deposit's id may be for the withdrawal to be
+    /// retried, or `None` to retry it at any point in the run
+    ///
+    /// The window is measured in transaction ids even if transactions have timestamps, since
+    /// a retry is triggered by a later deposit rather than by time passing.
+    pub window: Option<TransactionId>,
+}
+
+/// A destructive operation that was simulated rather than applied in sandbox mode
+///
+/// See [`Accounts::set_sandbox`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Simulated {
+    /// A disputed transaction would have been charged back
+    Chargeback { client: ClientId, tx: TransactionId },
+    /// An account would have been closed
+    Close { client: ClientId },
+    /// An account would have been unfrozen by an unfreeze transaction
+    Unfreeze { client: ClientId },
+    /// An admin operation would have been applied
+    Admin {
+        client: ClientId,
+        /// A description of the operation
+        op: String,
+        /// The reference of the operation, such as a ticket id
+        reference: String,
+    },
+}
+
+impl fmt::Display for Simulated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Simulated::Chargeback { client, tx } => write!(
+                f,
+                "would have charged back transaction {} of client {}",
+                tx, client
+            ),
+            Simulated::Close { client } => {
+                write!(f, "would have closed the account of client {}", client)
+            }
+            Simulated::Unfreeze { client } => {
+                write!(f, "would have unfrozen the account of client {}", client)
+            }
+            Simulated::Admin { op, reference, .. } => {
+                write!(f, "would have applied {} for {}", op, reference)
+            }
+        }
+    }
+}
+
+/// A withdrawal waiting to be retried
+#[derive(Debug, Clone, Copy)]
+struct ParkedWithdrawal {
+    tx_id: TransactionId,
+    amount: Amount,
+    timestamp: Option<Timestamp>,
+    attempts: u32,
+}
 
 /// A client's account
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Account {
     balance: Amount,
     held: Amount,
     frozen: bool,
-    history: HashMap<TransactionId, BalanceChange>,
-    disputed: HashSet<TransactionId>,
+    closed: bool,
+    /// Ordered by transaction id, so it can be paged through, see [`Accounts::history`]
+    history: BTreeMap<TransactionId, BalanceChange>,
+    /// The amount of each disputed transaction that is disputed
+    disputed: HashMap<TransactionId, Amount>,
+    pending_credit: Amount,
+    /// Deposits that have not settled, including disputed ones
+    settling: HashMap<TransactionId, Amount>,
+    /// The sum of the deposits that have not settled, excluding disputed ones
+    pending: Amount,
+    activity: Option<Activity>,
+    transfers: HashSet<TransactionId>,
+    /// Deposits and withdrawals removed from the history by a chargeback or resolution,
+    /// whose ids cannot be used again
+    reversed: HashSet<TransactionId>,
+    /// The parts of transactions in the history that were reversed by partial chargebacks or
+    /// resolutions, which cannot be disputed again
+    partly_reversed: HashMap<TransactionId, Amount>,
+    /// The funds of deposits that were charged back and not yet credited again by a
+    /// representment
+    charged_back: HashMap<TransactionId, Amount>,
+    notes: Vec<Note>,
 }
 
 // `Account`' fields are behind getters because they should only be modifiable through transactions
@@ -29,102 +275,1503 @@ impl Account {
     pub fn held(&self) -> Amount {
         self.held
     }
+    /// Get the account's pending credit
+    ///
+    /// This is the sum of disputed withdrawals. It is returned to the available balance
+    /// if a dispute is resolved, and is not part of the total balance.
+    pub fn pending_credit(&self) -> Amount {
+        self.pending_credit
+    }
+    /// Get the account's funds pending settlement
+    ///
+    /// This is the sum of deposits that have not settled yet, see
+    /// [`Accounts::set_settlement_period`]. Pending funds are part of the total balance, but
+    /// cannot be withdrawn or transferred until they settle.
+    pub fn pending(&self) -> Amount {
+        self.pending
+    }
     /// Check whether the account is frozen
     pub fn is_frozen(&self) -> bool {
         self.frozen
     }
+    /// Check whether the account is closed
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
     /// Get the account's total balance
     pub fn total(&self) -> Amount {
-        self.balance + self.held
+        self.balance + self.held + self.pending
+    }
+    /// Get the range of transactions in which the account was active
+    ///
+    /// Returns `None` if no deposits, withdrawals, or transfers were ever applied to the account
+    pub fn activity(&self) -> Option<Activity> {
+        self.activity
+    }
+    /// Get the notes attached to the account by admin operations, oldest first
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+    /// Attach a note to the account
+    ///
+    /// This is an administrative operation, see [`crate::admin`]
+    pub(crate) fn add_note(&mut self, note: Note) {
+        self.notes.push(note);
+    }
+    /// Close the account so that no more transactions can be executed on it
+    ///
+    /// Accounts with open disputes cannot be closed
+    pub fn close(&mut self) -> Result<(), TransactionError> {
+        if !self.disputed.is_empty() {
+            return Err(TransactionError::OpenDisputes(self.disputed.len()));
+        }
+        self.closed = true;
+        Ok(())
+    }
+    /// Add an accrual, which may be negative, to the available funds
+    fn accrue(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.balance = add(self.balance, amount)?;
+        Ok(())
+    }
+    /// Reopen a closed account
+    pub(crate) fn reopen(&mut self) {
+        self.closed = false;
+    }
+    /// Freeze an account, as a chargeback does
+    ///
+    /// This is an administrative operation, see [`crate::admin`]. Freezing an account that is
+    /// already frozen does nothing.
+    pub(crate) fn freeze(&mut self) -> Result<(), TransactionError> {
+        if self.closed {
+            return Err(TransactionError::AccountClosed);
+        }
+        self.frozen = true;
+        Ok(())
+    }
+    /// Unfreeze a frozen account
+    ///
+    /// This is an administrative operation, see [`crate::admin`]. Unfreezing an account
+    /// that is not frozen does nothing.
+    pub(crate) fn unfreeze(&mut self) -> Result<(), TransactionError> {
+        if self.closed {
+            return Err(TransactionError::AccountClosed);
+        }
+        self.frozen = false;
+        Ok(())
+    }
+    /// Adjust the account's available balance by a positive or negative amount
+    ///
+    /// This is an administrative operation, see [`crate::admin`]
+    pub(crate) fn adjust(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        if self.closed {
+            return Err(TransactionError::AccountClosed);
+        }
+        let balance = add(self.balance, amount)?;
+        if balance < Amount::default() {
+            return Err(TransactionError::InsufficientFunds {
+                current: self.balance,
+                requested: -amount,
+            });
+        }
+        add(self.total(), amount)?;
+        self.balance = balance;
+        Ok(())
+    }
+    /// Move another account's funds, history, and dispute state into this one
+    ///
+    /// Fails without changing either account if either is closed, if both accounts have used
+    /// the same transaction id, or if a combined amount would overflow.
+    fn merge(&mut self, other: Account) -> Result<(), TransactionError> {
+        if self.closed || other.closed {
+            return Err(TransactionError::AccountClosed);
+        }
+        let conflict = (other.history.keys())
+            .chain(&other.transfers)
+            .chain(&other.reversed)
+            .filter(|&&tx_id| self.is_used(tx_id))
+            .min();
+        if let Some(&tx_id) = conflict {
+            return Err(TransactionError::MergeConflict(tx_id));
+        }
+        let balance = add(self.balance, other.balance)?;
+        let held = add(self.held, other.held)?;
+        let pending = add(self.pending, other.pending)?;
+        let pending_credit = add(self.pending_credit, other.pending_credit)?;
+        add(add(balance, held)?, pending)?;
+        self.balance = balance;
+        self.held = held;
+        self.pending = pending;
+        self.pending_credit = pending_credit;
+        self.frozen |= other.frozen;
+        self.history.extend(other.history);
+        self.disputed.extend(other.disputed);
+        self.settling.extend(other.settling);
+        self.transfers.extend(other.transfers);
+        self.reversed.extend(other.reversed);
+        self.partly_reversed.extend(other.partly_reversed);
+        self.charged_back.extend(other.charged_back);
+        self.notes.extend(other.notes);
+        self.activity = match (self.activity, other.activity) {
+            (Some(a), Some(b)) => {
+                let first = if a.first_tx <= b.first_tx { a } else { b };
+                let last = if a.last_tx >= b.last_tx { a } else { b };
+                Some(Activity {
+                    first_tx: first.first_tx,
+                    first_timestamp: first.first_timestamp,
+                    last_tx: last.last_tx,
+                    last_timestamp: last.last_timestamp,
+                })
+            }
+            (a, b) => a.or(b),
+        };
+        Ok(())
+    }
+    /// Get the amount of a transaction in the history that has not been reversed
+    fn disputable(&self, tx_id: TransactionId, change: &BalanceChange) -> Amount {
+        change.amount
+            - self
+                .partly_reversed
+                .get(&tx_id)
+                .copied()
+                .unwrap_or_default()
+    }
+    /// Reverse part of a transaction in the history
+    ///
+    /// Once all of the transaction is reversed, it is removed from the history and its id
+    /// cannot be used again.
+    fn reverse(&mut self, tx_id: TransactionId, change: &BalanceChange, amount: Amount) {
+        if let Some(settling) = self.settling.get_mut(&tx_id) {
+            *settling -= amount;
+        }
+        let reversed = self.partly_reversed.remove(&tx_id).unwrap_or_default() + amount;
+        if reversed < change.amount {
+            self.partly_reversed.insert(tx_id, reversed);
+        } else {
+            self.history.remove(&tx_id);
+            self.settling.remove(&tx_id);
+            self.reversed.insert(tx_id);
+        }
+    }
+    /// Check whether a transaction id has been used by a deposit, withdrawal, or transfer
+    fn is_used(&self, tx_id: TransactionId) -> bool {
+        self.history.contains_key(&tx_id)
+            || self.transfers.contains(&tx_id)
+            || self.reversed.contains(&tx_id)
+    }
+    /// Record a deposit, withdrawal, or transfer as the account's most recent activity
+    fn record_activity(&mut self, tx_id: TransactionId, timestamp: Option<Timestamp>) {
+        self.activity = Some(match self.activity {
+            Some(activity) => Activity {
+                last_tx: tx_id,
+                last_timestamp: timestamp,
+                ..activity
+            },
+            None => Activity {
+                first_tx: tx_id,
+                last_tx: tx_id,
+                first_timestamp: timestamp,
+                last_timestamp: timestamp,
+            },
+        });
+    }
+    /// Check that a transfer out of the account can be made, returning the new balance
+    fn check_transfer_out(
+        &self,
+        tx_id: TransactionId,
+        amount: Amount,
+    ) -> Result<Amount, TransactionError> {
+        if self.closed {
+            return Err(TransactionError::AccountClosed);
+        }
+        if self.frozen {
+            return Err(TransactionError::AccountFrozen);
+        }
+        if self.is_used(tx_id) {
+            return Err(TransactionError::DuplicateTransactionId(tx_id));
+        }
+        if self.balance < amount {
+            return Err(TransactionError::InsufficientFunds {
+                current: self.balance,
+                requested: amount,
+            });
+        }
+        Ok(self.balance - amount)
+    }
+    /// Check that a transfer into the account can be made, returning the new balance
+    fn check_transfer_in(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Amount,
+    ) -> Result<Amount, TransactionError> {
+        if self.closed {
+            return Err(TransactionError::DestinationClosed(client_id));
+        }
+        if self.frozen {
+            return Err(TransactionError::DestinationFrozen(client_id));
+        }
+        if self.is_used(tx_id) {
+            return Err(TransactionError::DuplicateTransactionId(tx_id));
+        }
+        add(self.total(), amount)?;
+        add(self.balance, amount)
+    }
+    /// Complete a checked transfer into or out of the account
+    fn complete_transfer(
+        &mut self,
+        tx_id: TransactionId,
+        balance: Amount,
+        timestamp: Option<Timestamp>,
+    ) {
+        self.balance = balance;
+        self.transfers.insert(tx_id);
+        self.record_activity(tx_id, timestamp);
+    }
+    /// Execute a deposit whose funds are pending until it settles
+    fn deposit_pending(
+        &mut self,
+        tx_id: TransactionId,
+        change: BalanceChange,
+    ) -> Result<(), TransactionError> {
+        let amount = change.amount;
+        if self.closed {
+            return Err(TransactionError::AccountClosed);
+        }
+        if amount <= Amount::default() {
+            return Err(TransactionError::NonPositiveAmount(amount));
+        }
+        if self.is_used(tx_id) {
+            return Err(TransactionError::DuplicateTransactionId(tx_id));
+        }
+        add(self.total(), amount)?;
+        self.pending = add(self.pending, amount)?;
+        self.settling.insert(tx_id, amount);
+        self.history.insert(tx_id, change);
+        self.record_activity(tx_id, change.timestamp);
+        Ok(())
+    }
+    /// Settle a pending deposit, making its funds available
+    ///
+    /// A disputed deposit that settles is released to the available balance if the dispute is
+    /// resolved. Deposits that are not pending are ignored.
+    fn settle(&mut self, tx_id: TransactionId) -> Result<(), TransactionError> {
+        let Some(&amount) = self.settling.get(&tx_id) else {
+            return Ok(());
+        };
+        // The disputed part of the deposit is released when the dispute is resolved
+        let undisputed = amount - self.disputed.get(&tx_id).copied().unwrap_or_default();
+        self.balance = add(self.balance, undisputed)?;
+        self.pending -= undisputed;
+        self.settling.remove(&tx_id);
+        Ok(())
+    }
+    /// Get the balance after taking an amount from it, if that leaves it no further below
+    /// zero than the overdraft
+    fn debit(&self, amount: Amount, overdraft: Amount) -> Result<Amount, TransactionError> {
+        match sub(self.balance, amount) {
+            Ok(balance) if balance >= -overdraft => Ok(balance),
+            _ => Err(TransactionError::InsufficientFunds {
+                current: self.balance,
+                requested: amount,
+            }),
+        }
     }
     /// Execute a transaction on the account
+    ///
+    /// Transfers involve two accounts, so they can only be executed with
+    /// [`Accounts::transact`]. Unfreeze transactions are admin transactions, so they are only
+    /// accepted by accounts with [`Accounts::set_admin_transactions`] enabled.
     pub fn transact(&mut self, tx: Transaction) -> Result<(), TransactionError> {
+        if let Transaction::Unfreeze(_) = tx {
+            return Err(TransactionError::AdminRequired);
+        }
+        self.execute(tx, Overdraft::default())
+    }
+    /// Execute a transaction on the account, allowing withdrawals and fees to take the balance
+    /// as far below zero as the overdraft
+    fn execute(&mut self, tx: Transaction, overdraft: Overdraft) -> Result<(), TransactionError> {
+        // Prevent closed accounts from being used at all
+        if self.closed {
+            return Err(TransactionError::AccountClosed);
+        }
         match tx {
             Transaction::Change { tx_id, change } => {
-                if self.history.contains_key(&tx_id) {
+                if change.amount <= Amount::default() {
+                    return Err(TransactionError::NonPositiveAmount(change.amount));
+                }
+                if self.is_used(tx_id) {
                     return Err(TransactionError::DuplicateTransactionId(tx_id));
                 }
                 match change.kind {
-                    ChangeKind::Deposit => self.balance += change.amount,
+                    ChangeKind::Deposit => {
+                        // The total must stay representable as well as the balance
+                        add(self.total(), change.amount)?;
+                        self.balance = add(self.balance, change.amount)?;
+                    }
                     ChangeKind::Withdrawal => {
                         // Prevent frozen accounts from being withdrawn from
                         if self.frozen {
                             return Err(TransactionError::AccountFrozen);
                         }
                         // Ensure the funds are available
-                        if self.balance >= change.amount {
-                            self.balance -= change.amount;
-                        } else {
-                            return Err(TransactionError::InsufficentFunds {
-                                current: self.balance,
-                                requested: change.amount,
-                            });
-                        }
+                        self.balance = self.debit(change.amount, overdraft.withdrawals)?;
                     }
+                    // Fees are charged even to frozen accounts, and may overdraw them a little
+                    ChangeKind::Fee => self.balance = self.debit(change.amount, overdraft.fees)?,
                 }
                 self.history.insert(tx_id, change);
+                self.record_activity(tx_id, change.timestamp);
             }
-            Transaction::Dispute(tx_id) => {
-                // When initiating a dispute, put disputed funds into holding
-                if let Some(BalanceChange {
-                    kind: ChangeKind::Deposit,
-                    amount,
-                }) = self.history.get(&tx_id)
-                {
-                    self.balance -= *amount;
-                    self.held += *amount;
-                    self.disputed.insert(tx_id);
-                } else {
+            Transaction::Dispute { tx_id, .. } if self.disputed.contains_key(&tx_id) => {
+                return Err(TransactionError::AlreadyDisputed(tx_id))
+            }
+            Transaction::Dispute { tx_id, amount } => {
+                let Some(&change) = self.history.get(&tx_id) else {
                     return Err(TransactionError::InvalidDispute(tx_id));
+                };
+                let available = self.disputable(tx_id, &change);
+                let amount = match amount {
+                    Some(amount) => check_partial(tx_id, amount, available)?,
+                    None => available,
+                };
+                match change.kind {
+                    // A deposit that has not settled is held from its pending funds
+                    ChangeKind::Deposit if self.settling.contains_key(&tx_id) => {
+                        self.held = add(self.held, amount)?;
+                        self.pending -= amount;
+                    }
+                    // When initiating a dispute on a deposit, put disputed funds into holding
+                    ChangeKind::Deposit => {
+                        let balance = sub(self.balance, amount)?;
+                        self.held = add(self.held, amount)?;
+                        self.balance = balance;
+                    }
+                    // When initiating a dispute on a withdrawal or fee, the withdrawn funds become a
+                    // pending credit
+                    ChangeKind::Withdrawal | ChangeKind::Fee => {
+                        self.pending_credit = add(self.pending_credit, amount)?;
+                    }
                 }
+                self.disputed.insert(tx_id, amount);
             }
-            Transaction::Resolution { kind, tx_id } => {
-                if self.disputed.remove(&tx_id) {
-                    if let Some(BalanceChange {
-                        kind: ChangeKind::Deposit,
-                        amount,
-                    }) = self.history.get(&tx_id)
-                    {
+            Transaction::Resolution {
+                kind,
+                tx_id,
+                amount,
+            } => {
+                let Some(&disputed) = self.disputed.get(&tx_id) else {
+                    return Err(TransactionError::UndisputedResolution { tx_id, kind });
+                };
+                // The part of the dispute that is not charged back is resolved
+                let charged_back = match (kind, amount) {
+                    (ResolutionKind::Resolve, _) => Amount::default(),
+                    (ResolutionKind::Chargeback, Some(amount)) => {
+                        check_partial(tx_id, amount, disputed)?
+                    }
+                    (ResolutionKind::Chargeback, None) => disputed,
+                };
+                let resolved = disputed - charged_back;
+                match self.history.get(&tx_id).copied() {
+                    Some(
+                        change @ BalanceChange {
+                            kind: ChangeKind::Deposit,
+                            ..
+                        },
+                    ) => {
+                        if self.settling.contains_key(&tx_id) {
+                            // A resolved deposit that has not settled is pending again
+                            self.pending = add(self.pending, resolved)?;
+                        } else {
+                            // When resolving a disputed deposit, make disputed held funds available again
+                            self.balance = add(self.balance, resolved)?;
+                        }
+                        self.held -= disputed;
+                        if let ResolutionKind::Chargeback = kind {
+                            // When charging back a dispute, remove the held funds and freeze the
+                            // account. Once all of the deposit is charged back, it is removed from
+                            // the history so it cannot be disputed and charged back again.
+                            self.frozen = true;
+                            self.reverse(tx_id, &change, charged_back);
+                            *self.charged_back.entry(tx_id).or_default() += charged_back;
+                        }
+                    }
+                    Some(
+                        change @ BalanceChange {
+                            kind: ChangeKind::Withdrawal | ChangeKind::Fee,
+                            ..
+                        },
+                    ) => {
+                        // When resolving a disputed withdrawal or fee, return the withdrawn funds
+                        add(self.total(), resolved)?;
+                        self.balance = add(self.balance, resolved)?;
+                        self.pending_credit -= disputed;
                         match kind {
-                            ResolutionKind::Resolve => {
-                                // When resolving a disputed deposit, make disputed held funds available again
-                                self.balance += *amount;
-                                self.held -= *amount;
-                            }
+                            // The returned funds have been reversed, so they cannot be disputed again
+                            ResolutionKind::Resolve => self.reverse(tx_id, &change, resolved),
+                            // When charging back a disputed withdrawal, the withdrawal is final
                             ResolutionKind::Chargeback => {
-                                // When charging back a dispute, remove the held funds and freeze the account
-                                self.held -= *amount;
-                                self.frozen = true;
-                                // The transaction is removed from the history so it
-                                // cannot be disputed and charged back again
                                 self.history.remove(&tx_id);
+                                self.partly_reversed.remove(&tx_id);
+                                self.reversed.insert(tx_id);
                             }
                         }
                     }
-                } else {
-                    return Err(TransactionError::UndisputedResolution { tx_id, kind });
+                    None => {}
                 }
+                self.disputed.remove(&tx_id);
             }
+            Transaction::Unfreeze(_) => self.frozen = false,
+            Transaction::Representment(tx_id) => {
+                let Some(&amount) = self.charged_back.get(&tx_id) else {
+                    return Err(TransactionError::InvalidRepresentment(tx_id));
+                };
+                add(self.total(), amount)?;
+                self.balance = add(self.balance, amount)?;
+                self.charged_back.remove(&tx_id);
+            }
+            Transaction::Transfer { .. } => return Err(TransactionError::UnpairedTransfer),
         }
         Ok(())
     }
+    /// Write the account's full state, including history, in a line-based format
+    pub(crate) fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "account,{},{},{},{}",
+            self.balance, self.held, self.frozen, self.closed
+        )?;
+        // Entries are sorted so that saving the same account always gives the same output
+        for (tx_id, change) in &self.history {
+            let kind = match change.kind {
+                ChangeKind::Deposit => "deposit",
+                ChangeKind::Withdrawal => "withdrawal",
+                ChangeKind::Fee => "fee",
+            };
+            write!(writer, "history,{},{},{}", tx_id, kind, change.amount)?;
+            if let Some(timestamp) = change.timestamp {
+                write!(writer, ",{}", timestamp)?;
+            }
+            writeln!(writer)?;
+        }
+        let mut disputed: Vec<_> = self.disputed.iter().collect();
+        disputed.sort_unstable();
+        for (tx_id, amount) in disputed {
+            write!(writer, "disputed,{}", tx_id)?;
+            // Only partial disputes list the amount disputed
+            let change = self.history.get(tx_id);
+            if change.is_none_or(|change| *amount != self.disputable(*tx_id, change)) {
+                write!(writer, ",{}", amount)?;
+            }
+            writeln!(writer)?;
+        }
+        let mut transfers: Vec<_> = self.transfers.iter().collect();
+        transfers.sort_unstable();
+        for tx_id in transfers {
+            writeln!(writer, "transfer,{}", tx_id)?;
+        }
+        let mut reversed: Vec<_> = self.reversed.iter().collect();
+        reversed.sort_unstable();
+        for tx_id in reversed {
+            writeln!(writer, "reversed,{}", tx_id)?;
+        }
+        let mut charged_back: Vec<_> = self.charged_back.iter().collect();
+        charged_back.sort_unstable();
+        for (tx_id, amount) in charged_back {
+            writeln!(writer, "charged-back,{},{}", tx_id, amount)?;
+        }
+        let mut partly_reversed: Vec<_> = self.partly_reversed.iter().collect();
+        partly_reversed.sort_unstable();
+        for (tx_id, amount) in partly_reversed {
+            writeln!(writer, "partly-reversed,{},{}", tx_id, amount)?;
+        }
+        if self.pending_credit != Amount::default() {
+            writeln!(writer, "pending,{}", self.pending_credit)?;
+        }
+        let mut settling: Vec<_> = self.settling.iter().collect();
+        settling.sort_unstable();
+        for (tx_id, amount) in settling {
+            writeln!(writer, "settling,{},{}", tx_id, amount)?;
+        }
+        if let Some(activity) = self.activity {
+            write!(
+                writer,
+                "activity,{},{}",
+                activity.first_tx, activity.last_tx
+            )?;
+            // Only activity with timestamps lists them
+            if activity.first_timestamp.is_some() || activity.last_timestamp.is_some() {
+                let timestamp = |timestamp: Option<Timestamp>| {
+                    timestamp.map_or_else(String::new, |timestamp| timestamp.to_string())
+                };
+                write!(
+                    writer,
+                    ",{},{}",
+                    timestamp(activity.first_timestamp),
+                    timestamp(activity.last_timestamp)
+                )?;
+            }
+            writeln!(writer)?;
+        }
+        for note in &self.notes {
+            writeln!(
+                writer,
+                "note,{},{},{},{}",
+                note.timestamp,
+                escape_field(&note.operator),
+                escape_field(&note.reference),
+                escape_field(&note.text)
+            )?;
+        }
+        Ok(())
+    }
+    /// Read an account's full state as written by [`Account::save`]
+    pub(crate) fn load<R: BufRead>(reader: R) -> io::Result<Account> {
+        fn invalid() -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, "Invalid account data")
+        }
+        fn field<T: FromStr>(field: Option<&str>) -> io::Result<T> {
+            field.and_then(|f| f.parse().ok()).ok_or_else(invalid)
+        }
+        let mut account = Account::default();
+        let mut disputed = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut fields = line.split(',');
+            match fields.next() {
+                Some("account") => {
+                    account.balance = field(fields.next())?;
+                    account.held = field(fields.next())?;
+                    account.frozen = field(fields.next())?;
+                    account.closed = field(fields.next())?;
+                }
+                Some("history") => {
+                    let tx_id = field(fields.next())?;
+                    let kind = match fields.next() {
+                        Some("deposit") => ChangeKind::Deposit,
+                        Some("withdrawal") => ChangeKind::Withdrawal,
+                        Some("fee") => ChangeKind::Fee,
+                        _ => return Err(invalid()),
+                    };
+                    let amount = field(fields.next())?;
+                    // Entries saved before timestamps were recorded have none
+                    let timestamp = match fields.next() {
+                        Some(timestamp) => Some(field(Some(timestamp))?),
+                        None => None,
+                    };
+                    let change = BalanceChange {
+                        kind,
+                        amount,
+                        timestamp,
+                    };
+                    account.history.insert(tx_id, change);
+                }
+                Some("disputed") => {
+                    let tx_id = field(fields.next())?;
+                    // Disputes of all that remains of a transaction have no amount
+                    let amount = match fields.next() {
+                        Some(amount) => Some(field(Some(amount))?),
+                        None => None,
+                    };
+                    disputed.push((tx_id, amount));
+                }
+                Some("transfer") => {
+                    account.transfers.insert(field(fields.next())?);
+                }
+                Some("reversed") => {
+                    account.reversed.insert(field(fields.next())?);
+                }
+                Some("charged-back") => {
+                    let tx_id = field(fields.next())?;
+                    account.charged_back.insert(tx_id, field(fields.next())?);
+                }
+                Some("partly-reversed") => {
+                    let tx_id = field(fields.next())?;
+                    account.partly_reversed.insert(tx_id, field(fields.next())?);
+                }
+                Some("pending") => {
+                    account.pending_credit = field(fields.next())?;
+                }
+                Some("settling") => {
+                    let tx_id = field(fields.next())?;
+                    account.settling.insert(tx_id, field(fields.next())?);
+                }
+                Some("note") => {
+                    let timestamp = field(fields.next())?;
+                    let mut text = || fields.next().map(unescape_field).ok_or_else(invalid);
+                    account.notes.push(Note {
+                        timestamp,
+                        operator: text()?,
+                        reference: text()?,
+                        text: text()?,
+                    });
+                }
+                Some("activity") => {
+                    let first_tx = field(fields.next())?;
+                    let last_tx = field(fields.next())?;
+                    // Activity without timestamps, or saved before they were recorded, has none
+                    let mut timestamp = || match fields.next() {
+                        Some("") | None => Ok(None),
+                        timestamp => field(timestamp).map(Some),
+                    };
+                    account.activity = Some(Activity {
+                        first_tx,
+                        last_tx,
+                        first_timestamp: timestamp()?,
+                        last_timestamp: timestamp()?,
+                    });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        for (tx_id, amount) in disputed {
+            let amount = match (amount, account.history.get(&tx_id)) {
+                (Some(amount), _) => amount,
+                (None, Some(change)) => account.disputable(tx_id, change),
+                (None, None) => return Err(invalid()),
+            };
+            account.disputed.insert(tx_id, amount);
+        }
+        for (tx_id, &amount) in &account.settling {
+            let disputed = account.disputed.get(tx_id).copied().unwrap_or_default();
+            account.pending = add(account.pending, amount - disputed).map_err(|_| invalid())?;
+        }
+        Ok(account)
+    }
 }
 
 /// A collection of client [`Account`]s, indexed by client id
+///
+/// Amounts of executed transactions are rounded to the collection's [`Precision`].
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Accounts {
-    accounts: HashMap<ClientId, Account>,
+    // Ordered so that everything derived from accounts is reported in order of client id
+    accounts: BTreeMap<ClientId, Account>,
+    // A setting of the engine rather than account state, so it is not serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    precision: Precision,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    admin: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    dispute_limits: DisputeLimits,
+    /// The number of disputes each client has initiated during this run
+    #[cfg_attr(feature = "serde", serde(skip))]
+    disputes_initiated: HashMap<ClientId, usize>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    spill: Option<HistorySpill>,
+    /// The history entries in memory that may be spilled, oldest first
+    #[cfg_attr(feature = "serde", serde(skip))]
+    recent: VecDeque<(ClientId, TransactionId)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    retries: Option<WithdrawalRetries>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    parked: HashMap<ClientId, VecDeque<ParkedWithdrawal>>,
+    /// Parked withdrawals that ran out of retries
+    #[cfg_attr(feature = "serde", serde(skip))]
+    unsettled: Vec<(ClientId, TransactionId)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    representment_unfreezes: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    fee_overdraft: Amount,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    overdraft_limits: OverdraftLimits,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    accrual_schedule: Option<AccrualSchedule>,
+    /// The end of the current accrual period, once a timestamped transaction has been seen
+    #[cfg_attr(feature = "serde", serde(skip))]
+    accrual_due: Option<Timestamp>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    clock: Option<Arc<dyn Clock>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    balance_events: Option<Sender<BalanceEvent>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sandbox: bool,
+    /// The operations simulated in sandbox mode during this run
+    #[cfg_attr(feature = "serde", serde(skip))]
+    simulated: Vec<Simulated>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    settlement_period: SettlementPeriod,
+    /// Pending deposits without timestamps as the transaction id they settle at, the client,
+    /// and their own id, earliest first
+    ///
+    /// Entries for deposits that have already settled or been charged back are ignored.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    settlements: BinaryHeap<Reverse<(TransactionId, ClientId, TransactionId)>>,
+    /// Pending deposits with timestamps as the time they settle at, the client, and their own
+    /// id, earliest first
+    #[cfg_attr(feature = "serde", serde(skip))]
+    timed_settlements: BinaryHeap<Reverse<(Timestamp, ClientId, TransactionId)>>,
+    /// The largest id of a deposit, withdrawal, or transfer applied so far
+    #[cfg_attr(feature = "serde", serde(skip))]
+    latest_tx: Option<TransactionId>,
 }
 
 impl Accounts {
-    /// Execute a transaction
-    pub fn transact(&mut self, client_tx: ClientTransaction) -> Result<(), TransactionError> {
+    /// Create an empty collection of accounts that keeps amounts to some precision
+    pub fn with_precision(precision: Precision) -> Self {
+        Accounts {
+            accounts: BTreeMap::new(),
+            precision,
+            ..Accounts::default()
+        }
+    }
+    /// Set whether admin transactions, such as [`Transaction::Unfreeze`], are accepted
+    ///
+    /// They are rejected by default.
+    pub fn set_admin_transactions(&mut self, enabled: bool) {
+        self.admin = enabled;
+    }
+    /// Check whether admin transactions are accepted
+    pub fn admin_transactions(&self) -> bool {
+        self.admin
+    }
+    /// Get the precision amounts are kept to
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+    /// Set the limits on the disputes each client may initiate
+    pub fn set_dispute_limits(&mut self, limits: DisputeLimits) {
+        self.dispute_limits = limits;
+    }
+    /// Get the limits on the disputes each client may initiate
+    pub fn dispute_limits(&self) -> DisputeLimits {
+        self.dispute_limits
+    }
+    /// Keep a limited number of history entries in memory, moving older ones to disk
+    ///
+    /// This should be set before any transactions are executed. See [`crate::history`].
+    pub fn set_history_spill(&mut self, spill: HistorySpill) {
+        self.spill = Some(spill);
+    }
+    /// Get the setting for keeping history on disk, if there is one
+    pub fn history_spill(&self) -> Option<&HistorySpill> {
+        self.spill.as_ref()
+    }
+    /// Set the policy for retrying withdrawals rejected for insufficient funds
+    ///
+    /// Such withdrawals are not retried by default.
+    pub fn set_withdrawal_retries(&mut self, retries: Option<WithdrawalRetries>) {
+        self.retries = retries;
+    }
+    /// Get the policy for retrying withdrawals rejected for insufficient funds
+    pub fn withdrawal_retries(&self) -> Option<WithdrawalRetries> {
+        self.retries
+    }
+    /// Set whether a representment unfreezes the account once none of its chargebacks stand
+    ///
+    /// Accounts stay frozen after a representment by default.
+    pub fn set_representment_unfreezes(&mut self, enabled: bool) {
+        self.representment_unfreezes = enabled;
+    }
+    /// Check whether a representment unfreezes the account once none of its chargebacks stand
+    pub fn representment_unfreezes(&self) -> bool {
+        self.representment_unfreezes
+    }
+    /// Set how far below zero withdrawals may take each account's available funds
+    pub fn set_overdraft_limits(&mut self, limits: OverdraftLimits) {
+        self.overdraft_limits = limits;
+    }
+    /// Get how far below zero withdrawals may take each account's available funds
+    pub fn overdraft_limits(&self) -> &OverdraftLimits {
+        &self.overdraft_limits
+    }
+    /// Set how far past the overdraft limit fees may take an account's available funds
+    ///
+    /// Fees are rejected like withdrawals if there are not enough funds by default.
+    pub fn set_fee_overdraft(&mut self, overdraft: Amount) {
+        self.fee_overdraft = overdraft;
+    }
+    /// Get how far past the overdraft limit fees may take an account's available funds
+    pub fn fee_overdraft(&self) -> Amount {
+        self.fee_overdraft
+    }
+    /// Set a schedule for accruing interest and penalties as time passes, see [`AccrualSchedule`]
+    ///
+    /// Nothing accrues during processing by default.
+    pub fn set_accrual_schedule(&mut self, schedule: Option<AccrualSchedule>) {
+        self.accrual_schedule = schedule;
+    }
+    /// Get the schedule for accruing interest and penalties as time passes
+    pub fn accrual_schedule(&self) -> Option<AccrualSchedule> {
+        self.accrual_schedule
+    }
+    /// Set a clock to stamp transactions without timestamps with the time they are executed
+    ///
+    /// Stamped transactions are subject to the dispute window, move the accrual schedule, and
+    /// settle deposits like timestamped input. Transactions are not stamped by default.
+    pub fn set_clock(&mut self, clock: Option<Arc<dyn Clock>>) {
+        self.clock = clock;
+    }
+    /// Get the clock that stamps transactions without timestamps, if there is one
+    pub fn clock(&self) -> Option<&Arc<dyn Clock>> {
+        self.clock.as_ref()
+    }
+    /// Send an event for every change these accounts make to an account's balances, or `None`
+    /// to stop
+    ///
+    /// Events are sent in the order the accounts make the changes, including changes made by
+    /// settlements, accruals, retried withdrawals, and admin operations. Transactions applied
+    /// directly to an [`Account`] with [`Account::transact`] send no events. Events are not
+    /// sent by default, and are dropped once the receiver is gone. See [`crate::events`].
+    ///
+    /// When transactions are processed in parallel, each shard of the accounts sends the
+    /// events of its own clients as it applies their transactions. The events of each client
+    /// are still in order, but the events of different clients are interleaved in whatever
+    /// order the shards make their changes.
+    pub fn set_balance_events(&mut self, sink: Option<Sender<BalanceEvent>>) {
+        self.balance_events = sink;
+    }
+    /// Get the sender of balance events, if there is one
+    pub fn balance_events(&self) -> Option<&Sender<BalanceEvent>> {
+        self.balance_events.as_ref()
+    }
+    /// Get the balances of a client's account, which are zero if it does not exist
+    fn balances(&self, client: ClientId) -> Balances {
         self.accounts
-            .entry(client_tx.client)
-            .or_default()
-            .transact(client_tx.tx)
+            .get(&client)
+            .map(Balances::of)
+            .unwrap_or_default()
+    }
+    /// Apply an operation to the accounts, sending a balance event for each of some clients
+    /// whose balances it changed
+    pub(crate) fn observed<F>(
+        &mut self,
+        clients: &[ClientId],
+        tx: Option<TransactionId>,
+        kind: BalanceEventKind,
+        operation: F,
+    ) -> Result<(), TransactionError>
+    where
+        F: FnOnce(&mut Accounts) -> Result<(), TransactionError>,
+    {
+        if self.balance_events.is_none() {
+            return operation(self);
+        }
+        let before: Vec<Balances> = clients
+            .iter()
+            .map(|&client| self.balances(client))
+            .collect();
+        operation(self)?;
+        for (&client, before) in clients.iter().zip(before) {
+            let account = self.accounts.get(&client);
+            events::send(&self.balance_events, kind, client, tx, before, account);
+        }
+        Ok(())
+    }
+    /// Get the current time from the clock, or from the system if there is no clock
+    pub fn now(&self) -> Timestamp {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+    /// Set whether destructive operations are only simulated
+    ///
+    /// In sandbox mode, chargebacks, account closures, unfreeze transactions, and admin
+    /// operations are checked against a copy of the account instead of being applied. Those
+    /// that would have succeeded are recorded as [`Simulated`] operations. This is disabled by
+    /// default.
+    pub fn set_sandbox(&mut self, enabled: bool) {
+        self.sandbox = enabled;
+    }
+    /// Check whether destructive operations are only simulated
+    pub fn sandbox(&self) -> bool {
+        self.sandbox
+    }
+    /// Set how long deposits take to settle
+    ///
+    /// A deposit's funds are [pending](Account::pending) for a while, as ACH-style funding
+    /// takes days to clear. A deposit with a timestamp, whether from the input or the
+    /// [clock](Accounts::set_clock), settles once a transaction at least the period's time
+    /// past it is applied. A deposit without one settles once a deposit, withdrawal, or
+    /// transfer whose id is at least the period's number of transactions past the deposit's
+    /// is applied. Either way, its funds are available to the transactions after that one.
+    /// Rejected transactions do not settle deposits, and neither does a transaction whose id
+    /// is more than the period past any applied before it, since it is likely out of
+    /// sequence. Deposits already pending are scheduled to settle with the new period.
+    ///
+    /// Deposits settle immediately by default. When processing in parallel, each shard only
+    /// sees its own clients' transactions.
+    pub fn set_settlement_period(&mut self, period: SettlementPeriod) {
+        self.settlement_period = period;
+        self.settlements.clear();
+        self.timed_settlements.clear();
+        let clients: Vec<ClientId> = self.accounts.keys().copied().collect();
+        for client in clients {
+            self.schedule_settlements(client);
+        }
+    }
+    /// Get how long deposits take to settle
+    pub fn settlement_period(&self) -> SettlementPeriod {
+        self.settlement_period
+    }
+    /// Schedule the pending deposits of a client's account to settle
+    fn schedule_settlements(&mut self, client: ClientId) {
+        let Some(account) = self.accounts.get(&client) else {
+            return;
+        };
+        let deposits: Vec<(TransactionId, Option<Timestamp>)> = account
+            .settling
+            .keys()
+            .map(|&tx_id| {
+                let timestamp = account
+                    .history
+                    .get(&tx_id)
+                    .and_then(|change| change.timestamp);
+                (tx_id, timestamp)
+            })
+            .collect();
+        for (tx_id, timestamp) in deposits {
+            self.schedule_settlement(client, tx_id, timestamp);
+        }
+    }
+    /// Schedule a pending deposit to settle, by its timestamp if it has one
+    fn schedule_settlement(
+        &mut self,
+        client: ClientId,
+        tx_id: TransactionId,
+        timestamp: Option<Timestamp>,
+    ) {
+        let period = self.settlement_period;
+        if let (Some(time), Some(timestamp)) = (period.time, timestamp) {
+            let at = timestamp.saturating_add(time);
+            self.timed_settlements.push(Reverse((at, client, tx_id)));
+        } else if let Some(transactions) = period.transactions {
+            let at = tx_id.saturating_add(transactions);
+            self.settlements.push(Reverse((at, client, tx_id)));
+        }
+    }
+    /// Settle every pending deposit that is due after a transaction is applied
+    ///
+    /// `tx_id` is the id of the transaction if it is a deposit, withdrawal, or transfer, since
+    /// disputes and resolutions refer to earlier transactions.
+    fn settle_after(&mut self, tx_id: Option<TransactionId>, timestamp: Option<Timestamp>) {
+        let mut due = Vec::new();
+        if let Some(now) = timestamp {
+            while let Some(&Reverse((at, client, deposit))) = self.timed_settlements.peek() {
+                if at > now {
+                    break;
+                }
+                self.timed_settlements.pop();
+                due.push((client, deposit));
+            }
+        }
+        if let Some(now) = tx_id {
+            let latest = self
+                .latest_tx
+                .replace(self.latest_tx.map_or(now, |id| id.max(now)));
+            let jumped = match (latest, self.settlement_period.transactions) {
+                (Some(latest), Some(period)) => now > latest.saturating_add(period),
+                _ => false,
+            };
+            if jumped {
+                tracing::debug!(
+                    tx = now,
+                    "Not settling deposits after an out of sequence id"
+                );
+            }
+            while let Some(&Reverse((at, client, deposit))) = self.settlements.peek() {
+                if jumped || at > now {
+                    break;
+                }
+                self.settlements.pop();
+                due.push((client, deposit));
+            }
+        }
+        for (client, tx_id) in due {
+            let Some(account) = self.accounts.get_mut(&client) else {
+                continue;
+            };
+            let before = Balances::of(account);
+            match account.settle(tx_id) {
+                Ok(()) => events::send(
+                    &self.balance_events,
+                    BalanceEventKind::FundsSettled,
+                    client,
+                    Some(tx_id),
+                    before,
+                    Some(account),
+                ),
+                Err(e) => tracing::warn!(client, tx = tx_id, "Unable to settle deposit: {}", e),
+            }
+        }
+    }
+    /// Post the accruals of every period that ended at or before a time
+    fn accrue_until(&mut self, now: Timestamp) {
+        let Some(schedule) = self.accrual_schedule else {
+            return;
+        };
+        let mut due = self.accrual_due.unwrap_or_else(|| schedule.period_end(now));
+        while due <= now {
+            self.post_accruals(&schedule, due);
+            let Some(next) = due.checked_add(schedule.period.get()) else {
+                break;
+            };
+            due = next;
+        }
+        self.accrual_due = Some(due);
+    }
+    /// Post one period's accrual to the available funds of every open account
+    ///
+    /// Accruals are not transactions, so they are not kept in history and cannot be disputed.
+    fn post_accruals(&mut self, schedule: &AccrualSchedule, period_end: Timestamp) {
+        for (&client, account) in &mut self.accounts {
+            if account.closed {
+                continue;
+            }
+            let amount = match schedule.policy.accrual(account) {
+                Ok(amount) => amount.round(self.precision),
+                Err(e) => {
+                    tracing::warn!(client, period_end, "Unable to accrue: {}", e);
+                    continue;
+                }
+            };
+            let kind = match amount.cmp(&Amount::default()) {
+                Ordering::Greater => BalanceEventKind::FundsDeposited,
+                Ordering::Less => BalanceEventKind::FeeCharged,
+                Ordering::Equal => continue,
+            };
+            // Penalties are charged on held funds and the funds of frozen accounts, which are
+            // not available to pay them, so they are taken even if they overdraw the account
+            let before = Balances::of(account);
+            match account.accrue(amount) {
+                Ok(()) => {
+                    tracing::debug!(
+                        client,
+                        period_end,
+                        %amount,
+                        "Posted an accrual of {} to client {}",
+                        amount,
+                        client
+                    );
+                    events::send(
+                        &self.balance_events,
+                        kind,
+                        client,
+                        None,
+                        before,
+                        Some(account),
+                    );
+                }
+                Err(e) => tracing::warn!(client, period_end, "Unable to post accrual: {}", e),
+            }
+        }
+    }
+    /// Get the operations simulated in sandbox mode, in the order they were simulated
+    pub fn simulated(&self) -> &[Simulated] {
+        &self.simulated
+    }
+    /// Check an operation against a copy of a client's account, and record it as simulated
+    /// if it succeeds
+    pub(crate) fn simulate<F>(
+        &mut self,
+        client: ClientId,
+        simulated: Simulated,
+        operation: F,
+    ) -> Result<(), TransactionError>
+    where
+        F: FnOnce(&mut Accounts) -> Result<(), TransactionError>,
+    {
+        self.simulate_with(client, &[], simulated, operation)
+    }
+    /// Check an operation against copies of a client's account and the accounts of other
+    /// clients it involves, and record it as simulated if it succeeds
+    pub(crate) fn simulate_with<F>(
+        &mut self,
+        client: ClientId,
+        others: &[ClientId],
+        simulated: Simulated,
+        operation: F,
+    ) -> Result<(), TransactionError>
+    where
+        F: FnOnce(&mut Accounts) -> Result<(), TransactionError>,
+    {
+        let mut copy = Accounts::with_precision(self.precision);
+        copy.admin = self.admin;
+        for &client in std::iter::once(&client).chain(others) {
+            if let Some(account) = self.accounts.get(&client) {
+                copy.accounts.insert(client, account.clone());
+            }
+        }
+        operation(&mut copy)?;
+        tracing::info!(client, "Sandbox: {}", simulated);
+        self.simulated.push(simulated);
+        Ok(())
+    }
+    /// Get the parked withdrawals that have not succeeded, as client and transaction ids
+    ///
+    /// This includes withdrawals that ran out of retries and withdrawals still waiting to be
+    /// retried, in order of client id and then transaction id.
+    pub fn unsettled_withdrawals(&self) -> Vec<(ClientId, TransactionId)> {
+        let mut unsettled = self.unsettled.clone();
+        for (&client, parked) in &self.parked {
+            unsettled.extend(parked.iter().map(|withdrawal| (client, withdrawal.tx_id)));
+        }
+        unsettled.sort_unstable();
+        unsettled
+    }
+    /// Execute a transaction
+    ///
+    /// A deposit or withdrawal whose amount rounds to zero at the accounts' precision is
+    /// rejected.
+    pub fn transact(&mut self, mut client_tx: ClientTransaction) -> Result<(), TransactionError> {
+        if let (None, Some(clock)) = (client_tx.timestamp, &self.clock) {
+            client_tx.timestamp = Some(clock.now());
+        }
+        if let Some(timestamp) = client_tx.timestamp {
+            self.accrue_until(timestamp);
+        }
+        let timestamp = client_tx.timestamp;
+        let tx_id = match client_tx.tx {
+            Transaction::Change { tx_id, .. } | Transaction::Transfer { tx_id, .. } => Some(tx_id),
+            _ => None,
+        };
+        let result = self.execute(client_tx);
+        if result.is_ok() {
+            self.settle_after(tx_id, timestamp);
+        }
+        result
     }
-    /// Iterate over all accounts and their client ids
+    /// Execute a stamped transaction
+    fn execute(&mut self, client_tx: ClientTransaction) -> Result<(), TransactionError> {
+        match client_tx.tx.round(self.precision) {
+            Transaction::Transfer { tx_id, to, amount } => {
+                self.transfer(client_tx.client, to, tx_id, amount, client_tx.timestamp)
+            }
+            Transaction::Unfreeze(_) if !self.admin => Err(TransactionError::AdminRequired),
+            Transaction::Unfreeze(_) if self.sandbox => {
+                let client = client_tx.client;
+                let simulated = Simulated::Unfreeze { client };
+                self.simulate(client, simulated, |copy| copy.transact(client_tx))
+            }
+            Transaction::Dispute { tx_id, amount } => {
+                let (client, timestamp) = (client_tx.client, client_tx.timestamp);
+                if self.balance_events.is_none() {
+                    return self.dispute(client, tx_id, amount, timestamp);
+                }
+                let before = self.balances(client);
+                self.dispute(client, tx_id, amount, timestamp)?;
+                // The disputed transaction is in memory once it is disputed
+                let account = self.accounts.get(&client);
+                let disputed = account.and_then(|account| account.history.get(&tx_id));
+                let kind = match disputed.map(|change| change.kind) {
+                    Some(ChangeKind::Withdrawal | ChangeKind::Fee) => {
+                        BalanceEventKind::CreditPending
+                    }
+                    _ => BalanceEventKind::FundsHeld,
+                };
+                events::send(
+                    &self.balance_events,
+                    kind,
+                    client,
+                    Some(tx_id),
+                    before,
+                    account,
+                );
+                Ok(())
+            }
+            tx @ Transaction::Representment(tx_id) => {
+                let kind = BalanceEventKind::ChargebackReversed;
+                self.observed(&[client_tx.client], Some(tx_id), kind, |accounts| {
+                    let unfreezes = accounts.representment_unfreezes;
+                    let account = accounts.account_mut(client_tx.client);
+                    account.transact(tx)?;
+                    if unfreezes && account.charged_back.is_empty() {
+                        account.frozen = false;
+                    }
+                    Ok(())
+                })
+            }
+            Transaction::Resolution {
+                kind: ResolutionKind::Chargeback,
+                tx_id,
+                ..
+            } if self.sandbox => {
+                let client = client_tx.client;
+                let simulated = Simulated::Chargeback { client, tx: tx_id };
+                self.simulate(client, simulated, |copy| copy.transact(client_tx))
+            }
+            Transaction::Change { tx_id, mut change } => {
+                let client = client_tx.client;
+                // The history records when the change was made
+                change.timestamp = change.timestamp.or(client_tx.timestamp);
+                let tx = Transaction::Change { tx_id, change };
+                match (self.change(client, tx), change.kind, self.retries) {
+                    (
+                        Err(TransactionError::InsufficientFunds { current, requested }),
+                        ChangeKind::Withdrawal,
+                        Some(retries),
+                    ) if retries.max_retries > 0 => {
+                        self.parked
+                            .entry(client)
+                            .or_default()
+                            .push_back(ParkedWithdrawal {
+                                tx_id,
+                                amount: change.amount,
+                                timestamp: change.timestamp,
+                                attempts: 0,
+                            });
+                        Err(TransactionError::WithdrawalParked { current, requested })
+                    }
+                    (Ok(()), ChangeKind::Deposit, Some(retries)) => {
+                        self.retry_parked(client, tx_id, retries);
+                        Ok(())
+                    }
+                    (result, ..) => result,
+                }
+            }
+            tx => {
+                let client = client_tx.client;
+                // Admin transactions have been checked, so they are not rejected here
+                self.observed(&[client], Some(tx.tx_id()), event_kind(&tx), |accounts| {
+                    accounts
+                        .account_mut(client)
+                        .execute(tx, Overdraft::default())
+                })
+            }
+        }
+    }
+    /// Execute a deposit or withdrawal
+    fn change(&mut self, client: ClientId, tx: Transaction) -> Result<(), TransactionError> {
+        let tx_id = tx.tx_id();
+        self.check_spilled(client, tx_id)?;
+        self.make_room()?;
+        let account = self.accounts.entry(client).or_default();
+        let before = Balances::of(account);
+        match tx {
+            Transaction::Change {
+                change:
+                    change @ BalanceChange {
+                        kind: ChangeKind::Deposit,
+                        timestamp,
+                        ..
+                    },
+                ..
+            } if self.settlement_period.pends(timestamp) => {
+                account.deposit_pending(tx_id, change)?;
+                self.schedule_settlement(client, tx_id, timestamp);
+            }
+            tx => {
+                // Fees may go as far past the client's overdraft limit as the fee overdraft
+                let limit = self.overdraft_limits.limit(client);
+                let overdraft = Overdraft {
+                    withdrawals: limit,
+                    fees: limit
+                        .checked_add(self.fee_overdraft)
+                        .unwrap_or(Amount::from_units(i128::MAX)),
+                };
+                account.execute(tx, overdraft)?
+            }
+        }
+        let account = self.accounts.get(&client);
+        events::send(
+            &self.balance_events,
+            event_kind(&tx),
+            client,
+            Some(tx_id),
+            before,
+            account,
+        );
+        if self.spill.is_some() {
+            self.recent.push_back((client, tx_id));
+        }
+        Ok(())
+    }
+    /// Retry a client's parked withdrawals after a deposit, in the order they were parked
+    fn retry_parked(
+        &mut self,
+        client: ClientId,
+        deposit_id: TransactionId,
+        retries: WithdrawalRetries,
+    ) {
+        let Some(parked) = self.parked.remove(&client) else {
+            return;
+        };
+        let mut remaining = VecDeque::new();
+        for mut withdrawal in parked {
+            let tx_id = withdrawal.tx_id;
+            let expired = retries
+                .window
+                .is_some_and(|window| deposit_id.saturating_sub(tx_id) > window);
+            if !expired {
+                withdrawal.attempts += 1;
+                let change = BalanceChange {
+                    kind: ChangeKind::Withdrawal,
+                    amount: withdrawal.amount,
+                    timestamp: withdrawal.timestamp,
+                };
+                match self.change(client, Transaction::Change { tx_id, change }) {
+                    Ok(()) => {
+                        tracing::info!(client, tx = tx_id, "settled parked withdrawal");
+                        continue;
+                    }
+                    Err(_) if withdrawal.attempts < retries.max_retries => {
+                        remaining.push_back(withdrawal);
+                        continue;
+                    }
+                    Err(_) => {}
+                }
+            }
+            tracing::warn!(client, tx = tx_id, "gave up on parked withdrawal");
+            self.unsettled.push((client, tx_id));
+        }
+        if !remaining.is_empty() {
+            self.parked.insert(client, remaining);
+        }
+    }
+    /// Initiate a dispute, unless the client has reached a dispute limit or the disputed
+    /// transaction is outside the dispute window
+    fn dispute(
+        &mut self,
+        client: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionError> {
+        let initiated = self.disputes_initiated.entry(client).or_default();
+        if let Some(max) = self.dispute_limits.max_per_run {
+            if *initiated >= max {
+                return Err(TransactionError::DisputeRunLimit(max));
+            }
+        }
+        let account = self.accounts.entry(client).or_default();
+        if let Some(max) = self.dispute_limits.max_open {
+            if account.disputed.len() >= max {
+                return Err(TransactionError::OpenDisputeLimit(max));
+            }
+        }
+        // Disputed entries must be in memory
+        if let (Some(spill), Entry::Vacant(entry)) = (&self.spill, account.history.entry(tx_id)) {
+            if let Some(change) = spill
+                .store
+                .take(client, tx_id)
+                .map_err(TransactionError::History)?
+            {
+                entry.insert(change);
+                self.recent.push_back((client, tx_id));
+            }
+        }
+        let disputed_at = account
+            .history
+            .get(&tx_id)
+            .and_then(|change| change.timestamp);
+        if let (Some(window), Some(at), Some(disputed_at)) =
+            (self.dispute_limits.window, timestamp, disputed_at)
+        {
+            if at.saturating_sub(disputed_at) > window {
+                return Err(TransactionError::DisputeWindowExpired(tx_id));
+            }
+        }
+        account.transact(Transaction::Dispute { tx_id, amount })?;
+        *initiated += 1;
+        Ok(())
+    }
+    /// Check that a transaction id is not used by one of a client's spilled history entries
+    fn check_spilled(
+        &self,
+        client: ClientId,
+        tx_id: TransactionId,
+    ) -> Result<(), TransactionError> {
+        if let Some(spill) = &self.spill {
+            if spill
+                .store
+                .contains(client, tx_id)
+                .map_err(TransactionError::History)?
+            {
+                return Err(TransactionError::DuplicateTransactionId(tx_id));
+            }
+        }
+        Ok(())
+    }
+    /// Spill the oldest history entries until there is room for a new one in memory
+    fn make_room(&mut self) -> Result<(), TransactionError> {
+        let Some(spill) = &self.spill else {
+            return Ok(());
+        };
+        // Disputed entries must stay in memory, so they are moved to the back instead
+        let mut remaining = self.recent.len();
+        while self.recent.len() >= spill.limit.max(1) && remaining > 0 {
+            remaining -= 1;
+            let (client, tx_id) = self.recent.pop_front().unwrap();
+            let Some(account) = self.accounts.get_mut(&client) else {
+                continue;
+            };
+            if account.disputed.contains_key(&tx_id) {
+                self.recent.push_back((client, tx_id));
+                continue;
+            }
+            // The entry may have been removed by a resolution or chargeback
+            let Some(&change) = account.history.get(&tx_id) else {
+                continue;
+            };
+            let spilled = spill.store.put(client, tx_id, change);
+            match spilled {
+                Ok(true) => {
+                    account.history.remove(&tx_id);
+                }
+                // The slot is taken by another client's entry, so this one is tried again later,
+                // once that entry may have moved back into memory
+                Ok(false) => self.recent.push_back((client, tx_id)),
+                Err(e) => {
+                    self.recent.push_front((client, tx_id));
+                    return Err(TransactionError::History(e));
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Transfer funds from one account to another
+    ///
+    /// Both accounts are checked before either is changed, so a failed transfer changes
+    /// nothing.
+    fn transfer(
+        &mut self,
+        from: ClientId,
+        to: ClientId,
+        tx_id: TransactionId,
+        amount: Amount,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionError> {
+        if from == to {
+            return Err(TransactionError::SelfTransfer);
+        }
+        if amount <= Amount::default() {
+            return Err(TransactionError::NonPositiveAmount(amount));
+        }
+        self.check_spilled(from, tx_id)?;
+        self.check_spilled(to, tx_id)?;
+        let empty = Account::default();
+        let from_balance = self
+            .get(from)
+            .unwrap_or(&empty)
+            .check_transfer_out(tx_id, amount)?;
+        let to_balance = self
+            .get(to)
+            .unwrap_or(&empty)
+            .check_transfer_in(to, tx_id, amount)?;
+        let before = (self.balances(from), self.balances(to));
+        self.account_mut(from)
+            .complete_transfer(tx_id, from_balance, timestamp);
+        self.account_mut(to)
+            .complete_transfer(tx_id, to_balance, timestamp);
+        for (client, kind, before) in [
+            (from, BalanceEventKind::TransferSent, before.0),
+            (to, BalanceEventKind::TransferReceived, before.1),
+        ] {
+            let account = self.accounts.get(&client);
+            events::send(
+                &self.balance_events,
+                kind,
+                client,
+                Some(tx_id),
+                before,
+                account,
+            );
+        }
+        Ok(())
+    }
+    /// Iterate over all accounts and their client ids, in order of client id
     pub fn iter(&self) -> impl Iterator<Item = (ClientId, &Account)> {
         self.accounts.iter().map(|(&id, account)| (id, account))
     }
@@ -132,6 +1779,173 @@ impl Accounts {
     pub fn get(&self, client_id: ClientId) -> Option<&Account> {
         self.accounts.get(&client_id)
     }
+    /// Get up to `limit` of a client's history entries with transaction ids after `after`, in
+    /// order of transaction id
+    ///
+    /// The history holds the deposits, withdrawals, and fees that can still be disputed,
+    /// including those spilled to disk. Pass the last transaction id of one page as `after` to
+    /// get the next.
+    pub fn history(
+        &self,
+        client_id: ClientId,
+        after: Option<TransactionId>,
+        limit: usize,
+    ) -> Result<Vec<(TransactionId, BalanceChange)>, TransactionError> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        let mut page: Vec<_> = (self.accounts.get(&client_id))
+            .into_iter()
+            .flat_map(|account| account.history.range((start, Bound::Unbounded)))
+            .map(|(&tx_id, &change)| (tx_id, change))
+            .take(limit)
+            .collect();
+        if let Some(spill) = &self.spill {
+            let spilled = (spill.store)
+                .page(client_id, after, limit)
+                .map_err(TransactionError::History)?;
+            page.extend(spilled);
+            page.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+            page.truncate(limit);
+        }
+        Ok(page)
+    }
+    /// Get the account associated with the given client id, creating it if it does not exist
+    pub(crate) fn account_mut(&mut self, client_id: ClientId) -> &mut Account {
+        self.accounts.entry(client_id).or_default()
+    }
+    /// Close the account associated with the given client id
+    ///
+    /// In sandbox mode, the closure is only simulated.
+    pub fn close(&mut self, client_id: ClientId) -> Result<(), TransactionError> {
+        if self.sandbox {
+            let simulated = Simulated::Close { client: client_id };
+            return self.simulate(client_id, simulated, |copy| copy.close(client_id));
+        }
+        self.accounts.entry(client_id).or_default().close()
+    }
+    /// Accrue interest or penalties on the available funds of every open account
+    ///
+    /// `accrual` gets the change to an account's available funds for one period, such as with
+    /// [`AccrualPolicy::accrual`](crate::accrual::AccrualPolicy::accrual). Each change is
+    /// rounded to the accounts' precision, and closed accounts are skipped. Returns the clients
+    /// whose accounts changed and by how much. If a change would overflow, the accounts before
+    /// it keep their changes and the error is returned.
+    pub fn accrue<F>(&mut self, mut accrual: F) -> Result<Vec<(ClientId, Amount)>, TransactionError>
+    where
+        F: FnMut(ClientId, &Account) -> Result<Amount, TransactionError>,
+    {
+        let mut accrued = Vec::new();
+        for (&client_id, account) in &mut self.accounts {
+            if account.closed {
+                continue;
+            }
+            let amount = accrual(client_id, account)?.round(self.precision);
+            if amount != Amount::default() {
+                let before = Balances::of(account);
+                account.accrue(amount)?;
+                let kind = if amount > Amount::default() {
+                    BalanceEventKind::FundsDeposited
+                } else {
+                    BalanceEventKind::FeeCharged
+                };
+                events::send(
+                    &self.balance_events,
+                    kind,
+                    client_id,
+                    None,
+                    before,
+                    Some(account),
+                );
+                accrued.push((client_id, amount));
+            }
+        }
+        Ok(accrued)
+    }
+    /// Merge the account of one client into the account of another
+    ///
+    /// This is an administrative operation, see [`crate::admin`], for when the same customer
+    /// has two client ids. The balances, history, disputes, pending deposits, and notes of
+    /// `from` are moved to `into`, which is frozen if either account was. `from` is left as
+    /// an empty, closed account, so its id cannot be used again. The merge is rejected if
+    /// both accounts have used the same transaction id, since disputes of that id would be
+    /// ambiguous, and while history is kept on disk.
+    pub(crate) fn merge(&mut self, from: ClientId, into: ClientId) -> Result<(), TransactionError> {
+        if from == into {
+            return Err(TransactionError::SelfMerge);
+        }
+        if self.spill.is_some() {
+            return Err(TransactionError::History(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "accounts cannot be merged while history is kept on disk",
+            )));
+        }
+        let source = self.accounts.get(&from).cloned().unwrap_or_default();
+        let settling: Vec<(TransactionId, Option<Timestamp>)> = source
+            .settling
+            .keys()
+            .map(|&tx_id| {
+                let timestamp = source
+                    .history
+                    .get(&tx_id)
+                    .and_then(|change| change.timestamp);
+                (tx_id, timestamp)
+            })
+            .collect();
+        self.account_mut(into).merge(source)?;
+        self.accounts.insert(
+            from,
+            Account {
+                closed: true,
+                ..Account::default()
+            },
+        );
+        for (tx_id, timestamp) in settling {
+            self.schedule_settlement(into, tx_id, timestamp);
+        }
+        if let Some(parked) = self.parked.remove(&from) {
+            let queue = self.parked.entry(into).or_default();
+            queue.extend(parked);
+            queue
+                .make_contiguous()
+                .sort_by_key(|withdrawal| withdrawal.tx_id);
+        }
+        if let Some(initiated) = self.disputes_initiated.remove(&from) {
+            *self.disputes_initiated.entry(into).or_default() += initiated;
+        }
+        Ok(())
+    }
+    /// Insert an account, returning the account previously associated with the client id
+    pub(crate) fn insert(&mut self, client_id: ClientId, account: Account) -> Option<Account> {
+        let previous = self.accounts.insert(client_id, account);
+        self.schedule_settlements(client_id);
+        previous
+    }
+    /// Insert an account moved from other accounts for a moment, without scheduling its
+    /// pending deposits to settle, since they are still scheduled where it came from
+    pub(crate) fn insert_moved(&mut self, client_id: ClientId, account: Account) {
+        self.accounts.insert(client_id, account);
+    }
+    /// Get the number of pending deposits scheduled to settle
+    #[cfg(test)]
+    pub(crate) fn scheduled_settlements(&self) -> usize {
+        self.settlements.len() + self.timed_settlements.len()
+    }
+    /// Remove the account associated with the given client id
+    pub(crate) fn remove(&mut self, client_id: ClientId) -> Option<Account> {
+        self.accounts.remove(&client_id)
+    }
+    /// Move the accounts and parked withdrawals of another collection into this one
+    pub(crate) fn append(&mut self, other: Accounts) {
+        self.accounts.extend(other.accounts);
+        self.parked.extend(other.parked);
+        self.unsettled.extend(other.unsettled);
+        self.simulated.extend(other.simulated);
+        self.settlements.extend(other.settlements);
+        self.timed_settlements.extend(other.timed_settlements);
+        self.latest_tx = self.latest_tx.max(other.latest_tx);
+    }
 }
 
 impl Index<ClientId> for Accounts {
@@ -142,44 +1956,201 @@ impl Index<ClientId> for Accounts {
     }
 }
 
+/// Escape a free-text field so it contains no commas or line breaks
+fn escape_field(field: &str) -> String {
+    field
+        .replace('%', "%25")
+        .replace(',', "%2C")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+/// Reverse [`escape_field`]
+fn unescape_field(field: &str) -> String {
+    field
+        .replace("%0D", "\r")
+        .replace("%0A", "\n")
+        .replace("%2C", ",")
+        .replace("%25", "%")
+}
+
+/// Add to an amount, failing if the result would overflow
+fn add(amount: Amount, rhs: Amount) -> Result<Amount, TransactionError> {
+    amount.checked_add(rhs).ok_or(TransactionError::Overflow)
+}
+
+/// Check the amount of a partial dispute or chargeback against the amount available to it
+fn check_partial(
+    tx_id: TransactionId,
+    amount: Amount,
+    available: Amount,
+) -> Result<Amount, TransactionError> {
+    if amount <= Amount::default() {
+        return Err(TransactionError::NonPositiveAmount(amount));
+    }
+    if amount > available {
+        return Err(TransactionError::ExcessiveDisputeAmount { tx_id, available });
+    }
+    Ok(amount)
+}
+
+/// Subtract from an amount, failing if the result would overflow
+fn sub(amount: Amount, rhs: Amount) -> Result<Amount, TransactionError> {
+    amount.checked_sub(rhs).ok_or(TransactionError::Overflow)
+}
+
+/// Get the kind of balance event a transaction makes on the account it is executed on
+fn event_kind(tx: &Transaction) -> BalanceEventKind {
+    match tx {
+        Transaction::Change { change, .. } => match change.kind {
+            ChangeKind::Deposit => BalanceEventKind::FundsDeposited,
+            ChangeKind::Withdrawal => BalanceEventKind::FundsWithdrawn,
+            ChangeKind::Fee => BalanceEventKind::FeeCharged,
+        },
+        // The kind of a dispute depends on the disputed transaction, see `Accounts::execute`
+        Transaction::Dispute { .. } => BalanceEventKind::FundsHeld,
+        Transaction::Resolution {
+            kind: ResolutionKind::Resolve,
+            ..
+        } => BalanceEventKind::FundsReleased,
+        Transaction::Resolution {
+            kind: ResolutionKind::Chargeback,
+            ..
+        } => BalanceEventKind::ChargebackApplied,
+        Transaction::Representment(_) => BalanceEventKind::ChargebackReversed,
+        Transaction::Unfreeze(_) => BalanceEventKind::AccountUnfrozen,
+        Transaction::Transfer { .. } => BalanceEventKind::TransferSent,
+    }
+}
+
 /// An error that can occur when executing a transaction
-#[derive(Debug)]
+///
+/// Each kind of error has a stable [`code`](TransactionError::code) and
+/// [`number`](TransactionError::number), so callers can tell kinds apart without parsing
+/// messages, which may change.
+#[derive(Debug, Error)]
 pub enum TransactionError {
+    #[error("Account is frozen")]
     AccountFrozen,
-    InsufficentFunds {
-        current: Amount,
-        requested: Amount,
-    },
+    #[error("Attempted to withdraw {requested} from an account with {current} available")]
+    InsufficientFunds { current: Amount, requested: Amount },
+    #[error("The transaction with id {0} does not exist or cannot be disputed")]
     InvalidDispute(TransactionId),
+    #[error("A transaction with id {tx_id} was never disputed")]
     UndisputedResolution {
         tx_id: TransactionId,
         kind: ResolutionKind,
     },
+    #[error("Transaction id {0} has already been used")]
     DuplicateTransactionId(TransactionId),
+    #[error("Account is closed")]
+    AccountClosed,
+    #[error("Account cannot be closed while it has {0} open dispute(s)")]
+    OpenDisputes(usize),
+    #[error("Transaction would overflow the account's balance")]
+    Overflow,
+    #[error("Amount {0} must be greater than zero")]
+    NonPositiveAmount(Amount),
+    #[error("Cannot transfer funds to the same account")]
+    SelfTransfer,
+    #[error("Destination account of client {0} is frozen")]
+    DestinationFrozen(ClientId),
+    #[error("Destination account of client {0} is closed")]
+    DestinationClosed(ClientId),
+    #[error("Transfers must be executed on accounts rather than a single account")]
+    UnpairedTransfer,
+    #[error("Admin transactions are not enabled")]
+    AdminRequired,
+    #[error("The transaction with id {0} is already disputed")]
+    AlreadyDisputed(TransactionId),
+    #[error("Client already has the maximum of {0} open disputes")]
+    OpenDisputeLimit(usize),
+    #[error("Client has already initiated the maximum of {0} disputes in this run")]
+    DisputeRunLimit(usize),
+    #[error("The transaction with id {0} is too old to be disputed")]
+    DisputeWindowExpired(TransactionId),
+    #[error(
+        "Amount is more than the {available} of transaction {tx_id} that can be disputed or \
+         charged back"
+    )]
+    ExcessiveDisputeAmount {
+        tx_id: TransactionId,
+        available: Amount,
+    },
+    #[error("The transaction with id {0} has no chargeback to reverse")]
+    InvalidRepresentment(TransactionId),
+    #[error("Error accessing spilled history: {0}")]
+    History(#[source] io::Error),
+    #[error(
+        "Attempted to withdraw {requested} from an account with {current} available, parked for \
+         retry"
+    )]
+    WithdrawalParked { current: Amount, requested: Amount },
+    #[error("Cannot merge an account into itself")]
+    SelfMerge,
+    #[error("Both accounts have used transaction id {0}, so they cannot be merged")]
+    MergeConflict(TransactionId),
 }
 
-impl fmt::Display for TransactionError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl TransactionError {
+    /// Get a short, machine-readable code for the kind of error, such as `insufficient_funds`
+    pub const fn code(&self) -> &'static str {
         match self {
-            TransactionError::AccountFrozen => write!(f, "Account is frozen"),
-            TransactionError::InsufficentFunds { current, requested } => write!(
-                f,
-                "Attempted to withdraw {} from an account with {} avaiable",
-                requested, current
-            ),
-            TransactionError::InvalidDispute(tx_id) => write!(
-                f,
-                "The transaction with id {} does not exist or cannot be disputed",
-                tx_id
-            ),
-            TransactionError::UndisputedResolution { tx_id, .. } => {
-                write!(f, "A transaction with id {} was never disputed", tx_id)
-            }
-            TransactionError::DuplicateTransactionId(id) => {
-                write!(f, "Transaction id {} has already been used", id)
-            }
+            TransactionError::AccountFrozen => "account_frozen",
+            TransactionError::InsufficientFunds { .. } => "insufficient_funds",
+            TransactionError::InvalidDispute(_) => "invalid_dispute",
+            TransactionError::UndisputedResolution { .. } => "undisputed_resolution",
+            TransactionError::DuplicateTransactionId(_) => "duplicate_transaction_id",
+            TransactionError::AccountClosed => "account_closed",
+            TransactionError::OpenDisputes(_) => "open_disputes",
+            TransactionError::Overflow => "overflow",
+            TransactionError::NonPositiveAmount(_) => "non_positive_amount",
+            TransactionError::SelfTransfer => "self_transfer",
+            TransactionError::DestinationFrozen(_) => "destination_frozen",
+            TransactionError::DestinationClosed(_) => "destination_closed",
+            TransactionError::UnpairedTransfer => "unpaired_transfer",
+            TransactionError::AdminRequired => "admin_required",
+            TransactionError::AlreadyDisputed(_) => "already_disputed",
+            TransactionError::OpenDisputeLimit(_) => "open_dispute_limit",
+            TransactionError::DisputeRunLimit(_) => "dispute_run_limit",
+            TransactionError::DisputeWindowExpired(_) => "dispute_window_expired",
+            TransactionError::ExcessiveDisputeAmount { .. } => "excessive_dispute_amount",
+            TransactionError::InvalidRepresentment(_) => "invalid_representment",
+            TransactionError::History(_) => "history",
+            TransactionError::WithdrawalParked { .. } => "withdrawal_parked",
+            TransactionError::SelfMerge => "self_merge",
+            TransactionError::MergeConflict(_) => "merge_conflict",
+        }
+    }
+    /// Get a numeric code for the kind of error, between 1 and 99
+    ///
+    /// Numbers are never reused for a different kind of error, even if a kind is removed.
+    pub const fn number(&self) -> u16 {
+        match self {
+            TransactionError::AccountFrozen => 1,
+            TransactionError::InsufficientFunds { .. } => 2,
+            TransactionError::InvalidDispute(_) => 3,
+            TransactionError::UndisputedResolution { .. } => 4,
+            TransactionError::DuplicateTransactionId(_) => 5,
+            TransactionError::AccountClosed => 6,
+            TransactionError::OpenDisputes(_) => 7,
+            TransactionError::Overflow => 8,
+            TransactionError::NonPositiveAmount(_) => 9,
+            TransactionError::SelfTransfer => 10,
+            TransactionError::DestinationFrozen(_) => 11,
+            TransactionError::DestinationClosed(_) => 12,
+            TransactionError::UnpairedTransfer => 13,
+            TransactionError::AdminRequired => 14,
+            TransactionError::AlreadyDisputed(_) => 15,
+            TransactionError::OpenDisputeLimit(_) => 16,
+            TransactionError::DisputeRunLimit(_) => 17,
+            TransactionError::DisputeWindowExpired(_) => 18,
+            TransactionError::ExcessiveDisputeAmount { .. } => 19,
+            TransactionError::InvalidRepresentment(_) => 20,
+            TransactionError::History(_) => 21,
+            TransactionError::WithdrawalParked { .. } => 22,
+            TransactionError::SelfMerge => 23,
+            TransactionError::MergeConflict(_) => 24,
         }
     }
 }
-
-impl Error for TransactionError {}
@@ -0,0 +1,135 @@
+//! Interest and penalties on balances
+//!
+//! Accrual happens in explicit periods, such as once at the end of a run with `--accrue`, or
+//! as time passes in timestamped input with an [`AccrualSchedule`]. An [`AccrualPolicy`] sets
+//! the interest rate on available funds and how held funds and the funds of frozen accounts
+//! are treated, since disputed and frozen balances are often treated differently from the
+//! rest. For anything the policy cannot express, [`Accounts::accrue`](crate::Accounts::accrue) takes any function of an account.
+
+use std::{fmt, num::NonZeroU64, str::FromStr};
+
+use crate::{
+    account::{Account, TransactionError},
+    amount::Amount,
+    transaction::Timestamp,
+};
+
+/// How a kind of funds accrues
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FundsTreatment {
+    /// Earn the policy's interest rate
+    #[default]
+    Interest,
+    /// Earn nothing
+    Excluded,
+    /// Be charged a rate, which is taken from available funds
+    Penalty(Amount),
+}
+
+impl FundsTreatment {
+    /// Get the change to an account's available funds from some funds treated this way
+    ///
+    /// Only positive funds accrue.
+    fn accrual(self, funds: Amount, interest_rate: Amount) -> Result<Amount, TransactionError> {
+        if funds <= Amount::default() {
+            return Ok(Amount::default());
+        }
+        let accrual = match self {
+            FundsTreatment::Interest => funds.checked_mul(interest_rate),
+            FundsTreatment::Excluded => Some(Amount::default()),
+            FundsTreatment::Penalty(rate) => funds.checked_mul(rate).map(|penalty| -penalty),
+        };
+        accrual.ok_or(TransactionError::Overflow)
+    }
+}
+
+/// Parse a treatment of funds: `interest`, `exclude`, or `penalty:<rate>`
+impl FromStr for FundsTreatment {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interest" => Ok(FundsTreatment::Interest),
+            "exclude" => Ok(FundsTreatment::Excluded),
+            _ => match s.strip_prefix("penalty:") {
+                Some(rate) => Ok(FundsTreatment::Penalty(parse_rate(rate)?)),
+                None => Err("Funds must be treated as interest, exclude, or penalty:<rate>".into()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for FundsTreatment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FundsTreatment::Interest => write!(f, "interest"),
+            FundsTreatment::Excluded => write!(f, "exclude"),
+            FundsTreatment::Penalty(rate) => write!(f, "penalty:{}", rate),
+        }
+    }
+}
+
+/// Parse a non-negative rate, such as `0.05` for 5%
+pub fn parse_rate(s: &str) -> Result<Amount, String> {
+    let rate: Amount = s.parse()?;
+    if rate < Amount::default() {
+        return Err(format!("Rate {} must not be negative", rate));
+    }
+    Ok(rate)
+}
+
+/// How the funds of accounts accrue in each period
+///
+/// By default, nothing accrues.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccrualPolicy {
+    /// The rate of interest per period, such as `0.05` for 5%
+    pub interest_rate: Amount,
+    /// How held funds accrue
+    pub held: FundsTreatment,
+    /// How all the funds of a frozen account accrue, both available and held
+    pub frozen: FundsTreatment,
+}
+
+impl AccrualPolicy {
+    /// Get the change to an account's available funds for one period
+    ///
+    /// This can be passed to [`Accounts::accrue`](crate::Accounts::accrue).
+    pub fn accrual(&self, account: &Account) -> Result<Amount, TransactionError> {
+        let (available, held) = if account.is_frozen() {
+            (self.frozen, self.frozen)
+        } else {
+            (FundsTreatment::Interest, self.held)
+        };
+        let available = available.accrual(account.balance(), self.interest_rate)?;
+        let held = held.accrual(account.held(), self.interest_rate)?;
+        available
+            .checked_add(held)
+            .ok_or(TransactionError::Overflow)
+    }
+}
+
+/// Accrual of a policy during processing, once at the end of every period of time
+///
+/// Time is taken from the timestamps of transactions, and periods are counted from the Unix
+/// epoch, so a period of a day ends at midnight UTC. The first period is the one the first
+/// timestamped transaction is in. When a transaction is made after the end of a period, each
+/// account's accrual for that period is added to its available funds before it. Like
+/// [`Accounts::accrue`](crate::Accounts::accrue), accruals are not transactions, so they are
+/// not kept in history and cannot be disputed.
+/// See [`Accounts::set_accrual_schedule`](crate::Accounts::set_accrual_schedule).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccrualSchedule {
+    pub policy: AccrualPolicy,
+    /// The length of a period in seconds
+    pub period: NonZeroU64,
+}
+
+impl AccrualSchedule {
+    /// Get the end of the period a time is in
+    pub fn period_end(&self, timestamp: Timestamp) -> Timestamp {
+        let period = self.period.get();
+        (timestamp / period)
+            .saturating_add(1)
+            .saturating_mul(period)
+    }
+}
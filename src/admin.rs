@@ -0,0 +1,468 @@
+//! Administrative operations for manual remediation
+//!
+//! Admin operations are read from a CSV file with a header row and the columns
+//! `op, client, operator, reference, tx, amount, note, into`. Every operation must name the
+//! operator performing it and a reference, such as a ticket id, explaining it.
+//! The supported operations are:
+//!
+//! - `freeze` - freeze an account, as a chargeback would
+//! - `unfreeze` - unfreeze a frozen account
+//! - `adjust` - add a positive or negative `amount` to an account's available balance
+//! - `close` - close an account
+//! - `release-hold` - release the funds held by a dispute of transaction `tx`
+//! - `note` - attach a `note` to an account without changing it
+//! - `merge-accounts` - merge the account of `client` into the account of client `into`
+//!
+//! Any operation may have a `note`, which is attached to the account as a [`Note`] if
+//! the operation is applied.
+//!
+//! Every operation, whether it succeeds or not, is recorded in a [`Journal`].
+
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, Read, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use csv::{ReaderBuilder, StringRecord, Trim, Writer};
+
+use crate::{
+    account::{Accounts, Note, Simulated, TransactionError},
+    amount::Amount,
+    events::BalanceEventKind,
+    transaction::*,
+};
+
+/// A kind of administrative operation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminOpKind {
+    /// Freeze an account
+    Freeze,
+    /// Unfreeze a frozen account
+    Unfreeze,
+    /// Add a positive or negative amount to an account's available balance
+    Adjust(Amount),
+    /// Close an account
+    Close,
+    /// Release the funds held by a dispute of a transaction
+    ReleaseHold(TransactionId),
+    /// Only attach the operation's note to the account
+    Note,
+    /// Merge the account into the account of another client
+    Merge(ClientId),
+}
+
+impl AdminOpKind {
+    /// Get the name of the operation as it appears in input
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminOpKind::Freeze => "freeze",
+            AdminOpKind::Unfreeze => "unfreeze",
+            AdminOpKind::Adjust(_) => "adjust",
+            AdminOpKind::Close => "close",
+            AdminOpKind::ReleaseHold(_) => "release-hold",
+            AdminOpKind::Note => "note",
+            AdminOpKind::Merge(_) => "merge-accounts",
+        }
+    }
+}
+
+/// An administrative operation on a client's account
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminOp {
+    /// The client whose account is operated on
+    pub client: ClientId,
+    /// The operation
+    pub kind: AdminOpKind,
+    /// The operator performing the operation
+    pub operator: String,
+    /// A reference explaining the operation, such as a ticket id
+    pub reference: String,
+    /// A note to attach to the account if the operation is applied
+    pub note: Option<String>,
+}
+
+impl AdminOp {
+    /// Apply the operation to accounts
+    ///
+    /// In sandbox mode, the operation is only simulated, see [`Accounts::set_sandbox`].
+    pub fn apply(&self, accounts: &mut Accounts) -> Result<(), TransactionError> {
+        if accounts.sandbox() {
+            let simulated = Simulated::Admin {
+                client: self.client,
+                op: self.to_string(),
+                reference: self.reference.clone(),
+            };
+            let others = match self.kind {
+                AdminOpKind::Merge(into) => vec![into],
+                _ => Vec::new(),
+            };
+            return accounts
+                .simulate_with(self.client, &others, simulated, |copy| self.apply(copy));
+        }
+        self.apply_kind(accounts)?;
+        if let Some(text) = &self.note {
+            // The merged account is closed, so the note goes to the account it was merged into
+            let client = match self.kind {
+                AdminOpKind::Merge(into) => into,
+                _ => self.client,
+            };
+            let timestamp = accounts.now();
+            accounts.account_mut(client).add_note(Note {
+                timestamp,
+                operator: self.operator.clone(),
+                reference: self.reference.clone(),
+                text: text.clone(),
+            });
+        }
+        Ok(())
+    }
+    fn apply_kind(&self, accounts: &mut Accounts) -> Result<(), TransactionError> {
+        match self.kind {
+            AdminOpKind::Freeze => {
+                let kind = BalanceEventKind::AccountFrozen;
+                accounts.observed(&[self.client], None, kind, |accounts| {
+                    accounts.account_mut(self.client).freeze()
+                })
+            }
+            AdminOpKind::Unfreeze => {
+                let kind = BalanceEventKind::AccountUnfrozen;
+                accounts.observed(&[self.client], None, kind, |accounts| {
+                    accounts.account_mut(self.client).unfreeze()
+                })
+            }
+            AdminOpKind::Adjust(amount) => {
+                let amount = amount.round(accounts.precision());
+                let kind = BalanceEventKind::BalanceAdjusted;
+                accounts.observed(&[self.client], None, kind, |accounts| {
+                    accounts.account_mut(self.client).adjust(amount)
+                })
+            }
+            AdminOpKind::Close => accounts.close(self.client),
+            AdminOpKind::ReleaseHold(tx_id) => accounts.transact(ClientTransaction {
+                client: self.client,
+                tx: Transaction::resolution(ResolutionKind::Resolve, tx_id),
+                timestamp: None,
+            }),
+            AdminOpKind::Note => Ok(()),
+            AdminOpKind::Merge(into) => {
+                let kind = BalanceEventKind::AccountMerged;
+                accounts.observed(&[self.client, into], None, kind, |accounts| {
+                    accounts.merge(self.client, into)
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for AdminOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} client {}", self.kind.name(), self.client)?;
+        match self.kind {
+            AdminOpKind::Adjust(amount) => write!(f, " by {}", amount),
+            AdminOpKind::ReleaseHold(tx_id) => write!(f, " for transaction {}", tx_id),
+            AdminOpKind::Merge(into) => write!(f, " into client {}", into),
+            AdminOpKind::Freeze
+            | AdminOpKind::Unfreeze
+            | AdminOpKind::Close
+            | AdminOpKind::Note => Ok(()),
+        }
+    }
+}
+
+/// Read all admin operations from a CSV source
+///
+/// The whole source is validated before any operations are returned, so that a
+/// malformed file is never partially applied.
+pub fn read_ops<R: Read>(source: R) -> Result<Vec<AdminOp>, String> {
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(source);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Error reading admin operations: {}", e))?
+        .clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(name))
+    };
+    let required = |name: &str| {
+        column(name).ok_or_else(|| format!("Admin operations are missing a {:?} column", name))
+    };
+    let op_col = required("op")?;
+    let client_col = required("client")?;
+    let operator_col = required("operator")?;
+    let reference_col = required("reference")?;
+    let tx_col = column("tx");
+    let amount_col = column("amount");
+    let note_col = column("note");
+    let into_col = column("into");
+
+    let mut ops = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let row = i + 1;
+        let record = record.map_err(|e| format!("Error reading admin operation {}: {}", row, e))?;
+        let field = |col: Option<usize>| {
+            col.and_then(|col| record.get(col))
+                .filter(|field| !field.is_empty())
+        };
+        let parse_op = || -> Result<AdminOp, String> {
+            let required = |col: usize, name: &str| {
+                field(Some(col)).ok_or_else(|| format!("Missing {}", name))
+            };
+            let client = required(client_col, "client")?;
+            let client = client
+                .parse::<ClientId>()
+                .map_err(|_| format!("Invalid client id {:?}", client))?;
+            let kind = match required(op_col, "op")? {
+                "freeze" => AdminOpKind::Freeze,
+                "unfreeze" => AdminOpKind::Unfreeze,
+                "close" => AdminOpKind::Close,
+                "adjust" => {
+                    let amount = field(amount_col).ok_or("Missing amount")?;
+                    let amount = amount.parse::<Amount>()?;
+                    AdminOpKind::Adjust(amount)
+                }
+                "release-hold" => {
+                    let tx_id = field(tx_col).ok_or("Missing tx")?;
+                    let tx_id = tx_id
+                        .parse::<TransactionId>()
+                        .map_err(|_| format!("Invalid transaction id {:?}", tx_id))?;
+                    AdminOpKind::ReleaseHold(tx_id)
+                }
+                "note" => {
+                    field(note_col).ok_or("Missing note")?;
+                    AdminOpKind::Note
+                }
+                "merge-accounts" => {
+                    let into = field(into_col).ok_or("Missing into")?;
+                    let into = into
+                        .parse::<ClientId>()
+                        .map_err(|_| format!("Invalid client id {:?}", into))?;
+                    AdminOpKind::Merge(into)
+                }
+                op => return Err(format!("Invalid operation {:?}", op)),
+            };
+            Ok(AdminOp {
+                client,
+                kind,
+                operator: required(operator_col, "operator")?.into(),
+                reference: required(reference_col, "reference")?.into(),
+                note: field(note_col).map(Into::into),
+            })
+        };
+        ops.push(parse_op().map_err(|e| format!("Invalid admin operation {}: {}", row, e))?);
+    }
+    Ok(ops)
+}
+
+/// An append-only record of admin operations and their outcomes
+///
+/// Each entry is a CSV row with the columns
+/// `timestamp, operator, reference, op, client, tx, amount, result, note, into`,
+/// where `timestamp` is in seconds since the Unix epoch and `into` is the client a merged
+/// account was merged into.
+pub struct Journal<W: Write> {
+    writer: Writer<W>,
+}
+
+impl Journal<std::fs::File> {
+    /// Open a journal file for appending, creating it if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut journal = Journal::new(file);
+        if is_new {
+            journal.writer.write_record([
+                "timestamp",
+                "operator",
+                "reference",
+                "op",
+                "client",
+                "tx",
+                "amount",
+                "result",
+                "note",
+                "into",
+            ])?;
+            journal.writer.flush()?;
+        }
+        Ok(journal)
+    }
+}
+
+impl<W: Write> Journal<W> {
+    /// Create a journal that writes entries to a writer
+    pub fn new(writer: W) -> Self {
+        Journal {
+            writer: Writer::from_writer(writer),
+        }
+    }
+    /// Record an operation and its outcome
+    ///
+    /// Entries are flushed as soon as they are written.
+    pub fn record(
+        &mut self,
+        op: &AdminOp,
+        result: &Result<(), TransactionError>,
+    ) -> io::Result<()> {
+        let result = match result {
+            Ok(()) => "applied".into(),
+            Err(e) => format!("rejected: {}", e),
+        };
+        self.write(op, result)
+    }
+    /// Record an operation that was only simulated in sandbox mode
+    ///
+    /// The notes of simulated operations are not read by [`read_notes`].
+    pub fn record_simulated(&mut self, op: &AdminOp) -> io::Result<()> {
+        self.write(op, "simulated".into())
+    }
+    fn write(&mut self, op: &AdminOp, result: String) -> io::Result<()> {
+        let (tx, amount, into) = match op.kind {
+            AdminOpKind::Adjust(amount) => (String::new(), amount.to_string(), String::new()),
+            AdminOpKind::ReleaseHold(tx_id) => (tx_id.to_string(), String::new(), String::new()),
+            AdminOpKind::Merge(into) => (String::new(), String::new(), into.to_string()),
+            AdminOpKind::Freeze
+            | AdminOpKind::Unfreeze
+            | AdminOpKind::Close
+            | AdminOpKind::Note => (String::new(), String::new(), String::new()),
+        };
+        self.writer.write_record(&StringRecord::from(vec![
+            now().to_string(),
+            op.operator.clone(),
+            op.reference.clone(),
+            op.kind.name().into(),
+            op.client.to_string(),
+            tx,
+            amount,
+            result,
+            op.note.clone().unwrap_or_default(),
+            into,
+        ]))?;
+        self.writer.flush()
+    }
+}
+
+/// Read the notes attached to a client's account by the applied operations in a journal
+///
+/// Journals written before notes were supported have no notes.
+pub fn read_notes<R: Read>(journal: R, client: ClientId) -> Result<Vec<Note>, String> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_reader(journal);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Error reading journal: {}", e))?
+        .clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| format!("Journal is missing a {:?} column", name))
+    };
+    let timestamp_col = column("timestamp")?;
+    let operator_col = column("operator")?;
+    let reference_col = column("reference")?;
+    let client_col = column("client")?;
+    let result_col = column("result")?;
+    let Ok(note_col) = column("note") else {
+        return Ok(Vec::new());
+    };
+    let into_col = column("into").ok();
+    let mut notes = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("Error reading journal entry {}: {}", i + 1, e))?;
+        let field = |col: usize| record.get(col).unwrap_or_default();
+        let text = field(note_col);
+        // The notes of merges are attached to the account merged into
+        let noted = into_col
+            .map(field)
+            .filter(|into| !into.is_empty())
+            .unwrap_or_else(|| field(client_col));
+        if text.is_empty() || field(result_col) != "applied" || noted.parse() != Ok(client) {
+            continue;
+        }
+        notes.push(Note {
+            timestamp: field(timestamp_col)
+                .parse()
+                .map_err(|_| format!("Invalid timestamp in journal entry {}", i + 1))?,
+            operator: field(operator_col).into(),
+            reference: field(reference_col).into(),
+            text: text.into(),
+        });
+    }
+    Ok(notes)
+}
+
+/// Get the current time in seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Check a provided admin token against the expected one
+///
+/// The comparison takes the same time regardless of where the tokens differ.
+pub fn authenticate(provided: &str, expected: &str) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The environment variable holding the admin token
+pub const TOKEN_VAR: &str = "TRANSACTOR_ADMIN_TOKEN";
+
+/// Check a provided admin token against the one in [`TOKEN_VAR`]
+///
+/// Admin access is disabled if the variable is not set or is empty.
+pub fn authenticate_env(provided: Option<&str>) -> Result<(), String> {
+    let expected = std::env::var(TOKEN_VAR)
+        .map_err(|_| format!("Admin access is disabled because {} is not set", TOKEN_VAR))?;
+    let provided = provided.ok_or("Expected an admin token (--token)")?;
+    if expected.is_empty() || !authenticate(provided, &expected) {
+        return Err("Invalid admin token".into());
+    }
+    Ok(())
+}
+
+/// Apply admin operations to accounts, recording each one in a journal
+///
+/// Returns the number of operations that were applied and rejected. In sandbox mode, the
+/// operations that would have been applied are counted as applied.
+pub fn apply_ops<W: Write>(
+    ops: &[AdminOp],
+    accounts: &mut Accounts,
+    journal: &mut Journal<W>,
+) -> io::Result<(usize, usize)> {
+    let mut applied = 0;
+    let mut rejected = 0;
+    for op in ops {
+        let result = op.apply(accounts);
+        if result.is_ok() && accounts.sandbox() {
+            journal.record_simulated(op)?;
+        } else {
+            journal.record(op, &result)?;
+        }
+        match result {
+            Ok(()) => applied += 1,
+            Err(e) => {
+                rejected += 1;
+                tracing::warn!(
+                    reference = %op.reference,
+                    "Error applying {} for {}: {}",
+                    op,
+                    op.reference,
+                    e
+                );
+            }
+        }
+    }
+    Ok((applied, rejected))
+}
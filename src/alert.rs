@@ -0,0 +1,163 @@
+//! Alerts on account balances crossing thresholds during processing
+//!
+//! An [`AlertRule`] is written as a balance, a comparison, and a threshold, such as
+//! `total<0` or `held>1000`. An [`AlertMonitor`] checks accounts against its rules as
+//! transactions are executed, so that problems are reported the moment they occur.
+
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use crate::{account::Account, amount::Amount, transaction::ClientId};
+
+/// A balance of an account that can be checked by an [`AlertRule`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    /// The available balance
+    Available,
+    /// The held balance
+    Held,
+    /// The total balance
+    Total,
+}
+
+impl Balance {
+    /// Get the balance of an account
+    pub(crate) fn of(self, account: &Account) -> Amount {
+        match self {
+            Balance::Available => account.balance(),
+            Balance::Held => account.held(),
+            Balance::Total => account.total(),
+        }
+    }
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Balance::Available => write!(f, "available"),
+            Balance::Held => write!(f, "held"),
+            Balance::Total => write!(f, "total"),
+        }
+    }
+}
+
+/// A condition on an account balance that should raise an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlertRule {
+    /// The balance to check
+    pub balance: Balance,
+    /// Whether the alert is raised above the threshold, rather than below it
+    pub above: bool,
+    /// The threshold
+    pub threshold: Amount,
+}
+
+impl AlertRule {
+    /// Check whether an account meets the rule's condition
+    pub fn is_triggered(&self, account: &Account) -> bool {
+        let balance = self.balance.of(account);
+        if self.above {
+            balance > self.threshold
+        } else {
+            balance < self.threshold
+        }
+    }
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (balance, threshold, above) = if let Some((balance, threshold)) = s.split_once('<') {
+            (balance, threshold, false)
+        } else if let Some((balance, threshold)) = s.split_once('>') {
+            (balance, threshold, true)
+        } else {
+            return Err(format!(
+                "Invalid alert rule {:?}, expected a form like total<0",
+                s
+            ));
+        };
+        let balance = match balance.trim() {
+            "available" => Balance::Available,
+            "held" => Balance::Held,
+            "total" => Balance::Total,
+            balance => return Err(format!("Invalid balance {:?} in alert rule", balance)),
+        };
+        let threshold = threshold
+            .trim()
+            .parse::<Amount>()
+            .map_err(|e| format!("Invalid threshold in alert rule: {}", e))?;
+        Ok(AlertRule {
+            balance,
+            above,
+            threshold,
+        })
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comparison = if self.above { '>' } else { '<' };
+        write!(f, "{}{}{}", self.balance, comparison, self.threshold)
+    }
+}
+
+/// An alert raised when an account met an [`AlertRule`]'s condition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    /// The client whose account raised the alert
+    pub client: ClientId,
+    /// The rule whose condition was met
+    pub rule: AlertRule,
+    /// The balance that met the condition
+    pub value: Amount,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Client {} {} balance {} is {} {}",
+            self.client,
+            self.rule.balance,
+            self.value,
+            if self.rule.above { "above" } else { "below" },
+            self.rule.threshold
+        )
+    }
+}
+
+/// Checks accounts against alert rules as transactions are executed
+///
+/// An alert is only raised when an account starts meeting a rule's condition. It is
+/// raised again if the account stops meeting the condition and later meets it again.
+#[derive(Debug, Default)]
+pub struct AlertMonitor {
+    rules: Vec<AlertRule>,
+    triggered: HashSet<(ClientId, usize)>,
+}
+
+impl AlertMonitor {
+    /// Create a monitor for some rules
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        AlertMonitor {
+            rules,
+            triggered: HashSet::new(),
+        }
+    }
+    /// Check a client's account after a transaction and get any newly raised alerts
+    pub fn check(&mut self, client: ClientId, account: &Account) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            if !rule.is_triggered(account) {
+                self.triggered.remove(&(client, i));
+            } else if self.triggered.insert((client, i)) {
+                alerts.push(Alert {
+                    client,
+                    rule: *rule,
+                    value: rule.balance.of(account),
+                });
+            }
+        }
+        alerts
+    }
+}
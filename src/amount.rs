@@ -6,45 +6,302 @@ use std::{
     cmp::Ordering,
     fmt,
     ops::{Add, AddAssign, Neg, Sub, SubAssign},
+    str::FromStr,
 };
 
-const DECIMAL_POINT_MUL: f64 = 10_000.0;
+/// The number of decimal places amounts are kept to by default
+pub const AMOUNT_DECIMAL_PLACES: u32 = 4;
+
+/// The largest number of decimal places an [`Amount`] can represent
+pub const MAX_DECIMAL_PLACES: u32 = 8;
+
+/// Powers of ten up to the number of fixed-point units in 1, so they are not recomputed for
+/// every amount
+const POWERS_OF_TEN: [i128; MAX_DECIMAL_PLACES as usize + 1] = {
+    let mut powers = [1; MAX_DECIMAL_PLACES as usize + 1];
+    let mut i = 1;
+    while i < powers.len() {
+        powers[i] = powers[i - 1] * 10;
+        i += 1;
+    }
+    powers
+};
+
+/// The number of fixed-point units in 1
+const UNITS_PER_WHOLE: i128 = POWERS_OF_TEN[MAX_DECIMAL_PLACES as usize];
+
+const DECIMAL_POINT_MUL: f64 = UNITS_PER_WHOLE as f64;
+
+/// A number of decimal places that amounts are kept to
+///
+/// Amounts can always represent up to [`MAX_DECIMAL_PLACES`] decimal places. A precision
+/// limits them further, such as to 2 for a currency with cents or 0 for one without minor
+/// units. Amounts are rescaled to a precision with [`Amount::round`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Precision(u32);
+
+impl Precision {
+    /// The default precision of [`AMOUNT_DECIMAL_PLACES`] decimal places
+    pub const DEFAULT: Precision = Precision(AMOUNT_DECIMAL_PLACES);
+    /// Create a precision, or return `None` if it is more than [`MAX_DECIMAL_PLACES`]
+    pub const fn new(decimal_places: u32) -> Option<Self> {
+        if decimal_places <= MAX_DECIMAL_PLACES {
+            Some(Precision(decimal_places))
+        } else {
+            None
+        }
+    }
+    /// Get the number of decimal places
+    pub const fn decimal_places(self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision::DEFAULT
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().ok().and_then(Precision::new).ok_or_else(|| {
+            format!(
+                "Invalid precision {:?}, expected at most {} decimal places",
+                s, MAX_DECIMAL_PLACES
+            )
+        })
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
 
 /// A fixed-point number for use in representing amounts of money
 ///
 /// This type abstracts an integer as a fixed-point number to avoid floating-point errors,
 /// which are not acceptable when dealing with money.
+///
+/// Amounts are stored as a whole number of 10^-[`MAX_DECIMAL_PLACES`] units in an `i128`, so
+/// they range to about 1.7 × 10^30 either side of zero.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
-pub struct Amount(i64);
+pub struct Amount(i128);
 
 impl Amount {
     /// Attempt to create an amount from an `f64`
     pub fn from_f64(amount: f64) -> Option<Self> {
         let amount_multiplied = (amount * DECIMAL_POINT_MUL).round();
-        if amount_multiplied > i64::MAX as f64
-            || amount_multiplied < i64::MIN as f64
+        if amount_multiplied > i128::MAX as f64
+            || amount_multiplied < i128::MIN as f64
             || amount_multiplied.is_nan()
         {
             None
         } else {
-            Some(Amount(amount_multiplied as i64))
+            Some(Amount(amount_multiplied as i128))
         }
     }
     /// Get the amount as an `f64`
     pub fn as_f64(&self) -> f64 {
         self.0 as f64 / DECIMAL_POINT_MUL
     }
+    /// Add two amounts, returning `None` if the result would overflow
+    pub const fn checked_add(self, rhs: Self) -> Option<Self> {
+        match self.0.checked_add(rhs.0) {
+            Some(units) => Some(Amount(units)),
+            None => None,
+        }
+    }
+    /// Subtract an amount, returning `None` if the result would overflow
+    pub const fn checked_sub(self, rhs: Self) -> Option<Self> {
+        match self.0.checked_sub(rhs.0) {
+            Some(units) => Some(Amount(units)),
+            None => None,
+        }
+    }
+    /// Multiply by a rate, such as `0.05` for 5%, returning `None` if the result would overflow
+    ///
+    /// Digits beyond [`MAX_DECIMAL_PLACES`] are rounded half away from zero.
+    pub fn checked_mul(self, rate: Amount) -> Option<Self> {
+        let product = self.0.checked_mul(rate.0)?;
+        let mut units = product / UNITS_PER_WHOLE;
+        if (product % UNITS_PER_WHOLE).abs() * 2 >= UNITS_PER_WHOLE {
+            units += product.signum();
+        }
+        Some(Amount(units))
+    }
+    /// Rescale the amount to a precision
+    ///
+    /// Digits beyond the precision are rounded half away from zero. If that would
+    /// overflow, they are rounded toward zero instead.
+    pub fn round(self, precision: Precision) -> Self {
+        let step = POWERS_OF_TEN[(MAX_DECIMAL_PLACES - precision.0) as usize];
+        let remainder = self.0 % step;
+        let truncated = self.0 - remainder;
+        if remainder.unsigned_abs() * 2 < step.unsigned_abs() {
+            return Amount(truncated);
+        }
+        Amount(
+            truncated
+                .checked_add(step * remainder.signum())
+                .unwrap_or(truncated),
+        )
+    }
+    /// Get the number of decimal places needed to represent the amount exactly
+    fn decimal_places(self) -> u32 {
+        let mut frac = self.0 % UNITS_PER_WHOLE;
+        if frac == 0 {
+            return 0;
+        }
+        let mut places = MAX_DECIMAL_PLACES;
+        while frac % 10 == 0 {
+            frac /= 10;
+            places -= 1;
+        }
+        places
+    }
+    /// Create an amount from its underlying fixed-point representation
+    pub(crate) const fn from_units(units: i128) -> Self {
+        Amount(units)
+    }
+    /// Get the underlying fixed-point representation of the amount
+    pub(crate) const fn units(self) -> i128 {
+        self.0
+    }
+    /// Parse an amount exactly from a decimal string, telling malformed amounts apart from
+    /// those too large to represent
+    ///
+    /// See the [`FromStr`] implementation.
+    pub(crate) fn parse(s: &str) -> Result<Self, ParseAmountError> {
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if int.is_empty() && frac.is_empty()
+            || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return Err(ParseAmountError::Invalid);
+        }
+        // Digits beyond the supported precision are rounded half away from zero
+        let places = MAX_DECIMAL_PLACES as usize;
+        let round_up = frac.len() > places && frac.as_bytes()[places] >= b'5';
+        let frac = &frac[..frac.len().min(places)];
+        // Digits are accumulated directly rather than parsed from padded strings, since this
+        // is done for every row of input
+        let mut whole: i128 = 0;
+        for digit in int.bytes() {
+            whole = whole
+                .checked_mul(10)
+                .and_then(|whole| whole.checked_add((digit - b'0') as i128))
+                .ok_or(ParseAmountError::OutOfRange)?;
+        }
+        let frac = frac
+            .bytes()
+            .fold(0, |frac, digit| frac * 10 + (digit - b'0') as i128)
+            * POWERS_OF_TEN[places - frac.len()];
+        let units = whole
+            .checked_mul(UNITS_PER_WHOLE)
+            .and_then(|units| units.checked_add(frac + round_up as i128))
+            .ok_or(ParseAmountError::OutOfRange)?;
+        Ok(Amount(if negative { -units } else { units }))
+    }
+}
+
+/// Why a string could not be parsed as an [`Amount`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ParseAmountError {
+    /// The string is not a decimal number
+    Invalid,
+    /// The number is too large to represent
+    OutOfRange,
+}
+
+/// Parse an amount exactly from a decimal string
+///
+/// The string may start with a sign. Fractional digits beyond [`MAX_DECIMAL_PLACES`]
+/// are rounded half away from zero. Exponents are not accepted.
+impl FromStr for Amount {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s).map_err(|e| match e {
+            ParseAmountError::Invalid => format!("Invalid amount {:?}", s),
+            ParseAmountError::OutOfRange => format!("Amount {:?} is out of range", s),
+        })
+    }
+}
+
+/// Amounts are serialized as exact decimal strings, so they are never subject to
+/// floating-point rounding
+#[cfg(feature = "serde")]
+impl serde::Serialize for Amount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Amount {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
 }
 
 impl fmt::Debug for Amount {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.as_f64().fmt(f)
+        fmt::Display::fmt(self, f)
     }
 }
 
+/// Amounts are displayed exactly, with at least [`AMOUNT_DECIMAL_PLACES`] decimal places
+///
+/// A precision, as in `{:.2}`, displays exactly that many decimal places instead, rounding
+/// half away from zero.
 impl fmt::Display for Amount {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.as_f64().fmt(f)
+        let places = match f.precision() {
+            Some(places) => places,
+            None => AMOUNT_DECIMAL_PLACES.max(self.decimal_places()) as usize,
+        };
+        let stored_places = places.min(MAX_DECIMAL_PLACES as usize);
+        let amount = self.round(Precision(stored_places as u32));
+        let units = amount.0.unsigned_abs();
+        let per_whole = UNITS_PER_WHOLE.unsigned_abs();
+        let (mut int, mut frac) = (units / per_whole, units % per_whole);
+        // Digits are written backwards into a buffer on the stack, since formatting is done
+        // for every account and row of output
+        let mut buf = [0; 48];
+        let mut pos = buf.len();
+        if places > 0 {
+            frac /= POWERS_OF_TEN[MAX_DECIMAL_PLACES as usize - stored_places] as u128;
+            for _ in 0..stored_places {
+                pos -= 1;
+                buf[pos] = b'0' + (frac % 10) as u8;
+                frac /= 10;
+            }
+            pos -= 1;
+            buf[pos] = b'.';
+        }
+        loop {
+            pos -= 1;
+            buf[pos] = b'0' + (int % 10) as u8;
+            int /= 10;
+            if int == 0 {
+                break;
+            }
+        }
+        let digits = std::str::from_utf8(&buf[pos..]).expect("digits are ASCII");
+        if places > stored_places {
+            let extra = places - stored_places;
+            return f.pad_integral(amount.0 >= 0, "", &format!("{}{:0<extra$}", digits, ""));
+        }
+        f.pad_integral(amount.0 >= 0, "", digits)
     }
 }
 
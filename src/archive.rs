@@ -0,0 +1,83 @@
+//! Archival of closed accounts
+//!
+//! Closed accounts can be moved out of the live [`Accounts`] and into an [`Archive`],
+//! which stores each account's full state and history in its own file. If the
+//! client ever returns, the account can be unarchived and used again.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    account::{Account, Accounts},
+    transaction::ClientId,
+};
+
+/// A directory of archived accounts
+#[derive(Debug, Clone)]
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    /// Open an archive in a directory, creating the directory if it does not exist
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Archive { dir })
+    }
+    fn path(&self, client_id: ClientId) -> PathBuf {
+        self.dir.join(format!("client_{}.archive", client_id))
+    }
+    /// Check whether an account is archived
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.path(client_id).exists()
+    }
+    /// Move all closed accounts into the archive, returning the ids of the archived clients
+    ///
+    /// Each account is only removed from `accounts` once it has been fully written.
+    pub fn archive_closed(&self, accounts: &mut Accounts) -> io::Result<Vec<ClientId>> {
+        let closed: Vec<ClientId> = accounts
+            .iter()
+            .filter(|(_, account)| account.is_closed())
+            .map(|(client_id, _)| client_id)
+            .collect();
+        for &client_id in &closed {
+            // Write to a temporary file first so a partial write never looks archived
+            let path = self.path(client_id);
+            let temp_path = path.with_extension("tmp");
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            accounts[client_id].save(&mut writer)?;
+            writer.flush()?;
+            drop(writer);
+            fs::rename(&temp_path, &path)?;
+            accounts.remove(client_id);
+        }
+        Ok(closed)
+    }
+    /// Restore an archived account to `accounts` and reopen it
+    ///
+    /// Returns `false` if the account is not archived. Fails if the client already has a
+    /// live account, since it would be overwritten.
+    pub fn unarchive(&self, accounts: &mut Accounts, client_id: ClientId) -> io::Result<bool> {
+        let path = self.path(client_id);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if accounts.get(client_id).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Client {} already has a live account", client_id),
+            ));
+        }
+        let mut account = Account::load(BufReader::new(file))?;
+        account.reopen();
+        accounts.insert(client_id, account);
+        fs::remove_file(path)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,85 @@
+//! Minimal command-line argument handling
+//!
+//! Arguments are consumed as they are queried, so anything left over after
+//! all known flags have been taken is either a positional argument or an error.
+
+use std::{env, str::FromStr};
+
+/// Command-line arguments that have not yet been consumed
+#[derive(Debug)]
+pub struct Args {
+    args: Vec<String>,
+}
+
+impl Args {
+    /// Get the arguments passed to the program, excluding the program name
+    pub fn from_env() -> Self {
+        Args::new(env::args().skip(1))
+    }
+    /// Create from an arbitrary list of arguments
+    pub fn new<I>(args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Args {
+            args: args.into_iter().map(Into::into).collect(),
+        }
+    }
+    /// Take the first argument if it is the given subcommand
+    pub fn subcommand(&mut self, name: &str) -> bool {
+        if self.args.first().is_some_and(|arg| arg == name) {
+            self.args.remove(0);
+            true
+        } else {
+            false
+        }
+    }
+    /// Take a boolean flag, returning whether it was present
+    pub fn flag(&mut self, name: &str) -> bool {
+        let len = self.args.len();
+        self.args.retain(|arg| arg != name);
+        self.args.len() != len
+    }
+    /// Take the value following a flag, if the flag is present
+    pub fn value<T>(&mut self, name: &str) -> Result<Option<T>, String>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        Ok(self.values(name)?.pop())
+    }
+    /// Take the values following every occurrence of a flag
+    pub fn values<T>(&mut self, name: &str) -> Result<Vec<T>, String>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let mut values = Vec::new();
+        while let Some(i) = self.args.iter().position(|arg| arg == name) {
+            if i + 1 >= self.args.len() {
+                return Err(format!("Expected a value after {}", name));
+            }
+            let value = self.args.remove(i + 1);
+            self.args.remove(i);
+            let parsed = value
+                .parse()
+                .map_err(|e| format!("Invalid value {:?} for {}: {}", value, name, e))?;
+            values.push(parsed);
+        }
+        Ok(values)
+    }
+    /// Take the remaining positional arguments
+    ///
+    /// Fails if any unrecognized flags remain
+    pub fn finish(self) -> Result<Vec<String>, String> {
+        if let Some(flag) = self
+            .args
+            .iter()
+            .find(|arg| arg.starts_with("--") || arg.len() > 1 && arg.starts_with('-'))
+        {
+            return Err(format!("Unrecognized option {}", flag));
+        }
+        Ok(self.args)
+    }
+}
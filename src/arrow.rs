@@ -0,0 +1,129 @@
+//! Reading transactions from Arrow IPC data and writing accounts as Arrow record batches
+//!
+//! This module is enabled by the `arrow` feature. Arrow input has the same columns as CSV
+//! input with a header row: `type`, `client`, `tx`, and optionally `amount` and `to`. Columns
+//! are found by name, ignoring case, and may be of any type that can be displayed. Null values
+//! are treated as empty fields. Both the IPC file format and the IPC stream format are read.
+//!
+//! Account data is written as an IPC stream of a single record batch. Amounts are written as
+//! `Decimal128` values with a scale of the accounts' decimal places, so they are exact.
+
+use std::{
+    io::{self, Read, Seek, Write},
+    sync::Arc,
+};
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Decimal128Array, RecordBatch, UInt16Array, UInt32Array, UInt64Array,
+};
+use arrow_ipc::{
+    reader::{FileReader, StreamReader},
+    writer::StreamWriter,
+};
+use arrow_schema::{DataType, Field, Schema};
+
+use crate::{
+    account::{Account, Activity},
+    amount::{Amount, Precision, MAX_DECIMAL_PLACES},
+    batches::BatchRecords,
+    transaction::{ClientId, Timestamp, TransactionId},
+};
+
+/// An iterator over the transactions in an Arrow IPC file
+pub type ArrowFileRecords<R> = BatchRecords<FileReader<R>>;
+
+/// An iterator over the transactions in an Arrow IPC stream
+pub type ArrowStreamRecords<R> = BatchRecords<StreamReader<R>>;
+
+/// Iterate over the transactions in an Arrow IPC file
+///
+/// Fails if the data is not an Arrow IPC file or is missing a required column. Errors reading
+/// or parsing a row are reported with its row number.
+pub fn arrow_file_records<R: Read + Seek>(reader: R) -> Result<ArrowFileRecords<R>, String> {
+    let reader = FileReader::try_new(reader, None).map_err(|e| e.to_string())?;
+    BatchRecords::new(&reader.schema(), reader)
+}
+
+/// Iterate over the transactions in an Arrow IPC stream
+///
+/// Record batches are read as they are needed, so the stream may come from a pipe.
+/// See [`arrow_file_records`].
+pub fn arrow_stream_records<R: Read>(reader: R) -> Result<ArrowStreamRecords<R>, String> {
+    let reader = StreamReader::try_new(reader, None).map_err(|e| e.to_string())?;
+    BatchRecords::new(&reader.schema(), reader)
+}
+
+/// Write accounts as an Arrow IPC stream of a single record batch
+///
+/// The columns are the same as in CSV output, with extended data if `extended` is set.
+pub(crate) fn write_accounts<'a, W, I>(
+    accounts: I,
+    precision: Precision,
+    extended: bool,
+    writer: W,
+) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (ClientId, &'a Account)>,
+{
+    let scale = precision.decimal_places();
+    let decimal =
+        |amount: Amount| amount.round(precision).units() / 10i128.pow(MAX_DECIMAL_PLACES - scale);
+    let amounts = |values: Vec<i128>| -> io::Result<ArrayRef> {
+        let array = Decimal128Array::from(values)
+            .with_precision_and_scale(38, scale as i8)
+            .map_err(io::Error::other)?;
+        Ok(Arc::new(array))
+    };
+    let accounts: Vec<_> = accounts.into_iter().collect();
+    let amount_field = |name| Field::new(name, DataType::Decimal128(38, scale as i8), false);
+    let mut fields = vec![
+        Field::new("client", DataType::UInt16, false),
+        amount_field("available"),
+        amount_field("held"),
+        amount_field("total"),
+        Field::new("locked", DataType::Boolean, false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt16Array::from_iter_values(
+            accounts.iter().map(|(client_id, _)| *client_id),
+        )),
+        amounts(accounts.iter().map(|(_, a)| decimal(a.balance())).collect())?,
+        amounts(accounts.iter().map(|(_, a)| decimal(a.held())).collect())?,
+        amounts(accounts.iter().map(|(_, a)| decimal(a.total())).collect())?,
+        Arc::new(BooleanArray::from_iter(
+            accounts.iter().map(|(_, a)| Some(a.is_frozen())),
+        )),
+    ];
+    if extended {
+        fields.extend([
+            Field::new("first_tx", DataType::UInt32, true),
+            Field::new("last_tx", DataType::UInt32, true),
+            amount_field("pending"),
+            Field::new("first_timestamp", DataType::UInt64, true),
+            Field::new("last_timestamp", DataType::UInt64, true),
+        ]);
+        let activity = |f: fn(Activity) -> TransactionId| -> ArrayRef {
+            Arc::new(UInt32Array::from_iter(
+                accounts.iter().map(|(_, a)| a.activity().map(f)),
+            ))
+        };
+        let timestamps = |f: fn(Activity) -> Option<Timestamp>| -> ArrayRef {
+            Arc::new(UInt64Array::from_iter(
+                accounts.iter().map(|(_, a)| a.activity().and_then(f)),
+            ))
+        };
+        columns.extend([
+            activity(|activity| activity.first_tx),
+            activity(|activity| activity.last_tx),
+            amounts(accounts.iter().map(|(_, a)| decimal(a.pending())).collect())?,
+            timestamps(|activity| activity.first_timestamp),
+            timestamps(|activity| activity.last_timestamp),
+        ]);
+    }
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(io::Error::other)?;
+    let mut writer = StreamWriter::try_new(writer, &schema).map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
@@ -0,0 +1,293 @@
+//! An append-only log of applied transactions
+//!
+//! An [`AuditLog`] records every transaction that was applied, in the order it was applied,
+//! along with the balances of the client's account afterwards. Replaying the log from the
+//! starting state shows exactly how the final state was reached. Rejected transactions are
+//! not recorded.
+//!
+//! Entries form a hash chain: each one ends with a hash of itself and the hash of the entry
+//! before it. [`verify`] re-derives the chain from the input the log was produced from, so
+//! any entry that was changed, inserted, or removed is found.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use csv::{Reader, StringRecord, Writer};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    account::{Accounts, TransactionError},
+    process_transaction_source_with,
+    transaction::{ClientTransaction, Transaction},
+};
+
+/// The columns of an audit log
+const COLUMNS: [&str; 11] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "to",
+    "timestamp",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "hash",
+];
+
+/// An append-only log of applied transactions
+///
+/// Each entry is a CSV row with the columns
+/// `type, client, tx, amount, to, timestamp, available, held, total, locked, hash`. The first
+/// six are the transaction as it appears in input, and the next four are the client's balances
+/// after it was applied. For a transfer, these are the balances of the sending client.
+///
+/// `hash` is the hex-encoded SHA-256 hash of the previous entry's hash followed by the
+/// entry's other columns, each preceded by a comma. The first entry's previous hash is empty.
+pub struct AuditLog<W: Write> {
+    writer: Writer<W>,
+    /// The hash of the latest entry
+    hash: String,
+    error: Option<io::Error>,
+}
+
+impl AuditLog<File> {
+    /// Open an audit log file for appending, creating it if it does not exist
+    ///
+    /// The hash chain continues from the last entry in the file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut log = AuditLog::new(file);
+        if is_new {
+            log.writer.write_record(COLUMNS)?;
+        } else {
+            let mut entries = read_entries(File::open(path)?)?;
+            while let Some(entry) = entries.next()? {
+                log.hash = entry[COLUMNS.len() - 1].into();
+            }
+        }
+        Ok(log)
+    }
+}
+
+impl<W: Write> AuditLog<W> {
+    /// Create an audit log that writes entries to a writer, starting a new hash chain
+    pub fn new(writer: W) -> Self {
+        AuditLog {
+            writer: Writer::from_writer(writer),
+            hash: String::new(),
+            error: None,
+        }
+    }
+    /// Observe the execution of a transaction, recording it if it was applied
+    ///
+    /// Entries are buffered. If writing fails, nothing more is recorded and the error is
+    /// returned by [`AuditLog::flush`].
+    pub fn observe(
+        &mut self,
+        tx: &ClientTransaction,
+        result: &Result<(), TransactionError>,
+        accounts: &Accounts,
+    ) {
+        if result.is_err() || self.error.is_some() {
+            return;
+        }
+        let entry = entry(tx, accounts, &self.hash);
+        self.hash = entry[COLUMNS.len() - 1].clone();
+        if let Err(e) = self.writer.write_record(&entry) {
+            self.error = Some(e.into());
+        }
+    }
+    /// Write any buffered entries
+    ///
+    /// If an earlier entry failed to be written, its error is returned instead.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+}
+
+/// Get the entry for an applied transaction, chained to the previous entry's hash
+fn entry(tx: &ClientTransaction, accounts: &Accounts, previous: &str) -> [String; 11] {
+    let places = accounts.precision().decimal_places() as usize;
+    let amount = (tx.tx.amount())
+        .map(|amount| format!("{:.*}", places, amount))
+        .unwrap_or_default();
+    let to = match tx.tx {
+        Transaction::Transfer { to, .. } => Some(to),
+        _ => None,
+    };
+    let balances = accounts.get(tx.client).map(|account| {
+        [
+            format!("{:.*}", places, account.balance()),
+            format!("{:.*}", places, account.held()),
+            format!("{:.*}", places, account.total()),
+            account.is_frozen().to_string(),
+        ]
+    });
+    let [available, held, total, locked] = balances.unwrap_or_default();
+    let mut entry = [
+        tx.tx.type_name().into(),
+        tx.client.to_string(),
+        tx.tx.tx_id().to_string(),
+        amount,
+        to.map(|to| to.to_string()).unwrap_or_default(),
+        tx.timestamp
+            .map(|timestamp| timestamp.to_string())
+            .unwrap_or_default(),
+        available,
+        held,
+        total,
+        locked,
+        String::new(),
+    ];
+    let mut hasher = Sha256::new();
+    hasher.update(previous);
+    for field in &entry[..COLUMNS.len() - 1] {
+        hasher.update(",");
+        hasher.update(field);
+    }
+    entry[COLUMNS.len() - 1] = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    entry
+}
+
+/// A reader of the entries of an audit log
+struct Entries<R: Read> {
+    reader: Reader<R>,
+    record: StringRecord,
+}
+
+impl<R: Read> Entries<R> {
+    /// Read the next entry
+    fn next(&mut self) -> io::Result<Option<&StringRecord>> {
+        if !self.reader.read_record(&mut self.record)? {
+            return Ok(None);
+        }
+        if self.record.len() != COLUMNS.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Entry on line {} has {} columns instead of {}",
+                    self.record.position().map_or(0, |pos| pos.line()),
+                    self.record.len(),
+                    COLUMNS.len()
+                ),
+            ));
+        }
+        Ok(Some(&self.record))
+    }
+}
+
+/// Start reading the entries of an audit log, checking its header row
+fn read_entries<R: Read>(log: R) -> io::Result<Entries<R>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(log);
+    if reader.headers()? != COLUMNS.as_slice() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected a header row of {}", COLUMNS.join(",")),
+        ));
+    }
+    Ok(Entries {
+        reader,
+        record: StringRecord::new(),
+    })
+}
+
+/// The result of checking an audit log against the input it was produced from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// Every entry matches the input
+    Verified {
+        /// The number of entries
+        entries: u64,
+    },
+    /// An entry differs from the one the input produces
+    Mismatch {
+        /// The number of the entry, counting from 1
+        entry: u64,
+    },
+    /// The log has an entry after every one the input produces
+    Inserted {
+        /// The number of the first extra entry, counting from 1
+        entry: u64,
+    },
+    /// The log ends before every entry the input produces
+    Omitted {
+        /// The number of the first missing entry, counting from 1
+        entry: u64,
+    },
+}
+
+impl Verification {
+    /// Get the number of entries if every entry matches, or a description of the first that
+    /// does not
+    pub fn check(self) -> Result<u64, String> {
+        match self {
+            Verification::Verified { entries } => Ok(entries),
+            Verification::Mismatch { entry } => Err(format!(
+                "Entry {} of the audit log does not match the input",
+                entry
+            )),
+            Verification::Inserted { entry } => Err(format!(
+                "The audit log has entries from entry {} on that the input does not produce",
+                entry
+            )),
+            Verification::Omitted { entry } => Err(format!(
+                "The audit log is missing entries from entry {} on",
+                entry
+            )),
+        }
+    }
+}
+
+/// Check that an audit log was produced from exactly some input
+///
+/// The input's transactions are applied to `accounts`, which should be as they were when the
+/// log was started, and the entries they produce are compared with the log's. The first entry
+/// that does not match is reported.
+pub fn verify<L, I>(log: L, input: I, accounts: &mut Accounts) -> Result<Verification, String>
+where
+    L: Read,
+    I: Read,
+{
+    let mut entries = read_entries(log).map_err(|e| format!("Invalid audit log: {}", e))?;
+    let (mut count, mut hash) = (0, String::new());
+    // Once an entry does not match, the rest of the input is not checked
+    let mut outcome = None;
+    process_transaction_source_with(input, accounts, |record, result, accounts| {
+        if result.is_err() || outcome.is_some() {
+            return;
+        }
+        count += 1;
+        let expected = entry(&record.tx, accounts, &hash);
+        outcome = match entries.next() {
+            Ok(Some(actual)) if actual == expected.as_slice() => {
+                hash = expected[COLUMNS.len() - 1].clone();
+                return;
+            }
+            Ok(Some(_)) => Some(Ok(Verification::Mismatch { entry: count })),
+            Ok(None) => Some(Ok(Verification::Omitted { entry: count })),
+            Err(e) => Some(Err(format!("Invalid audit log: {}", e))),
+        };
+    })?;
+    if let Some(outcome) = outcome {
+        return outcome;
+    }
+    match entries.next() {
+        Ok(Some(_)) => Ok(Verification::Inserted { entry: count + 1 }),
+        Ok(None) => Ok(Verification::Verified { entries: count }),
+        Err(e) => Err(format!("Invalid audit log: {}", e)),
+    }
+}
@@ -0,0 +1,303 @@
+//! Reading transactions from Avro files
+//!
+//! This module is enabled by the `avro` feature. An Avro object container file is decoded
+//! with the schema it was written with, and then each record is validated against the bundled
+//! [`TRANSACTION_SCHEMA`]. Writers may use a `string` instead of the `TransactionType` enum, and
+//! may leave out the optional `amount` and `to` fields. Amounts are strings, so they are exact.
+//!
+//! Since Avro has no lines, the `line_no` of each [`Record`] is its 1-based row number, and
+//! its `line` is the record's fields joined by commas.
+
+use std::{
+    fmt,
+    io::{self, Read},
+};
+
+use avro_schema::{
+    read::{fallible_streaming_iterator::FallibleStreamingIterator, BlockStreamingIterator},
+    schema::Schema,
+};
+
+use crate::{input::Record, transaction::ClientTransaction};
+
+/// The Avro schema that every transaction record must match
+pub const TRANSACTION_SCHEMA: &str = include_str!("../avro/transaction.avsc");
+
+/// A decoded Avro value
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Boolean(bool),
+    /// An `int` or `long`
+    Integer(i64),
+    /// A `float` or `double`
+    Float(f64),
+    /// `bytes` or a `fixed`
+    Bytes(Vec<u8>),
+    /// A `string` or an enum symbol
+    String(String),
+    Array(Vec<Value>),
+    /// A `map` or a record's fields, in order
+    Fields(Vec<(String, Value)>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bytes(bytes) => write!(f, "{}", String::from_utf8_lossy(bytes)),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Array(_) | Value::Fields(_) => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Value {
+    /// Check whether the value is valid for a schema
+    ///
+    /// Integers are valid for floating-point schemas, and strings are valid for enums that
+    /// have them as a symbol. Record fields that are missing are treated as null.
+    fn matches(&self, schema: &Schema) -> bool {
+        match (schema, self) {
+            (Schema::Null, Value::Null) => true,
+            (Schema::Boolean, Value::Boolean(_)) => true,
+            (Schema::Int(_), Value::Integer(i)) => i32::try_from(*i).is_ok(),
+            (Schema::Long(_), Value::Integer(_)) => true,
+            (Schema::Float | Schema::Double, Value::Float(_) | Value::Integer(_)) => true,
+            (Schema::Bytes(_), Value::Bytes(_)) => true,
+            (Schema::Fixed(fixed), Value::Bytes(bytes)) => bytes.len() == fixed.size,
+            (Schema::String(_), Value::String(_)) => true,
+            (Schema::Enum(e), Value::String(s)) => e.symbols.contains(s),
+            (Schema::Array(items), Value::Array(values)) => {
+                values.iter().all(|value| value.matches(items))
+            }
+            (Schema::Map(items), Value::Fields(values)) => {
+                values.iter().all(|(_, value)| value.matches(items))
+            }
+            (Schema::Record(record), Value::Fields(_)) => record
+                .fields
+                .iter()
+                .all(|field| self.field(&field.name).matches(&field.schema)),
+            (Schema::Union(schemas), value) => schemas.iter().any(|schema| value.matches(schema)),
+            _ => false,
+        }
+    }
+    /// Get a field of a record, or null if it is missing
+    fn field(&self, name: &str) -> &Value {
+        match self {
+            Value::Fields(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map_or(&Value::Null, |(_, value)| value),
+            _ => &Value::Null,
+        }
+    }
+}
+
+/// Decode a zigzag-encoded variable-length integer
+fn decode_long<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        n |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok((n >> 1) as i64 ^ -((n & 1) as i64));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Integer is too long",
+    ))
+}
+
+/// Decode a length followed by that many bytes
+fn decode_bytes<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = usize::try_from(decode_long(reader)?)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Negative length"))?;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+/// Decode the items of an array or map, which are written in blocks
+fn decode_blocks<R, T>(
+    reader: &mut R,
+    mut item: impl FnMut(&mut R) -> io::Result<T>,
+) -> io::Result<Vec<T>>
+where
+    R: Read,
+{
+    let mut items = Vec::new();
+    loop {
+        let count = decode_long(reader)?;
+        if count == 0 {
+            return Ok(items);
+        }
+        // A negative count is followed by the size of the block in bytes
+        if count < 0 {
+            decode_long(reader)?;
+        }
+        for _ in 0..count.unsigned_abs() {
+            items.push(item(reader)?);
+        }
+    }
+}
+
+/// Decode a value that was written with a schema
+fn decode<R: Read>(reader: &mut R, schema: &Schema) -> io::Result<Value> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    Ok(match schema {
+        Schema::Null => Value::Null,
+        Schema::Boolean => {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            Value::Boolean(byte[0] != 0)
+        }
+        Schema::Int(_) | Schema::Long(_) => Value::Integer(decode_long(reader)?),
+        Schema::Float => {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            Value::Float(f32::from_le_bytes(bytes).into())
+        }
+        Schema::Double => {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Value::Float(f64::from_le_bytes(bytes))
+        }
+        Schema::Bytes(_) => Value::Bytes(decode_bytes(reader)?),
+        Schema::String(_) => Value::String(
+            String::from_utf8(decode_bytes(reader)?).map_err(|_| invalid("Invalid string"))?,
+        ),
+        Schema::Fixed(fixed) => {
+            let mut bytes = vec![0; fixed.size];
+            reader.read_exact(&mut bytes)?;
+            Value::Bytes(bytes)
+        }
+        Schema::Enum(e) => {
+            let symbol = usize::try_from(decode_long(reader)?)
+                .ok()
+                .and_then(|i| e.symbols.get(i))
+                .ok_or_else(|| invalid("Invalid enum symbol"))?;
+            Value::String(symbol.clone())
+        }
+        Schema::Union(schemas) => {
+            let schema = usize::try_from(decode_long(reader)?)
+                .ok()
+                .and_then(|i| schemas.get(i))
+                .ok_or_else(|| invalid("Invalid union branch"))?;
+            decode(reader, schema)?
+        }
+        Schema::Array(items) => Value::Array(decode_blocks(reader, |r| decode(r, items))?),
+        Schema::Map(items) => Value::Fields(decode_blocks(reader, |r| {
+            let key = String::from_utf8(decode_bytes(r)?).map_err(|_| invalid("Invalid key"))?;
+            Ok((key, decode(r, items)?))
+        })?),
+        Schema::Record(record) => Value::Fields(
+            record
+                .fields
+                .iter()
+                .map(|field| Ok((field.name.clone(), decode(reader, &field.schema)?)))
+                .collect::<io::Result<_>>()?,
+        ),
+    })
+}
+
+/// Iterate over the transactions in an Avro file
+///
+/// Fails if the data is not an Avro object container file. Records that cannot be decoded or
+/// do not match [`TRANSACTION_SCHEMA`] are reported with their row number.
+pub fn avro_records<R: Read>(mut reader: R) -> Result<AvroRecords<R>, String> {
+    let metadata = avro_schema::read::read_metadata(&mut reader)
+        .map_err(|e| format!("Invalid Avro file: {}", e))?;
+    let schema = serde_json::from_str(TRANSACTION_SCHEMA).expect("invalid transaction schema");
+    Ok(AvroRecords {
+        blocks: BlockStreamingIterator::new(reader, metadata.compression, metadata.marker),
+        writer_schema: Schema::Record(metadata.record),
+        schema,
+        block: io::Cursor::new(Vec::new()),
+        remaining: 0,
+        row_no: 0,
+    })
+}
+
+/// An iterator over the transactions in an Avro file
+///
+/// See [`avro_records`]
+pub struct AvroRecords<R: Read> {
+    blocks: BlockStreamingIterator<R>,
+    /// The schema the file was written with
+    writer_schema: Schema,
+    /// The schema every record must match
+    schema: Schema,
+    /// The data of the current block
+    block: io::Cursor<Vec<u8>>,
+    /// The number of records left in the current block
+    remaining: usize,
+    row_no: usize,
+}
+
+impl<R: Read> AvroRecords<R> {
+    /// Convert a decoded record to a transaction
+    fn transaction(&self, value: &Value) -> Result<Record, String> {
+        if !value.matches(&self.schema) {
+            return Err("Record does not match the transaction schema".into());
+        }
+        let fields = ["type", "client", "tx", "amount", "to"].map(|name| value.field(name));
+        let [tx_type, client, tx, amount, to] = fields.map(|value| match value {
+            Value::Null => None,
+            value => Some(value.to_string()),
+        });
+        let tx = ClientTransaction::from_fields(
+            tx_type.as_deref(),
+            client.as_deref(),
+            tx.as_deref(),
+            amount.as_deref(),
+            to.as_deref(),
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Record {
+            line_no: self.row_no,
+            line: fields.map(Value::to_string).join(","),
+            tx,
+        })
+    }
+}
+
+impl<R: Read> Iterator for AvroRecords<R> {
+    type Item = Result<Record, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining == 0 {
+            match self.blocks.next() {
+                Ok(Some(block)) => {
+                    self.block = io::Cursor::new(block.data.clone());
+                    self.remaining = block.number_of_rows;
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    return Some(Err(format!("Error reading row {}: {}", self.row_no + 1, e)))
+                }
+            }
+        }
+        self.remaining -= 1;
+        self.row_no += 1;
+        let value = match decode(&mut self.block, &self.writer_schema) {
+            Ok(value) => value,
+            Err(e) => {
+                // The rest of the block cannot be found
+                self.remaining = 0;
+                return Some(Err(format!("Error reading row {}: {}", self.row_no, e)));
+            }
+        };
+        Some(
+            self.transaction(&value)
+                .map_err(|e| format!("Invalid transaction on row {}: {}", self.row_no, e)),
+        )
+    }
+}
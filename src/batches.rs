@@ -0,0 +1,99 @@
+//! Reading transactions from Arrow record batches
+//!
+//! This is shared by the `arrow` and `parquet` modules. Columns are found by name, as in a CSV
+//! header row, and may be of any type that can be displayed.
+
+use arrow_array::RecordBatch;
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_schema::Schema;
+use csv::StringRecord;
+
+use crate::input::{Columns, Record};
+
+/// An iterator over the transactions in a sequence of record batches
+///
+/// Since batches have no lines, the `line_no` of each [`Record`] is its 1-based row number,
+/// and its `line` is the row's fields joined by commas.
+pub struct BatchRecords<B> {
+    batches: B,
+    columns: Columns,
+    /// The remaining rows of the current batch
+    rows: std::vec::IntoIter<StringRecord>,
+    row_no: usize,
+}
+
+impl<B, E> BatchRecords<B>
+where
+    B: Iterator<Item = Result<RecordBatch, E>>,
+    E: ToString,
+{
+    /// Read the transactions in batches with some schema
+    ///
+    /// Fails if the schema is missing a required column.
+    pub(crate) fn new(schema: &Schema, batches: B) -> Result<Self, String> {
+        let names: StringRecord = schema.fields().iter().map(|field| field.name()).collect();
+        let columns = Columns::from_header(&names)?.ok_or("Missing a \"type\" column")?;
+        Ok(BatchRecords {
+            batches,
+            columns,
+            rows: Vec::new().into_iter(),
+            row_no: 0,
+        })
+    }
+}
+
+impl<B, E> Iterator for BatchRecords<B>
+where
+    B: Iterator<Item = Result<RecordBatch, E>>,
+    E: ToString,
+{
+    type Item = Result<Record, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                self.row_no += 1;
+                let line_no = self.row_no;
+                return Some(match self.columns.parse(&row, None) {
+                    Ok(tx) => Ok(Record {
+                        line_no,
+                        line: row.iter().collect::<Vec<_>>().join(","),
+                        tx,
+                    }),
+                    Err(e) => Err(format!("Invalid transaction on row {}: {}", line_no, e)),
+                });
+            }
+            let rows = self
+                .batches
+                .next()?
+                .map_err(|e| e.to_string())
+                .and_then(|batch| format_rows(&batch));
+            match rows {
+                Ok(rows) => self.rows = rows.into_iter(),
+                Err(e) => {
+                    return Some(Err(format!("Error reading row {}: {}", self.row_no + 1, e)))
+                }
+            }
+        }
+    }
+}
+
+/// Format every field of a record batch as a string
+///
+/// Null values are formatted as empty fields.
+fn format_rows(batch: &RecordBatch) -> Result<Vec<StringRecord>, String> {
+    let options = FormatOptions::default();
+    let formatters = batch
+        .columns()
+        .iter()
+        .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok((0..batch.num_rows())
+        .map(|row| {
+            formatters
+                .iter()
+                .map(|formatter| formatter.value(row).to_string())
+                .collect()
+        })
+        .collect())
+}
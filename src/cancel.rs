@@ -0,0 +1,54 @@
+//! Cancelling long processing jobs
+//!
+//! Services that embed the engine may need to stop a job partway through, such as when shutting
+//! down. A [`CancellationToken`] is shared with the job, which checks it between transactions,
+//! so the accounts are always left with every transaction up to some line applied and none
+//! after it.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::summary::ProcessingSummary;
+
+/// A token for asking a processing job to stop
+///
+/// Clones of a token share its state, so one clone can be given to the job and another kept to
+/// cancel it from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+    /// Ask jobs with this token to stop
+    ///
+    /// Jobs stop before their next transaction. A job waiting on its input stops once the read
+    /// returns.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    /// Check whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// The outcome of a processing job that may have been cancelled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Processed {
+    /// A summary of the transactions that were applied and rejected
+    pub summary: ProcessingSummary,
+    /// The line of the last transaction that was applied or rejected, if any
+    ///
+    /// Every transaction before it has been applied to the accounts, and none after it, so a
+    /// cancelled job can be resumed from the next line.
+    pub last_line: Option<usize>,
+    /// Whether the job stopped because it was cancelled rather than at the end of its input
+    pub cancelled: bool,
+}
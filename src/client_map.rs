@@ -0,0 +1,150 @@
+//! Mapping external customer references to client ids
+//!
+//! Some sources identify customers by alphanumeric references rather than numeric client ids.
+//! A [`ClientMap`] assigns each reference a [`ClientId`] the first time it is seen, so the same
+//! reference always maps to the same account. The map is stored as a CSV file with the columns
+//! `reference, client` so it can be kept between runs.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use csv::{ReaderBuilder, Trim, Writer};
+
+use crate::transaction::ClientId;
+
+/// A persistent mapping between external customer references and client ids
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientMap {
+    clients: HashMap<String, ClientId>,
+    references: BTreeMap<ClientId, String>,
+}
+
+impl ClientMap {
+    /// Create an empty map
+    pub fn new() -> Self {
+        ClientMap::default()
+    }
+    /// Load a map from a file, or create an empty map if the file does not exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        match File::open(path) {
+            Ok(file) => ClientMap::read(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(ClientMap::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+    /// Read a map from a CSV source
+    ///
+    /// The whole source is validated, and every reference and client id must appear only once.
+    pub fn read<R: Read>(source: R) -> Result<Self, String> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(source);
+        let headers = reader
+            .headers()
+            .map_err(|e| format!("Error reading client map: {}", e))?
+            .clone();
+        let required = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("Client map is missing a {:?} column", name))
+        };
+        let reference_col = required("reference")?;
+        let client_col = required("client")?;
+        let mut map = ClientMap::new();
+        for (i, record) in reader.records().enumerate() {
+            let row = i + 1;
+            let record =
+                record.map_err(|e| format!("Error reading client map row {}: {}", row, e))?;
+            let mut parse_row = || -> Result<(), String> {
+                let reference = record.get(reference_col).unwrap_or_default();
+                validate_reference(reference)?;
+                let client = record.get(client_col).unwrap_or_default();
+                let client = client
+                    .parse::<ClientId>()
+                    .map_err(|_| format!("Invalid client id {:?}", client))?;
+                if map.clients.contains_key(reference) {
+                    return Err(format!(
+                        "Reference {:?} is mapped more than once",
+                        reference
+                    ));
+                }
+                if map.references.contains_key(&client) {
+                    return Err(format!("Client {} is mapped more than once", client));
+                }
+                map.insert(reference.into(), client);
+                Ok(())
+            };
+            parse_row().map_err(|e| format!("Invalid client map row {}: {}", row, e))?;
+        }
+        Ok(map)
+    }
+    /// Write the map as CSV, ordered by client id
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = Writer::from_writer(writer);
+        writer.write_record(["reference", "client"])?;
+        for (client, reference) in &self.references {
+            writer.write_record([reference.as_str(), client.to_string().as_str()])?;
+        }
+        writer.flush()
+    }
+    /// Get the client id of a reference, assigning it the next free id if it has none
+    ///
+    /// Ids are assigned in increasing order. Once the largest id has been assigned, the
+    /// smallest unused id is assigned instead.
+    pub fn client(&mut self, reference: &str) -> Result<ClientId, String> {
+        if let Some(&client) = self.clients.get(reference) {
+            return Ok(client);
+        }
+        validate_reference(reference)?;
+        let next = match self.references.last_key_value() {
+            None => Some(0),
+            Some((&last, _)) => last.checked_add(1).or_else(|| {
+                (0..=ClientId::MAX).find(|client| !self.references.contains_key(client))
+            }),
+        };
+        let client = next.ok_or_else(|| {
+            format!(
+                "Unable to map reference {:?}: every client id is in use",
+                reference
+            )
+        })?;
+        self.insert(reference.into(), client);
+        Ok(client)
+    }
+    /// Get the client id of a reference, if it has one
+    pub fn get(&self, reference: &str) -> Option<ClientId> {
+        self.clients.get(reference).copied()
+    }
+    /// Get the reference a client id is mapped from, if it has one
+    pub fn reference(&self, client: ClientId) -> Option<&str> {
+        self.references.get(&client).map(String::as_str)
+    }
+    /// Get the number of mapped references
+    pub fn len(&self) -> usize {
+        self.references.len()
+    }
+    /// Check if no references are mapped
+    pub fn is_empty(&self) -> bool {
+        self.references.is_empty()
+    }
+    fn insert(&mut self, reference: String, client: ClientId) {
+        self.clients.insert(reference.clone(), client);
+        self.references.insert(client, reference);
+    }
+}
+
+/// Check that a reference is non-empty and made of ASCII letters, digits, `-`, `_` and `.`
+fn validate_reference(reference: &str) -> Result<(), String> {
+    let valid = !reference.is_empty()
+        && reference
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid client reference {:?}", reference))
+    }
+}
@@ -0,0 +1,68 @@
+//! Sources of the current time
+//!
+//! Time-based behavior, such as dispute windows, accrual schedules, and settlement, follows the
+//! timestamps of transactions. Transactions without timestamps can be stamped with the time
+//! they are executed by giving [`Accounts`](crate::Accounts) a [`Clock`]. [`SystemClock`]
+//! reads the system time, and a [`TestClock`] only moves when told to, so that tests and
+//! simulations are deterministic.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::transaction::Timestamp;
+
+/// A source of the current time
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Get the current time in seconds since the Unix epoch
+    fn now(&self) -> Timestamp;
+}
+
+/// A clock that reads the system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    }
+}
+
+/// A clock that is set by hand
+///
+/// Clones of a clock share its time, so one clone can be given to the accounts and another
+/// kept to move it.
+#[derive(Debug, Clone, Default)]
+pub struct TestClock {
+    now: Arc<AtomicU64>,
+}
+
+impl TestClock {
+    /// Create a clock that starts at a time
+    pub fn new(now: Timestamp) -> Self {
+        TestClock {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+    /// Set the time
+    pub fn set(&self, now: Timestamp) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+    /// Move the time forward by some seconds
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::Relaxed)
+    }
+}
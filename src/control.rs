@@ -0,0 +1,269 @@
+//! Managing a running server over its admin API
+//!
+//! [`Server::serve_admin`] accepts TCP connections that send admin requests, each a line of
+//! JSON carrying the admin token held in [`TOKEN_VAR`](admin::TOKEN_VAR), such as
+//! `{"token":"...","command":"inspect","client":3}`. Every request gets a line of JSON in
+//! response, either `{"status":"ok",...}` or `{"status":"error","error":"..."}`, and a
+//! connection that sends the wrong token is closed after its response. The commands are:
+//!
+//! - `freeze` and `unfreeze` apply the [`AdminOp`] of the same name to the account of
+//!   `client`. They must name the `operator` and a `reference`, and may carry a `note`. They
+//!   are recorded in the server's journal if it has one, see [`Server::set_journal`].
+//! - `inspect` gets the account of `client` as `"account"`, with its extended data, whether it
+//!   is closed, and its notes.
+//! - `stats` gets counts of accounts, transactions, and connections as `"stats"`.
+//! - `drain` refuses new connections from then on, see [`Server::drain`].
+//!
+//! Admin connections do not count towards the [`ConnectionLimits`](crate::server::ConnectionLimits),
+//! and are still accepted once the server is drained.
+//!
+//! [`request`] sends a command to a running server, as `transactor admin --connect` does.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    admin::{self, AdminOp, AdminOpKind},
+    amount::Amount,
+    output::account_json,
+    server::Server,
+    transaction::ClientId,
+};
+
+/// The most bytes in an admin request
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// A command sent to the admin API of a running server
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Apply a `freeze` or `unfreeze` operation
+    Op(AdminOp),
+    /// Get the account of a client
+    Inspect(ClientId),
+    /// Get counts of accounts, transactions, and connections
+    Stats,
+    /// Refuse new connections
+    Drain,
+}
+
+impl Command {
+    /// Get the request for the command, carrying an admin token
+    pub fn to_json(&self, token: &str) -> Value {
+        match self {
+            Command::Op(op) => json!({
+                "token": token,
+                "command": op.kind.name(),
+                "client": op.client,
+                "operator": op.operator,
+                "reference": op.reference,
+                "note": op.note,
+            }),
+            Command::Inspect(client) => {
+                json!({ "token": token, "command": "inspect", "client": client })
+            }
+            Command::Stats => json!({ "token": token, "command": "stats" }),
+            Command::Drain => json!({ "token": token, "command": "drain" }),
+        }
+    }
+    /// Parse the command of a request
+    fn from_json(request: &Value) -> Result<Self, String> {
+        let field = |name: &str| request.get(name).filter(|value| !value.is_null());
+        let string = |name: &str| match field(name) {
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(format!("Invalid {}", name)),
+            None => Ok(None),
+        };
+        let required = |name: &str| string(name)?.ok_or_else(|| format!("Missing {}", name));
+        let client = || {
+            let client = field("client").ok_or("Missing client")?;
+            (client.as_u64())
+                .and_then(|client| ClientId::try_from(client).ok())
+                .ok_or_else(|| format!("Invalid client id {}", client))
+        };
+        let op = |kind| -> Result<Command, String> {
+            Ok(Command::Op(AdminOp {
+                client: client()?,
+                kind,
+                operator: required("operator")?,
+                reference: required("reference")?,
+                note: string("note")?,
+            }))
+        };
+        match required("command")?.as_str() {
+            "freeze" => op(AdminOpKind::Freeze),
+            "unfreeze" => op(AdminOpKind::Unfreeze),
+            "inspect" => Ok(Command::Inspect(client()?)),
+            "stats" => Ok(Command::Stats),
+            "drain" => Ok(Command::Drain),
+            command => Err(format!("Invalid command {:?}", command)),
+        }
+    }
+}
+
+impl Server {
+    /// Accept admin connections and handle each one on its own thread
+    ///
+    /// This only returns if accepting connections fails.
+    pub fn serve_admin(&self, listener: TcpListener) -> io::Result<()> {
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = stream?;
+                scope.spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    let result = stream
+                        .try_clone()
+                        .and_then(|writer| self.handle_admin(stream, writer));
+                    if let Err(e) = result {
+                        tracing::warn!(?peer, "Admin connection failed: {}", e);
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+    /// Handle the admin requests from one connection until it ends, writing a response to each
+    pub fn handle_admin<R, W>(&self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: Read,
+        W: Write,
+    {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let limit = MAX_REQUEST_SIZE as u64 + 1;
+            if (&mut reader).take(limit).read_until(b'\n', &mut line)? == 0 {
+                return Ok(());
+            }
+            if line.len() > MAX_REQUEST_SIZE {
+                let message = format!("Request is longer than {} bytes", MAX_REQUEST_SIZE);
+                writeln!(writer, "{}", json!({ "status": "error", "error": message }))?;
+                return writer.flush();
+            }
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            let request: Value = match serde_json::from_slice(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    let message = format!("Invalid request: {}", e);
+                    writeln!(writer, "{}", json!({ "status": "error", "error": message }))?;
+                    writer.flush()?;
+                    continue;
+                }
+            };
+            let token = request.get("token").and_then(Value::as_str);
+            if let Err(e) = admin::authenticate_env(token) {
+                tracing::warn!("Admin request refused: {}", e);
+                writeln!(writer, "{}", json!({ "status": "error", "error": e }))?;
+                return writer.flush();
+            }
+            let response = match Command::from_json(&request).and_then(|command| {
+                tracing::info!(?command, "Admin request");
+                self.admin_command(command)
+            }) {
+                Ok(fields) => {
+                    let mut response = json!({ "status": "ok" });
+                    if let (Some(response), Value::Object(fields)) =
+                        (response.as_object_mut(), fields)
+                    {
+                        response.extend(fields);
+                    }
+                    response
+                }
+                Err(e) => json!({ "status": "error", "error": e }),
+            };
+            writeln!(writer, "{}", response)?;
+            writer.flush()?;
+        }
+    }
+    /// Execute an admin command, getting the fields of its response
+    fn admin_command(&self, command: Command) -> Result<Value, String> {
+        match command {
+            Command::Op(op) => self.apply_admin(&op).map(|()| json!({})),
+            Command::Inspect(client) => self.with_accounts(|accounts| {
+                let account = accounts
+                    .get(client)
+                    .ok_or_else(|| format!("Client {} has no account", client))?;
+                let places = accounts.precision().decimal_places() as usize;
+                let mut object = account_json(client, account, places, true);
+                object["closed"] = json!(account.is_closed());
+                object["notes"] = (account.notes().iter())
+                    .map(|note| {
+                        json!({
+                            "timestamp": note.timestamp,
+                            "operator": note.operator,
+                            "reference": note.reference,
+                            "text": note.text,
+                        })
+                    })
+                    .collect();
+                Ok(json!({ "account": object }))
+            }),
+            Command::Stats => {
+                let mut stats = self.with_accounts(|accounts| {
+                    let mut count = 0;
+                    let mut frozen = 0;
+                    let mut closed = 0;
+                    let mut held = Amount::default();
+                    for (_, account) in accounts.iter() {
+                        count += 1;
+                        frozen += account.is_frozen() as usize;
+                        closed += account.is_closed() as usize;
+                        held = held.checked_add(account.held()).unwrap_or(held);
+                    }
+                    let places = accounts.precision().decimal_places() as usize;
+                    json!({
+                        "accounts": count,
+                        "frozen": frozen,
+                        "closed": closed,
+                        "held": format!("{:.*}", places, held),
+                    })
+                });
+                let (transactions, rejected) = self.transaction_counts();
+                stats["transactions"] = json!(transactions);
+                stats["rejected"] = json!(rejected);
+                stats["connections"] = json!(self.connections());
+                stats["draining"] = json!(self.is_draining());
+                Ok(json!({ "stats": stats }))
+            }
+            Command::Drain => {
+                self.drain();
+                Ok(json!({}))
+            }
+        }
+    }
+}
+
+/// Send a command to the admin API of a running server and get its response
+///
+/// Fails with the server's error if the command failed.
+pub fn request(address: &str, token: &str, command: &Command) -> Result<Value, String> {
+    let stream = TcpStream::connect(address)
+        .map_err(|e| format!("Unable to connect to {}: {}", address, e))?;
+    let error = |e: io::Error| format!("Error talking to {}: {}", address, e);
+    writeln!(&stream, "{}", command.to_json(token)).map_err(error)?;
+    let mut line = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut line)
+        .map_err(error)?;
+    if line.is_empty() {
+        return Err(format!(
+            "{} closed the connection without responding",
+            address
+        ));
+    }
+    let response: Value = serde_json::from_str(&line)
+        .map_err(|e| format!("Invalid response from {}: {}", address, e))?;
+    match response["status"].as_str() {
+        Some("ok") => Ok(response),
+        _ => Err(response["error"]
+            .as_str()
+            .map_or_else(|| format!("Invalid response from {}", address), Into::into)),
+    }
+}
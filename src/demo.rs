@@ -0,0 +1,163 @@
+//! A small dataset for exploring the engine
+//!
+//! [`transactions`] generates transactions for a handful of clients, each in a different
+//! situation: an account with only deposits and withdrawals, an open dispute, a resolved
+//! dispute, a chargeback that froze the account, a disputed withdrawal, a transfer, and a
+//! rejected withdrawal. The seed picks the amounts and which client is in which situation, so
+//! the same seed always generates the same dataset.
+
+use std::io::{self, Write};
+
+use crate::{
+    amount::Amount,
+    transaction::{ClientId, ClientTransaction, ResolutionKind, Transaction, TransactionId},
+    Accounts,
+};
+
+/// The situations clients can be in, one per client
+const SCENARIOS: [Scenario; 7] = [
+    Scenario::Plain,
+    Scenario::OpenDispute,
+    Scenario::ResolvedDispute,
+    Scenario::Chargeback,
+    Scenario::DisputedWithdrawal,
+    Scenario::Transfer,
+    Scenario::Overdrawn,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Scenario {
+    Plain,
+    OpenDispute,
+    ResolvedDispute,
+    Chargeback,
+    DisputedWithdrawal,
+    Transfer,
+    Overdrawn,
+}
+
+/// A splitmix64 generator, which is plenty for picking demo amounts
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    /// Get a number in `0..n`
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+    /// Get an amount between 1.00 and 500.00
+    fn amount(&mut self) -> Amount {
+        let cents = 100 + self.below(49_901);
+        format!("{}.{:02}", cents / 100, cents % 100)
+            .parse()
+            .unwrap()
+    }
+}
+
+/// Generate the transactions of the demo dataset from a seed
+///
+/// Transactions of different clients are interleaved, as they would be in real input.
+pub fn transactions(seed: u64) -> Vec<ClientTransaction> {
+    let mut rng = Rng(seed);
+    let mut scenarios = SCENARIOS;
+    // Fisher-Yates shuffle
+    for i in (1..scenarios.len()).rev() {
+        scenarios.swap(i, rng.below(i as u64 + 1) as usize);
+    }
+    let mut next_tx: TransactionId = 1;
+    let mut tx_id = || {
+        next_tx += 1;
+        next_tx - 1
+    };
+    let mut per_client: Vec<Vec<ClientTransaction>> = Vec::new();
+    for (i, scenario) in scenarios.into_iter().enumerate() {
+        let client = i as ClientId + 1;
+        let deposit = tx_id();
+        let amount = rng.amount();
+        let mut txs = vec![Transaction::deposit(deposit, amount)];
+        match scenario {
+            Scenario::Plain => {
+                txs.push(Transaction::deposit(tx_id(), rng.amount()));
+                txs.push(Transaction::withdrawal(tx_id(), amount));
+            }
+            Scenario::OpenDispute => txs.push(Transaction::dispute(deposit)),
+            Scenario::ResolvedDispute => {
+                txs.push(Transaction::dispute(deposit));
+                txs.push(Transaction::resolution(ResolutionKind::Resolve, deposit));
+            }
+            Scenario::Chargeback => {
+                txs.push(Transaction::deposit(tx_id(), rng.amount()));
+                txs.push(Transaction::dispute(deposit));
+                txs.push(Transaction::resolution(ResolutionKind::Chargeback, deposit));
+            }
+            Scenario::DisputedWithdrawal => {
+                let withdrawal = tx_id();
+                txs.push(Transaction::withdrawal(withdrawal, amount));
+                txs.push(Transaction::dispute(withdrawal));
+            }
+            Scenario::Transfer => {
+                // Any client other than this one
+                let others = SCENARIOS.len() as u64 - 1;
+                let to = (i as u64 + 1 + rng.below(others)) % (others + 1);
+                let to = to as ClientId + 1;
+                txs.push(Transaction::transfer(tx_id(), to, amount));
+            }
+            Scenario::Overdrawn => {
+                // More than the deposit and any transfer to this client
+                let more = amount
+                    .checked_add(rng.amount())
+                    .and_then(|more| more.checked_add(Amount::from_f64(500.0)?))
+                    .unwrap();
+                txs.push(Transaction::withdrawal(tx_id(), more));
+            }
+        }
+        per_client.push(
+            txs.into_iter()
+                .map(|tx| ClientTransaction {
+                    client,
+                    tx,
+                    timestamp: None,
+                })
+                .collect(),
+        );
+    }
+    // Interleave the clients' transactions, keeping each client's in order
+    let mut transactions = Vec::new();
+    let mut positions = vec![0; per_client.len()];
+    while transactions.len() < per_client.iter().map(Vec::len).sum() {
+        let i = rng.below(per_client.len() as u64) as usize;
+        if let Some(tx) = per_client[i].get(positions[i]) {
+            transactions.push(tx.clone());
+            positions[i] += 1;
+        }
+    }
+    transactions
+}
+
+/// Write the transactions of the demo dataset of a seed as CSV input
+pub fn write_transactions<W: Write>(seed: u64, mut writer: W) -> io::Result<()> {
+    writeln!(writer, "type,client,tx,amount,to")?;
+    for tx in transactions(seed) {
+        writeln!(writer, "{}", tx)?;
+    }
+    writer.flush()
+}
+
+/// Create accounts from the demo dataset of a seed
+///
+/// Admin transactions are enabled, so frozen accounts can be unfrozen while exploring.
+pub fn accounts(seed: u64) -> Accounts {
+    let mut accounts = Accounts::default();
+    accounts.set_admin_transactions(true);
+    for tx in transactions(seed) {
+        // Some transactions are meant to be rejected
+        let _ = accounts.transact(tx);
+    }
+    accounts
+}
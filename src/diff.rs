@@ -0,0 +1,211 @@
+//! Comparing the state in two snapshots
+//!
+//! Before traffic is cut over from one deployment to another, the new deployment's state can be
+//! checked against the old one's. [`diff_snapshots`] compares every field of every account in
+//! two snapshots, such as those saved with `--save-state` or served at `GET /state`, and lists
+//! the fields that differ. Fields that are expected to differ, such as the timestamps of notes,
+//! can be ignored.
+//!
+//! Fields are named after their line in the snapshot. Fields of a transaction or note include
+//! its id or position, such as `history[5].amount` or `notes[0].timestamp`. Fields are ignored
+//! by their name without ids or positions, such as `history.amount` or `notes.timestamp`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+use crate::{snapshot::SNAPSHOT_VERSION, transaction::ClientId};
+
+/// The fields that are ignored unless others are given
+pub const DEFAULT_IGNORED: &[&str] = &["notes.timestamp"];
+
+/// A field of an account whose value differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDifference {
+    pub client: ClientId,
+    /// The field, such as `balance` or `history[5].amount`
+    pub field: String,
+    /// The value in the first snapshot, or `None` if it does not have the field
+    pub left: Option<String>,
+    /// The value in the second snapshot, or `None` if it does not have the field
+    pub right: Option<String>,
+}
+
+/// The fields of every account in a snapshot, in the order they were written
+type SnapshotFields = BTreeMap<ClientId, Vec<(String, String)>>;
+
+/// Read the fields of every account in a snapshot
+fn read_fields<R: Read>(snapshot: R) -> Result<SnapshotFields, String> {
+    let mut lines = BufReader::new(snapshot).lines();
+    let header = lines
+        .next()
+        .transpose()
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+    if header != format!("snapshot,{}", SNAPSHOT_VERSION) {
+        return Err(format!("Unsupported snapshot header {:?}", header));
+    }
+    let mut accounts = SnapshotFields::new();
+    let mut current: Option<(ClientId, usize)> = None;
+    for line in lines {
+        let line = line.map_err(|e| e.to_string())?;
+        if let Some(id) = line.strip_prefix("client,") {
+            let client_id = id
+                .parse()
+                .map_err(|_| format!("Invalid client id {:?}", id))?;
+            accounts.entry(client_id).or_default();
+            current = Some((client_id, 0));
+            continue;
+        }
+        let Some((client_id, notes)) = &mut current else {
+            return Err(format!("Expected a client, found {:?}", line));
+        };
+        let fields = accounts.get_mut(client_id).unwrap();
+        let mut values = line.split(',');
+        let kind = values.next().unwrap_or_default();
+        let mut push = |field: String, value: Option<&str>| -> Result<(), String> {
+            let value = value.ok_or_else(|| format!("Invalid snapshot line {:?}", line))?;
+            fields.push((field, value.into()));
+            Ok(())
+        };
+        match kind {
+            "account" => {
+                for field in ["balance", "held", "frozen", "closed"] {
+                    push(field.into(), values.next())?;
+                }
+            }
+            "history" | "settling" | "disputed" | "transfer" | "reversed" | "partly-reversed"
+            | "charged-back" => {
+                let tx = values.next().unwrap_or_default();
+                match kind {
+                    "history" => {
+                        push(format!("history[{}].kind", tx), values.next())?;
+                        push(format!("history[{}].amount", tx), values.next())?;
+                        if let Some(timestamp) = values.next() {
+                            push(format!("history[{}].timestamp", tx), Some(timestamp))?;
+                        }
+                    }
+                    "settling" => push(format!("settling[{}]", tx), values.next())?,
+                    "disputed" => {
+                        push(format!("disputed[{}]", tx), Some("true"))?;
+                        // Only partial disputes list the amount disputed
+                        if let Some(amount) = values.next() {
+                            push(format!("disputed[{}].amount", tx), Some(amount))?;
+                        }
+                    }
+                    "partly-reversed" => push(format!("partly_reversed[{}]", tx), values.next())?,
+                    "charged-back" => push(format!("charged_back[{}]", tx), values.next())?,
+                    _ => push(format!("{}[{}]", kind, tx), Some("true"))?,
+                }
+            }
+            "pending" => push("pending_credit".into(), values.next())?,
+            "activity" => {
+                push("activity.first_tx".into(), values.next())?;
+                push("activity.last_tx".into(), values.next())?;
+                for field in ["first_timestamp", "last_timestamp"] {
+                    if let Some(timestamp) = values.next().filter(|value| !value.is_empty()) {
+                        push(format!("activity.{}", field), Some(timestamp))?;
+                    }
+                }
+            }
+            "note" => {
+                for field in ["timestamp", "operator", "reference", "text"] {
+                    push(format!("notes[{}].{}", notes, field), values.next())?;
+                }
+                *notes += 1;
+            }
+            // Lines from newer versions are compared whole
+            _ => push(
+                kind.into(),
+                Some(line.get(kind.len() + 1..).unwrap_or_default()),
+            )?,
+        }
+    }
+    Ok(accounts)
+}
+
+/// Get the name of a field without ids or positions, as it is ignored by
+fn ignored_name(field: &str) -> String {
+    let mut name = String::new();
+    let mut depth = 0;
+    for c in field.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ if depth == 0 => name.push(c),
+            _ => {}
+        }
+    }
+    name
+}
+
+/// Compare the fields of every account in two snapshots
+///
+/// Differences are listed in order of client, with the fields of each account in the order
+/// of the first snapshot followed by those only in the second. Fields with an ignored name
+/// are not compared.
+pub fn diff_snapshots<L, R>(
+    left: L,
+    right: R,
+    ignored: &[String],
+) -> Result<Vec<StateDifference>, String>
+where
+    L: Read,
+    R: Read,
+{
+    let left = read_fields(left).map_err(|e| format!("Invalid first snapshot: {}", e))?;
+    let right = read_fields(right).map_err(|e| format!("Invalid second snapshot: {}", e))?;
+    let compared = |field: &String| !ignored.contains(&ignored_name(field));
+    let no_fields = Vec::new();
+    let mut clients: Vec<ClientId> = left.keys().chain(right.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+    let mut differences = Vec::new();
+    for client in clients {
+        let left = left.get(&client).unwrap_or(&no_fields);
+        let right = right.get(&client).unwrap_or(&no_fields);
+        let left_values: HashMap<&String, &String> = left.iter().map(|(f, v)| (f, v)).collect();
+        let right_values: HashMap<&String, &String> = right.iter().map(|(f, v)| (f, v)).collect();
+        for (field, value) in left.iter().filter(|(field, _)| compared(field)) {
+            let other = right_values.get(field);
+            if other != Some(&value) {
+                differences.push(StateDifference {
+                    client,
+                    field: field.clone(),
+                    left: Some(value.clone()),
+                    right: other.map(|value| value.to_string()),
+                });
+            }
+        }
+        for (field, value) in right.iter().filter(|(field, _)| compared(field)) {
+            if !left_values.contains_key(field) {
+                differences.push(StateDifference {
+                    client,
+                    field: field.clone(),
+                    left: None,
+                    right: Some(value.clone()),
+                });
+            }
+        }
+    }
+    Ok(differences)
+}
+
+/// Write differences as CSV with the columns `client, field, left, right`
+///
+/// A value is empty if its snapshot does not have the field.
+pub fn write_csv<W: Write>(differences: &[StateDifference], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "client,field,left,right")?;
+    for difference in differences {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            difference.client,
+            difference.field,
+            difference.left.as_deref().unwrap_or_default(),
+            difference.right.as_deref().unwrap_or_default()
+        )?;
+    }
+    writer.flush()
+}
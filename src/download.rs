@@ -0,0 +1,120 @@
+//! Reading input from HTTP and HTTPS URLs
+//!
+//! A [`Download`] streams the body of a URL as it is read, so batch jobs can pull exports
+//! straight from a file service without staging them on disk first. If the connection fails
+//! partway through, the download resumes where it left off with a `Range` request.
+
+use std::{
+    io::{self, Read},
+    thread,
+    time::Duration,
+};
+
+use ureq::{Agent, Error};
+
+/// The most times a download is resumed in a row without making progress
+const MAX_RETRIES: u32 = 5;
+
+/// How long to wait before the first retry, which doubles with each retry in a row
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A streaming download of a URL
+///
+/// Transient failures, such as dropped connections, timeouts, and server errors, are retried
+/// after a delay, resuming from the last byte read. Client errors, such as `404 Not Found`,
+/// are not retried.
+pub struct Download {
+    agent: Agent,
+    url: String,
+    body: Box<dyn Read + Send>,
+    /// The number of bytes read so far
+    offset: u64,
+    /// The number of retries in a row without making progress
+    retries: u32,
+}
+
+impl Download {
+    /// Start downloading a URL
+    pub fn open(url: &str) -> io::Result<Self> {
+        let agent = Agent::new_with_defaults();
+        let body = Download::request(&agent, url, 0).map_err(into_io)?;
+        Ok(Download {
+            agent,
+            url: url.into(),
+            body,
+            offset: 0,
+            retries: 0,
+        })
+    }
+    /// Request the body of the URL from an offset
+    fn request(agent: &Agent, url: &str, offset: u64) -> Result<Box<dyn Read + Send>, Error> {
+        let mut request = agent
+            .get(url)
+            // Offsets are into the body as it is sent, so it must not be compressed in transit
+            .header("Accept-Encoding", "identity");
+        if offset > 0 {
+            request = request.header("Range", format!("bytes={}-", offset));
+        }
+        let response = request.call()?;
+        let partial = response.status() == 206;
+        let mut body = response.into_body().into_reader();
+        // A server that does not support ranges sends the whole body again
+        if offset > 0 && !partial {
+            io::copy(&mut (&mut body).take(offset), &mut io::sink())?;
+        }
+        Ok(Box::new(body))
+    }
+    /// Resume the download after a failure, or return the failure if it is not transient or
+    /// there have been too many retries
+    fn resume(&mut self, error: Error) -> io::Result<()> {
+        let mut error = error;
+        loop {
+            if matches!(error, Error::StatusCode(400..=499)) || self.retries >= MAX_RETRIES {
+                return Err(into_io(error));
+            }
+            let delay = RETRY_DELAY * 2u32.pow(self.retries);
+            self.retries += 1;
+            tracing::warn!(
+                url = %self.url,
+                offset = self.offset,
+                "Download failed, resuming in {:?}: {}",
+                delay,
+                error
+            );
+            thread::sleep(delay);
+            match Download::request(&self.agent, &self.url, self.offset) {
+                Ok(body) => {
+                    self.body = body;
+                    return Ok(());
+                }
+                Err(e) => error = e,
+            }
+        }
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.body.read(buf) {
+                Ok(read) => {
+                    if read > 0 {
+                        self.offset += read as u64;
+                        self.retries = 0;
+                    }
+                    return Ok(read);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => self.resume(Error::Io(e))?,
+            }
+        }
+    }
+}
+
+/// Convert a download error into an IO error, since downloads are read as input
+fn into_io(error: Error) -> io::Error {
+    match error {
+        Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
@@ -0,0 +1,155 @@
+//! A report of duplicate transaction ids
+//!
+//! Transactions rejected because their id was already used usually mean the input was exported
+//! twice or an upstream system reuses ids. A [`Duplicates`] report lists each collision with the
+//! line the id was first used on, so the problem can be quantified apart from other rejections.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use csv::Writer;
+
+use crate::{
+    account::TransactionError,
+    amount::Amount,
+    input::Record,
+    transaction::{ClientId, ClientTransaction, Timestamp, Transaction, TransactionId},
+};
+
+/// The parts of a transaction that are compared to tell if a duplicate is a repeat of the
+/// original
+#[derive(PartialEq)]
+struct Payload {
+    kind: &'static str,
+    amount: Option<Amount>,
+    to: Option<ClientId>,
+    timestamp: Option<Timestamp>,
+}
+
+impl From<&ClientTransaction> for Payload {
+    fn from(tx: &ClientTransaction) -> Self {
+        Payload {
+            kind: tx.tx.type_name(),
+            amount: tx.tx.amount(),
+            to: match tx.tx {
+                Transaction::Transfer { to, .. } => Some(to),
+                _ => None,
+            },
+            timestamp: tx.timestamp,
+        }
+    }
+}
+
+/// A CSV report of transactions rejected for reusing a transaction id
+///
+/// The report has a header row and the columns `line_no, line, client, tx, first_line_no,
+/// matched`. `first_line_no` is the line the id was first used on, and `matched` is whether the
+/// duplicate has the same type, amount, recipient, and timestamp as that transaction. Both are
+/// empty if the id was used before this run, such as in loaded state.
+pub struct Duplicates<W: Write> {
+    writer: Writer<W>,
+    /// The line and payload of every transaction that used an id, by client and id
+    seen: HashMap<(ClientId, TransactionId), (usize, Payload)>,
+    count: usize,
+    matched: usize,
+    error: Option<io::Error>,
+}
+
+impl Duplicates<File> {
+    /// Create a report file, replacing any existing one
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Duplicates::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> Duplicates<W> {
+    /// Create a report that writes to a writer
+    pub fn new(writer: W) -> Self {
+        let mut duplicates = Duplicates {
+            writer: Writer::from_writer(writer),
+            seen: HashMap::new(),
+            count: 0,
+            matched: 0,
+            error: None,
+        };
+        if let Err(e) = duplicates.writer.write_record([
+            "line_no",
+            "line",
+            "client",
+            "tx",
+            "first_line_no",
+            "matched",
+        ]) {
+            duplicates.error = Some(e.into());
+        }
+        duplicates
+    }
+    /// Observe the execution of a transaction
+    ///
+    /// Transactions that use an id are remembered, and those rejected as duplicates are written
+    /// to the report. Rows are buffered. If writing fails, nothing more is written and the error
+    /// is returned by [`Duplicates::flush`].
+    pub fn observe(&mut self, record: &Record, result: &Result<(), TransactionError>) {
+        let tx = &record.tx;
+        match result {
+            // Parked withdrawals use their id once they are applied
+            Ok(()) | Err(TransactionError::WithdrawalParked { .. }) => {
+                if let Transaction::Change { tx_id, .. } | Transaction::Transfer { tx_id, .. } =
+                    tx.tx
+                {
+                    self.seen
+                        .entry((tx.client, tx_id))
+                        .or_insert_with(|| (record.line_no, Payload::from(tx)));
+                    if let Transaction::Transfer { to, .. } = tx.tx {
+                        self.seen
+                            .entry((to, tx_id))
+                            .or_insert_with(|| (record.line_no, Payload::from(tx)));
+                    }
+                }
+            }
+            Err(TransactionError::DuplicateTransactionId(tx_id)) => {
+                let first = self.seen.get(&(tx.client, *tx_id));
+                let matched = first.map(|(_, payload)| *payload == Payload::from(tx));
+                self.count += 1;
+                if matched == Some(true) {
+                    self.matched += 1;
+                }
+                if self.error.is_some() {
+                    return;
+                }
+                if let Err(e) = self.writer.write_record([
+                    record.line_no.to_string().as_str(),
+                    &record.line,
+                    &tx.client.to_string(),
+                    &tx_id.to_string(),
+                    &first.map_or(String::new(), |(line_no, _)| line_no.to_string()),
+                    &matched.map_or(String::new(), |matched| matched.to_string()),
+                ]) {
+                    self.error = Some(e.into());
+                }
+            }
+            Err(_) => {}
+        }
+    }
+    /// The number of duplicates observed so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    /// The number of duplicates observed so far that matched their original transaction
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+    /// Write any buffered rows
+    ///
+    /// If an earlier row failed to be written, its error is returned instead.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,170 @@
+//! Estimates of the memory a run needs
+//!
+//! [`estimate`] samples the start of an input and extrapolates to the whole of it. Every
+//! deposit and withdrawal in memory, every dispute, and every transfer is kept in a hash
+//! table, so these dominate. Accounts are counted as they appear in the sample, and new
+//! clients are assumed to keep appearing at the rate they did in the second half of it.
+//!
+//! Estimates are deliberately pessimistic. Hash tables are assumed to be as full as they are
+//! while growing, when both the old and new tables are allocated, and rejected deposits and
+//! withdrawals are assumed to be kept.
+
+use std::{collections::HashSet, fmt, io::Read, mem::size_of};
+
+use crate::{
+    account::Account,
+    input::records,
+    transaction::{BalanceChange, ClientId, Transaction, TransactionId},
+};
+
+/// How many bytes of hash tables each byte of entries takes at the peak of growing
+const HASH_TABLE_PEAK: f64 = 3.0;
+
+/// How many bytes of the map of accounts each byte of accounts takes
+const BTREE_OVERHEAD: f64 = 1.5;
+
+/// The memory needed to process an input, as estimated from a sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// The number of transactions sampled
+    pub sampled: usize,
+    /// Whether the sample covers the whole input, in which case the counts are exact
+    pub complete: bool,
+    /// The number of transactions in the input
+    pub transactions: u64,
+    /// The number of client accounts
+    pub clients: u64,
+    /// The number of deposits and withdrawals kept in memory
+    pub history: u64,
+    /// The number of disputes
+    pub disputes: u64,
+    /// The number of transfers
+    pub transfers: u64,
+    /// The peak memory used by accounts, in bytes
+    pub peak_bytes: u64,
+}
+
+/// Estimate the memory needed to process an input from a sample of its transactions
+///
+/// `input_len` is the length of the whole input in bytes. `history_limit` is the most
+/// deposits and withdrawals kept in memory, as when history is kept on disk.
+pub fn estimate<R: Read>(
+    source: R,
+    input_len: u64,
+    history_limit: Option<usize>,
+    sample: usize,
+) -> Estimate {
+    let mut clients = HashSet::new();
+    let (mut sampled, mut sampled_len) = (0, 0);
+    let (mut history, mut disputes, mut transfers) = (0, 0, 0);
+    let mut late_new_clients = 0;
+    let mut records = records(source);
+    // Invalid transactions are skipped, as they are in a run
+    for record in records.by_ref().take(sample).flatten() {
+        sampled += 1;
+        sampled_len += record.line.len() as u64 + 1;
+        let is_new = clients.insert(record.tx.client);
+        if let Transaction::Transfer { to, .. } = record.tx.tx {
+            clients.insert(to);
+        }
+        if is_new && sampled > sample / 2 {
+            late_new_clients += 1;
+        }
+        match record.tx.tx {
+            Transaction::Change { .. } => history += 1,
+            Transaction::Dispute { .. } => disputes += 1,
+            Transaction::Transfer { .. } => transfers += 1,
+            Transaction::Resolution { .. }
+            | Transaction::Unfreeze(_)
+            | Transaction::Representment(_) => {}
+        }
+    }
+    let complete = records.next().is_none();
+
+    // Extrapolate from the sample
+    let scale = if complete || sampled_len == 0 {
+        1.0
+    } else {
+        (input_len as f64 / sampled_len as f64).max(1.0)
+    };
+    let transactions = (sampled as f64 * scale) as u64;
+    let clients = if complete {
+        clients.len() as u64
+    } else {
+        let rate = late_new_clients as f64 / (sampled - sample / 2).max(1) as f64;
+        let remaining = transactions.saturating_sub(sampled as u64);
+        (clients.len() as u64 + (rate * remaining as f64) as u64).min(ClientId::MAX as u64 + 1)
+    };
+    let history = (history as f64 * scale) as u64;
+    let history = history_limit.map_or(history, |limit| history.min(limit as u64));
+    let disputes = (disputes as f64 * scale) as u64;
+    let transfers = (transfers as f64 * scale) as u64;
+
+    let hash_table = |entries: u64, entry_size: usize| {
+        (entries as f64 * entry_size as f64 * HASH_TABLE_PEAK) as u64
+    };
+    let accounts = (clients as f64
+        * (size_of::<ClientId>() + size_of::<Account>()) as f64
+        * BTREE_OVERHEAD) as u64;
+    let mut peak_bytes = accounts
+        + hash_table(history, size_of::<(TransactionId, BalanceChange)>())
+        + hash_table(disputes, size_of::<TransactionId>())
+        + hash_table(transfers, size_of::<TransactionId>());
+    // History that may be moved to disk is also tracked in a queue, which doubles as it grows
+    if history_limit.is_some() {
+        peak_bytes += history * 2 * size_of::<(ClientId, TransactionId)>() as u64;
+    }
+    Estimate {
+        sampled,
+        complete,
+        transactions,
+        clients,
+        history,
+        disputes,
+        transfers,
+        peak_bytes,
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let about = if self.complete { "" } else { "about " };
+        if self.complete {
+            writeln!(f, "Sampled all {} transactions", self.sampled)?;
+        } else {
+            writeln!(
+                f,
+                "Sampled {} of about {} transactions",
+                self.sampled, self.transactions
+            )?;
+        }
+        writeln!(f, "Clients: {}{}", about, self.clients)?;
+        writeln!(
+            f,
+            "Deposits and withdrawals in memory: {}{}",
+            about, self.history
+        )?;
+        writeln!(f, "Disputes: {}{}", about, self.disputes)?;
+        writeln!(f, "Transfers: {}{}", about, self.transfers)?;
+        writeln!(f, "Peak memory: about {}", Bytes(self.peak_bytes))
+    }
+}
+
+/// A number of bytes, formatted in the largest unit it has at least one of
+struct Bytes(u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", size, UNITS[unit])
+    }
+}
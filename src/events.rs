@@ -0,0 +1,191 @@
+//! A stream of balance mutations
+//!
+//! Downstream systems, such as ledgers and projections, often need to know how each
+//! transaction changed an account rather than only the final state. With
+//! [`Accounts::set_balance_events`](crate::Accounts::set_balance_events), the accounts send a
+//! [`BalanceEvent`] for every change they make to an account's available, held, or total
+//! funds, or to whether it is locked, with its balances before and after the change. A lone
+//! [`Account`] sends no events.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::mpsc::{Receiver, Sender},
+};
+
+use serde_json::json;
+
+use crate::{
+    account::Account,
+    amount::Amount,
+    transaction::{ClientId, TransactionId},
+};
+
+/// The balances of an account at a point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Balances {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// The funds of disputed withdrawals and fees, which are not part of the total
+    pub pending_credit: Amount,
+    pub locked: bool,
+}
+
+impl Balances {
+    /// Get the balances of an account
+    pub fn of(account: &Account) -> Self {
+        Balances {
+            available: account.balance(),
+            held: account.held(),
+            total: account.total(),
+            pending_credit: account.pending_credit(),
+            locked: account.is_frozen(),
+        }
+    }
+}
+
+/// What changed an account's balances
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BalanceEventKind {
+    /// A deposit, including accrued interest, was applied
+    FundsDeposited,
+    /// A withdrawal, including a parked withdrawal that was retried, was applied
+    FundsWithdrawn,
+    /// A fee, including an accrued penalty, was charged
+    FeeCharged,
+    /// Funds of a deposit were held by a dispute
+    FundsHeld,
+    /// Funds of a withdrawal or fee became a pending credit because of a dispute
+    CreditPending,
+    /// A dispute was resolved, releasing its funds
+    FundsReleased,
+    /// A dispute was charged back
+    ChargebackApplied,
+    /// A chargeback was reversed by a representment
+    ChargebackReversed,
+    /// Funds were transferred to another account
+    TransferSent,
+    /// Funds were transferred from another account
+    TransferReceived,
+    /// A pending deposit settled, making its funds available
+    FundsSettled,
+    /// The account was frozen by an admin operation
+    AccountFrozen,
+    /// The account was unfrozen
+    AccountUnfrozen,
+    /// The available balance was adjusted by an admin operation
+    BalanceAdjusted,
+    /// The account was merged into another by an admin operation, or another into it
+    AccountMerged,
+}
+
+impl BalanceEventKind {
+    /// Get the name of the kind of event, such as `funds_deposited`
+    pub const fn name(self) -> &'static str {
+        match self {
+            BalanceEventKind::FundsDeposited => "funds_deposited",
+            BalanceEventKind::FundsWithdrawn => "funds_withdrawn",
+            BalanceEventKind::FeeCharged => "fee_charged",
+            BalanceEventKind::FundsHeld => "funds_held",
+            BalanceEventKind::CreditPending => "credit_pending",
+            BalanceEventKind::FundsReleased => "funds_released",
+            BalanceEventKind::ChargebackApplied => "chargeback_applied",
+            BalanceEventKind::ChargebackReversed => "chargeback_reversed",
+            BalanceEventKind::TransferSent => "transfer_sent",
+            BalanceEventKind::TransferReceived => "transfer_received",
+            BalanceEventKind::FundsSettled => "funds_settled",
+            BalanceEventKind::AccountFrozen => "account_frozen",
+            BalanceEventKind::AccountUnfrozen => "account_unfrozen",
+            BalanceEventKind::BalanceAdjusted => "balance_adjusted",
+            BalanceEventKind::AccountMerged => "account_merged",
+        }
+    }
+}
+
+impl fmt::Display for BalanceEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A change to the balances of an account
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BalanceEvent {
+    pub kind: BalanceEventKind,
+    pub client: ClientId,
+    /// The transaction that made the change, which admin operations do not have
+    pub tx: Option<TransactionId>,
+    pub before: Balances,
+    pub after: Balances,
+}
+
+impl BalanceEvent {
+    /// Write the event as a line of JSON, with amounts as exact decimal strings
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let balances = |balances: &Balances| {
+            json!({
+                "available": balances.available.to_string(),
+                "held": balances.held.to_string(),
+                "total": balances.total.to_string(),
+                "pending_credit": balances.pending_credit.to_string(),
+                "locked": balances.locked,
+            })
+        };
+        let event = json!({
+            "event": self.kind.name(),
+            "client": self.client,
+            "tx": self.tx,
+            "before": balances(&self.before),
+            "after": balances(&self.after),
+        });
+        writeln!(writer, "{}", event)
+    }
+}
+
+/// Write events as lines of JSON as they are received, until every sender is dropped
+///
+/// The writer is flushed whenever no more events are waiting, so it keeps up with live input.
+pub fn write_json_lines<W: Write>(
+    receiver: Receiver<BalanceEvent>,
+    mut writer: W,
+) -> io::Result<()> {
+    while let Ok(event) = receiver.recv() {
+        event.write_json(&mut writer)?;
+        for event in receiver.try_iter() {
+            event.write_json(&mut writer)?;
+        }
+        writer.flush()?;
+    }
+    writer.flush()
+}
+
+/// Send an event for a change to an account's balances, if there is a sink and they changed
+///
+/// Events are dropped once the receiver is gone.
+pub(crate) fn send(
+    sink: &Option<Sender<BalanceEvent>>,
+    kind: BalanceEventKind,
+    client: ClientId,
+    tx: Option<TransactionId>,
+    before: Balances,
+    account: Option<&Account>,
+) {
+    let Some(sink) = sink else {
+        return;
+    };
+    let after = account.map(Balances::of).unwrap_or_default();
+    if after != before {
+        let _ = sink.send(BalanceEvent {
+            kind,
+            client,
+            tx,
+            before,
+            after,
+        });
+    }
+}
@@ -0,0 +1,243 @@
+//! Tools for cutting transaction streams down into smaller ones
+//!
+//! Every stream produced here is itself valid input. Disputes, resolutions, and
+//! chargebacks are only written if the transaction of the same client they refer to was
+//! written to the same stream, so no output contains a dangling reference.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    ops::Range,
+    path::Path,
+};
+
+use crate::{records, transaction::*, Accounts, Record};
+
+const HEADER: &str = "type,client,tx,amount,to,timestamp";
+
+/// Criteria for selecting transactions from a stream
+///
+/// Empty criteria match everything
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only select transactions for these clients
+    pub clients: Vec<ClientId>,
+    /// Only select transactions of these types
+    pub types: Vec<String>,
+    /// Only select transactions whose id, or referenced id, is in this range
+    pub range: Option<Range<TransactionId>>,
+}
+
+impl Filter {
+    /// Check if a transaction matches the filter
+    pub fn matches(&self, tx: &ClientTransaction) -> bool {
+        (self.clients.is_empty() || self.clients.contains(&tx.client))
+            && (self.types.is_empty() || self.types.iter().any(|ty| ty == tx.tx.type_name()))
+            && self
+                .range
+                .as_ref()
+                .is_none_or(|range| range.contains(&tx.tx.tx_id()))
+    }
+}
+
+/// Parse a transaction id range of the form `start..end`, where either bound may be omitted
+pub fn parse_range(s: &str) -> Result<Range<TransactionId>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| format!("Invalid range {:?}, expected start..end", s))?;
+    let bound = |bound: &str, default| {
+        if bound.trim().is_empty() {
+            Ok(default)
+        } else {
+            bound
+                .trim()
+                .parse::<TransactionId>()
+                .map_err(|_| format!("Invalid range bound {:?}", bound))
+        }
+    };
+    Ok(bound(start, TransactionId::MIN)?..bound(end, TransactionId::MAX)?)
+}
+
+/// The transactions written to a stream, used to keep dispute references consistent
+#[derive(Default)]
+struct Written(HashSet<(ClientId, TransactionId)>);
+
+impl Written {
+    /// Check if a record should be written, recording it if so
+    ///
+    /// Records that refer to a transaction that was not written are not.
+    fn admit(&mut self, record: &Record) -> bool {
+        let (client, tx_id) = (record.tx.client, record.tx.tx.tx_id());
+        match record.tx.tx {
+            Transaction::Change { .. } => {
+                self.0.insert((client, tx_id));
+            }
+            Transaction::Transfer { to, .. } => {
+                self.0.insert((client, tx_id));
+                self.0.insert((to, tx_id));
+            }
+            Transaction::Unfreeze(_) => {}
+            // References are to transactions of the same client, as they are when executed
+            Transaction::Dispute { .. }
+            | Transaction::Resolution { .. }
+            | Transaction::Representment(_) => return self.0.contains(&(client, tx_id)),
+        }
+        true
+    }
+}
+
+/// A transaction stream that keeps dispute references consistent as records are written
+struct Output<W> {
+    writer: W,
+    written: Written,
+}
+
+impl<W: Write> Output<W> {
+    fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", HEADER)?;
+        Ok(Output {
+            writer,
+            written: Written::default(),
+        })
+    }
+    /// Write a record, unless it refers to a transaction that was not written
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        if self.written.admit(record) {
+            writeln!(self.writer, "{}", record.tx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the transactions from a source that match a filter
+pub fn filter<R, W>(source: R, filter: &Filter, dest: W) -> Result<(), String>
+where
+    R: Read,
+    W: Write,
+{
+    let mut output = Output::new(dest).map_err(|e| format!("Error writing output: {}", e))?;
+    for record in records(source) {
+        let record = record?;
+        if filter.matches(&record.tx) {
+            output
+                .write(&record)
+                .map_err(|e| format!("Error writing output: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write all transactions from a source that affect a client
+///
+/// This includes the client's deposits and withdrawals, transfers to or from the client,
+/// and the client's disputes, resolutions, and chargebacks that refer to them. Order is
+/// preserved.
+///
+/// The sender of a transfer to the client is not written, so the transfer would fail when
+/// the output is replayed. Instead, the source is executed as it is read, and each transfer
+/// to the client that succeeds is written as a deposit of the same id, amount, and
+/// timestamp. Replaying the output gives the client the same balances.
+pub fn extract<R, W>(source: R, client: ClientId, dest: W) -> Result<(), String>
+where
+    R: Read,
+    W: Write,
+{
+    let mut output = Output::new(dest).map_err(|e| format!("Error writing output: {}", e))?;
+    let mut accounts = Accounts::default();
+    for record in records(source) {
+        let record = record?;
+        let executed = accounts.transact(record.tx.clone()).is_ok();
+        let written = match record.tx.tx {
+            Transaction::Transfer { tx_id, to, amount }
+                if to == client && record.tx.client != client =>
+            {
+                if !executed {
+                    continue;
+                }
+                // Transfers cannot be disputed, so the deposit is not recorded as written and
+                // disputes that refer to it are dropped
+                let deposit = ClientTransaction {
+                    client,
+                    tx: Transaction::deposit(tx_id, amount),
+                    timestamp: record.tx.timestamp,
+                };
+                writeln!(output.writer, "{}", deposit)
+            }
+            _ if record.tx.client == client => output.write(&record),
+            _ => Ok(()),
+        };
+        written.map_err(|e| format!("Error writing output: {}", e))?;
+    }
+    Ok(())
+}
+
+/// The most files [`split_by_client`] keeps open at once
+const MAX_OPEN_FILES: usize = 64;
+
+/// Split the transactions from a source into one file per client in the given directory
+///
+/// Files are named `client_<id>.csv`. Transfers are written to the file of the client that
+/// submitted them. Records are written as they are read, and at most [`MAX_OPEN_FILES`]
+/// files are kept open, so the least recently written one is closed to make room and
+/// reopened if its client appears again.
+pub fn split_by_client<R>(source: R, out_dir: &Path) -> Result<(), String>
+where
+    R: Read,
+{
+    fs::create_dir_all(out_dir).map_err(|e| format!("Unable to create {:?}: {}", out_dir, e))?;
+    let path_of = |client| out_dir.join(format!("client_{}.csv", client));
+    let flush = |client, mut file: BufWriter<File>| {
+        file.flush()
+            .map_err(|e| format!("Unable to write {:?}: {}", path_of(client), e))
+    };
+    let mut written: HashMap<ClientId, Written> = HashMap::new();
+    // Open files, least recently written first
+    let mut open: VecDeque<(ClientId, BufWriter<File>)> = VecDeque::new();
+    for record in records(source) {
+        let record = record?;
+        let client = record.tx.client;
+        let path = path_of(client);
+        let write_error = |e: io::Error| format!("Unable to write {:?}: {}", path, e);
+        let client_written = match written.entry(client) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                // The first record of a client replaces any existing file
+                let mut file = BufWriter::new(File::create(&path).map_err(write_error)?);
+                writeln!(file, "{}", HEADER).map_err(write_error)?;
+                open.push_back((client, file));
+                entry.insert(Written::default())
+            }
+        };
+        if !client_written.admit(&record) {
+            continue;
+        }
+        // Move the client's file to the back, reopening it if it was closed
+        match open
+            .iter()
+            .position(|(open_client, _)| *open_client == client)
+        {
+            Some(i) => {
+                let entry = open.remove(i).expect("position is in bounds");
+                open.push_back(entry);
+            }
+            None => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .map_err(write_error)?;
+                open.push_back((client, BufWriter::new(file)));
+            }
+        }
+        let file = &mut open.back_mut().expect("the client's file is open").1;
+        writeln!(file, "{}", record.tx).map_err(write_error)?;
+        if open.len() > MAX_OPEN_FILES {
+            let (client, file) = open.pop_front().expect("there are open files");
+            flush(client, file)?;
+        }
+    }
+    for (client, file) in open {
+        flush(client, file)?;
+    }
+    Ok(())
+}
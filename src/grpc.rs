@@ -0,0 +1,256 @@
+//! Serving transactions and accounts over gRPC
+//!
+//! This module is enabled by the `grpc` feature. [`Server::serve_grpc`] exposes a server as the
+//! `transactor.Transactor` service described in `proto/transactor.proto`, which clients in
+//! other languages can generate code from:
+//!
+//! - `SubmitTransaction` applies a transaction. It fails with `INVALID_ARGUMENT` if the
+//!   transaction is malformed, or `FAILED_PRECONDITION` if it is rejected.
+//! - `GetAccount` gets the account of one client. It fails with `NOT_FOUND` if the client has
+//!   no account.
+//! - `ListTransactions` gets a page of a client's history, as with [`Server::history`]. It
+//!   fails with `NOT_FOUND` if the client has no account.
+//!
+//! Amounts are decimal strings. Each `SubmitTransaction` call is recorded as a new source.
+//!
+//! Under the [`ConnectionLimits`](crate::server::ConnectionLimits), calls fail with
+//! `RESOURCE_EXHAUSTED` if too many connections are being served, and messages over the size
+//! limit are rejected. Calls on one connection beyond the in-flight limit wait for earlier
+//! ones to finish. Once the server is drained, calls fail with `UNAVAILABLE`.
+
+use std::{io, net::TcpListener, sync::Arc};
+
+use tonic::{transport::server::TcpIncoming, Request, Response, Status};
+
+use crate::{
+    server::{Connection, Refused, Server},
+    transaction::{ChangeKind, ClientId, ClientTransaction},
+};
+
+use self::proto::{
+    transactor_server::{Transactor, TransactorServer},
+    Account, GetAccountRequest, HistoryEntry, ListTransactionsRequest, SubmitTransactionRequest,
+    SubmitTransactionResponse, TransactionPage, TransactionType,
+};
+
+/// The messages of the service, along with its generated client and server
+pub mod proto {
+    /// The type of a transaction
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum TransactionType {
+        Unspecified = 0,
+        Deposit = 1,
+        Withdrawal = 2,
+        Dispute = 3,
+        Resolve = 4,
+        Chargeback = 5,
+        Transfer = 6,
+        Unfreeze = 7,
+        Representment = 8,
+        Fee = 9,
+    }
+
+    /// A transaction to apply
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubmitTransactionRequest {
+        #[prost(enumeration = "TransactionType", tag = "1")]
+        pub r#type: i32,
+        #[prost(uint32, tag = "2")]
+        pub client: u32,
+        #[prost(uint32, tag = "3")]
+        pub tx: u32,
+        /// Only for deposits, withdrawals, transfers, and partial disputes and chargebacks
+        #[prost(string, tag = "4")]
+        pub amount: String,
+        /// The destination client, only for transfers
+        #[prost(uint32, optional, tag = "5")]
+        pub to: Option<u32>,
+    }
+
+    /// The response to a transaction that was applied
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SubmitTransactionResponse {}
+
+    /// A request for the account of a client
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetAccountRequest {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+    }
+
+    /// The balances of a client's account
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Account {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+        #[prost(string, tag = "2")]
+        pub available: String,
+        #[prost(string, tag = "3")]
+        pub held: String,
+        #[prost(string, tag = "4")]
+        pub total: String,
+        #[prost(bool, tag = "5")]
+        pub locked: bool,
+    }
+
+    /// A request for a page of a client's history
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListTransactionsRequest {
+        #[prost(uint32, tag = "1")]
+        pub client: u32,
+        /// Only get transactions with ids after this one
+        #[prost(uint32, optional, tag = "2")]
+        pub after: Option<u32>,
+        /// The most transactions to get
+        #[prost(uint32, optional, tag = "3")]
+        pub limit: Option<u32>,
+    }
+
+    /// A deposit, withdrawal, or fee in a client's history
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HistoryEntry {
+        #[prost(uint32, tag = "1")]
+        pub tx: u32,
+        #[prost(enumeration = "TransactionType", tag = "2")]
+        pub r#type: i32,
+        #[prost(string, tag = "3")]
+        pub amount: String,
+        #[prost(uint64, optional, tag = "4")]
+        pub timestamp: Option<u64>,
+    }
+
+    /// A page of a client's history
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TransactionPage {
+        #[prost(message, repeated, tag = "1")]
+        pub transactions: Vec<HistoryEntry>,
+        /// The `after` of the next page, unset if this is the last page
+        #[prost(uint32, optional, tag = "2")]
+        pub next_after: Option<u32>,
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/transactor.Transactor.rs"));
+}
+
+impl Server {
+    /// Accept gRPC connections and serve the `transactor.Transactor` service
+    ///
+    /// This runs its own async runtime, and only returns if the listener cannot be used.
+    pub fn serve_grpc(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        listener.set_nonblocking(true)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let limits = self.limits();
+        let mut service = TransactorServer::from_arc(self);
+        if let Some(max) = limits.max_request_size {
+            service = service.max_decoding_message_size(max);
+        }
+        let mut builder = tonic::transport::Server::builder();
+        if let Some(max) = limits.max_in_flight {
+            builder = builder.concurrency_limit_per_connection(max);
+        }
+        runtime.block_on(async {
+            let incoming = TcpIncoming::from(tokio::net::TcpListener::from_std(listener)?);
+            builder
+                .add_service(service)
+                .serve_with_incoming(incoming)
+                .await
+                .map_err(io::Error::other)
+        })
+    }
+    /// Count a call as a connection while it is handled
+    fn connect_call(&self) -> Result<Connection<'_>, Status> {
+        self.connect().map_err(|e| match e {
+            Refused::Limit(_) => Status::resource_exhausted(e.to_string()),
+            Refused::Draining => Status::unavailable(e.to_string()),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Transactor for Server {
+    async fn submit_transaction(
+        &self,
+        request: Request<SubmitTransactionRequest>,
+    ) -> Result<Response<SubmitTransactionResponse>, Status> {
+        let _connection = self.connect_call()?;
+        let request = request.into_inner();
+        let tx_type = match request.r#type() {
+            TransactionType::Unspecified => None,
+            TransactionType::Deposit => Some("deposit"),
+            TransactionType::Withdrawal => Some("withdrawal"),
+            TransactionType::Dispute => Some("dispute"),
+            TransactionType::Resolve => Some("resolve"),
+            TransactionType::Chargeback => Some("chargeback"),
+            TransactionType::Transfer => Some("transfer"),
+            TransactionType::Unfreeze => Some("unfreeze"),
+            TransactionType::Representment => Some("representment"),
+            TransactionType::Fee => Some("fee"),
+        };
+        let tx = ClientTransaction::from_fields(
+            tx_type,
+            Some(&request.client.to_string()),
+            Some(&request.tx.to_string()),
+            Some(request.amount.as_str()).filter(|amount| !amount.is_empty()),
+            request.to.map(|to| to.to_string()).as_deref(),
+        )
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        self.transact(self.next_source(), tx)
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        Ok(Response::new(SubmitTransactionResponse {}))
+    }
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> Result<Response<Account>, Status> {
+        let _connection = self.connect_call()?;
+        let client = request.into_inner().client;
+        self.with_accounts(|accounts| {
+            let places = accounts.precision().decimal_places() as usize;
+            let account = ClientId::try_from(client)
+                .ok()
+                .and_then(|client| accounts.get(client))
+                .ok_or_else(|| Status::not_found(format!("Client {} has no account", client)))?;
+            Ok(Response::new(Account {
+                client,
+                available: format!("{:.*}", places, account.balance()),
+                held: format!("{:.*}", places, account.held()),
+                total: format!("{:.*}", places, account.total()),
+                locked: account.is_frozen(),
+            }))
+        })
+    }
+    async fn list_transactions(
+        &self,
+        request: Request<ListTransactionsRequest>,
+    ) -> Result<Response<TransactionPage>, Status> {
+        let _connection = self.connect_call()?;
+        let request = request.into_inner();
+        let not_found = || Status::not_found(format!("Client {} has no account", request.client));
+        let client = ClientId::try_from(request.client).map_err(|_| not_found())?;
+        let limit = request.limit.map(|limit| limit as usize);
+        let page = self
+            .history(client, request.after, limit)
+            .ok_or_else(not_found)?
+            .map_err(Status::internal)?;
+        let places = self.with_accounts(|accounts| accounts.precision().decimal_places() as usize);
+        let transactions = (page.entries.into_iter())
+            .map(|(tx, change)| HistoryEntry {
+                tx,
+                r#type: match change.kind {
+                    ChangeKind::Deposit => TransactionType::Deposit,
+                    ChangeKind::Withdrawal => TransactionType::Withdrawal,
+                    ChangeKind::Fee => TransactionType::Fee,
+                } as i32,
+                amount: format!("{:.*}", places, change.amount),
+                timestamp: change.timestamp,
+            })
+            .collect();
+        Ok(Response::new(TransactionPage {
+            transactions,
+            next_after: page.next_after,
+        }))
+    }
+}
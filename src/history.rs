@@ -0,0 +1,353 @@
+//! Keeping transaction history on disk
+//!
+//! Every deposit and withdrawal is kept in its account's history so that it can be disputed
+//! later, so history grows with the input. With a [`HistorySpill`], [`Accounts`] keep only
+//! the most recent entries in memory and move older ones to a [`HistoryBackend`] on disk.
+//! Entries are moved back into memory when they are disputed.
+//!
+//! A [`HistoryStore`] is a single file indexed by transaction id, with a fixed-size slot for
+//! each id. Slots of unused ids are never written, so on file systems with sparse files the
+//! store only takes up space for the entries in it. With the `sled` feature, a
+//! `SledHistoryStore` keeps entries in an embedded database keyed by client and
+//! transaction id instead, which suits sparse or reused transaction ids.
+//!
+//! History on disk is not included when accounts are saved or archived.
+//!
+//! [`Accounts`]: crate::Accounts
+
+use std::{
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    amount::Amount,
+    transaction::{BalanceChange, ChangeKind, ClientId, Timestamp, TransactionId},
+};
+
+/// The size of the slot of each transaction id
+///
+/// A slot holds the kind of change, the amount, the timestamp, and the client id.
+const SLOT_SIZE: u64 = 32;
+
+/// The size of an encoded history entry, see [`encode_change`]
+const CHANGE_SIZE: usize = 25;
+
+/// The bit of the first byte of an encoded history entry that marks it as having a timestamp
+const TIMESTAMPED: u8 = 0x80;
+
+/// An on-disk store of history entries, keyed by client and transaction id
+pub trait HistoryBackend: Debug + Send + Sync {
+    /// Store a client's history entry
+    ///
+    /// Returns `false` without storing the entry if the backend has no room for it.
+    fn put(
+        &self,
+        client: ClientId,
+        tx_id: TransactionId,
+        change: BalanceChange,
+    ) -> io::Result<bool>;
+    /// Check whether a client has a history entry in the store
+    fn contains(&self, client: ClientId, tx_id: TransactionId) -> io::Result<bool>;
+    /// Remove a client's history entry from the store and return it
+    fn take(&self, client: ClientId, tx_id: TransactionId) -> io::Result<Option<BalanceChange>>;
+    /// Get up to `limit` of a client's history entries with transaction ids after `after`,
+    /// in order of transaction id
+    fn page(
+        &self,
+        client: ClientId,
+        after: Option<TransactionId>,
+        limit: usize,
+    ) -> io::Result<Vec<(TransactionId, BalanceChange)>>;
+}
+
+/// A history store in a file, indexed by transaction id
+#[derive(Debug)]
+pub struct HistoryStore {
+    file: Mutex<File>,
+}
+
+impl HistoryStore {
+    /// Create a new, empty store at a path, replacing any file already there
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(HistoryStore {
+            file: Mutex::new(file),
+        })
+    }
+    /// Read the slot of a transaction id
+    fn read_slot(file: &mut File, tx_id: TransactionId) -> io::Result<[u8; SLOT_SIZE as usize]> {
+        let mut slot = [0; SLOT_SIZE as usize];
+        let offset = tx_id as u64 * SLOT_SIZE;
+        // Slots past the end of the file are empty
+        if offset < file.metadata()?.len() {
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut slot)?;
+        }
+        Ok(slot)
+    }
+    /// Write the slot of a transaction id
+    fn write_slot(
+        file: &mut File,
+        tx_id: TransactionId,
+        slot: &[u8; SLOT_SIZE as usize],
+    ) -> io::Result<()> {
+        file.seek(SeekFrom::Start(tx_id as u64 * SLOT_SIZE))?;
+        file.write_all(slot)
+    }
+}
+
+/// Entries are rejected if their transaction id is already used by an entry of another client.
+impl HistoryBackend for HistoryStore {
+    fn put(
+        &self,
+        client: ClientId,
+        tx_id: TransactionId,
+        change: BalanceChange,
+    ) -> io::Result<bool> {
+        let mut file = self.file.lock().unwrap();
+        let slot = Self::read_slot(&mut file, tx_id)?;
+        if slot[0] != 0 && slot_client(&slot) != client {
+            return Ok(false);
+        }
+        let mut slot = [0; SLOT_SIZE as usize];
+        slot[..CHANGE_SIZE].copy_from_slice(&encode_change(change));
+        slot[CHANGE_SIZE..CHANGE_SIZE + 2].copy_from_slice(&client.to_le_bytes());
+        Self::write_slot(&mut file, tx_id, &slot)?;
+        Ok(true)
+    }
+    fn contains(&self, client: ClientId, tx_id: TransactionId) -> io::Result<bool> {
+        let mut file = self.file.lock().unwrap();
+        let slot = Self::read_slot(&mut file, tx_id)?;
+        Ok(slot[0] != 0 && slot_client(&slot) == client)
+    }
+    fn take(&self, client: ClientId, tx_id: TransactionId) -> io::Result<Option<BalanceChange>> {
+        let mut file = self.file.lock().unwrap();
+        let slot = Self::read_slot(&mut file, tx_id)?;
+        if slot[0] == 0 || slot_client(&slot) != client {
+            return Ok(None);
+        }
+        let change = decode_change(slot[..CHANGE_SIZE].try_into().unwrap())?;
+        Self::write_slot(&mut file, tx_id, &[0; SLOT_SIZE as usize])?;
+        Ok(Some(change))
+    }
+    /// The file is ordered by transaction id but holds every client's entries, so this reads
+    /// slots from `after` until it finds `limit` of the client's entries or reaches the end.
+    fn page(
+        &self,
+        client: ClientId,
+        after: Option<TransactionId>,
+        limit: usize,
+    ) -> io::Result<Vec<(TransactionId, BalanceChange)>> {
+        let mut page = Vec::new();
+        let start = match after {
+            Some(TransactionId::MAX) => return Ok(page),
+            Some(after) => after as u64 + 1,
+            None => 0,
+        };
+        let mut file = self.file.lock().unwrap();
+        let len = file.metadata()?.len();
+        if limit == 0 || start * SLOT_SIZE >= len {
+            return Ok(page);
+        }
+        file.seek(SeekFrom::Start(start * SLOT_SIZE))?;
+        let mut reader = BufReader::new(&mut *file);
+        let mut slot = [0; SLOT_SIZE as usize];
+        for tx_id in start..len / SLOT_SIZE {
+            reader.read_exact(&mut slot)?;
+            if slot[0] != 0 && slot_client(&slot) == client {
+                let change = decode_change(slot[..CHANGE_SIZE].try_into().unwrap())?;
+                page.push((tx_id as TransactionId, change));
+                if page.len() == limit {
+                    break;
+                }
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// Get the client id of a slot
+///
+/// A slot starts with the encoded change, followed by the client id. The first byte of an
+/// encoded change is never 0, so it also marks the slot as used.
+fn slot_client(slot: &[u8; SLOT_SIZE as usize]) -> ClientId {
+    ClientId::from_le_bytes([slot[CHANGE_SIZE], slot[CHANGE_SIZE + 1]])
+}
+
+/// Encode a history entry as its kind followed by its amount and its timestamp
+///
+/// The first byte is the kind, with the [`TIMESTAMPED`] bit set if there is a timestamp.
+fn encode_change(change: BalanceChange) -> [u8; CHANGE_SIZE] {
+    let mut bytes = [0; CHANGE_SIZE];
+    bytes[0] = match change.kind {
+        ChangeKind::Deposit => 1,
+        ChangeKind::Withdrawal => 2,
+        ChangeKind::Fee => 3,
+    };
+    bytes[1..17].copy_from_slice(&change.amount.units().to_le_bytes());
+    if let Some(timestamp) = change.timestamp {
+        bytes[0] |= TIMESTAMPED;
+        bytes[17..].copy_from_slice(&timestamp.to_le_bytes());
+    }
+    bytes
+}
+
+/// Decode a history entry encoded by [`encode_change`]
+fn decode_change(bytes: [u8; CHANGE_SIZE]) -> io::Result<BalanceChange> {
+    let kind = match bytes[0] & !TIMESTAMPED {
+        1 => ChangeKind::Deposit,
+        2 => ChangeKind::Withdrawal,
+        3 => ChangeKind::Fee,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid history entry",
+            ))
+        }
+    };
+    let amount = Amount::from_units(i128::from_le_bytes(bytes[1..17].try_into().unwrap()));
+    let timestamp = (bytes[0] & TIMESTAMPED != 0)
+        .then(|| Timestamp::from_le_bytes(bytes[17..].try_into().unwrap()));
+    Ok(BalanceChange {
+        kind,
+        amount,
+        timestamp,
+    })
+}
+
+/// A setting for keeping history on disk
+///
+/// See the [module-level documentation](self).
+#[derive(Debug, Clone)]
+pub struct HistorySpill {
+    /// The store older history entries are moved to
+    pub store: Arc<dyn HistoryBackend>,
+    /// The most history entries to keep in memory
+    pub limit: usize,
+}
+
+impl HistorySpill {
+    /// The most history entries kept in memory if no limit is given
+    pub const DEFAULT_LIMIT: usize = 1_000_000;
+    /// Create a history store at a path and keep history beyond a limit in it
+    ///
+    /// A path starting with `sled:` is a [`SledHistoryStore`] database, which needs the `sled`
+    /// feature, and any other path is a [`HistoryStore`] file.
+    pub fn create(path: &str, limit: Option<usize>) -> Result<Self, String> {
+        let store: Arc<dyn HistoryBackend> = match path.strip_prefix("sled:") {
+            #[cfg(feature = "sled")]
+            Some(path) => Arc::new(
+                SledHistoryStore::create(Path::new(path))
+                    .map_err(|e| format!("Unable to create history database {:?}: {}", path, e))?,
+            ),
+            #[cfg(not(feature = "sled"))]
+            Some(path) => {
+                return Err(format!(
+                    "Unable to create history database {:?}: transactor was built without the `sled` feature",
+                    path
+                ))
+            }
+            None => Arc::new(
+                HistoryStore::create(Path::new(path))
+                    .map_err(|e| format!("Unable to create history file {:?}: {}", path, e))?,
+            ),
+        };
+        Ok(HistorySpill {
+            store,
+            limit: limit.unwrap_or(HistorySpill::DEFAULT_LIMIT),
+        })
+    }
+}
+
+/// A history store in an embedded sled database, keyed by client and transaction id
+///
+/// This is enabled by the `sled` feature.
+#[cfg(feature = "sled")]
+#[derive(Debug)]
+pub struct SledHistoryStore {
+    tree: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledHistoryStore {
+    /// Create a new, empty store in a directory, clearing any store already there
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let tree = sled::open(path).map_err(io::Error::other)?;
+        tree.clear().map_err(io::Error::other)?;
+        Ok(SledHistoryStore { tree })
+    }
+    fn key(client: ClientId, tx_id: TransactionId) -> [u8; 6] {
+        let mut key = [0; 6];
+        key[..2].copy_from_slice(&client.to_be_bytes());
+        key[2..].copy_from_slice(&tx_id.to_be_bytes());
+        key
+    }
+}
+
+#[cfg(feature = "sled")]
+impl HistoryBackend for SledHistoryStore {
+    fn put(
+        &self,
+        client: ClientId,
+        tx_id: TransactionId,
+        change: BalanceChange,
+    ) -> io::Result<bool> {
+        self.tree
+            .insert(Self::key(client, tx_id), &encode_change(change))
+            .map_err(io::Error::other)?;
+        Ok(true)
+    }
+    fn contains(&self, client: ClientId, tx_id: TransactionId) -> io::Result<bool> {
+        self.tree
+            .contains_key(Self::key(client, tx_id))
+            .map_err(io::Error::other)
+    }
+    fn take(&self, client: ClientId, tx_id: TransactionId) -> io::Result<Option<BalanceChange>> {
+        let Some(bytes) = self
+            .tree
+            .remove(Self::key(client, tx_id))
+            .map_err(io::Error::other)?
+        else {
+            return Ok(None);
+        };
+        let bytes = bytes
+            .as_ref()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid history entry"))?;
+        decode_change(bytes).map(Some)
+    }
+    fn page(
+        &self,
+        client: ClientId,
+        after: Option<TransactionId>,
+        limit: usize,
+    ) -> io::Result<Vec<(TransactionId, BalanceChange)>> {
+        let start = match after {
+            Some(TransactionId::MAX) => return Ok(Vec::new()),
+            Some(after) => after + 1,
+            None => 0,
+        };
+        // Keys start with the client, so a client's entries are in order of transaction id
+        self.tree
+            .range(Self::key(client, start)..=Self::key(client, TransactionId::MAX))
+            .take(limit)
+            .map(|entry| {
+                let (key, bytes) = entry.map_err(io::Error::other)?;
+                let tx_id = TransactionId::from_be_bytes(key[2..].try_into().unwrap());
+                let bytes = bytes.as_ref().try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Invalid history entry")
+                })?;
+                Ok((tx_id, decode_change(bytes)?))
+            })
+            .collect()
+    }
+}
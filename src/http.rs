@@ -0,0 +1,292 @@
+//! Serving transactions and accounts over HTTP
+//!
+//! This module is enabled by the `http` feature. [`Server::serve_http`] exposes a server as a
+//! JSON API with these endpoints:
+//!
+//! - `POST /transactions` applies the transactions in the request body, which holds lines of
+//!   input in either format, as sent to a TCP connection. The response is a JSON array with
+//!   a result for each transaction, either `{"status":"ok"}` or
+//!   `{"status":"error","error":"..."}`.
+//! - `GET /accounts` gets every account, as in JSON output.
+//! - `GET /accounts/{client}` gets the account of one client.
+//! - `GET /accounts/{client}/transactions?after={tx}&limit={n}` gets a page of the client's
+//!   history, the deposits, withdrawals, and fees that can still be disputed, in order of
+//!   transaction id. Both parameters are optional, and `limit` is 100 by default and at most
+//!   1000. The response is `{"transactions":[...],"next_after":...}`, where each transaction
+//!   is `{"tx":...,"type":...,"amount":...,"timestamp":...}` and `next_after` is the `after`
+//!   of the next page, or `null` if this is the last one.
+//! - `GET /metrics` gets the server's [`Metrics`] in the Prometheus text format.
+//! - `GET /state` gets a snapshot of every account, as saved with `--save-state`, for
+//!   comparing the state of two servers with the `diff` module.
+//! - `GET /protocol` gets the newest version of the protocol the server speaks and the
+//!   features it supports, as `{"version":...,"features":[...]}`, see the `server` module.
+//! - `GET /submitters` gets the clients that have submitted the most transactions over the
+//!   last minute, as a JSON array of `{"client":...,"per_minute":...,"total":...}` objects.
+//!
+//! A request may name the version of the protocol it speaks in a `Transactor-Protocol` header,
+//! and requests without one are answered as in version 1. From version 2, the error results of
+//! `POST /transactions` also have a machine-readable `"code"`, such as `insufficient_funds`.
+//!
+//! Requests over the [`ConnectionLimits`](crate::server::ConnectionLimits) get `503` if there
+//! are too many connections, or `413` if the body is too long or has too many transactions,
+//! and none of their transactions are applied. Once the server is drained, every request
+//! gets `503`.
+//!
+//! Each `POST` request is recorded as a new source.
+
+use std::{
+    io::{self, Read},
+    net::TcpListener,
+    thread,
+};
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::{
+    input::Parser,
+    metrics::{self, Metrics, TOP_SUBMITTERS},
+    output::account_json,
+    server::{protocol_features, LimitExceeded, Rejected, Server, PROTOCOL_VERSION},
+    transaction::{ClientId, Transaction},
+};
+
+/// The header a request names the version of the protocol it speaks in
+pub const PROTOCOL_HEADER: &str = "Transactor-Protocol";
+
+impl Server {
+    /// Accept HTTP requests and handle each one on its own thread
+    ///
+    /// This only returns if the listener cannot be used.
+    pub fn serve_http(&self, listener: TcpListener) -> io::Result<()> {
+        let http = tiny_http::Server::from_listener(listener, None).map_err(io::Error::other)?;
+        thread::scope(|scope| {
+            for request in http.incoming_requests() {
+                scope.spawn(move || {
+                    let method = request.method().clone();
+                    let url = request.url().to_owned();
+                    let result = match self.connect() {
+                        Ok(_connection) => self.respond(request),
+                        Err(e) => respond_json(request, 503, error(e.to_string())),
+                    };
+                    if let Err(e) = result {
+                        tracing::warn!(%method, url, "Failed to respond to HTTP request: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+    /// Handle an HTTP request and send the response
+    fn respond(&self, mut request: Request) -> io::Result<()> {
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let query = query.to_owned();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if let (Method::Get, ["metrics"]) = (request.method(), segments.as_slice()) {
+            return respond_metrics(request, self.metrics());
+        }
+        if let (Method::Get, ["state"]) = (request.method(), segments.as_slice()) {
+            let mut snapshot = Vec::new();
+            self.with_accounts(|accounts| accounts.write_snapshot(&mut snapshot))?;
+            let content_type = Header::from_bytes("Content-Type", "text/plain").unwrap();
+            return request.respond(Response::from_data(snapshot).with_header(content_type));
+        }
+        let version = match protocol_version(&request) {
+            Ok(version) => version,
+            Err(e) => return respond_json(request, 400, error(e)),
+        };
+        let (status, body) = match (request.method(), segments.as_slice()) {
+            (Method::Post, ["transactions"]) => self.post_transactions(&mut request, version),
+            (Method::Get, ["protocol"]) => (
+                200,
+                json!({ "version": PROTOCOL_VERSION, "features": protocol_features() }),
+            ),
+            (Method::Get, ["accounts"]) => (200, self.get_accounts()),
+            (Method::Get, ["submitters"]) => (200, self.get_submitters()),
+            (Method::Get, ["accounts", client]) => match client.parse::<ClientId>() {
+                Ok(client) => self.get_account(client),
+                Err(_) => (400, error(format!("Invalid client id {:?}", client))),
+            },
+            (Method::Get, ["accounts", client, "transactions"]) => {
+                match client.parse::<ClientId>() {
+                    Ok(client) => self.get_history(client, &query),
+                    Err(_) => (400, error(format!("Invalid client id {:?}", client))),
+                }
+            }
+            (
+                _,
+                ["transactions" | "accounts" | "metrics" | "protocol" | "state" | "submitters"]
+                | ["accounts", _]
+                | ["accounts", _, "transactions"],
+            ) => (405, error("Method not allowed".into())),
+            _ => (404, error("Not found".into())),
+        };
+        respond_json(request, status, body)
+    }
+    /// Apply the transactions in the body of a request
+    ///
+    /// The whole body is parsed before any transaction is applied, so a request over the
+    /// limits is rejected without applying any.
+    fn post_transactions(&self, request: &mut Request, version: u32) -> (u16, Value) {
+        let source = self.next_source();
+        let limits = self.limits();
+        let mut body = String::new();
+        // Reading stops past the size limit, so a long body is never held whole
+        let limit = limits
+            .max_request_size
+            .map_or(u64::MAX, |max| max as u64 + 1);
+        if let Err(e) = request.as_reader().take(limit).read_to_string(&mut body) {
+            return (400, error(format!("Error reading request: {}", e)));
+        }
+        if let Some(max) = limits.max_request_size.filter(|&max| body.len() > max) {
+            return (413, error(LimitExceeded::RequestSize(max).to_string()));
+        }
+        let mut parser = Parser::new(None);
+        let mut records: Vec<_> = (body.lines())
+            .filter_map(|line| parser.parse_line(line.into()))
+            .collect();
+        records.extend(parser.finish().map(Err));
+        if let Some(max) = limits.max_in_flight.filter(|&max| records.len() > max) {
+            return (413, error(LimitExceeded::InFlight(max).to_string()));
+        }
+        let results = records
+            .into_iter()
+            .map(|record| {
+                match (record.map_err(Rejected::from))
+                    .and_then(|record| self.transact(source, record.tx))
+                {
+                    Ok(()) => json!({ "status": "ok" }),
+                    Err(e) if version >= 2 => {
+                        json!({ "status": "error", "error": e.to_string(), "code": e.code() })
+                    }
+                    Err(e) => json!({ "status": "error", "error": e.to_string() }),
+                }
+            })
+            .collect();
+        (200, Value::Array(results))
+    }
+    /// Get every account
+    fn get_accounts(&self) -> Value {
+        self.with_accounts(|accounts| {
+            let places = accounts.precision().decimal_places() as usize;
+            accounts
+                .iter()
+                .map(|(client_id, account)| account_json(client_id, account, places, false))
+                .collect()
+        })
+    }
+    /// Get the clients submitting the most transactions
+    fn get_submitters(&self) -> Value {
+        self.top_submitters(TOP_SUBMITTERS)
+            .into_iter()
+            .map(|rate| {
+                json!({
+                    "client": rate.client,
+                    "per_minute": rate.per_minute,
+                    "total": rate.total,
+                })
+            })
+            .collect()
+    }
+    /// Get the account of one client
+    fn get_account(&self, client_id: ClientId) -> (u16, Value) {
+        self.with_accounts(|accounts| {
+            let places = accounts.precision().decimal_places() as usize;
+            match accounts.get(client_id) {
+                Some(account) => (200, account_json(client_id, account, places, false)),
+                None => (404, error(format!("Client {} has no account", client_id))),
+            }
+        })
+    }
+    /// Get a page of the history of one client
+    fn get_history(&self, client_id: ClientId, query: &str) -> (u16, Value) {
+        let mut after = None;
+        let mut limit = None;
+        for (name, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+            let parsed = match name {
+                "after" => value.parse().map(|value| after = Some(value)),
+                "limit" => value.parse().map(|value| limit = Some(value)),
+                _ => continue,
+            };
+            if parsed.is_err() {
+                return (400, error(format!("Invalid {} {:?}", name, value)));
+            }
+        }
+        let places = self.with_accounts(|accounts| accounts.precision().decimal_places() as usize);
+        match self.history(client_id, after, limit) {
+            Some(Ok(page)) => {
+                let transactions: Vec<Value> = (page.entries.iter())
+                    .map(|&(tx_id, change)| {
+                        json!({
+                            "tx": tx_id,
+                            "type": Transaction::Change { tx_id, change }.type_name(),
+                            "amount": format!("{:.*}", places, change.amount),
+                            "timestamp": change.timestamp,
+                        })
+                    })
+                    .collect();
+                let body = json!({ "transactions": transactions, "next_after": page.next_after });
+                (200, body)
+            }
+            Some(Err(e)) => (500, error(e)),
+            None => (404, error(format!("Client {} has no account", client_id))),
+        }
+    }
+}
+
+/// Accept HTTP requests for metrics alone
+///
+/// `GET /metrics` gets the metrics in the Prometheus text format, and every other request
+/// gets `404`. This only returns if the listener cannot be used.
+pub fn serve_metrics(metrics: &Metrics, listener: TcpListener) -> io::Result<()> {
+    let http = tiny_http::Server::from_listener(listener, None).map_err(io::Error::other)?;
+    for request in http.incoming_requests() {
+        let result = match (request.method(), request.url().split('?').next()) {
+            (Method::Get, Some("/metrics")) => respond_metrics(request, metrics.render()),
+            _ => request.respond(Response::empty(404)),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to respond to metrics request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Send a JSON response to a request
+fn respond_json(request: Request, status: u16, body: Value) -> io::Result<()> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    request.respond(
+        Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(content_type),
+    )
+}
+
+/// Get the version of the protocol to speak to a request
+///
+/// This is the older of [`PROTOCOL_VERSION`] and the version in the request's
+/// `Transactor-Protocol` header, or 1 if it has none.
+fn protocol_version(request: &Request) -> Result<u32, String> {
+    let Some(header) =
+        (request.headers().iter()).find(|header| header.field.equiv(PROTOCOL_HEADER))
+    else {
+        return Ok(1);
+    };
+    let version = header.value.as_str().trim();
+    (version.parse::<u32>())
+        .ok()
+        .filter(|&version| version > 0)
+        .map(|version| version.min(PROTOCOL_VERSION))
+        .ok_or_else(|| format!("Invalid protocol version {:?}", version))
+}
+
+/// Send rendered metrics in response to a request
+fn respond_metrics(request: Request, metrics: String) -> io::Result<()> {
+    let content_type = Header::from_bytes("Content-Type", metrics::CONTENT_TYPE).unwrap();
+    request.respond(Response::from_string(metrics).with_header(content_type))
+}
+
+/// Get the body of an error response
+fn error(message: String) -> Value {
+    json!({ "error": message })
+}
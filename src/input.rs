@@ -0,0 +1,698 @@
+//! Reading transactions from input
+//!
+//! Two input formats are supported, see [`InputFormat`].
+//!
+//! CSV input may start with a header row, in which case columns are mapped by name and may
+//! appear in any order. Without a header, columns are expected in the order
+//! `type, client, tx, amount, to, timestamp`. Fields may be quoted, and surrounding whitespace is
+//! ignored. The `to` and `timestamp` columns are optional, and a timestamp is a whole number of
+//! seconds since the Unix epoch.
+//!
+//! JSON Lines input has one object per line, with the same field names as the CSV header,
+//! such as `{"type":"deposit","client":1,"tx":5,"amount":"3.21"}`.
+//!
+//! With [`records_with_clients`], the `client` and `to` fields are external customer
+//! references that are translated to client ids through a [`ClientMap`].
+
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    str::FromStr,
+};
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    account::TransactionError,
+    client_map::ClientMap,
+    transaction::{ClientTransaction, TransactionParseError},
+};
+
+/// A transaction parsed from a record of input
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// The 1-based line number the transaction was on
+    pub line_no: usize,
+    /// The line the transaction was parsed from
+    ///
+    /// If a quoted field spans multiple lines, this contains all of them.
+    pub line: String,
+    /// The parsed transaction
+    pub tx: ClientTransaction,
+}
+
+/// An error reading or processing input, with where in the input it happened
+///
+/// Errors reading and parsing input carry the line they are on, so diagnostics can point at
+/// the offending record. Errors raised by callbacks to stop processing may not be on a line.
+#[derive(Debug)]
+pub struct ProcessError {
+    /// The 1-based line number of the record the error is on
+    ///
+    /// If a quoted field spans multiple lines, this is the first of them.
+    pub line_no: Option<usize>,
+    /// The offset in bytes of the start of the line in the input
+    pub offset: Option<u64>,
+    /// The raw line of input the error is on, if it was read
+    pub line: Option<String>,
+    /// What went wrong
+    pub kind: ProcessErrorKind,
+}
+
+/// A kind of [`ProcessError`]
+#[derive(Debug, Error)]
+pub enum ProcessErrorKind {
+    /// The input could not be read
+    #[error(transparent)]
+    Read(io::Error),
+    /// The header row is missing a required column
+    #[error("{0}")]
+    Header(String),
+    /// A record is not valid CSV or JSON
+    #[error("{0}")]
+    Record(String),
+    /// A client reference could not be translated to a client id
+    #[error("{0}")]
+    Client(String),
+    /// A record is not a valid transaction
+    #[error(transparent)]
+    Parse(TransactionParseError),
+    /// A quoted field was still open at the end of the input
+    #[error("Unclosed quote")]
+    UnclosedQuote,
+    /// A transaction was rejected, and the callback stopped processing because of it
+    #[error(transparent)]
+    Rejected(TransactionError),
+    /// Processing was stopped for some other reason
+    #[error("{0}")]
+    Other(String),
+}
+
+impl ProcessErrorKind {
+    /// Get a short, machine-readable code for the kind of error, such as `invalid_amount`
+    ///
+    /// Parse errors and rejected transactions have the codes of their errors.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessErrorKind::Read(_) => "read_error",
+            ProcessErrorKind::Header(_) => "invalid_header",
+            ProcessErrorKind::Record(_) => "invalid_record",
+            ProcessErrorKind::Client(_) => "invalid_client_reference",
+            ProcessErrorKind::Parse(e) => e.code(),
+            ProcessErrorKind::UnclosedQuote => "unclosed_quote",
+            ProcessErrorKind::Rejected(e) => e.code(),
+            ProcessErrorKind::Other(_) => "other",
+        }
+    }
+}
+
+impl ProcessError {
+    /// Create an error for a rejected transaction, to stop processing at it
+    pub fn rejected(record: &Record, error: TransactionError) -> Self {
+        ProcessError {
+            line_no: Some(record.line_no),
+            offset: None,
+            line: Some(record.line.clone()),
+            kind: ProcessErrorKind::Rejected(error),
+        }
+    }
+    /// Create an error on a line of input
+    fn on_line(line_no: usize, offset: u64, line: Option<String>, kind: ProcessErrorKind) -> Self {
+        ProcessError {
+            line_no: Some(line_no),
+            offset: Some(offset),
+            line,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(line_no) = self.line_no else {
+            return write!(f, "{}", self.kind);
+        };
+        match &self.kind {
+            ProcessErrorKind::Read(e) => write!(f, "Error reading line {}: {}", line_no, e),
+            ProcessErrorKind::Header(e) => write!(f, "Invalid header on line {}: {}", line_no, e),
+            ProcessErrorKind::Record(e) => write!(f, "Invalid record on line {}: {}", line_no, e),
+            ProcessErrorKind::UnclosedQuote => write!(f, "Unclosed quote on line {}", line_no),
+            ProcessErrorKind::Rejected(e) => {
+                write!(f, "Error executing transaction on line {}: {}", line_no, e)
+            }
+            ProcessErrorKind::Client(_)
+            | ProcessErrorKind::Parse(_)
+            | ProcessErrorKind::Other(_) => {
+                write!(f, "Invalid transaction on line {}: {}", line_no, self.kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ProcessErrorKind::Read(e) => Some(e),
+            ProcessErrorKind::Parse(e) => Some(e),
+            ProcessErrorKind::Rejected(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Errors raised by callbacks are not on a line of input
+impl From<String> for ProcessError {
+    fn from(message: String) -> Self {
+        ProcessError {
+            line_no: None,
+            offset: None,
+            line: None,
+            kind: ProcessErrorKind::Other(message),
+        }
+    }
+}
+
+impl From<ProcessError> for String {
+    fn from(error: ProcessError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A format of transaction input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Comma-separated values, optionally with a header row
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl InputFormat {
+    /// Detect the format of input from its first non-empty line
+    fn detect(line: &str) -> Self {
+        if line.trim_start().starts_with('{') {
+            InputFormat::JsonLines
+        } else {
+            InputFormat::Csv
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" => Ok(InputFormat::JsonLines),
+            _ => Err(format!("Unknown input format {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputFormat::Csv => write!(f, "csv"),
+            InputFormat::JsonLines => write!(f, "jsonl"),
+        }
+    }
+}
+
+/// The position of each field within a record
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Columns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    to: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+impl Columns {
+    /// The columns of input without a header row
+    const DEFAULT: Columns = Columns {
+        tx_type: 0,
+        client: 1,
+        tx: 2,
+        amount: Some(3),
+        to: Some(4),
+        timestamp: Some(5),
+    };
+    /// Map columns from a header row, or return `None` if the record is not a header
+    pub(crate) fn from_header(record: &StringRecord) -> Result<Option<Self>, String> {
+        let position = |name: &str| {
+            record
+                .iter()
+                .position(|field| field.eq_ignore_ascii_case(name))
+        };
+        let Some(tx_type) = position("type") else {
+            return Ok(None);
+        };
+        let required =
+            |name| position(name).ok_or(format!("Header is missing a {:?} column", name));
+        Ok(Some(Columns {
+            tx_type,
+            client: required("client")?,
+            tx: required("tx")?,
+            amount: position("amount"),
+            to: position("to"),
+            timestamp: position("timestamp"),
+        }))
+    }
+    /// Parse a transaction from a record, translating client references if there is a map
+    pub(crate) fn parse(
+        &self,
+        record: &StringRecord,
+        mut clients: Option<&mut ClientMap>,
+    ) -> Result<ClientTransaction, ProcessErrorKind> {
+        let client = map_client(clients.as_deref_mut(), record.get(self.client))?;
+        let to = self
+            .to
+            .and_then(|i| record.get(i))
+            .filter(|s| !s.is_empty());
+        let to = map_client(clients, to)?;
+        ClientTransaction::from_fields(
+            record.get(self.tx_type),
+            client.as_deref(),
+            record.get(self.tx),
+            self.amount
+                .and_then(|i| record.get(i))
+                .filter(|s| !s.is_empty()),
+            to.as_deref(),
+        )
+        .and_then(|tx| tx.with_timestamp(self.timestamp.and_then(|i| record.get(i))))
+        .map_err(ProcessErrorKind::Parse)
+    }
+}
+
+/// Translate a client reference to a client id if there is a map
+fn map_client<'a>(
+    clients: Option<&mut ClientMap>,
+    field: Option<&'a str>,
+) -> Result<Option<Cow<'a, str>>, ProcessErrorKind> {
+    match (clients, field) {
+        (Some(clients), Some(reference)) => Ok(Some(
+            clients
+                .client(reference)
+                .map_err(ProcessErrorKind::Client)?
+                .to_string()
+                .into(),
+        )),
+        (_, field) => Ok(field.map(Cow::Borrowed)),
+    }
+}
+
+/// Split a line of input into its fields
+///
+/// Lines without quotes are split directly, which gives the same fields the CSV parser
+/// would, since only quotes change how it treats commas. Lines with quotes are handed to
+/// the CSV parser.
+fn split_fields(line: &str) -> Result<StringRecord, String> {
+    if !line.contains('"') {
+        return Ok(line.split(',').map(str::trim).collect());
+    }
+    let mut record = StringRecord::new();
+    ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(line.as_bytes())
+        .read_record(&mut record)
+        .map_err(|e| e.to_string())?;
+    Ok(record)
+}
+
+/// Check if a record ends inside a quoted field, so it continues on the next line
+///
+/// This follows the rules of the CSV parser in [`split_fields`]: a quote only opens a
+/// quoted field at the start of a field, `""` inside a quoted field is an escaped quote,
+/// and any other quote is part of the field's text.
+fn has_open_quote(record: &str) -> bool {
+    let mut chars = record.chars().peekable();
+    let mut field_start = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field_start => loop {
+                match chars.next() {
+                    None => return true,
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                    }
+                    Some('"') => break,
+                    Some(_) => {}
+                }
+            },
+            ',' => {
+                field_start = true;
+                continue;
+            }
+            _ => {}
+        }
+        field_start = false;
+    }
+    false
+}
+
+/// Parse a transaction from a line of JSON
+fn parse_json(
+    line: &str,
+    mut clients: Option<&mut ClientMap>,
+) -> Result<ClientTransaction, ProcessErrorKind> {
+    let object: Map<String, Value> =
+        serde_json::from_str(line).map_err(|e| ProcessErrorKind::Record(e.to_string()))?;
+    // Fields may be given as strings or numbers
+    let field = |name: &str| match object.get(name) {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(value) => Some(value.to_string()),
+    };
+    let client = field("client");
+    let client = map_client(clients.as_deref_mut(), client.as_deref())?;
+    let to = field("to");
+    let to = map_client(clients, to.as_deref())?;
+    ClientTransaction::from_fields(
+        field("type").as_deref(),
+        client.as_deref(),
+        field("tx").as_deref(),
+        field("amount").as_deref(),
+        to.as_deref(),
+    )
+    .and_then(|tx| tx.with_timestamp(field("timestamp").as_deref()))
+    .map_err(ProcessErrorKind::Parse)
+}
+
+/// The state of parsing input line by line
+///
+/// This is shared by [`records`] and the async readers of the `async` feature.
+#[derive(Debug, Default)]
+pub(crate) struct Parser {
+    line_no: usize,
+    /// The offset in bytes of the next line
+    offset: u64,
+    format: Option<InputFormat>,
+    columns: Option<Columns>,
+    /// A record with an unclosed quote, and the line number and offset it started at
+    pending: Option<(usize, u64, String)>,
+}
+
+impl Parser {
+    /// Create a parser for input in a specific format, or detect it from the first line
+    pub(crate) fn new(format: Option<InputFormat>) -> Self {
+        Parser {
+            format,
+            ..Parser::default()
+        }
+    }
+    /// Parse the next line of input
+    ///
+    /// Returns `None` if the line does not complete a transaction, such as an empty line,
+    /// the header row, or part of a quoted field that spans multiple lines. Lines are assumed
+    /// to have ended with a single `\n`.
+    pub(crate) fn parse_line(&mut self, line: String) -> Option<Result<Record, ProcessError>> {
+        let len = line.len() + 1;
+        self.parse_line_with(line, len, None)
+    }
+    /// Count a line of input that is not part of any record, such as a protocol frame
+    pub(crate) fn skip_line(&mut self, line: &str) {
+        self.line_no += 1;
+        self.offset += line.len() as u64 + 1;
+    }
+    /// Parse the next line of input, which was `len` bytes long with its line ending,
+    /// translating client references if there is a map
+    fn parse_line_with(
+        &mut self,
+        line: String,
+        len: usize,
+        clients: Option<&mut ClientMap>,
+    ) -> Option<Result<Record, ProcessError>> {
+        self.line_no += 1;
+        let offset = self.offset;
+        self.offset += len as u64;
+        let (line_no, offset, line) = match self.pending.take() {
+            // Quoted fields may contain newlines, so keep reading until all quotes are closed
+            Some((line_no, offset, mut pending)) => {
+                pending.push('\n');
+                pending.push_str(&line);
+                (line_no, offset, pending)
+            }
+            // Skip empty lines
+            None if line.trim().is_empty() => return None,
+            None => (self.line_no, offset, line),
+        };
+        let error = |line: String, kind| ProcessError::on_line(line_no, offset, Some(line), kind);
+        // Detect the format from the first line if it was not given
+        let format = *self
+            .format
+            .get_or_insert_with(|| InputFormat::detect(&line));
+        if format == InputFormat::JsonLines {
+            return Some(match parse_json(&line, clients) {
+                Ok(tx) => Ok(Record { line_no, line, tx }),
+                Err(kind) => Err(error(line, kind)),
+            });
+        }
+        if has_open_quote(&line) {
+            self.pending = Some((line_no, offset, line));
+            return None;
+        }
+        let record = match split_fields(&line) {
+            Ok(record) => record,
+            Err(e) => return Some(Err(error(line, ProcessErrorKind::Record(e)))),
+        };
+        // The first record may be a header
+        let columns = match self.columns {
+            Some(columns) => columns,
+            None => match Columns::from_header(&record) {
+                Ok(Some(header)) => {
+                    self.columns = Some(header);
+                    return None;
+                }
+                Ok(None) => *self.columns.insert(Columns::DEFAULT),
+                Err(e) => return Some(Err(error(line, ProcessErrorKind::Header(e)))),
+            },
+        };
+        // Parse transaction
+        Some(match columns.parse(&record, clients) {
+            Ok(tx) => Ok(Record { line_no, line, tx }),
+            Err(kind) => Err(error(line, kind)),
+        })
+    }
+    /// Get the length in bytes of a record with an unclosed quote that is waiting for more lines
+    pub(crate) fn pending_len(&self) -> usize {
+        self.pending
+            .as_ref()
+            .map_or(0, |(_, _, record)| record.len())
+    }
+    /// Drop a record with an unclosed quote, so the next line starts a new record
+    pub(crate) fn discard_pending(&mut self) {
+        self.pending = None;
+    }
+    /// Describe an error reading the next line of input
+    pub(crate) fn read_error(&self, error: io::Error) -> ProcessError {
+        ProcessError::on_line(
+            self.line_no + 1,
+            self.offset,
+            None,
+            ProcessErrorKind::Read(error),
+        )
+    }
+    /// Finish parsing, returning an error if a quoted field was never closed
+    pub(crate) fn finish(&mut self) -> Option<ProcessError> {
+        let (line_no, offset, line) = self.pending.take()?;
+        Some(ProcessError::on_line(
+            line_no,
+            offset,
+            Some(line),
+            ProcessErrorKind::UnclosedQuote,
+        ))
+    }
+}
+
+/// An iterator over the transactions parsed from a reader
+///
+/// See [`records`]
+struct Records<'a, R> {
+    reader: BufReader<R>,
+    parser: Parser,
+    clients: Option<&'a mut ClientMap>,
+}
+
+impl<'a, R: Read> Records<'a, R> {
+    fn new(source: R, format: Option<InputFormat>, clients: Option<&'a mut ClientMap>) -> Self {
+        Records {
+            reader: BufReader::new(source),
+            parser: Parser::new(format),
+            clients,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Records<'_, R> {
+    type Item = Result<Record, ProcessError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Lines are read whole so that the offset of each one is known
+            let mut line = String::new();
+            let len = match self.reader.read_line(&mut line) {
+                Ok(0) => return self.parser.finish().map(Err),
+                Ok(len) => len,
+                Err(e) => return Some(Err(self.parser.read_error(e))),
+            };
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            if let Some(record) =
+                self.parser
+                    .parse_line_with(line, len, self.clients.as_deref_mut())
+            {
+                return Some(record);
+            }
+        }
+    }
+}
+
+/// Iterate over the transactions parsed from a reader
+///
+/// The format is detected from the first non-empty line. Empty lines and the header row
+/// are skipped. Errors are reported with the line they are on.
+pub fn records<R>(source: R) -> impl Iterator<Item = Result<Record, ProcessError>>
+where
+    R: Read,
+{
+    Records::new(source, None, None)
+}
+
+/// Iterate over the transactions parsed from a reader in a specific format
+///
+/// See [`records`]
+pub fn records_with_format<R>(
+    source: R,
+    format: InputFormat,
+) -> impl Iterator<Item = Result<Record, ProcessError>>
+where
+    R: Read,
+{
+    Records::new(source, Some(format), None)
+}
+
+/// Iterate over the transactions parsed from a reader, with client references
+///
+/// The `client` and `to` fields are references such as `"ACME-042"` rather than client ids.
+/// Each is translated through the map, and references that are not yet mapped are assigned
+/// the next free client id. See [`records`].
+pub fn records_with_clients<'a, R>(
+    source: R,
+    clients: &'a mut ClientMap,
+) -> impl Iterator<Item = Result<Record, ProcessError>> + 'a
+where
+    R: Read + 'a,
+{
+    Records::new(source, None, Some(clients))
+}
+
+/// The first bytes of a gzip stream
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// The first bytes of a Zstandard frame
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wrap a reader so that gzip or Zstandard input is decompressed as it is read
+///
+/// Compression is detected from the first bytes of the input, so this also works for
+/// standard input. Other input is read as it is. Decompression requires the `compression`
+/// feature.
+pub fn decompressed<'a, R>(source: R) -> io::Result<Box<dyn Read + 'a>>
+where
+    R: Read + 'a,
+{
+    let mut source = BufReader::new(source);
+    let start = source.fill_buf()?;
+    let format = if start.starts_with(GZIP_MAGIC) {
+        "gzip"
+    } else if start.starts_with(ZSTD_MAGIC) {
+        "zstd"
+    } else {
+        return Ok(Box::new(source));
+    };
+    #[cfg(feature = "compression")]
+    {
+        Ok(match format {
+            "gzip" => Box::new(flate2::bufread::MultiGzDecoder::new(source)),
+            _ => Box::new(zstd::Decoder::with_buffer(source)?),
+        })
+    }
+    #[cfg(not(feature = "compression"))]
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Input is compressed with {}, but transactor was built without the `compression` \
+             feature",
+            format
+        ),
+    ))
+}
+
+/// A reader that checks the SHA-256 digest of everything read through it
+///
+/// When the end of the input is reached, an error of kind [`io::ErrorKind::InvalidData`] is
+/// returned instead if the digest is not the expected one. Since that is only known at the
+/// end, input should be read through to the end before any of it is processed, as
+/// [`source::open_raw`](crate::source::open_raw) does.
+pub struct Sha256Reader<R> {
+    inner: R,
+    hasher: Sha256,
+    /// The expected digest as lowercase hexadecimal
+    expected: String,
+    checked: bool,
+}
+
+impl<R: Read> Sha256Reader<R> {
+    /// Wrap a reader with the digest it is expected to have, as 64 hexadecimal digits
+    pub fn new(inner: R, expected: &str) -> Result<Self, String> {
+        if expected.len() != 64 || !expected.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!(
+                "Invalid SHA-256 digest {:?}, expected 64 hexadecimal digits",
+                expected
+            ));
+        }
+        Ok(Sha256Reader {
+            inner,
+            hasher: Sha256::new(),
+            expected: expected.to_ascii_lowercase(),
+            checked: false,
+        })
+    }
+}
+
+impl<R: Read> Read for Sha256Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        if read == 0 && !buf.is_empty() && !self.checked {
+            self.checked = true;
+            let digest: String = self
+                .hasher
+                .finalize_reset()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            if digest != self.expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "SHA-256 digest is {}, but {} was expected",
+                        digest, self.expected
+                    ),
+                ));
+            }
+        }
+        Ok(read)
+    }
+}
@@ -0,0 +1,126 @@
+//! Consuming transactions from Kafka
+//!
+//! This module is enabled by the `kafka` feature. Each message of a topic holds one or more
+//! lines of transaction input in either format, see [`records`](crate::records). Every
+//! message is parsed on its own, so it may start with its own header row, and line numbers
+//! count from the start of the message.
+//!
+//! Offsets are only committed after the accounts have been persisted, so after a crash the
+//! messages since the last commit are consumed again.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use rdkafka::{
+    consumer::{BaseConsumer, CommitMode, Consumer},
+    ClientConfig, Message,
+};
+
+use crate::{input::Parser, Accounts, Record, TransactionError};
+
+/// A consumer of transactions from a Kafka topic
+pub struct KafkaSource {
+    consumer: BaseConsumer,
+    /// Whether messages have been consumed since offsets were last committed
+    uncommitted: bool,
+}
+
+impl KafkaSource {
+    /// Connect to Kafka brokers and subscribe to a topic as part of a consumer group
+    ///
+    /// `brokers` is a comma-separated list of `host:port` pairs. A group that has not committed
+    /// offsets before starts from the beginning of the topic.
+    pub fn connect(brokers: &str, topic: &str, group: &str) -> io::Result<Self> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .map_err(io::Error::other)?;
+        consumer.subscribe(&[topic]).map_err(io::Error::other)?;
+        Ok(KafkaSource {
+            consumer,
+            uncommitted: false,
+        })
+    }
+    /// Wait up to `timeout` for the next message and parse its transactions
+    fn poll(&mut self, timeout: Duration) -> Result<Option<Vec<Record>>, String> {
+        let Some(message) = self.consumer.poll(timeout) else {
+            return Ok(None);
+        };
+        let message = message.map_err(|e| format!("Error consuming from Kafka: {}", e))?;
+        self.uncommitted = true;
+        let records = parse_message(message.payload().unwrap_or_default()).map_err(|e| {
+            format!(
+                "Error in message {} of partition {}: {}",
+                message.offset(),
+                message.partition(),
+                e
+            )
+        })?;
+        Ok(Some(records))
+    }
+    /// Commit the offsets of every message consumed so far
+    fn commit(&mut self) -> io::Result<()> {
+        if self.uncommitted {
+            self.consumer
+                .commit_consumer_state(CommitMode::Sync)
+                .map_err(io::Error::other)?;
+            self.uncommitted = false;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the transactions in the payload of a message
+pub(crate) fn parse_message(payload: &[u8]) -> Result<Vec<Record>, String> {
+    let payload = std::str::from_utf8(payload).map_err(|e| e.to_string())?;
+    let mut parser = Parser::new(None);
+    let mut records = Vec::new();
+    for line in payload.lines() {
+        if let Some(record) = parser.parse_line(line.into()) {
+            records.push(record?);
+        }
+    }
+    match parser.finish() {
+        Some(e) => Err(e.into()),
+        None => Ok(records),
+    }
+}
+
+/// Apply transactions consumed from Kafka to accounts, passing the result of each one to a
+/// callback
+///
+/// Every `interval`, `on_interval` is called with the accounts so that they can be emitted or
+/// persisted. Once it succeeds, the offsets of the consumed messages are committed.
+///
+/// This only returns if consuming, parsing or `on_interval` fails.
+pub fn process_kafka_source_with<F, G>(
+    source: &mut KafkaSource,
+    accounts: &mut Accounts,
+    interval: Duration,
+    mut on_executed: F,
+    mut on_interval: G,
+) -> Result<(), String>
+where
+    F: FnMut(&Record, Result<(), TransactionError>, &Accounts),
+    G: FnMut(&Accounts) -> Result<(), String>,
+{
+    let _span = tracing::info_span!("process_kafka").entered();
+    loop {
+        let deadline = Instant::now() + interval;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            for record in source.poll(timeout)?.into_iter().flatten() {
+                let result = accounts.transact(record.tx.clone());
+                on_executed(&record, result, accounts);
+            }
+        }
+        on_interval(accounts)?;
+        source
+            .commit()
+            .map_err(|e| format!("Error committing Kafka offsets: {}", e))?;
+    }
+}
@@ -0,0 +1,82 @@
+//! A neutral interchange type for transactions
+//!
+//! A [`LedgerEntry`] describes a transaction without depending on how the engine represents
+//! it, so integrations can produce and consume entries without tracking changes to
+//! [`Transaction`]. Entries convert to [`ClientTransaction`]s with the same validation as
+//! input, and every [`ClientTransaction`] converts to an entry.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    amount::Amount,
+    transaction::{ClientId, ClientTransaction, Transaction, TransactionId, TransactionParseError},
+};
+
+/// A transaction in a form meant for exchange with other systems
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LedgerEntry {
+    /// The client that submitted the transaction
+    pub client: ClientId,
+    /// The id of the transaction, or of the transaction it refers to
+    pub tx: TransactionId,
+    /// The type of the transaction as it appears in input, such as `deposit`
+    pub kind: String,
+    /// The amount of a deposit, withdrawal, or transfer
+    pub amount: Option<Amount>,
+    /// The client receiving a transfer
+    pub to: Option<ClientId>,
+    /// The currency of the amount, such as `USD`
+    ///
+    /// The engine keeps a single currency, so this is not checked when converting to a
+    /// [`ClientTransaction`].
+    pub currency: Option<String>,
+    /// When the transaction happened, in seconds since the Unix epoch
+    pub timestamp: Option<u64>,
+    /// Any other data the producer of the entry attaches to it
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl From<ClientTransaction> for LedgerEntry {
+    fn from(client_tx: ClientTransaction) -> Self {
+        let to = match client_tx.tx {
+            Transaction::Transfer { to, .. } => Some(to),
+            _ => None,
+        };
+        LedgerEntry {
+            client: client_tx.client,
+            tx: client_tx.tx.tx_id(),
+            kind: client_tx.tx.type_name().into(),
+            amount: client_tx.tx.amount(),
+            to,
+            currency: None,
+            timestamp: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+/// The currency, timestamp, and metadata of the entry are dropped
+impl TryFrom<LedgerEntry> for ClientTransaction {
+    type Error = TransactionParseError;
+    fn try_from(entry: LedgerEntry) -> Result<Self, Self::Error> {
+        let (client, tx_id) = (entry.client, entry.tx);
+        let amount = || entry.amount.ok_or(TransactionParseError::MissingAmount);
+        let to = || entry.to.ok_or(TransactionParseError::MissingDestination);
+        let partial = |tx: ClientTransaction| match entry.amount {
+            Some(amount) => tx.partial(amount),
+            None => Ok(tx),
+        };
+        match entry.kind.trim() {
+            "deposit" => ClientTransaction::deposit(client, tx_id).amount_value(amount()?),
+            "withdrawal" => ClientTransaction::withdrawal(client, tx_id).amount_value(amount()?),
+            "fee" => ClientTransaction::fee(client, tx_id).amount_value(amount()?),
+            "transfer" => ClientTransaction::transfer(client, tx_id, to()?).amount_value(amount()?),
+            "dispute" => partial(ClientTransaction::dispute(client, tx_id)),
+            "resolve" => Ok(ClientTransaction::resolve(client, tx_id)),
+            "chargeback" => partial(ClientTransaction::chargeback(client, tx_id)),
+            "unfreeze" | "unlock" => Ok(ClientTransaction::unfreeze(client, tx_id)),
+            kind => Err(TransactionParseError::InvalidTransactionType(kind.into())),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! A simple transaction engine
+//!
+//! Transactions are applied to client [`Account`]s held in [`Accounts`].
+//! They can be constructed directly or parsed from CSV input with [`process_transaction_source`].
+//! With the `async` feature, transactions can also be applied from async readers and streams,
+//! see the `stream` module. With the `kafka` feature, they can be consumed from a Kafka topic,
+//! see the `kafka` module. With the `parquet` feature, they can be read from Parquet files,
+//! see the `parquet` module.
+
+pub mod account;
+pub mod accrual;
+pub mod admin;
+pub mod alert;
+pub mod amount;
+pub mod archive;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod audit;
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(any(feature = "arrow", feature = "parquet"))]
+pub mod batches;
+pub mod cancel;
+pub mod client_map;
+pub mod clock;
+pub mod control;
+pub mod demo;
+pub mod diff;
+#[cfg(feature = "download")]
+pub mod download;
+pub mod duplicates;
+pub mod estimate;
+pub mod events;
+pub mod filter;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod history;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod input;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod ledger;
+pub mod logging;
+pub mod metrics;
+pub mod notify;
+pub mod output;
+pub mod parallel;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod priority;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+pub mod progress;
+pub mod query;
+pub mod reconcile;
+pub mod record;
+pub mod rejects;
+pub mod run;
+pub mod schema;
+pub mod server;
+pub mod service;
+pub mod settings;
+pub mod snapshot;
+pub mod source;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod statement;
+pub mod store;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod summary;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+#[cfg(test)]
+mod test;
+pub mod transaction;
+pub mod trend;
+pub mod vectors;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+use std::io::Read;
+
+pub use account::{
+    Account, Accounts, Activity, DisputeLimits, Note, OverdraftLimits, SettlementPeriod, Simulated,
+    TransactionError, WithdrawalRetries,
+};
+pub use amount::{Amount, Precision};
+pub use cancel::{CancellationToken, Processed};
+pub use input::{records, ProcessError, ProcessErrorKind, Record};
+pub use ledger::LedgerEntry;
+pub use parallel::process_transaction_source_parallel;
+#[cfg(feature = "async")]
+pub use stream::{
+    process_async_transaction_source, process_async_transaction_source_with,
+    process_transaction_stream,
+};
+pub use summary::ProcessingSummary;
+pub use transaction::{ClientTransaction, Transaction, TransactionParseError};
+
+/// The number of transactions grouped into each `apply_batch` tracing span
+const TRACING_BATCH_SIZE: usize = 10_000;
+
+/// Apply transactions parsed from a reader and apply each one to accounts
+///
+/// Returns a summary of the transactions that were applied and rejected, or the first error
+/// reading or parsing input, with the line it is on.
+pub fn process_transaction_source<R>(
+    source: R,
+    accounts: &mut Accounts,
+) -> Result<ProcessingSummary, ProcessError>
+where
+    R: Read,
+{
+    let mut summary = ProcessingSummary::default();
+    process_transaction_source_with(source, accounts, |record, result, _| {
+        summary.observe(&record.tx, &result);
+        if let Err(e) = result {
+            logging::rejected(record, &e);
+        }
+    })?;
+    Ok(summary)
+}
+
+/// Apply transactions parsed from a reader to accounts until the input ends or a token is
+/// cancelled
+///
+/// The token is checked before each transaction, so a cancelled job returns normally with a
+/// summary of the transactions applied so far and the line it stopped after. Errors reading or
+/// parsing input are still returned as errors.
+pub fn process_transaction_source_with_cancel<R>(
+    source: R,
+    accounts: &mut Accounts,
+    token: &CancellationToken,
+) -> Result<Processed, ProcessError>
+where
+    R: Read,
+{
+    let mut processed = Processed::default();
+    let mut cancelled = false;
+    let records = records(source).take_while(|_| {
+        cancelled = token.is_cancelled();
+        !cancelled
+    });
+    try_process_records_with(records, accounts, |record, result, _| {
+        processed.summary.observe(&record.tx, &result);
+        processed.last_line = Some(record.line_no);
+        if let Err(e) = result {
+            logging::rejected(record, &e);
+        }
+        Ok(())
+    })?;
+    processed.cancelled = cancelled;
+    Ok(processed)
+}
+
+/// Apply transactions parsed from a reader to accounts, passing the result of each one to a callback
+///
+/// The callback also receives the accounts as they are after the transaction.
+pub fn process_transaction_source_with<R, F>(
+    source: R,
+    accounts: &mut Accounts,
+    mut on_executed: F,
+) -> Result<(), ProcessError>
+where
+    R: Read,
+    F: FnMut(&Record, Result<(), TransactionError>, &Accounts),
+{
+    try_process_transaction_source_with(source, accounts, |record, result, accounts| {
+        on_executed(record, result, accounts);
+        Ok(())
+    })
+}
+
+/// Apply transactions parsed from a reader to accounts, passing the result of each one to a
+/// callback that may stop processing
+///
+/// If the callback returns an error, no more transactions are applied and the error is returned.
+/// [`ProcessError::rejected`] creates an error to stop at a rejected transaction.
+pub fn try_process_transaction_source_with<R, F>(
+    source: R,
+    accounts: &mut Accounts,
+    on_executed: F,
+) -> Result<(), ProcessError>
+where
+    R: Read,
+    F: FnMut(&Record, Result<(), TransactionError>, &Accounts) -> Result<(), ProcessError>,
+{
+    try_process_records_with(records(source), accounts, on_executed)
+}
+
+/// Apply already parsed transactions to accounts, passing the result of each one to a callback
+/// that may stop processing
+///
+/// This is for input that is not read as lines, such as Parquet files with the `parquet`
+/// feature. Processing stops at the first error in `records`, or when the callback returns an
+/// error. The error type is that of `records`, such as [`ProcessError`] for [`records`] or
+/// `String` for the batch readers.
+pub fn try_process_records_with<I, E, F>(
+    records: I,
+    accounts: &mut Accounts,
+    mut on_executed: F,
+) -> Result<(), E>
+where
+    I: IntoIterator<Item = Result<Record, E>>,
+    F: FnMut(&Record, Result<(), TransactionError>, &Accounts) -> Result<(), E>,
+{
+    let _span = tracing::info_span!("process_source").entered();
+    let mut batch_span = None;
+    for (i, record) in records.into_iter().enumerate() {
+        // Break on I/O or parse error
+        let record = record?;
+
+        // Group transactions into batches for tracing
+        if i % TRACING_BATCH_SIZE == 0 {
+            // The previous batch must be exited before the next is entered
+            drop(batch_span.take());
+            batch_span = Some(
+                tracing::info_span!(
+                    "apply_batch",
+                    batch = i / TRACING_BATCH_SIZE,
+                    first_line = record.line_no
+                )
+                .entered(),
+            );
+        }
+
+        // Apply transaction
+        let _span = logging::transaction_span(&record).entered();
+        let result = accounts.transact(record.tx.clone());
+        on_executed(&record, result, accounts)?;
+    }
+    drop(batch_span);
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! Structured logging of tracing events
+//!
+//! Processing emits [`tracing`] events, such as for every rejected transaction, with fields
+//! for the line, client, and transaction they are about. At the `debug` level, every
+//! transaction is also in a span with those fields. [`init`] writes events to standard error,
+//! either as text or as JSON objects that log aggregators can correlate by their fields.
+
+use std::{
+    fmt,
+    io::{self, IsTerminal},
+    str::FromStr,
+};
+
+use tracing::Span;
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, registry::Registry, Layer};
+
+use crate::{
+    account::{Accounts, TransactionError},
+    input::Record,
+};
+
+/// A layer of a tracing subscriber, such as one that exports spans
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The format of log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// A line of text for each event, with its fields as `key=value` pairs
+    #[default]
+    Text,
+    /// A JSON object for each event, with a timestamp, its fields, and the spans it is in
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("Log format must be text or json".into()),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Get the `debug` span that a transaction is applied in
+pub(crate) fn transaction_span(record: &Record) -> Span {
+    tracing::debug_span!(
+        "transaction",
+        line = record.line_no,
+        client = record.tx.client,
+        tx = record.tx.tx.tx_id()
+    )
+}
+
+/// Log a transaction that was rejected
+///
+/// The event is a warning with the transaction's `line`, `client`, and `tx`, and the `reason`
+/// it was rejected as a [`TransactionError::code`].
+pub fn rejected(record: &Record, error: &TransactionError) {
+    tracing::warn!(
+        line = record.line_no,
+        client = record.tx.client,
+        tx = record.tx.tx.tx_id(),
+        reason = error.code(),
+        "Error executing transaction on line {}: {}",
+        record.line_no,
+        error
+    );
+}
+
+/// Log the parked withdrawals of some accounts that never succeeded
+pub fn unsettled_withdrawals(accounts: &Accounts) {
+    for (client, tx_id) in accounts.unsettled_withdrawals() {
+        tracing::warn!(
+            client,
+            tx = tx_id,
+            "Parked withdrawal {} of client {} was never settled",
+            tx_id,
+            client
+        );
+    }
+}
+
+/// Write events at or above a level to standard error, and install any other layers
+///
+/// Other layers, such as one that exports spans, see every span and event regardless of the
+/// level. This can only be called once.
+pub fn init(level: LevelFilter, format: LogFormat, others: Vec<BoxedLayer>) -> Result<(), String> {
+    let log: BoxedLayer = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .with_ansi(io::stderr().is_terminal())
+            .without_time()
+            .with_target(false)
+            .with_filter(level)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(io::stderr)
+            .with_filter(level)
+            .boxed(),
+    };
+    let mut layers = vec![log];
+    layers.extend(others);
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layers))
+        .map_err(|e| format!("Unable to install tracing subscriber: {}", e))
+}
@@ -1,83 +1,820 @@
-mod account;
-mod amount;
-#[cfg(test)]
-mod test;
-mod transaction;
+mod args;
 
 use std::{
-    env,
-    fs::File,
-    io::{BufRead, BufReader, Read},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
     process::exit,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use account::Accounts;
-use transaction::ClientTransaction;
+use tracing_subscriber::filter::LevelFilter;
+use transactor::{
+    accrual::{self, AccrualPolicy, AccrualSchedule},
+    admin::{self, AdminOp, AdminOpKind, Journal},
+    audit,
+    control::{self, Command},
+    demo, diff, estimate,
+    filter::{self, Filter},
+    history::HistorySpill,
+    logging::{self, LogFormat},
+    notify::{self, Notifier},
+    output::{self, Output, OutputFormat},
+    process_transaction_source, process_transaction_source_with,
+    reconcile::{self, DisputeLedger},
+    record::{self, Recorder},
+    run::{self, KafkaOptions, RunOptions},
+    schema,
+    server::{self, ConnectionLimits, Listeners, Server},
+    settings::Settings,
+    source::{self, InputOptions},
+    statement::Statement,
+    transaction::{ClientId, TRANSACTION_TYPES},
+    trend, vectors, Accounts, Amount, DisputeLimits, OverdraftLimits, SettlementPeriod,
+    WithdrawalRetries,
+};
+
+use args::Args;
+
+/// The address `demo` listens for TCP connections on if no address is given
+const DEFAULT_DEMO_ADDRESS: &str = "127.0.0.1:9000";
+
+/// The number of transactions `estimate` samples if no sample size is given
+const DEFAULT_ESTIMATE_SAMPLE: usize = 100_000;
 
 fn main() {
-    // Get the input file path
-    let input_path = if let Some(path) = env::args().nth(1) {
-        path
-    } else {
-        eprintln!("Expected input file path");
+    let mut args = Args::from_env();
+    // Spans are exported until the subcommand finishes
+    let result = init_logging(&mut args).and_then(|_telemetry| dispatch(args));
+    if let Err(e) = result {
+        eprintln!("{}", e);
         exit(1);
-    };
+    }
+}
 
-    // Open the input file
-    let input_file = match File::open(&input_path) {
-        Ok(file) => file,
-        Err(e) => {
-            eprintln!("Unable to open {:?}: {}", input_path, e);
-            return;
+/// Run the subcommand named by the first argument, or process transactions if there is none
+fn dispatch(mut args: Args) -> Result<(), String> {
+    if args.subcommand("split") {
+        split(args)
+    } else if args.subcommand("filter") {
+        filter(args)
+    } else if args.subcommand("extract") {
+        extract(args)
+    } else if args.subcommand("diff-state") {
+        diff_state(args)
+    } else if args.subcommand("demo") {
+        demo(args)
+    } else if args.subcommand("estimate") {
+        estimate(args)
+    } else if args.subcommand("replay") {
+        replay(args)
+    } else if args.subcommand("schema") {
+        schema(args)
+    } else if args.subcommand("serve") {
+        serve(args)
+    } else if args.subcommand("statement") {
+        statement(args)
+    } else if args.subcommand("admin") {
+        admin(args)
+    } else if args.subcommand("reconcile") {
+        reconcile(args)
+    } else if args.subcommand("trend") {
+        trend(args)
+    } else if args.subcommand("vectors") {
+        vectors(args)
+    } else if args.subcommand("verify-audit") {
+        verify_audit(args)
+    } else {
+        run(args)
+    }
+}
+
+/// Keeps spans being exported with `--otlp-endpoint` until dropped
+#[cfg(feature = "otel")]
+type Telemetry = Option<transactor::telemetry::TelemetryGuard>;
+#[cfg(not(feature = "otel"))]
+type Telemetry = ();
+
+/// Write log events as set by `--log-level` and `--log-format`, and export spans to
+/// `--otlp-endpoint`
+fn init_logging(args: &mut Args) -> Result<Telemetry, String> {
+    let level: LevelFilter = args.value("--log-level")?.unwrap_or(LevelFilter::INFO);
+    let format: LogFormat = args.value("--log-format")?.unwrap_or_default();
+    let otlp_endpoint: Option<String> = args.value("--otlp-endpoint")?;
+    #[cfg(feature = "otel")]
+    {
+        let (layers, telemetry) = match otlp_endpoint {
+            Some(endpoint) => {
+                let (layer, telemetry) = transactor::telemetry::init(&endpoint)?;
+                (vec![layer], Some(telemetry))
+            }
+            None => (Vec::new(), None),
+        };
+        logging::init(level, format, layers)?;
+        Ok(telemetry)
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        if otlp_endpoint.is_some() {
+            return Err(
+                "Unable to export traces: transactor was built without the `otel` feature".into(),
+            );
         }
+        logging::init(level, format, Vec::new())
+    }
+}
+
+/// Execute the transactions in a file and output the resulting account data
+fn run(mut args: Args) -> Result<(), String> {
+    let output = output(&mut args)?;
+    let mut settings = settings(&mut args)?;
+    settings.sandbox = args.flag("--sandbox");
+    settings.load_state = args.value("--load-state")?;
+    let accrual = accrual_policy(&mut args)?;
+    settings.accrual_schedule = accrual_schedule(&mut args, accrual)?;
+    let notify = Notifier {
+        webhook: args.value("--notify-webhook")?,
+        smtp: args.value("--notify-smtp")?,
+        from: args.value("--notify-from")?,
+        to: args.value("--notify-to")?,
+        event_version: {
+            let accepted: Vec<u32> = args.values("--notify-event-version")?;
+            notify::negotiate_event_version(&accepted).ok_or_else(|| {
+                format!(
+                    "None of the requested event versions are supported. Supported versions: {:?}",
+                    notify::EVENT_VERSIONS
+                )
+            })?
+        },
     };
 
-    // Initialize accounts
-    let mut accounts = Accounts::default();
+    let alerts = args.values("--alert")?;
+    let threads: Option<NonZeroUsize> = args.value("--threads")?;
+    let progress: Option<NonZeroU64> = args.value("--progress")?;
+    let strict = args.flag("--strict");
+    let input = InputOptions {
+        sequential: args.flag("--sequential-read"),
+        sha256: args.value("--expect-sha256")?,
+    };
+    let low_priority = args.flag("--low-priority");
+    let low_io_priority = args.flag("--low-io-priority");
+    let summary = args.flag("--summary");
+    let metrics: Option<String> = args.value("--metrics")?;
+    let profile_out: Option<PathBuf> = args.value("--profile-out")?;
+    let save_state: Option<PathBuf> = args.value("--save-state")?;
+    let store: Option<String> = args.value("--store")?;
+    let audit_log: Option<PathBuf> = args.value("--audit-log")?;
+    let rejects: Option<PathBuf> = args.value("--rejects")?;
+    let duplicates: Option<PathBuf> = args.value("--duplicates")?;
+    let balance_events: Option<PathBuf> = args.value("--balance-events")?;
+    let client_map: Option<PathBuf> = args.value("--client-map")?;
+    let kafka = kafka_options(&mut args)?;
 
-    // Process all transactions from file
-    if let Err(e) = process_transaction_source(input_file, &mut accounts) {
-        eprintln!("{}", e);
-        exit(1);
+    run::run(RunOptions {
+        settings,
+        output,
+        inputs: input_paths(args)?,
+        input,
+        threads,
+        progress,
+        strict,
+        low_priority,
+        low_io_priority,
+        summary,
+        metrics,
+        profile_out,
+        save_state,
+        store,
+        audit_log,
+        rejects,
+        duplicates,
+        balance_events,
+        client_map,
+        accrual,
+        kafka,
+        alerts,
+        notify,
+    })
+}
+
+/// Replay a recording of transactions and output the resulting account data
+fn replay(mut args: Args) -> Result<(), String> {
+    let output = output(&mut args)?;
+    let mut settings = settings(&mut args)?;
+    settings.sandbox = args.flag("--sandbox");
+    let mut accounts = settings.accounts()?;
+    record::replay(open_input(args)?, &mut accounts)?;
+    logging::unsettled_withdrawals(&accounts);
+    output.write(&accounts)
+}
+
+/// Check that an audit log was produced from exactly some input
+fn verify_audit(mut args: Args) -> Result<(), String> {
+    let mut settings = settings(&mut args)?;
+    settings.load_state = args.value("--load-state")?;
+    let mut accounts = settings.accounts()?;
+    let [log_path, input_path]: [String; 2] = args
+        .finish()?
+        .try_into()
+        .map_err(|_| "Expected an audit log and an input file")?;
+    let log = File::open(&log_path)
+        .map_err(|e| format!("Unable to open audit log {:?}: {}", log_path, e))?;
+    let entries = audit::verify(log, source::open(&input_path)?, &mut accounts)?.check()?;
+    eprintln!("Verified {} entries", entries);
+    Ok(())
+}
+
+/// Compare the accounts in two snapshots and write the fields that differ
+///
+/// Fails if any compared field differs, so it can gate a cutover.
+fn diff_state(mut args: Args) -> Result<(), String> {
+    let mut ignored: Vec<String> = args.values("--ignore")?;
+    if !args.flag("--no-default-ignores") {
+        ignored.extend(diff::DEFAULT_IGNORED.iter().map(|field| field.to_string()));
     }
+    let [left_path, right_path]: [String; 2] = args
+        .finish()?
+        .try_into()
+        .map_err(|_| "Expected two snapshot files")?;
+    let differences = diff::diff_snapshots(
+        source::open(&left_path)?,
+        source::open(&right_path)?,
+        &ignored,
+    )?;
+    if differences.is_empty() {
+        eprintln!("The snapshots match");
+        return Ok(());
+    }
+    diff::write_csv(&differences, io::stdout().lock())
+        .map_err(|e| format!("Error writing output: {}", e))?;
+    Err(format!(
+        "The snapshots differ in {} field(s)",
+        differences.len()
+    ))
+}
 
-    // Output account data on stdout
-    println!("client,available,held,total,locked");
-    for (client_id, account) in accounts.iter() {
-        println!(
-            "{},{},{},{},{}",
-            client_id,
-            account.balance(),
-            account.held(),
-            account.total(),
-            account.is_frozen()
-        );
+/// Print the JSON Schema with a name, or every schema if no name is given
+fn schema(args: Args) -> Result<(), String> {
+    let names = args.finish()?;
+    let schema = match names.as_slice() {
+        [] => schema::all(),
+        [name] => schema::schema(name).ok_or_else(|| {
+            format!(
+                "Unknown schema {:?}. Schemas are: {}",
+                name,
+                schema::SCHEMAS.join(", ")
+            )
+        })?,
+        [_, extra, ..] => return Err(format!("Unexpected argument {:?}", extra)),
+    };
+    println!("{:#}", schema);
+    Ok(())
+}
+
+/// Serve a generated dataset for exploring the engine until interrupted
+fn demo(mut args: Args) -> Result<(), String> {
+    let seed = match args.value("--seed")? {
+        Some(seed) => seed,
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_nanos() as u64,
+    };
+    let print = args.flag("--print");
+    let mut listeners = listeners(&mut args)?;
+    if let Some(extra) = args.finish()?.first() {
+        return Err(format!("Unexpected argument {:?}", extra));
+    }
+    // Write the dataset as input, such as to try other options on
+    if print {
+        return demo::write_transactions(seed, BufWriter::new(io::stdout().lock()))
+            .map_err(|e| e.to_string());
+    }
+    if listeners.is_empty() {
+        listeners.tcp = Some(DEFAULT_DEMO_ADDRESS.into());
+    }
+    let accounts = demo::accounts(seed);
+    eprintln!("Demo dataset with --seed {}:", seed);
+    output::write_accounts(&accounts, OutputFormat::Csv, io::stderr())
+        .map_err(|e| e.to_string())?;
+    if let Some(address) = &listeners.tcp {
+        eprintln!("Send transactions to {}, such as with `nc`", address);
     }
+    server::listen(Server::new(accounts), listeners)
 }
 
-/// Apply transactions parsed from a reader and apply each one to accounts
-fn process_transaction_source<R>(source: R, accounts: &mut Accounts) -> Result<(), String>
-where
-    R: Read,
-{
-    for (i, line) in BufReader::new(source).lines().enumerate() {
-        let line_no = i + 1;
-        // Break on I/O error
-        let line = line.map_err(|e| format!("Error reading line {}: {}", line_no, e))?;
-        // Skip empty lines or header row if it is present
-        if line.trim().is_empty() || i == 0 && line.trim().starts_with("type") {
-            continue;
+/// Estimate the peak memory a run of an input file needs
+fn estimate(mut args: Args) -> Result<(), String> {
+    let sample = args.value("--sample")?.unwrap_or(DEFAULT_ESTIMATE_SAMPLE);
+    // History is not written to the file, so it is not created
+    let history_limit = args.value("--history-limit")?;
+    let history_limit = match args.value::<String>("--history-file")? {
+        Some(_) => Some(history_limit.unwrap_or(HistorySpill::DEFAULT_LIMIT)),
+        None if history_limit.is_some() => {
+            return Err("Expected a history file (--history-file)".into())
         }
+        None => None,
+    };
+    let path = input_path(args)?;
+    if path == "-" {
+        return Err("Expected an input file to estimate".into());
+    }
+    let input_len = fs::metadata(&path)
+        .map_err(|e| format!("Unable to open {:?}: {}", path, e))?
+        .len();
+    let estimate = estimate::estimate(source::open(&path)?, input_len, history_limit, sample);
+    print!("{}", estimate);
+    Ok(())
+}
 
-        // Parse transaction
-        let tx = line
-            .parse::<ClientTransaction>()
-            .map_err(|e| format!("Invalid transaction on line {}: {}", line_no, e))?;
+/// Get the addresses to listen on from `--tcp`, `--http`, `--grpc`, and `--websocket`
+fn listeners(args: &mut Args) -> Result<Listeners, String> {
+    let listeners = Listeners {
+        tcp: args.value("--tcp")?,
+        http: args.value("--http")?,
+        grpc: args.value("--grpc")?,
+        websocket: args.value("--websocket")?,
+        admin: args.value("--admin-api")?,
+    };
+    listeners.check()?;
+    Ok(listeners)
+}
 
-        // Apply transaction
-        if let Err(e) = accounts.transact(tx.clone()) {
-            eprintln!("Error executing transaction on line {}: {}", line_no, e);
+/// Serve transactions from TCP connections, HTTP requests, and gRPC calls until interrupted
+fn serve(mut args: Args) -> Result<(), String> {
+    let listeners = listeners(&mut args)?;
+    if listeners.is_empty() {
+        return Err(
+            "Expected an address to listen on (--tcp, --http, --grpc, or --websocket)".into(),
+        );
+    }
+    let mut settings = settings(&mut args)?;
+    settings.load_state = args.value("--load-state")?;
+    let accounts = settings.accounts()?;
+    let record_path: Option<PathBuf> = args.value("--record")?;
+    let journal_path: Option<PathBuf> = args.value("--journal")?;
+    if listeners.admin.is_some() {
+        let token = std::env::var(admin::TOKEN_VAR).unwrap_or_default();
+        if token.is_empty() {
+            return Err(format!(
+                "Unable to serve the admin API because {} is not set",
+                admin::TOKEN_VAR
+            ));
         }
+    } else if journal_path.is_some() {
+        return Err("A journal (--journal) needs the admin API (--admin-api)".into());
+    }
+    let limits = ConnectionLimits {
+        max_connections: args.value("--max-connections")?,
+        max_in_flight: args.value("--max-in-flight")?,
+        max_request_size: args.value("--max-request-size")?,
+    };
+    let windows_service = args.flag("--windows-service");
+    #[cfg(not(all(windows, feature = "windows-service")))]
+    if windows_service {
+        return Err(
+            "Unable to run as a Windows service: transactor was built without the \
+            `windows-service` feature for Windows"
+                .into(),
+        );
+    }
+    if let Some(extra) = args.finish()?.first() {
+        return Err(format!("Unexpected argument {:?}", extra));
+    }
+    let mut server = match record_path {
+        Some(path) => {
+            let recorder = File::create(&path)
+                .and_then(|file| Recorder::new(Box::new(file) as Box<dyn Write + Send>))
+                .map_err(|e| format!("Unable to create recording {:?}: {}", path, e))?;
+            Server::with_recorder(accounts, recorder)
+        }
+        None => Server::new(accounts),
+    };
+    server.set_limits(limits);
+    if let Some(path) = journal_path {
+        let journal = Journal::open(&path)
+            .map_err(|e| format!("Unable to open journal {:?}: {}", path, e))?;
+        server.set_journal(journal);
+    }
+    #[cfg(all(windows, feature = "windows-service"))]
+    if windows_service {
+        return transactor::service::run_windows_service(move || server::listen(server, listeners));
+    }
+    server::listen(server, listeners)
+}
+
+/// Apply a file of admin operations on top of the transactions in a file, or send a command
+/// to a running server with `--connect`
+///
+/// The caller must provide the admin token held in `TRANSACTOR_ADMIN_TOKEN`
+fn admin(mut args: Args) -> Result<(), String> {
+    if let Some(address) = args.value::<String>("--connect")? {
+        return admin_connect(args, &address);
+    }
+    admin::authenticate_env(args.value::<String>("--token")?.as_deref())?;
+    let ops_path: String = args
+        .value("--ops")?
+        .ok_or("Expected an admin operations file (--ops)")?;
+    let journal_path: PathBuf = args
+        .value("--journal")?
+        .ok_or("Expected a journal file (--journal)")?;
+    let output = output(&mut args)?;
+    let precision = args.value("--decimal-places")?.unwrap_or_default();
+    let sandbox = args.flag("--sandbox");
+    // Validate every operation before touching any state
+    let ops = admin::read_ops(source::open(&ops_path)?)?;
+    let mut accounts = Accounts::with_precision(precision);
+    accounts.set_admin_transactions(true);
+    accounts.set_sandbox(sandbox);
+    process_transaction_source(open_input(args)?, &mut accounts)?;
+    let mut journal = Journal::open(&journal_path)
+        .map_err(|e| format!("Unable to open journal {:?}: {}", journal_path, e))?;
+    let (applied, rejected) = admin::apply_ops(&ops, &mut accounts, &mut journal)
+        .map_err(|e| format!("Error writing journal {:?}: {}", journal_path, e))?;
+    eprintln!(
+        "{} {} admin operation(s), rejected {}",
+        if sandbox { "Simulated" } else { "Applied" },
+        applied,
+        rejected
+    );
+    output.write(&accounts)
+}
+
+/// Send a command to the admin API of a running server, printing what it gets
+fn admin_connect(mut args: Args, address: &str) -> Result<(), String> {
+    let token: String = args
+        .value("--token")?
+        .ok_or("Expected an admin token (--token)")?;
+    let operator: Option<String> = args.value("--operator")?;
+    let reference: Option<String> = args.value("--reference")?;
+    let note: Option<String> = args.value("--note")?;
+    let mut positional = args.finish()?.into_iter();
+    let name = positional
+        .next()
+        .ok_or("Expected a command (freeze, unfreeze, inspect, stats, or drain)")?;
+    let mut client = || -> Result<ClientId, String> {
+        let client = positional.next().ok_or("Expected a client id")?;
+        (client.parse()).map_err(|_| format!("Invalid client id {:?}", client))
+    };
+    let command = match name.as_str() {
+        "freeze" | "unfreeze" => Command::Op(AdminOp {
+            client: client()?,
+            kind: if name == "freeze" {
+                AdminOpKind::Freeze
+            } else {
+                AdminOpKind::Unfreeze
+            },
+            operator: operator.ok_or("Expected an operator (--operator)")?,
+            reference: reference.ok_or("Expected a reference (--reference)")?,
+            note,
+        }),
+        "inspect" => Command::Inspect(client()?),
+        "stats" => Command::Stats,
+        "drain" => Command::Drain,
+        _ => return Err(format!("Invalid admin command {:?}", name)),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("Unexpected argument {:?}", extra));
+    }
+    let response = control::request(address, &token, &command)?;
+    match command {
+        Command::Inspect(_) => println!("{:#}", response["account"]),
+        Command::Stats => println!("{:#}", response["stats"]),
+        Command::Op(_) | Command::Drain => {}
     }
     Ok(())
 }
+
+/// Get the settings shared by the commands that execute transactions
+///
+/// Sandbox mode, loading state, and accrual schedules are left to the commands that support
+/// them.
+fn settings(args: &mut Args) -> Result<Settings, String> {
+    let admin = args.flag("--admin");
+    Ok(Settings {
+        precision: args.value("--decimal-places")?.unwrap_or_default(),
+        admin,
+        token: if admin { args.value("--token")? } else { None },
+        dispute_limits: dispute_limits(args)?,
+        withdrawal_retries: withdrawal_retries(args)?,
+        settlement_period: settlement_period(args)?,
+        representment_unfreezes: args.flag("--representment-unfreezes"),
+        fee_overdraft: fee_overdraft(args)?,
+        overdraft_limits: overdraft_limits(args)?,
+        history_spill: history_spill(args)?,
+        ..Settings::default()
+    })
+}
+
+/// Get the limits on disputes per client from `--max-open-disputes`, `--max-disputes-per-run`,
+/// and `--dispute-window`
+fn dispute_limits(args: &mut Args) -> Result<DisputeLimits, String> {
+    let window = match args.value::<u64>("--dispute-window")? {
+        Some(days) => Some(
+            days.checked_mul(24 * 60 * 60)
+                .ok_or("Dispute window (--dispute-window) is too long")?,
+        ),
+        None => None,
+    };
+    Ok(DisputeLimits {
+        max_open: args.value("--max-open-disputes")?,
+        max_per_run: args.value("--max-disputes-per-run")?,
+        window,
+    })
+}
+
+/// Get how long deposits take to settle from `--settlement-days` and `--settlement-period`
+fn settlement_period(args: &mut Args) -> Result<SettlementPeriod, String> {
+    let time = match args.value::<u64>("--settlement-days")? {
+        Some(days) => Some(
+            days.checked_mul(24 * 60 * 60)
+                .ok_or("Settlement period (--settlement-days) is too long")?,
+        ),
+        None => None,
+    };
+    Ok(SettlementPeriod {
+        time,
+        transactions: args.value("--settlement-period")?,
+    })
+}
+
+/// Get how far fees may overdraw an account from `--fee-overdraft`
+fn fee_overdraft(args: &mut Args) -> Result<Amount, String> {
+    let overdraft: Amount = args.value("--fee-overdraft")?.unwrap_or_default();
+    if overdraft < Amount::default() {
+        return Err("Fee overdraft (--fee-overdraft) cannot be negative".into());
+    }
+    Ok(overdraft)
+}
+
+/// Get how far withdrawals may overdraw accounts from `--overdraft` and `--overdraft-limits`
+fn overdraft_limits(args: &mut Args) -> Result<OverdraftLimits, String> {
+    let default: Amount = args.value("--overdraft")?.unwrap_or_default();
+    if default < Amount::default() {
+        return Err("Overdraft limit (--overdraft) cannot be negative".into());
+    }
+    let mut limits = OverdraftLimits {
+        default,
+        ..OverdraftLimits::default()
+    };
+    if let Some(path) = args.value::<PathBuf>("--overdraft-limits")? {
+        let file = File::open(&path).map_err(|e| format!("Unable to open {:?}: {}", path, e))?;
+        limits.read_clients(file)?;
+    }
+    Ok(limits)
+}
+
+/// Get the policy for accruing interest and penalties from `--accrue`, `--held-funds`, and
+/// `--frozen-funds`
+fn accrual_policy(args: &mut Args) -> Result<Option<AccrualPolicy>, String> {
+    let interest_rate = match args.value::<String>("--accrue")? {
+        Some(rate) => Some(
+            accrual::parse_rate(&rate)
+                .map_err(|e| format!("Invalid value {:?} for --accrue: {}", rate, e))?,
+        ),
+        None => None,
+    };
+    let held = args.value("--held-funds")?;
+    let frozen = args.value("--frozen-funds")?;
+    if interest_rate.is_none() && held.is_none() && frozen.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(AccrualPolicy {
+        interest_rate: interest_rate.unwrap_or_default(),
+        held: held.unwrap_or_default(),
+        frozen: frozen.unwrap_or_default(),
+    }))
+}
+
+/// Get the schedule for accruing a policy during processing from `--accrue-every`
+fn accrual_schedule(
+    args: &mut Args,
+    policy: Option<AccrualPolicy>,
+) -> Result<Option<AccrualSchedule>, String> {
+    let Some(days) = args.value::<NonZeroU64>("--accrue-every")? else {
+        return Ok(None);
+    };
+    let policy =
+        policy.ok_or("--accrue-every requires --accrue, --held-funds, or --frozen-funds")?;
+    let period = days
+        .checked_mul(NonZeroU64::new(24 * 60 * 60).unwrap())
+        .ok_or("Accrual period (--accrue-every) is too long")?;
+    Ok(Some(AccrualSchedule { policy, period }))
+}
+
+/// Get the Kafka input options from `--kafka`, `--topic`, `--group` and `--emit-interval`
+fn kafka_options(args: &mut Args) -> Result<Option<KafkaOptions>, String> {
+    let topic = args.value("--topic")?;
+    let group = args.value("--group")?;
+    let interval: Option<NonZeroU64> = args.value("--emit-interval")?;
+    let Some(brokers) = args.value("--kafka")? else {
+        return match (topic, group, interval) {
+            (None, None, None) => Ok(None),
+            _ => Err("Expected Kafka brokers (--kafka)".into()),
+        };
+    };
+    Ok(Some(KafkaOptions {
+        brokers,
+        topic: topic.ok_or("Expected a Kafka topic (--topic)")?,
+        group: group.unwrap_or_else(|| run::DEFAULT_KAFKA_GROUP.into()),
+        interval: Duration::from_secs(interval.map_or(run::DEFAULT_EMIT_INTERVAL, NonZeroU64::get)),
+    }))
+}
+
+/// Get the policy for retrying withdrawals from `--retry-withdrawals` and `--retry-window`
+fn withdrawal_retries(args: &mut Args) -> Result<Option<WithdrawalRetries>, String> {
+    let window = args.value("--retry-window")?;
+    let Some(max_retries) = args.value("--retry-withdrawals")? else {
+        return match window {
+            Some(_) => Err("Expected a number of retries (--retry-withdrawals)".into()),
+            None => Ok(None),
+        };
+    };
+    Ok(Some(WithdrawalRetries {
+        max_retries,
+        window,
+    }))
+}
+
+/// Get the setting for keeping history on disk from `--history-file` and `--history-limit`
+fn history_spill(args: &mut Args) -> Result<Option<HistorySpill>, String> {
+    let limit = args.value("--history-limit")?;
+    let Some(path) = args.value::<String>("--history-file")? else {
+        return match limit {
+            Some(_) => Err("Expected a history file (--history-file)".into()),
+            None => Ok(None),
+        };
+    };
+    HistorySpill::create(&path, limit).map(Some)
+}
+
+/// Get where and how to output account data from `--output-format`, `--output`,
+/// `--extended`, `--where`, and `--output-shards`
+fn output(args: &mut Args) -> Result<Output, String> {
+    let output = Output {
+        format: args.value("--output-format")?.unwrap_or_default(),
+        path: args.value("--output")?,
+        extended: args.flag("--extended"),
+        query: args.value("--where")?,
+        shards: args.value("--output-shards")?,
+    };
+    output.check()?;
+    Ok(output)
+}
+
+/// Split a transaction file into one file per client
+fn split(mut args: Args) -> Result<(), String> {
+    if !args.flag("--by-client") {
+        return Err("Expected a split mode (--by-client)".into());
+    }
+    let out_dir: PathBuf = args
+        .value("--out-dir")?
+        .ok_or("Expected an output directory (--out-dir)")?;
+    filter::split_by_client(open_input(args)?, &out_dir)
+}
+
+/// Write the transactions in a file that match some criteria to stdout
+fn filter(mut args: Args) -> Result<(), String> {
+    let types: Vec<String> = args.values("--type")?;
+    if let Some(ty) = types
+        .iter()
+        .find(|ty| !TRANSACTION_TYPES.contains(&ty.as_str()))
+    {
+        return Err(format!("Invalid transaction type {:?}", ty));
+    }
+    let filter = Filter {
+        clients: args.values("--client")?,
+        types,
+        range: args
+            .value::<String>("--range")?
+            .map(|range| filter::parse_range(&range))
+            .transpose()?,
+    };
+    filter::filter(open_input(args)?, &filter, io::stdout().lock())
+}
+
+/// Write the transactions in a file that affect a single client to stdout
+fn extract(mut args: Args) -> Result<(), String> {
+    let client = args
+        .value("--client")?
+        .ok_or("Expected a client id (--client)")?;
+    filter::extract(open_input(args)?, client, io::stdout().lock())
+}
+
+/// Write a statement of a single client's account activity to stdout
+fn statement(mut args: Args) -> Result<(), String> {
+    let client = args
+        .value("--client")?
+        .ok_or("Expected a client id (--client)")?;
+    let format: String = args.value("--format")?.unwrap_or_else(|| "text".into());
+    let journal_path: Option<String> = args.value("--journal")?;
+    let mut statement = Statement::generate(open_input(args)?, client)?;
+    if let Some(path) = journal_path {
+        statement
+            .notes
+            .extend(admin::read_notes(source::open(&path)?, client)?);
+    }
+    let stdout = io::stdout().lock();
+    match format.as_str() {
+        "text" => statement.render_text(stdout),
+        "html" => statement.render_html(stdout),
+        _ => return Err(format!("Invalid statement format {:?}", format)),
+    }
+    .map_err(|e| format!("Error writing statement: {}", e))
+}
+
+/// Cross-check a processor's chargeback report against the disputes in a file
+fn reconcile(mut args: Args) -> Result<(), String> {
+    let report_path: String = args
+        .value("--chargebacks")?
+        .ok_or("Expected a chargeback report (--chargebacks)")?;
+    let report = reconcile::read_chargebacks(source::open(&report_path)?)?;
+    let mut ledger = DisputeLedger::default();
+    process_transaction_source_with(
+        open_input(args)?,
+        &mut Accounts::default(),
+        |record, result, _| ledger.observe(&record.tx, &result),
+    )?;
+    let check = reconcile::cross_check(&ledger, &report);
+    check
+        .write_csv(io::stdout().lock())
+        .map_err(|e| format!("Error writing discrepancies: {}", e))?;
+    eprintln!(
+        "{} chargeback(s) matched, {} discrepancy(ies)",
+        check.matched,
+        check.discrepancies.len()
+    );
+    Ok(())
+}
+
+/// Write a time series of a client's account data from a directory of reports to stdout
+fn trend(mut args: Args) -> Result<(), String> {
+    let client = args
+        .value("--client")?
+        .ok_or("Expected a client id (--client)")?;
+    let dir = input_path(args)?;
+    if dir == "-" {
+        return Err("Expected a directory of reports".into());
+    }
+    let points = trend::trend(Path::new(&dir), client)?;
+    trend::write_trend(&points, io::stdout().lock())
+        .map_err(|e| format!("Error writing trend: {}", e))
+}
+
+/// Export the test vectors, or check an implementation against them
+///
+/// Without `--export` or `--run`, transactor checks itself.
+fn vectors(mut args: Args) -> Result<(), String> {
+    if let Some(dir) = args.value::<PathBuf>("--export")? {
+        args.finish()?;
+        vectors::export(&dir).map_err(|e| format!("Unable to export to {:?}: {}", dir, e))?;
+        eprintln!(
+            "Exported {} test vectors to {:?}",
+            vectors::VECTORS.len(),
+            dir
+        );
+        return Ok(());
+    }
+    let program: Option<String> = args.value("--run")?;
+    let program_args = args.finish()?;
+    if program.is_none() && !program_args.is_empty() {
+        return Err(format!("Unexpected argument {:?}", program_args[0]));
+    }
+    let command = program.as_deref().map(|program| (program, &*program_args));
+    let failed = vectors::check_all(command, io::stdout().lock()).map_err(|e| e.to_string())?;
+    if failed > 0 {
+        return Err(format!(
+            "{} of {} test vectors failed",
+            failed,
+            vectors::VECTORS.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Open the input named by the remaining arguments
+fn open_input(args: Args) -> Result<Box<dyn io::Read>, String> {
+    source::open(&input_path(args)?)
+}
+
+/// Get the input paths from the remaining arguments
+///
+/// If there are none, this is `-`, meaning stdin.
+fn input_paths(args: Args) -> Result<Vec<String>, String> {
+    let paths = args.finish()?;
+    if paths.is_empty() {
+        return Ok(vec!["-".into()]);
+    }
+    Ok(paths)
+}
+
+/// Get the path of the only input file, or `-` for stdin if none is given
+fn input_path(args: Args) -> Result<String, String> {
+    let mut paths = args.finish()?.into_iter();
+    let path = paths.next().unwrap_or_else(|| "-".into());
+    if let Some(extra) = paths.next() {
+        return Err(format!("Unexpected argument {:?}", extra));
+    }
+    Ok(path)
+}
@@ -0,0 +1,255 @@
+//! Metrics in the Prometheus text format
+//!
+//! [`Metrics`] counts transactions as they are executed and can be shared between the threads
+//! that execute them and the thread that exposes the metrics. With the `http` feature, the
+//! metrics of a server are served at `GET /metrics`, and `http::serve_metrics` serves them on
+//! their own.
+//!
+//! A server also tracks how fast each client submits transactions, so that integrations that
+//! submit too many can be found. Rates are measured over a sliding window of a minute.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    account::{Accounts, TransactionError},
+    amount::Amount,
+    transaction::{ClientId, ClientTransaction, ResolutionKind, Transaction},
+};
+
+/// The content type of the Prometheus text format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The number of clients whose submission rates are rendered
+pub const TOP_SUBMITTERS: usize = 10;
+
+/// The window that submission rates are measured over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// How fast a client submits transactions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubmissionRate {
+    pub client: ClientId,
+    /// Transactions submitted over the last minute
+    pub per_minute: f64,
+    /// Transactions submitted since the server started
+    pub total: u64,
+}
+
+/// The submissions of a single client
+///
+/// Submissions are counted in fixed windows. The rate over the last window's length is
+/// estimated from the current window and the part of the previous window that overlaps it.
+#[derive(Debug)]
+struct Submissions {
+    total: u64,
+    window_start: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl Submissions {
+    /// Move the windows forward to contain a time
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= 2 * RATE_WINDOW {
+            self.previous = 0;
+            self.current = 0;
+            self.window_start = now;
+        } else if elapsed >= RATE_WINDOW {
+            self.previous = self.current;
+            self.current = 0;
+            self.window_start += RATE_WINDOW;
+        }
+    }
+    /// Estimate the submissions over the window before a time
+    fn rate(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        let overlap = |elapsed: Duration| 1.0 - elapsed.as_secs_f64() / RATE_WINDOW.as_secs_f64();
+        if elapsed >= 2 * RATE_WINDOW {
+            0.0
+        } else if elapsed >= RATE_WINDOW {
+            self.current as f64 * overlap(elapsed - RATE_WINDOW)
+        } else {
+            self.previous as f64 * overlap(elapsed) + self.current as f64
+        }
+    }
+}
+
+/// Counters of executed transactions and gauges of accounts
+#[derive(Debug, Default)]
+pub struct Metrics {
+    transactions: AtomicU64,
+    chargebacks: AtomicU64,
+    /// Rejected transactions by [`TransactionError::code`]
+    rejections: Mutex<BTreeMap<&'static str, u64>>,
+    /// The number of accounts, the total held funds, and the decimal places to write them
+    /// with, as of the last update
+    accounts: Mutex<(usize, Amount, u32)>,
+    submissions: Mutex<HashMap<ClientId, Submissions>>,
+}
+
+impl Metrics {
+    /// Create metrics with every counter at zero
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+    /// Observe the execution of a transaction
+    pub fn observe(&self, tx: &ClientTransaction, result: &Result<(), TransactionError>) {
+        self.transactions.fetch_add(1, Ordering::Relaxed);
+        match result {
+            Ok(()) => {
+                if let Transaction::Resolution {
+                    kind: ResolutionKind::Chargeback,
+                    ..
+                } = tx.tx
+                {
+                    self.chargebacks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(e) => *self.rejections.lock().unwrap().entry(e.code()).or_default() += 1,
+        }
+    }
+    /// Get the number of transactions executed, including rejected ones
+    pub fn transactions(&self) -> u64 {
+        self.transactions.load(Ordering::Relaxed)
+    }
+    /// Get the number of rejected transactions
+    pub fn rejections(&self) -> u64 {
+        self.rejections.lock().unwrap().values().sum()
+    }
+    /// Observe a client submitting a transaction, whether or not it is applied
+    pub fn observe_submission(&self, client: ClientId) {
+        self.observe_submission_at(client, Instant::now());
+    }
+    pub(crate) fn observe_submission_at(&self, client: ClientId, now: Instant) {
+        let mut submissions = self.submissions.lock().unwrap();
+        let submissions = submissions.entry(client).or_insert(Submissions {
+            total: 0,
+            window_start: now,
+            current: 0,
+            previous: 0,
+        });
+        submissions.advance(now);
+        submissions.current += 1;
+        submissions.total += 1;
+    }
+    /// Get the clients that have submitted the most transactions over the last minute
+    ///
+    /// Clients with the same rate are ordered by their total submissions.
+    pub fn top_submitters(&self, count: usize) -> Vec<SubmissionRate> {
+        self.top_submitters_at(count, Instant::now())
+    }
+    pub(crate) fn top_submitters_at(&self, count: usize, now: Instant) -> Vec<SubmissionRate> {
+        let mut rates: Vec<SubmissionRate> = self
+            .submissions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&client, submissions)| SubmissionRate {
+                client,
+                per_minute: submissions.rate(now),
+                total: submissions.total,
+            })
+            .collect();
+        rates.sort_by(|a, b| {
+            (b.per_minute.total_cmp(&a.per_minute))
+                .then(b.total.cmp(&a.total))
+                .then(a.client.cmp(&b.client))
+        });
+        rates.truncate(count);
+        rates
+    }
+    /// Update the gauges of accounts
+    ///
+    /// Gauges are only as recent as their last update.
+    pub fn update_accounts(&self, accounts: &Accounts) {
+        let mut held = Amount::default();
+        let mut count = 0;
+        for (_, account) in accounts.iter() {
+            held = held.checked_add(account.held()).unwrap_or(held);
+            count += 1;
+        }
+        let places = accounts.precision().decimal_places();
+        *self.accounts.lock().unwrap() = (count, held, places);
+    }
+    /// Render the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        metric(
+            "transactor_transactions_total",
+            "counter",
+            "Transactions executed, including rejected ones",
+            &[(
+                String::new(),
+                self.transactions.load(Ordering::Relaxed).to_string(),
+            )],
+        );
+        let rejections: Vec<(String, String)> = self
+            .rejections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(code, count)| (format!("{{reason=\"{}\"}}", code), count.to_string()))
+            .collect();
+        metric(
+            "transactor_rejections_total",
+            "counter",
+            "Rejected transactions by reason",
+            &rejections,
+        );
+        metric(
+            "transactor_chargebacks_total",
+            "counter",
+            "Chargebacks applied",
+            &[(
+                String::new(),
+                self.chargebacks.load(Ordering::Relaxed).to_string(),
+            )],
+        );
+        let (count, held, places) = *self.accounts.lock().unwrap();
+        metric(
+            "transactor_accounts",
+            "gauge",
+            "Client accounts",
+            &[(String::new(), count.to_string())],
+        );
+        metric(
+            "transactor_held_funds",
+            "gauge",
+            "Funds held by disputes across all accounts",
+            &[(String::new(), format!("{:.*}", places as usize, held))],
+        );
+        let submitters: Vec<(String, String)> = self
+            .top_submitters(TOP_SUBMITTERS)
+            .into_iter()
+            .map(|rate| {
+                (
+                    format!("{{client=\"{}\"}}", rate.client),
+                    format!("{:.2}", rate.per_minute),
+                )
+            })
+            .collect();
+        metric(
+            "transactor_client_submission_rate",
+            "gauge",
+            "Transactions submitted over the last minute by the clients submitting the most",
+            &submitters,
+        );
+        out
+    }
+}
@@ -0,0 +1,455 @@
+//! Writing account data
+//!
+//! See [`OutputFormat`] for the supported formats.
+
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde_json::json;
+
+use crate::{
+    account::Account, account::Accounts, client_map::ClientMap, query::Query, transaction::ClientId,
+};
+
+/// A format for writing account data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Comma-separated values with a header row
+    #[default]
+    Csv,
+    /// A single JSON array of account objects
+    Json,
+    /// One JSON account object per line
+    JsonLines,
+    /// An Arrow IPC stream of a single record batch, with exact decimal amounts
+    #[cfg(feature = "arrow")]
+    Arrow,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            #[cfg(feature = "arrow")]
+            "arrow" => Ok(OutputFormat::Arrow),
+            #[cfg(not(feature = "arrow"))]
+            "arrow" => Err(
+                "Unable to write Arrow output: transactor was built without the `arrow` feature"
+                    .into(),
+            ),
+            _ => Err(format!("Unknown output format {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Csv => write!(f, "csv"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::JsonLines => write!(f, "jsonl"),
+            #[cfg(feature = "arrow")]
+            OutputFormat::Arrow => write!(f, "arrow"),
+        }
+    }
+}
+
+/// Get an account as a JSON object
+///
+/// Amounts are written as decimal strings so they are not subject to floating-point rounding.
+pub(crate) fn account_json(
+    client_id: ClientId,
+    account: &Account,
+    places: usize,
+    extended: bool,
+) -> serde_json::Value {
+    let mut object = json!({
+        "client": client_id,
+        "available": format!("{:.*}", places, account.balance()),
+        "held": format!("{:.*}", places, account.held()),
+        "total": format!("{:.*}", places, account.total()),
+        "locked": account.is_frozen(),
+    });
+    if extended {
+        let activity = account.activity();
+        object["first_tx"] = json!(activity.map(|activity| activity.first_tx));
+        object["last_tx"] = json!(activity.map(|activity| activity.last_tx));
+        object["pending"] = json!(format!("{:.*}", places, account.pending()));
+        object["first_timestamp"] = json!(activity.and_then(|activity| activity.first_timestamp));
+        object["last_timestamp"] = json!(activity.and_then(|activity| activity.last_timestamp));
+    }
+    object
+}
+
+/// Write the data of all accounts in some format
+///
+/// Amounts are written with exactly as many decimal places as the accounts' precision.
+pub fn write_accounts<W>(accounts: &Accounts, format: OutputFormat, writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    write(accounts, None, None, format, false, None, writer)
+}
+
+/// Write the data of all accounts in some format, including extended account data
+///
+/// In addition to the usual columns, this includes `first_tx` and `last_tx`, the ids of
+/// the first and most recent deposits or withdrawals on each account. They are empty for
+/// accounts that never had any. It also includes `pending`, the funds of deposits that have
+/// not settled, which are part of `total` but not `available`, and `first_timestamp` and
+/// `last_timestamp`, the timestamps of the first and most recent transactions if they had them.
+pub fn write_extended_accounts<W>(
+    accounts: &Accounts,
+    format: OutputFormat,
+    writer: W,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    write(accounts, None, None, format, true, None, writer)
+}
+
+/// Write the data of the accounts that match a query in some format
+///
+/// See [`write_accounts`] and [`write_extended_accounts`].
+pub fn write_matching_accounts<W>(
+    accounts: &Accounts,
+    query: &Query,
+    format: OutputFormat,
+    extended: bool,
+    writer: W,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    write(accounts, Some(query), None, format, extended, None, writer)
+}
+
+/// Write the data of accounts with the external references their client ids are mapped from
+///
+/// The `client` column holds each account's reference in the map, or its client id if it has
+/// none. Arrow output is not supported, as its `client` column is numeric. See
+/// [`write_matching_accounts`].
+pub fn write_accounts_with_clients<W>(
+    accounts: &Accounts,
+    query: Option<&Query>,
+    format: OutputFormat,
+    extended: bool,
+    clients: &ClientMap,
+    writer: W,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    write(
+        accounts,
+        query,
+        None,
+        format,
+        extended,
+        Some(clients),
+        writer,
+    )
+}
+
+/// A part of the range of client ids, for splitting account data between several outputs
+///
+/// The range is split into `count` contiguous parts of nearly equal size, so each shard
+/// holds the accounts of a fixed range of client ids, still in order of client id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// The index of the shard, from 0
+    pub index: usize,
+    /// The number of shards
+    pub count: usize,
+}
+
+impl Shard {
+    /// The number of client ids
+    const CLIENTS: usize = ClientId::MAX as usize + 1;
+    /// Get the shard of `count` shards that holds a client's account
+    pub fn of(client_id: ClientId, count: usize) -> Self {
+        Shard {
+            index: client_id as usize * count / Shard::CLIENTS,
+            count,
+        }
+    }
+    /// Get the range of client ids whose accounts the shard holds
+    ///
+    /// The range is empty if there are more shards than client ids.
+    pub fn clients(&self) -> RangeInclusive<usize> {
+        let start = (self.index * Shard::CLIENTS).div_ceil(self.count);
+        let end = ((self.index + 1) * Shard::CLIENTS).div_ceil(self.count);
+        start..=end.saturating_sub(1)
+    }
+}
+
+/// Write the data of the accounts in one shard of the client ids
+///
+/// The accounts may also be limited by a query and written with the references their client
+/// ids are mapped from. See [`write_matching_accounts`] and [`write_accounts_with_clients`].
+pub fn write_shard<W>(
+    accounts: &Accounts,
+    shard: Shard,
+    query: Option<&Query>,
+    format: OutputFormat,
+    extended: bool,
+    clients: Option<&ClientMap>,
+    writer: W,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    write(
+        accounts,
+        query,
+        Some(shard),
+        format,
+        extended,
+        clients,
+        writer,
+    )
+}
+
+fn write<W>(
+    accounts: &Accounts,
+    query: Option<&Query>,
+    shard: Option<Shard>,
+    format: OutputFormat,
+    extended: bool,
+    clients: Option<&ClientMap>,
+    mut writer: W,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let precision = accounts.precision();
+    let places = precision.decimal_places() as usize;
+    // Clients are written as the references they are mapped from if there is a map
+    let reference = |client_id: ClientId| clients.and_then(|clients| clients.reference(client_id));
+    let account_json = |client_id: ClientId, account: &Account| {
+        let mut object = account_json(client_id, account, places, extended);
+        if let Some(reference) = reference(client_id) {
+            object["client"] = json!(reference);
+        }
+        object
+    };
+    let accounts = || {
+        accounts.iter().filter(|(client_id, account)| {
+            shard.is_none_or(|shard| Shard::of(*client_id, shard.count) == shard)
+                && query.is_none_or(|query| query.matches(*client_id, account))
+        })
+    };
+    match format {
+        OutputFormat::Csv => {
+            write!(writer, "client,available,held,total,locked")?;
+            if extended {
+                write!(
+                    writer,
+                    ",first_tx,last_tx,pending,first_timestamp,last_timestamp"
+                )?;
+            }
+            writeln!(writer)?;
+            for (client_id, account) in accounts() {
+                match reference(client_id) {
+                    Some(reference) => write!(writer, "{}", reference)?,
+                    None => write!(writer, "{}", client_id)?,
+                }
+                write!(
+                    writer,
+                    ",{:.*},{:.*},{:.*},{}",
+                    places,
+                    account.balance(),
+                    places,
+                    account.held(),
+                    places,
+                    account.total(),
+                    account.is_frozen()
+                )?;
+                if extended {
+                    match account.activity() {
+                        Some(activity) => {
+                            write!(writer, ",{},{}", activity.first_tx, activity.last_tx)?
+                        }
+                        None => write!(writer, ",,")?,
+                    }
+                    write!(writer, ",{:.*}", places, account.pending())?;
+                    let activity = account.activity();
+                    for timestamp in [
+                        activity.and_then(|activity| activity.first_timestamp),
+                        activity.and_then(|activity| activity.last_timestamp),
+                    ] {
+                        match timestamp {
+                            Some(timestamp) => write!(writer, ",{}", timestamp)?,
+                            None => write!(writer, ",")?,
+                        }
+                    }
+                }
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Json => {
+            let accounts: Vec<_> = accounts()
+                .map(|(client_id, account)| account_json(client_id, account))
+                .collect();
+            serde_json::to_writer_pretty(&mut writer, &accounts)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            for (client_id, account) in accounts() {
+                serde_json::to_writer(&mut writer, &account_json(client_id, account))?;
+                writeln!(writer)?;
+            }
+        }
+        #[cfg(feature = "arrow")]
+        OutputFormat::Arrow => {
+            if clients.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Arrow output cannot use client references",
+                ));
+            }
+            crate::arrow::write_accounts(accounts(), precision, extended, &mut writer)?;
+        }
+    }
+    writer.flush()
+}
+
+/// Where and how to write account data
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub format: OutputFormat,
+    /// The file to write to, or `None` for standard output
+    pub path: Option<PathBuf>,
+    /// Whether to write the extended columns, see [`write_extended_accounts`]
+    pub extended: bool,
+    /// Only write the accounts that match a query
+    pub query: Option<Query>,
+    /// Split the accounts between this many files, see [`Shard`]
+    pub shards: Option<usize>,
+}
+
+impl Output {
+    /// Check that the output can be written, before any work is done to produce it
+    pub fn check(&self) -> Result<(), String> {
+        match self.shards {
+            Some(_) if self.path.is_none() => {
+                Err("--output-shards requires an output file (--output)".into())
+            }
+            Some(0) => Err("--output-shards must be at least 1".into()),
+            _ => Ok(()),
+        }
+    }
+    /// Write account data to the output file, or to stdout if there is none
+    ///
+    /// Files are written atomically, see [`write_atomic`].
+    pub fn write(&self, accounts: &Accounts) -> Result<(), String> {
+        self.write_with_clients(accounts, None)
+    }
+    /// Write account data with clients as the references they are mapped from, if there is a map
+    pub fn write_with_clients(
+        &self,
+        accounts: &Accounts,
+        clients: Option<&ClientMap>,
+    ) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return self
+                .write_to(accounts, clients, io::stdout().lock())
+                .map_err(|e| format!("Error writing output: {}", e));
+        };
+        let Some(count) = self.shards else {
+            return write_atomic(path, |file| self.write_to(accounts, clients, file));
+        };
+        for index in 0..count {
+            let shard = Shard { index, count };
+            write_atomic(&shard_path(path, shard), |file| {
+                write_shard(
+                    accounts,
+                    shard,
+                    self.query.as_ref(),
+                    self.format,
+                    self.extended,
+                    clients,
+                    file,
+                )
+            })?;
+        }
+        Ok(())
+    }
+    fn write_to<W: Write>(
+        &self,
+        accounts: &Accounts,
+        clients: Option<&ClientMap>,
+        writer: W,
+    ) -> io::Result<()> {
+        if let Some(clients) = clients {
+            write_accounts_with_clients(
+                accounts,
+                self.query.as_ref(),
+                self.format,
+                self.extended,
+                clients,
+                writer,
+            )
+        } else if let Some(query) = &self.query {
+            write_matching_accounts(accounts, query, self.format, self.extended, writer)
+        } else if self.extended {
+            write_extended_accounts(accounts, self.format, writer)
+        } else {
+            write_accounts(accounts, self.format, writer)
+        }
+    }
+}
+
+/// Get the path of a shard's output file, with the shard's index before the extension
+///
+/// Indices are padded with zeros so the files sort in order, such as `accounts.03.csv`.
+pub fn shard_path(path: &Path, shard: Shard) -> PathBuf {
+    let width = (shard.count - 1).to_string().len();
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(".{:0width$}", shard.index, width = width));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Write a file atomically by writing to a temporary file and renaming it into place
+///
+/// The file is never left partially written, even if writing fails.
+pub fn write_atomic<F>(path: &Path, write: F) -> Result<(), String>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("Invalid output path {:?}", path))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let result = File::create(&temp_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&temp_path, path)
+    });
+    result.map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        format!("Error writing {:?}: {}", path, e)
+    })
+}
@@ -0,0 +1,269 @@
+//! Processing transactions on multiple threads
+//!
+//! Accounts are split into shards by client, and each shard is owned by a worker thread.
+//! Transactions are read on the calling thread and sent to the worker of their client's
+//! shard, so the transactions of each client are still applied in order.
+//!
+//! A transfer between clients of different shards is applied on the calling thread once
+//! both shards' workers have caught up with every earlier transaction. Input with many such
+//! transfers gains little from more threads.
+//!
+//! Clients are assigned to shards as they first appear, to the shard with the fewest
+//! transactions waiting to be applied. A client stays in its shard for the rest of the run, so
+//! a shard that a hot client is assigned to gets fewer of the clients that appear after it.
+//! [`process_transaction_source_parallel_with_progress`] reports how many transactions each
+//! shard has applied and how many are waiting, which shows when the input is skewed.
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    history::HistorySpill, logging, records, transaction::ClientId, Accounts, Record, Transaction,
+    TransactionError,
+};
+
+/// The number of transactions sent to a worker at once
+const BATCH_SIZE: usize = 1024;
+
+/// A message to a worker thread
+enum Message {
+    /// Apply a batch of transactions
+    Apply(Vec<Record>),
+    /// Acknowledge that every earlier batch has been applied
+    Flush(Sender<()>),
+}
+
+/// The progress of one shard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardProgress {
+    /// The number of transactions the shard has applied
+    pub applied: u64,
+    /// The number of transactions sent to the shard that it has yet to apply
+    pub queued: u64,
+    /// The number of clients assigned to the shard
+    pub clients: usize,
+}
+
+/// Apply transactions parsed from a reader to accounts on multiple threads, passing the result
+/// of each one to a callback
+///
+/// The callback is called from the worker threads. It receives the shard of accounts that
+/// contains the transaction's client, as it is after the transaction. Callbacks for a single
+/// client are called in order, but callbacks for different clients may be called in any order.
+///
+/// Once all transactions are applied, the shards are merged back into `accounts`. The result
+/// is the same as that of [`process_transaction_source_with`](crate::process_transaction_source_with).
+/// Accounts with an [accrual schedule](Accounts::set_accrual_schedule) cannot be processed in
+/// parallel, and an error is returned without applying any transactions.
+pub fn process_transaction_source_parallel<R, F>(
+    source: R,
+    accounts: &mut Accounts,
+    threads: NonZeroUsize,
+    on_executed: F,
+) -> Result<(), String>
+where
+    R: Read,
+    F: Fn(&Record, Result<(), TransactionError>, &Accounts) + Sync,
+{
+    process_transaction_source_parallel_with_progress(
+        source,
+        accounts,
+        threads,
+        Duration::MAX,
+        |_| {},
+        on_executed,
+    )
+}
+
+/// Apply transactions parsed from a reader to accounts on multiple threads, reporting the
+/// progress of each shard
+///
+/// This is like [`process_transaction_source_parallel`], but `on_progress` is called on the
+/// calling thread about every `interval` with the progress of every shard, and once more when
+/// all transactions are applied.
+pub fn process_transaction_source_parallel_with_progress<R, P, F>(
+    source: R,
+    accounts: &mut Accounts,
+    threads: NonZeroUsize,
+    interval: Duration,
+    mut on_progress: P,
+    on_executed: F,
+) -> Result<(), String>
+where
+    R: Read,
+    P: FnMut(&[ShardProgress]),
+    F: Fn(&Record, Result<(), TransactionError>, &Accounts) + Sync,
+{
+    // Accruals are posted to every account at the end of a period, which a shard cannot tell
+    // from its own clients' transactions
+    if accounts.accrual_schedule().is_some() {
+        return Err("Accounts with an accrual schedule cannot be processed in parallel".into());
+    }
+    let _span = tracing::info_span!("process_source_parallel", threads).entered();
+    let threads = threads.get();
+    let mut progress = vec![ShardProgress::default(); threads];
+    let applied: Vec<AtomicU64> = (0..threads).map(|_| AtomicU64::new(0)).collect();
+    let mut assigned: HashMap<ClientId, usize> = HashMap::new();
+
+    // Split existing accounts into shards
+    let mut shards: Vec<Mutex<Accounts>> = (0..threads)
+        .map(|_| {
+            let mut shard = Accounts::with_precision(accounts.precision());
+            shard.set_admin_transactions(accounts.admin_transactions());
+            shard.set_dispute_limits(accounts.dispute_limits());
+            shard.set_withdrawal_retries(accounts.withdrawal_retries());
+            shard.set_settlement_period(accounts.settlement_period());
+            shard.set_sandbox(accounts.sandbox());
+            shard.set_representment_unfreezes(accounts.representment_unfreezes());
+            shard.set_fee_overdraft(accounts.fee_overdraft());
+            shard.set_overdraft_limits(accounts.overdraft_limits().clone());
+            shard.set_clock(accounts.clock().cloned());
+            shard.set_balance_events(accounts.balance_events().cloned());
+            // Memory is shared between the shards
+            if let Some(spill) = accounts.history_spill() {
+                shard.set_history_spill(HistorySpill {
+                    limit: spill.limit.div_ceil(threads),
+                    ..spill.clone()
+                });
+            }
+            Mutex::new(shard)
+        })
+        .collect();
+    let clients: Vec<ClientId> = accounts.iter().map(|(client, _)| client).collect();
+    for (i, client) in clients.into_iter().enumerate() {
+        let account = accounts.remove(client).unwrap();
+        let shard = i % threads;
+        shards[shard].get_mut().unwrap().insert(client, account);
+        assigned.insert(client, shard);
+        progress[shard].clients += 1;
+    }
+    // Get the shard of a client, assigning new clients to the least busy shard
+    let mut shard_of = |client: ClientId, progress: &mut [ShardProgress]| {
+        *assigned.entry(client).or_insert_with(|| {
+            let (shard, _) = progress
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, shard)| (shard.queued, shard.clients))
+                .unwrap();
+            progress[shard].clients += 1;
+            shard
+        })
+    };
+
+    let result: Result<Vec<u64>, String> = thread::scope(|scope| {
+        let on_executed = &on_executed;
+        let senders: Vec<Sender<Message>> = shards
+            .iter()
+            .zip(&applied)
+            .map(|(shard, applied)| {
+                let (sender, receiver) = mpsc::channel();
+                scope.spawn(move || {
+                    for message in receiver {
+                        match message {
+                            Message::Apply(batch) => {
+                                let mut shard = shard.lock().unwrap();
+                                for record in batch {
+                                    let _span = logging::transaction_span(&record).entered();
+                                    let result = shard.transact(record.tx.clone());
+                                    on_executed(&record, result, &shard);
+                                    applied.fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                            Message::Flush(ack) => {
+                                let _ = ack.send(());
+                            }
+                        }
+                    }
+                });
+                sender
+            })
+            .collect();
+        let mut batches: Vec<Vec<Record>> = vec![Vec::new(); threads];
+        let mut sent = vec![0; threads];
+        // Workers only stop early if they panic, which is propagated by the scope
+        let send = |shard: usize, batch: &mut Vec<Record>, sent: &mut [u64]| {
+            if !batch.is_empty() {
+                sent[shard] += batch.len() as u64;
+                let _ = senders[shard].send(Message::Apply(std::mem::take(batch)));
+            }
+        };
+        let mut last_report = Instant::now();
+        for record in records(source) {
+            // Break on I/O or parse error
+            let record = record?;
+            let from = shard_of(record.tx.client, &mut progress);
+            let client = match record.tx.tx {
+                Transaction::Transfer { to, .. } if shard_of(to, &mut progress) != from => to,
+                _ => {
+                    batches[from].push(record);
+                    if batches[from].len() == BATCH_SIZE {
+                        send(from, &mut batches[from], &mut sent);
+                        // Queue depths decide which shard new clients are assigned to
+                        update(&mut progress, &sent, &applied);
+                        if last_report.elapsed() >= interval {
+                            on_progress(&progress);
+                            last_report = Instant::now();
+                        }
+                    }
+                    continue;
+                }
+            };
+            let to = shard_of(client, &mut progress);
+            // Wait for both shards to catch up before transferring between them
+            let (ack, acked) = mpsc::channel();
+            for shard in [from, to] {
+                send(shard, &mut batches[shard], &mut sent);
+                let _ = senders[shard].send(Message::Flush(ack.clone()));
+            }
+            drop(ack);
+            while acked.recv().is_ok() {}
+            let _span = logging::transaction_span(&record).entered();
+            // Move the destination account into the source's shard for the transfer. Its pending
+            // deposits stay scheduled in its own shard.
+            let mut source = shards[from].lock().unwrap();
+            let mut destination = shards[to].lock().unwrap();
+            if let Some(account) = destination.remove(client) {
+                source.insert_moved(client, account);
+            }
+            let result = source.transact(record.tx.clone());
+            if let Some(account) = source.remove(client) {
+                destination.insert_moved(client, account);
+            }
+            on_executed(&record, result, &source);
+            // The transfer counts as applied by the source's shard
+            sent[from] += 1;
+            applied[from].fetch_add(1, Ordering::Relaxed);
+        }
+        for (shard, batch) in batches.iter_mut().enumerate() {
+            send(shard, batch, &mut sent);
+        }
+        Ok(sent)
+    });
+
+    // Merge shards
+    for shard in shards {
+        accounts.append(shard.into_inner().unwrap());
+    }
+    let sent = result?;
+    update(&mut progress, &sent, &applied);
+    on_progress(&progress);
+    Ok(())
+}
+
+/// Update the progress of every shard from the number of transactions sent to and applied by it
+fn update(progress: &mut [ShardProgress], sent: &[u64], applied: &[AtomicU64]) {
+    for ((progress, &sent), applied) in progress.iter_mut().zip(sent).zip(applied) {
+        progress.applied = applied.load(Ordering::Relaxed);
+        progress.queued = sent.saturating_sub(progress.applied);
+    }
+}
@@ -0,0 +1,30 @@
+//! Reading transactions from Parquet files
+//!
+//! This module is enabled by the `parquet` feature. A Parquet file has the same columns as
+//! CSV input with a header row: `type`, `client`, `tx`, and optionally `amount` and `to`.
+//! Columns are found by name, ignoring case, and may be of any type that can be displayed,
+//! such as strings, integers, floats, or decimals. Null values are treated as empty fields.
+//!
+//! Record batches are read as they are needed, so files larger than memory can be processed.
+//! Since Parquet has no lines, the `line_no` of each [`Record`](crate::Record) is its 1-based
+//! row number, and its `line` is the row's fields joined by commas.
+
+use std::fs::File;
+
+use ::parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+
+use crate::batches::BatchRecords;
+
+/// An iterator over the transactions in a Parquet file
+pub type ParquetRecords = BatchRecords<ParquetRecordBatchReader>;
+
+/// Iterate over the transactions in a Parquet file
+///
+/// Fails if the file is not Parquet or is missing a required column. Errors reading or
+/// parsing a row are reported with its row number.
+pub fn parquet_records(file: File) -> Result<ParquetRecords, String> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file).map_err(|e| e.to_string())?;
+    let schema = builder.schema().clone();
+    let reader = builder.build().map_err(|e| e.to_string())?;
+    BatchRecords::new(&schema, reader)
+}
@@ -0,0 +1,105 @@
+//! Hints to the operating system for sharing a host politely
+//!
+//! Giant batch runs can starve other workloads on shared hosts. [`lower_cpu_priority`] and
+//! [`lower_io_priority`] make the process yield to them, like `nice` and `ionice`, and
+//! [`advise_sequential`] tells the operating system that a file will be read from start to
+//! end, so it can read further ahead. Hints the platform does not support return an error of
+//! kind [`io::ErrorKind::Unsupported`].
+
+use std::{fs::File, io};
+
+/// Lower the CPU priority of the process to the lowest, like `nice -n 19`
+///
+/// On Windows, this puts the process in background mode, which lowers its IO priority too.
+pub fn lower_cpu_priority() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        // The lowest priority is the highest niceness
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(windows)]
+    {
+        background_mode()
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        Err(unsupported("Lowering CPU priority"))
+    }
+}
+
+/// Lower the IO priority of the process so it only uses disks no one else is using, like
+/// `ionice -c 3`
+///
+/// This is supported on Linux and Windows. On Windows, this puts the process in background
+/// mode, which lowers its CPU priority too.
+pub fn lower_io_priority() -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        let priority = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        // There is no wrapper for ioprio_set in libc
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(windows)]
+    {
+        background_mode()
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    {
+        Err(unsupported("Lowering IO priority"))
+    }
+}
+
+/// Advise the operating system that a file will be read sequentially, like
+/// `posix_fadvise(POSIX_FADV_SEQUENTIAL)`
+///
+/// This is supported on Linux, Android, and FreeBSD.
+pub fn advise_sequential(file: &File) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    {
+        use std::os::fd::AsRawFd;
+
+        // The advice covers the whole file, and the error is returned rather than set in errno
+        let error =
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+        Ok(())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    {
+        let _ = file;
+        Err(unsupported("Advising sequential reads"))
+    }
+}
+
+/// Put the process in background mode, which lowers both its CPU and IO priority
+#[cfg(windows)]
+fn background_mode() -> io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess, SetPriorityClass, PROCESS_MODE_BACKGROUND_BEGIN,
+    };
+
+    if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// An error for a hint that is not supported on this platform
+#[cfg(not(target_os = "linux"))]
+fn unsupported(hint: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported on this platform", hint),
+    )
+}
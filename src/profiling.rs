@@ -0,0 +1,48 @@
+//! CPU profiling of processing
+//!
+//! This module, enabled by the `profiling` feature, samples the call stacks of the process
+//! while transactions are processed and renders them as a flamegraph.
+
+use std::{fs::File, path::PathBuf};
+
+use pprof::{ProfilerGuard, ProfilerGuardBuilder};
+
+/// The number of call stack samples taken per second
+///
+/// This is deliberately not a round number, so sampling does not line up with periodic work.
+const SAMPLE_FREQUENCY: i32 = 997;
+
+/// Samples call stacks until finished
+pub struct Profiler {
+    guard: ProfilerGuard<'static>,
+    out: PathBuf,
+}
+
+/// Start profiling, writing a flamegraph SVG to `out` when finished
+pub fn start(out: impl Into<PathBuf>) -> Result<Profiler, String> {
+    let guard = ProfilerGuardBuilder::default()
+        .frequency(SAMPLE_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|e| format!("Unable to start profiler: {}", e))?;
+    Ok(Profiler {
+        guard,
+        out: out.into(),
+    })
+}
+
+impl Profiler {
+    /// Stop profiling and write the flamegraph
+    pub fn finish(self) -> Result<(), String> {
+        let report = self
+            .guard
+            .report()
+            .build()
+            .map_err(|e| format!("Unable to build profile: {}", e))?;
+        let file = File::create(&self.out)
+            .map_err(|e| format!("Unable to create {:?}: {}", self.out, e))?;
+        report
+            .flamegraph(file)
+            .map_err(|e| format!("Unable to write flamegraph to {:?}: {}", self.out, e))
+    }
+}
@@ -0,0 +1,150 @@
+//! Reporting progress through long runs
+//!
+//! An [`InputProgress`] logs how much of the input has been read and how quickly transactions
+//! are being applied, and [`shard_reporter`] does the same for each shard when processing in
+//! parallel. Reports are `info` events, made at most once per interval.
+
+use std::{
+    cell::Cell,
+    io::{self, Read},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::parallel::ShardProgress;
+
+/// Reports progress through the input
+pub struct InputProgress {
+    interval: Duration,
+    /// The number of bytes read from the input so far
+    read: Rc<Cell<u64>>,
+    /// The size of the input, if it is a file
+    size: Option<u64>,
+    lines: usize,
+    transactions: u64,
+    /// When the previous report was made, and the number of transactions at that point
+    last: (Instant, u64),
+}
+
+impl InputProgress {
+    /// Create a reporter that reports once per interval
+    ///
+    /// `read` is the number of bytes read so far, such as counted by a [`CountingReader`],
+    /// and `size` is the size of the input, if it is known.
+    pub fn new(interval: Duration, read: Rc<Cell<u64>>, size: Option<u64>) -> Self {
+        InputProgress {
+            interval,
+            read,
+            size,
+            lines: 0,
+            transactions: 0,
+            last: (Instant::now(), 0),
+        }
+    }
+    /// Observe the execution of a transaction, reporting progress if the interval has passed
+    pub fn observe(&mut self, line_no: usize) {
+        self.lines = line_no;
+        self.transactions += 1;
+        if self.last.0.elapsed() >= self.interval {
+            self.report();
+        }
+    }
+    /// Report the bytes, lines, and transactions processed, and the throughput since the
+    /// previous report
+    pub fn report(&mut self) {
+        let (last_time, last_transactions) = self.last;
+        let secs = last_time.elapsed().as_secs_f64().max(f64::EPSILON);
+        let throughput = (self.transactions - last_transactions) as f64 / secs;
+        let (read, lines, transactions) = (self.read.get(), self.lines, self.transactions);
+        match self.size {
+            Some(size) => tracing::info!(
+                read,
+                size,
+                lines,
+                transactions,
+                throughput,
+                "Read {} of {} bytes ({:.1}%), {} lines, {} transactions ({:.0}/s)",
+                read,
+                size,
+                read as f64 * 100.0 / size.max(1) as f64,
+                lines,
+                transactions,
+                throughput
+            ),
+            None => tracing::info!(
+                read,
+                lines,
+                transactions,
+                throughput,
+                "Read {} bytes, {} lines, {} transactions ({:.0}/s)",
+                read,
+                lines,
+                transactions,
+                throughput
+            ),
+        }
+        self.last = (Instant::now(), self.transactions);
+    }
+}
+
+/// Get a function that reports the progress of each shard
+///
+/// Throughput is measured since the previous report. Each report ends with how unevenly
+/// transactions have been split between the shards.
+pub fn shard_reporter() -> impl FnMut(&[ShardProgress]) {
+    let mut last = (Instant::now(), Vec::new());
+    move |shards| {
+        let (last_time, last_applied) = &mut last;
+        let secs = last_time.elapsed().as_secs_f64().max(f64::EPSILON);
+        last_applied.resize(shards.len(), 0);
+        for (i, (shard, last_applied)) in shards.iter().zip(last_applied.iter_mut()).enumerate() {
+            let throughput = (shard.applied - *last_applied) as f64 / secs;
+            tracing::info!(
+                shard = i,
+                applied = shard.applied,
+                queued = shard.queued,
+                clients = shard.clients,
+                throughput,
+                "Shard {}: {} applied ({:.0}/s), {} queued, {} clients",
+                i,
+                shard.applied,
+                throughput,
+                shard.queued,
+                shard.clients
+            );
+            *last_applied = shard.applied;
+        }
+        *last_time = Instant::now();
+        let total: u64 = shards.iter().map(|shard| shard.applied).sum();
+        let busiest = shards.iter().map(|shard| shard.applied).max().unwrap_or(0);
+        if total > 0 {
+            let imbalance = busiest as f64 * shards.len() as f64 / total as f64;
+            tracing::info!(
+                imbalance,
+                "The busiest shard applied {:.2}x its share of transactions",
+                imbalance
+            );
+        }
+    }
+}
+
+/// A reader that counts the bytes read from it
+pub struct CountingReader<R> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R> CountingReader<R> {
+    /// Wrap a reader, adding the bytes read from it to a count
+    pub fn new(inner: R, read: Rc<Cell<u64>>) -> Self {
+        CountingReader { inner, read }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
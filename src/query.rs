@@ -0,0 +1,198 @@
+//! Filter expressions over account summaries
+//!
+//! A [`Query`] is a boolean expression such as `held > 0 && !locked`. It is made of
+//! comparisons of a balance (`available`, `held` or `total`) or the `client` id with a
+//! number, and of the flags `locked` and `closed`. These are combined with `!`, `&&`, `||`
+//! and parentheses, where `&&` binds more tightly than `||`.
+
+use std::str::FromStr;
+
+use crate::{account::Account, alert::Balance, amount::Amount, transaction::ClientId};
+
+/// A comparison of two values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+}
+
+impl Comparison {
+    fn test<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+        }
+    }
+}
+
+/// A filter expression over account summaries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// Compare a balance with an amount
+    Balance(Balance, Comparison, Amount),
+    /// Compare the client id with a number
+    Client(Comparison, ClientId),
+    /// Whether the account is locked
+    Locked,
+    /// Whether the account is closed
+    Closed,
+    /// Whether a query does not match
+    Not(Box<Query>),
+    /// Whether both queries match
+    And(Box<Query>, Box<Query>),
+    /// Whether either query matches
+    Or(Box<Query>, Box<Query>),
+}
+
+impl Query {
+    /// Check whether a client's account matches the query
+    pub fn matches(&self, client: ClientId, account: &Account) -> bool {
+        match self {
+            Query::Balance(balance, comparison, amount) => {
+                comparison.test(balance.of(account), *amount)
+            }
+            Query::Client(comparison, id) => comparison.test(client, *id),
+            Query::Locked => account.is_frozen(),
+            Query::Closed => account.is_closed(),
+            Query::Not(query) => !query.matches(client, account),
+            Query::And(a, b) => a.matches(client, account) && b.matches(client, account),
+            Query::Or(a, b) => a.matches(client, account) || b.matches(client, account),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let query = parser.or()?;
+        match parser.next() {
+            None => Ok(query),
+            Some(token) => Err(format!("Unexpected {:?} in query", token)),
+        }
+    }
+}
+
+/// Operators, longest first so that `<=` is not read as `<`
+const OPERATORS: [&str; 11] = ["&&", "||", "<=", ">=", "==", "!=", "<", ">", "!", "(", ")"];
+
+/// Split a query into words, numbers and operators
+fn tokenize(s: &str) -> Result<Vec<&str>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let len = if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            op.len()
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(format!("Unexpected {:?} in query", rest));
+            }
+            len
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of a query
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.tokens.get(self.pos) == Some(&token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+    fn or(&mut self) -> Result<Query, String> {
+        let mut query = self.and()?;
+        while self.eat("||") {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+    fn and(&mut self) -> Result<Query, String> {
+        let mut query = self.unary()?;
+        while self.eat("&&") {
+            query = Query::And(Box::new(query), Box::new(self.unary()?));
+        }
+        Ok(query)
+    }
+    fn unary(&mut self) -> Result<Query, String> {
+        if self.eat("!") {
+            return Ok(Query::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let query = self.or()?;
+            if !self.eat(")") {
+                return Err("Expected a closing parenthesis in query".into());
+            }
+            return Ok(query);
+        }
+        let balance = match self.next() {
+            Some("locked") => return Ok(Query::Locked),
+            Some("closed") => return Ok(Query::Closed),
+            Some("client") => None,
+            Some("available") => Some(Balance::Available),
+            Some("held") => Some(Balance::Held),
+            Some("total") => Some(Balance::Total),
+            Some(token) => return Err(format!("Unknown field {:?} in query", token)),
+            None => return Err("Unexpected end of query".into()),
+        };
+        let comparison = match self.next() {
+            Some("<") => Comparison::Less,
+            Some("<=") => Comparison::LessOrEqual,
+            Some(">") => Comparison::Greater,
+            Some(">=") => Comparison::GreaterOrEqual,
+            Some("==") => Comparison::Equal,
+            Some("!=") => Comparison::NotEqual,
+            _ => return Err("Expected a comparison in query".into()),
+        };
+        let value = self.next().ok_or("Expected a value in query")?;
+        Ok(match balance {
+            Some(balance) => Query::Balance(
+                balance,
+                comparison,
+                value
+                    .parse()
+                    .map_err(|e| format!("Invalid amount {:?} in query: {}", value, e))?,
+            ),
+            None => Query::Client(
+                comparison,
+                value
+                    .parse()
+                    .map_err(|e| format!("Invalid client {:?} in query: {}", value, e))?,
+            ),
+        })
+    }
+}
@@ -0,0 +1,230 @@
+//! Cross-checking disputes against processor chargeback reports
+//!
+//! Payment processors report the chargebacks they have issued. A report is read from a CSV
+//! file with a header row and at least the columns `client, tx`; other columns are ignored.
+//! [`cross_check`] compares a report with the disputes executed by the engine, as recorded
+//! in a [`DisputeLedger`], and lists chargebacks the engine has no record of and disputes
+//! the processor never acknowledged.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    io::{self, Read, Write},
+};
+
+use csv::{ReaderBuilder, Trim};
+
+use crate::{account::TransactionError, transaction::*};
+
+/// A chargeback listed in a processor's report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProcessorChargeback {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+
+/// Read the chargebacks listed in a processor's report
+pub fn read_chargebacks<R: Read>(source: R) -> Result<Vec<ProcessorChargeback>, String> {
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(source);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Error reading chargeback report: {}", e))?
+        .clone();
+    let required = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Chargeback report is missing a {:?} column", name))
+    };
+    let client_col = required("client")?;
+    let tx_col = required("tx")?;
+
+    let mut chargebacks = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let row = i + 1;
+        let record = record.map_err(|e| format!("Error reading chargeback {}: {}", row, e))?;
+        let field = |col: usize| record.get(col).unwrap_or_default();
+        let invalid = |name: &str, field: &str| {
+            format!("Invalid chargeback {}: invalid {} {:?}", row, name, field)
+        };
+        let client = field(client_col);
+        let tx = field(tx_col);
+        chargebacks.push(ProcessorChargeback {
+            client: client.parse().map_err(|_| invalid("client id", client))?,
+            tx: tx.parse().map_err(|_| invalid("transaction id", tx))?,
+        });
+    }
+    Ok(chargebacks)
+}
+
+/// The state of a dispute executed by the engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeStatus {
+    /// The dispute has not been resolved or charged back
+    Open,
+    /// The dispute was resolved
+    Resolved,
+    /// The dispute was charged back
+    ChargedBack,
+    /// The dispute was charged back, and the chargeback was reversed by a representment
+    Represented,
+}
+
+impl fmt::Display for DisputeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisputeStatus::Open => write!(f, "open"),
+            DisputeStatus::Resolved => write!(f, "resolved"),
+            DisputeStatus::ChargedBack => write!(f, "charged_back"),
+            DisputeStatus::Represented => write!(f, "represented"),
+        }
+    }
+}
+
+/// A record of the disputes executed by the engine and their latest states
+#[derive(Debug, Default)]
+pub struct DisputeLedger {
+    disputes: BTreeMap<(ClientId, TransactionId), DisputeStatus>,
+}
+
+impl DisputeLedger {
+    /// Observe the result of executing a transaction
+    ///
+    /// Only disputes and resolutions that were applied are recorded.
+    pub fn observe(&mut self, tx: &ClientTransaction, result: &Result<(), TransactionError>) {
+        if result.is_err() {
+            return;
+        }
+        let (tx_id, status) = match tx.tx {
+            Transaction::Change { .. }
+            | Transaction::Transfer { .. }
+            | Transaction::Unfreeze(_) => return,
+            Transaction::Dispute { tx_id, .. } => (tx_id, DisputeStatus::Open),
+            Transaction::Resolution { kind, tx_id, .. } => match kind {
+                ResolutionKind::Resolve => (tx_id, DisputeStatus::Resolved),
+                ResolutionKind::Chargeback => (tx_id, DisputeStatus::ChargedBack),
+            },
+            Transaction::Representment(tx_id) => (tx_id, DisputeStatus::Represented),
+        };
+        self.disputes.insert((tx.client, tx_id), status);
+    }
+    /// Get the state of the latest dispute of a transaction, if it was ever disputed
+    pub fn status(&self, client: ClientId, tx_id: TransactionId) -> Option<DisputeStatus> {
+        self.disputes.get(&(client, tx_id)).copied()
+    }
+}
+
+/// A disagreement between the engine and a processor's chargeback report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// The processor charged back a transaction that the engine has not charged back
+    UnrecordedChargeback {
+        client: ClientId,
+        tx: TransactionId,
+        /// The state of the engine's dispute of the transaction, if there was one
+        status: Option<DisputeStatus>,
+    },
+    /// The engine has an open or charged back dispute that the processor did not report
+    UnacknowledgedDispute {
+        client: ClientId,
+        tx: TransactionId,
+        status: DisputeStatus,
+    },
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Discrepancy::UnrecordedChargeback { client, tx, status } => {
+                write!(
+                    f,
+                    "Processor charged back transaction {} of client {}, ",
+                    tx, client
+                )?;
+                match status {
+                    Some(status) => write!(f, "but its dispute is {}", status),
+                    None => write!(f, "but it was never disputed"),
+                }
+            }
+            Discrepancy::UnacknowledgedDispute { client, tx, status } => write!(
+                f,
+                "Dispute of transaction {} of client {} is {}, but the processor did not report it",
+                tx, client, status
+            ),
+        }
+    }
+}
+
+/// The result of cross-checking a processor's chargeback report
+#[derive(Debug, Default)]
+pub struct CrossCheck {
+    /// The number of reported chargebacks the engine also charged back
+    pub matched: usize,
+    /// The disagreements, in order of client id and then transaction id
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Cross-check a processor's chargeback report against the engine's disputes
+///
+/// Resolved disputes and reversed chargebacks are not expected to appear in the report.
+pub fn cross_check(ledger: &DisputeLedger, report: &[ProcessorChargeback]) -> CrossCheck {
+    let reported: BTreeSet<_> = report
+        .iter()
+        .map(|chargeback| (chargeback.client, chargeback.tx))
+        .collect();
+    let mut check = CrossCheck::default();
+    for &(client, tx) in &reported {
+        match ledger.status(client, tx) {
+            Some(DisputeStatus::ChargedBack) => check.matched += 1,
+            status => {
+                check
+                    .discrepancies
+                    .push(Discrepancy::UnrecordedChargeback { client, tx, status })
+            }
+        }
+    }
+    for (&(client, tx), &status) in &ledger.disputes {
+        let expected = !matches!(status, DisputeStatus::Resolved | DisputeStatus::Represented);
+        if expected && !reported.contains(&(client, tx)) {
+            check
+                .discrepancies
+                .push(Discrepancy::UnacknowledgedDispute { client, tx, status });
+        }
+    }
+    check
+        .discrepancies
+        .sort_by_key(|discrepancy| match discrepancy {
+            Discrepancy::UnrecordedChargeback { client, tx, .. }
+            | Discrepancy::UnacknowledgedDispute { client, tx, .. } => (*client, *tx),
+        });
+    check
+}
+
+impl CrossCheck {
+    /// Write the discrepancies as CSV with the columns `discrepancy, client, tx, status`
+    ///
+    /// `status` is the state of the engine's dispute, or `none` if there was no dispute.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "discrepancy,client,tx,status")?;
+        for discrepancy in &self.discrepancies {
+            match discrepancy {
+                Discrepancy::UnrecordedChargeback { client, tx, status } => {
+                    write!(writer, "unrecorded_chargeback,{},{},", client, tx)?;
+                    match status {
+                        Some(status) => writeln!(writer, "{}", status)?,
+                        None => writeln!(writer, "none")?,
+                    }
+                }
+                Discrepancy::UnacknowledgedDispute { client, tx, status } => writeln!(
+                    writer,
+                    "unacknowledged_dispute,{},{},{}",
+                    client, tx, status
+                )?,
+            }
+        }
+        writer.flush()
+    }
+}
@@ -0,0 +1,200 @@
+//! Recording and replaying the order in which transactions are applied
+//!
+//! When transactions arrive from multiple sources at once, such as several
+//! connections to a server, the order in which they are applied is not determined
+//! by any single input. A recording captures that order so it can be replayed exactly.
+//!
+//! Recordings are CSV files whose rows are a source id followed by a transaction,
+//! in the same format as regular input.
+//!
+//! By default every record is committed to the underlying writer as soon as it is
+//! recorded. With [`GroupCommit`], records are instead committed in groups, which
+//! is much faster on slow disks. Only committed records survive a crash, so a
+//! transaction should not be acknowledged until [`Recorder::committed`] covers it.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{account::Accounts, transaction::ClientTransaction};
+
+/// An identifier for a source of transactions, such as a connection
+pub type SourceId = u64;
+
+const HEADER: &str = "source,type,client,tx,amount";
+
+/// Thresholds for committing recorded transactions in groups
+///
+/// A group is committed as soon as either threshold is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupCommit {
+    /// The maximum number of records in a group
+    pub max_records: usize,
+    /// The maximum time a record may wait to be committed
+    ///
+    /// This is checked whenever a transaction is recorded and by [`Recorder::commit_if_due`]
+    pub max_delay: Duration,
+}
+
+impl GroupCommit {
+    /// Commit every record on its own
+    pub const EACH: GroupCommit = GroupCommit {
+        max_records: 1,
+        max_delay: Duration::ZERO,
+    };
+}
+
+/// Writes a recording of the order in which transactions are applied
+#[derive(Debug)]
+pub struct Recorder<W: Write> {
+    writer: W,
+    group_commit: GroupCommit,
+    /// Records that have not been committed yet
+    ///
+    /// They are kept separately so the writer only ever receives whole groups.
+    buffer: Vec<u8>,
+    /// The number of records in the buffer
+    pending: usize,
+    /// When the oldest uncommitted record was recorded
+    pending_since: Option<Instant>,
+    committed: u64,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Start a new recording that commits every record as soon as it is recorded
+    pub fn new(writer: W) -> io::Result<Self> {
+        Recorder::with_group_commit(writer, GroupCommit::EACH)
+    }
+    /// Start a new recording that commits records in groups
+    pub fn with_group_commit(mut writer: W, group_commit: GroupCommit) -> io::Result<Self> {
+        writeln!(writer, "{}", HEADER)?;
+        writer.flush()?;
+        Ok(Recorder {
+            writer,
+            group_commit,
+            buffer: Vec::new(),
+            pending: 0,
+            pending_since: None,
+            committed: 0,
+        })
+    }
+    /// Record that a transaction from some source is being applied
+    ///
+    /// This must be called in the same critical section that applies the transaction,
+    /// so that the recorded order is the applied order.
+    pub fn record(&mut self, source: SourceId, tx: &ClientTransaction) -> io::Result<()> {
+        writeln!(self.buffer, "{},{}", source, tx)?;
+        self.pending += 1;
+        self.pending_since.get_or_insert_with(Instant::now);
+        if self.pending >= self.group_commit.max_records {
+            self.commit()
+        } else {
+            self.commit_if_due()
+        }
+    }
+    /// Commit the pending records if the oldest has waited longer than the group's maximum delay
+    ///
+    /// Callers that may go a while without recording anything should call this periodically.
+    pub fn commit_if_due(&mut self) -> io::Result<()> {
+        match self.pending_since {
+            Some(since) if since.elapsed() >= self.group_commit.max_delay => self.commit(),
+            _ => Ok(()),
+        }
+    }
+    /// Commit all pending records to the underlying writer
+    pub fn commit(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.buffer)?;
+        self.writer.flush()?;
+        self.buffer.clear();
+        self.committed += self.pending as u64;
+        self.pending = 0;
+        self.pending_since = None;
+        Ok(())
+    }
+    /// Get the number of records that have been committed
+    ///
+    /// Transactions up to this count can be acknowledged, as they will be replayed after a crash.
+    pub fn committed(&self) -> u64 {
+        self.committed
+    }
+    /// Get a reference to the underlying writer
+    ///
+    /// It only contains committed records.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+    /// Commit any pending records and finish the recording, returning the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.commit()?;
+        Ok(self.writer)
+    }
+}
+
+/// A transaction read from a recording
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    /// The source the transaction came from
+    pub source: SourceId,
+    /// The transaction
+    pub tx: ClientTransaction,
+}
+
+/// Iterate over the transactions in a recording, in the order they were applied
+pub fn recorded<R>(recording: R) -> impl Iterator<Item = Result<Recorded, String>>
+where
+    R: Read,
+{
+    BufReader::new(recording)
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line_no = i + 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(format!("Error reading line {}: {}", line_no, e))),
+            };
+            // Skip empty lines and the header row
+            if line.trim().is_empty() || i == 0 && line.trim() == HEADER {
+                return None;
+            }
+            let invalid = |e: &dyn std::fmt::Display| {
+                format!("Invalid recording entry on line {}: {}", line_no, e)
+            };
+            let Some((source, tx)) = line.split_once(',') else {
+                return Some(Err(invalid(&"Missing source id")));
+            };
+            let source = match source.trim().parse::<SourceId>() {
+                Ok(source) => source,
+                Err(e) => return Some(Err(invalid(&e))),
+            };
+            Some(
+                tx.parse::<ClientTransaction>()
+                    .map(|tx| Recorded { source, tx })
+                    .map_err(|e| invalid(&e)),
+            )
+        })
+}
+
+/// Apply the transactions in a recording to accounts in the order they were originally applied
+pub fn replay<R>(recording: R, accounts: &mut Accounts) -> Result<(), String>
+where
+    R: Read,
+{
+    for recorded in recorded(recording) {
+        let recorded = recorded?;
+        let (client, tx) = (recorded.tx.client, recorded.tx.tx.tx_id());
+        if let Err(e) = accounts.transact(recorded.tx) {
+            tracing::warn!(
+                source = recorded.source,
+                client,
+                tx,
+                reason = e.code(),
+                "Error executing transaction from source {}: {}",
+                recorded.source,
+                e
+            );
+        }
+    }
+    Ok(())
+}
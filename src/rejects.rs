@@ -0,0 +1,77 @@
+//! A report of rejected transactions
+//!
+//! Transactions that are parsed but rejected by the engine, such as withdrawals from accounts
+//! without enough funds, are written to a [`Rejects`] report so they can be followed up on.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use csv::Writer;
+
+use crate::{account::TransactionError, input::Record};
+
+/// A CSV report of rejected transactions
+///
+/// The report has a header row and the columns `line_no, line, reason, error`. `line` is the
+/// line of input the transaction was parsed from, `reason` is a machine-readable code from
+/// [`TransactionError::code`], and `error` is the human-readable message.
+pub struct Rejects<W: Write> {
+    writer: Writer<W>,
+    error: Option<io::Error>,
+}
+
+impl Rejects<File> {
+    /// Create a report file, replacing any existing one
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Rejects::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> Rejects<W> {
+    /// Create a report that writes to a writer
+    pub fn new(writer: W) -> Self {
+        let mut rejects = Rejects {
+            writer: Writer::from_writer(writer),
+            error: None,
+        };
+        if let Err(e) = rejects
+            .writer
+            .write_record(["line_no", "line", "reason", "error"])
+        {
+            rejects.error = Some(e.into());
+        }
+        rejects
+    }
+    /// Observe the execution of a transaction, writing it to the report if it was rejected
+    ///
+    /// Rows are buffered. If writing fails, nothing more is written and the error is returned
+    /// by [`Rejects::flush`].
+    pub fn observe(&mut self, record: &Record, result: &Result<(), TransactionError>) {
+        let Err(e) = result else {
+            return;
+        };
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.writer.write_record([
+            record.line_no.to_string().as_str(),
+            &record.line,
+            e.code(),
+            &e.to_string(),
+        ]) {
+            self.error = Some(e.into());
+        }
+    }
+    /// Write any buffered rows
+    ///
+    /// If an earlier row failed to be written, its error is returned instead.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+}
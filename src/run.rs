@@ -0,0 +1,580 @@
+//! Processing input files from start to finish
+//!
+//! [`run`] is what the `transactor` command does without a subcommand: it executes the
+//! transactions of every input, or consumes them from Kafka, and then writes the resulting
+//! account data along with every report that was asked for in [`RunOptions`].
+
+use std::{
+    cell::Cell,
+    fs::File,
+    io::BufWriter,
+    num::{NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+    rc::Rc,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    accrual::AccrualPolicy,
+    alert::{AlertMonitor, AlertRule},
+    audit::AuditLog,
+    client_map::ClientMap,
+    duplicates::Duplicates,
+    events, input, logging,
+    metrics::Metrics,
+    notify::{DigestTracker, Notifier},
+    output::{self, Output},
+    parallel, priority,
+    progress::{self, CountingReader, InputProgress},
+    rejects::Rejects,
+    settings::Settings,
+    source::{self, BatchInput, InputOptions},
+    store::{self, StoreWriter},
+    try_process_records_with, try_process_transaction_source_with, Accounts, ProcessError,
+    ProcessingSummary, Record, SettlementPeriod,
+};
+
+/// The Kafka consumer group used if none is given
+pub const DEFAULT_KAFKA_GROUP: &str = "transactor";
+
+/// The number of seconds between emitting accounts when consuming from Kafka if no interval is
+/// given
+pub const DEFAULT_EMIT_INTERVAL: u64 = 60;
+
+/// Options for consuming transactions from Kafka, which needs the `kafka` feature
+#[derive(Debug, Clone)]
+pub struct KafkaOptions {
+    pub brokers: String,
+    pub topic: String,
+    pub group: String,
+    /// How often accounts are emitted and offsets committed
+    pub interval: Duration,
+}
+
+/// What to process and what to produce from it
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    pub settings: Settings,
+    pub output: Output,
+    /// The input paths, applied in order to the same accounts, see [`source::open`]
+    pub inputs: Vec<String>,
+    pub input: InputOptions,
+    /// Consume transactions from Kafka instead of reading inputs
+    pub kafka: Option<KafkaOptions>,
+    /// Process each input in parallel with this many threads
+    pub threads: Option<NonZeroUsize>,
+    /// Report progress once per this many seconds
+    pub progress: Option<NonZeroU64>,
+    /// Stop at the first rejected transaction
+    pub strict: bool,
+    pub low_priority: bool,
+    pub low_io_priority: bool,
+    /// Write a summary of the transactions applied and rejected to standard error
+    pub summary: bool,
+    /// The address to serve metrics on, which needs the `http` feature
+    pub metrics: Option<String>,
+    /// The file to write a flamegraph to, which needs the `profiling` feature
+    pub profile_out: Option<PathBuf>,
+    pub save_state: Option<PathBuf>,
+    /// An account store, see [`store::open`]
+    pub store: Option<String>,
+    pub audit_log: Option<PathBuf>,
+    pub rejects: Option<PathBuf>,
+    pub duplicates: Option<PathBuf>,
+    pub balance_events: Option<PathBuf>,
+    pub client_map: Option<PathBuf>,
+    /// Accrue interest and penalties once at the end of the run
+    ///
+    /// With an accrual schedule in the settings, accruals are posted during processing
+    /// instead.
+    pub accrual: Option<AccrualPolicy>,
+    pub alerts: Vec<AlertRule>,
+    pub notify: Notifier,
+}
+
+impl RunOptions {
+    /// Check that the options can be used together
+    pub fn check(&self) -> Result<(), String> {
+        self.output.check()?;
+        let threads = self.threads.is_some();
+        let kafka = self.kafka.is_some();
+        let store = self.store.is_some();
+        let client_map = self.client_map.is_some();
+        let schedule = self.settings.accrual_schedule.is_some();
+        if store && threads {
+            return Err("--store cannot be used with --threads".into());
+        }
+        if store && self.settings.load_state.is_some() {
+            return Err("--store cannot be used with --load-state".into());
+        }
+        if kafka && threads {
+            return Err("--kafka cannot be used with --threads".into());
+        }
+        if self.settings.settlement_period != SettlementPeriod::default() && threads {
+            return Err(
+                "--settlement-period and --settlement-days cannot be used with --threads".into(),
+            );
+        }
+        if self.strict && threads {
+            return Err("--strict cannot be used with --threads".into());
+        }
+        // The hash chain follows the order transactions are applied in, which differs between
+        // threaded runs
+        if self.audit_log.is_some() && threads {
+            return Err("--audit-log cannot be used with --threads".into());
+        }
+        if schedule && threads {
+            return Err("--accrue-every cannot be used with --threads".into());
+        }
+        // The store only saves the accounts of the transactions it observes
+        if schedule && store {
+            return Err("--accrue-every cannot be used with --store".into());
+        }
+        if client_map && threads {
+            return Err("--client-map cannot be used with --threads".into());
+        }
+        #[cfg(feature = "arrow")]
+        if client_map && self.output.format == output::OutputFormat::Arrow {
+            return Err("--client-map cannot be used with Arrow output".into());
+        }
+        if self.strict && kafka {
+            return Err("--strict cannot be used with --kafka".into());
+        }
+        if self.accrual.is_some() && !schedule && kafka {
+            return Err("--accrue cannot be used with --kafka without --accrue-every".into());
+        }
+        if self.progress.is_some() && kafka {
+            return Err("--progress cannot be used with --kafka".into());
+        }
+        if client_map && kafka {
+            return Err("--client-map cannot be used with --kafka".into());
+        }
+        #[cfg(not(feature = "http"))]
+        if self.metrics.is_some() {
+            return Err(
+                "Unable to serve metrics: transactor was built without the `http` feature".into(),
+            );
+        }
+        #[cfg(not(feature = "profiling"))]
+        if self.profile_out.is_some() {
+            return Err(
+                "Unable to profile: transactor was built without the `profiling` feature".into(),
+            );
+        }
+        if kafka && self.inputs.iter().any(|path| path != "-") {
+            return Err("--kafka cannot be used with an input file".into());
+        }
+        // One digest cannot be expected of several inputs
+        if self.input.sha256.is_some() && self.inputs.len() > 1 {
+            return Err("--expect-sha256 cannot be used with more than one input".into());
+        }
+        for path in &self.inputs {
+            let Some(batch_input) = BatchInput::detect(path) else {
+                continue;
+            };
+            if source::is_url(path) {
+                return Err(format!("{} input cannot be read from a URL", batch_input));
+            }
+            let incompatible = [
+                (self.input.sha256.is_some(), "--expect-sha256"),
+                (threads, "--threads"),
+                (self.progress.is_some(), "--progress"),
+                (client_map, "--client-map"),
+            ];
+            if let Some((_, option)) = incompatible.iter().find(|(used, _)| *used) {
+                return Err(format!(
+                    "{} input cannot be used with {}",
+                    batch_input, option
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Execute the transactions of every input and write the resulting account data
+///
+/// Inputs are applied in order to the same accounts. Once they have all been processed, the
+/// accounts are persisted and written to the output along with every report, and a digest of
+/// accounts frozen or charged back is sent to the notifier.
+pub fn run(options: RunOptions) -> Result<(), String> {
+    options.check()?;
+    options.settings.authenticate()?;
+    let RunOptions {
+        settings,
+        output,
+        inputs,
+        input: input_options,
+        kafka,
+        threads,
+        progress,
+        strict,
+        low_priority,
+        low_io_priority,
+        summary,
+        metrics,
+        profile_out,
+        save_state,
+        store,
+        audit_log,
+        rejects,
+        duplicates,
+        balance_events,
+        client_map,
+        accrual,
+        alerts,
+        notify,
+    } = options;
+    // With a schedule, the policy is applied during processing instead of at the end
+    let accrual = accrual.filter(|_| settings.accrual_schedule.is_none());
+
+    // Lower priority before any input is read, so all of the run is polite
+    if low_priority {
+        if let Err(e) = priority::lower_cpu_priority() {
+            tracing::warn!("Unable to lower CPU priority: {}", e);
+        }
+    }
+    if low_io_priority {
+        if let Err(e) = priority::lower_io_priority() {
+            tracing::warn!("Unable to lower IO priority: {}", e);
+        }
+    }
+
+    // Input with an expected digest is checked before anything is written
+    let verified = match (&input_options.sha256, inputs.as_slice()) {
+        (Some(_), [path]) => Some(source::open_raw(path, &input_options)?),
+        _ => None,
+    };
+    let verified = Cell::new(verified);
+    let open_raw = |path: &str| match verified.take() {
+        Some(input) => Ok(input),
+        None => source::open_raw(path, &input_options),
+    };
+
+    // Serve metrics for as long as transactions are processed
+    let metrics = metrics.map(|address| serve_metrics(&address)).transpose()?;
+
+    let mut accounts = settings.accounts()?;
+    // Balance events are written on their own thread as they are sent
+    let balance_events = match &balance_events {
+        Some(path) => {
+            let file = File::create(path)
+                .map_err(|e| format!("Unable to create balance events {:?}: {}", path, e))?;
+            let (sender, receiver) = mpsc::channel();
+            accounts.set_balance_events(Some(sender));
+            Some(thread::spawn(move || {
+                events::write_json_lines(receiver, BufWriter::new(file))
+            }))
+        }
+        None => None,
+    };
+    let store = match &store {
+        Some(spec) => {
+            let mut store = store::open(spec)?;
+            store
+                .load(&mut accounts)
+                .map_err(|e| format!("Unable to load accounts from {}: {}", spec, e))?;
+            Some(StoreWriter::new(store))
+        }
+        None => None,
+    };
+    let store = Mutex::new(store);
+    let audit_log = audit_log
+        .map(|path| {
+            AuditLog::open(&path).map_err(|e| format!("Unable to open audit log {:?}: {}", path, e))
+        })
+        .transpose()?;
+    let audit_log = Mutex::new(audit_log);
+    let rejects = rejects
+        .map(|path| {
+            Rejects::create(&path)
+                .map_err(|e| format!("Unable to create rejects report {:?}: {}", path, e))
+        })
+        .transpose()?;
+    let rejects = Mutex::new(rejects);
+    let duplicates = duplicates
+        .map(|path| {
+            Duplicates::create(&path)
+                .map_err(|e| format!("Unable to create duplicates report {:?}: {}", path, e))
+        })
+        .transpose()?;
+    let duplicates = Mutex::new(duplicates);
+    let clients = client_map
+        .as_ref()
+        .map(|path| {
+            ClientMap::load(path)
+                .map_err(|e| format!("Unable to load client map {:?}: {}", path, e))
+        })
+        .transpose()?;
+    let clients = Mutex::new(clients);
+    let summary = Mutex::new(summary.then(ProcessingSummary::default));
+    let tracker = Mutex::new(DigestTracker::new(&accounts));
+    let alerts = Mutex::new(AlertMonitor::new(alerts));
+
+    // Profile processing
+    #[cfg(feature = "profiling")]
+    let profiler = profile_out.map(crate::profiling::start).transpose()?;
+    #[cfg(not(feature = "profiling"))]
+    let _ = profile_out;
+
+    // Process all transactions from file
+    let on_executed = |record: &Record, result, accounts: &Accounts| {
+        tracker.lock().unwrap().observe(&record.tx, &result);
+        if let Some(store) = &mut *store.lock().unwrap() {
+            store.observe(&record.tx, accounts);
+        }
+        if let Some(audit_log) = &mut *audit_log.lock().unwrap() {
+            audit_log.observe(&record.tx, &result, accounts);
+        }
+        if let Some(rejects) = &mut *rejects.lock().unwrap() {
+            rejects.observe(record, &result);
+        }
+        if let Some(duplicates) = &mut *duplicates.lock().unwrap() {
+            duplicates.observe(record, &result);
+        }
+        if let Some(summary) = &mut *summary.lock().unwrap() {
+            summary.observe(&record.tx, &result);
+        }
+        if let Some(metrics) = &metrics {
+            metrics.observe(&record.tx, &result);
+        }
+        if let Err(e) = result {
+            logging::rejected(record, &e);
+        }
+        // Raise alerts as soon as balances cross their thresholds
+        if let Some(account) = accounts.get(record.tx.client) {
+            for alert in alerts.lock().unwrap().check(record.tx.client, account) {
+                tracing::warn!(
+                    line = record.line_no,
+                    client = alert.client,
+                    rule = %alert.rule,
+                    value = %alert.value,
+                    "Alert on line {}: {}",
+                    record.line_no,
+                    alert
+                );
+            }
+        }
+    };
+    // Persist and output account data, which is done periodically when consuming from Kafka
+    let emit = |accounts: &Accounts| {
+        if let Some(metrics) = &metrics {
+            metrics.update_accounts(accounts);
+        }
+        if let Some(store) = &mut *store.lock().unwrap() {
+            store
+                .flush(accounts)
+                .map_err(|e| format!("Unable to save accounts to the store: {}", e))?;
+        }
+        if let Some(audit_log) = &mut *audit_log.lock().unwrap() {
+            audit_log
+                .flush()
+                .map_err(|e| format!("Unable to write to the audit log: {}", e))?;
+        }
+        if let Some(rejects) = &mut *rejects.lock().unwrap() {
+            rejects
+                .flush()
+                .map_err(|e| format!("Unable to write the rejects report: {}", e))?;
+        }
+        if let Some(duplicates) = &mut *duplicates.lock().unwrap() {
+            duplicates
+                .flush()
+                .map_err(|e| format!("Unable to write the duplicates report: {}", e))?;
+        }
+        if let Some(path) = &save_state {
+            accounts
+                .save_snapshot(path)
+                .map_err(|e| format!("Unable to save state to {:?}: {}", path, e))?;
+        }
+        let clients = clients.lock().unwrap();
+        if let (Some(path), Some(clients)) = (&client_map, &*clients) {
+            output::write_atomic(path, |file| clients.write(file))?;
+        }
+        output.write_with_clients(accounts, clients.as_ref())
+    };
+    // Files are applied in order to the same accounts
+    let process_file = |input_path: &str, accounts: &mut Accounts| -> Result<(), String> {
+        let _span = tracing::info_span!("process_file", path = %input_path).entered();
+        if let Some(threads) = threads {
+            let input = source::decompress(input_path, open_raw(input_path)?)?;
+            match progress {
+                Some(interval) => parallel::process_transaction_source_parallel_with_progress(
+                    input,
+                    accounts,
+                    threads,
+                    Duration::from_secs(interval.get()),
+                    progress::shard_reporter(),
+                    on_executed,
+                )?,
+                None => parallel::process_transaction_source_parallel(
+                    input,
+                    accounts,
+                    threads,
+                    on_executed,
+                )?,
+            }
+            return Ok(());
+        }
+        let read = Rc::new(Cell::new(0));
+        let mut progress = progress.map(|interval| {
+            InputProgress::new(
+                Duration::from_secs(interval.get()),
+                read.clone(),
+                source::input_size(input_path),
+            )
+        });
+        let on_executed = |record: &Record, result: Result<(), _>, accounts: &Accounts| {
+            let rejected = result.is_err();
+            on_executed(record, result, accounts);
+            if let Some(progress) = &mut progress {
+                progress.observe(record.line_no);
+            }
+            // Stop at the first rejected transaction
+            if strict && rejected {
+                return Err(format!(
+                    "Stopped at the rejected transaction on line {} (--strict)",
+                    record.line_no
+                )
+                .into());
+            }
+            Ok(())
+        };
+        if let Some(batch_input) = BatchInput::detect(input_path) {
+            let records = batch_input.records(input_path)?;
+            try_process_records_with(
+                records.map(|record| record.map_err(ProcessError::from)),
+                accounts,
+                on_executed,
+            )?;
+        } else {
+            // Progress is measured in bytes of the file, which may be compressed
+            let input = CountingReader::new(open_raw(input_path)?, read);
+            let input = source::decompress(input_path, Box::new(input))?;
+            match &mut *clients.lock().unwrap() {
+                Some(clients) => try_process_records_with(
+                    input::records_with_clients(input, clients),
+                    accounts,
+                    on_executed,
+                )?,
+                None => try_process_transaction_source_with(input, accounts, on_executed)?,
+            }
+        }
+        if let Some(progress) = &mut progress {
+            progress.report();
+        }
+        Ok(())
+    };
+    match kafka {
+        Some(kafka) => consume_kafka(&kafka, &mut accounts, &on_executed, &emit)?,
+        None => {
+            for path in &inputs {
+                let result = process_file(path, &mut accounts);
+                // Errors are reported with the file they are in when there are several
+                if inputs.len() > 1 {
+                    result.map_err(|e| format!("Error in {:?}: {}", path, e))?;
+                } else {
+                    result?;
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "profiling")]
+    if let Some(profiler) = profiler {
+        profiler.finish()?;
+    }
+
+    // Accrue interest and penalties for the period the input covers
+    if let Some(policy) = accrual {
+        let accrued = accounts
+            .accrue(|_, account| policy.accrual(account))
+            .map_err(|e| format!("Unable to accrue interest and penalties: {}", e))?;
+        let places = accounts.precision().decimal_places() as usize;
+        for (client, amount) in accrued {
+            tracing::info!(client, %amount, "Accrued {:.*} for client {}", places, amount, client);
+            if let Some(store) = &mut *store.lock().unwrap() {
+                store.mark_changed(client);
+            }
+        }
+    }
+
+    logging::unsettled_withdrawals(&accounts);
+    if let Some(duplicates) = &*duplicates.lock().unwrap() {
+        if duplicates.count() > 0 {
+            tracing::warn!(
+                count = duplicates.count(),
+                matched = duplicates.matched(),
+                "{} transactions reused an id, {} of them with the same payload as the original",
+                duplicates.count(),
+                duplicates.matched()
+            );
+        }
+    }
+    if let Some(summary) = summary.into_inner().unwrap() {
+        let places = accounts.precision().decimal_places() as usize;
+        eprint!("{:.*}", places, summary);
+    }
+
+    emit(&accounts)?;
+
+    // The writer finishes once every sender is dropped
+    accounts.set_balance_events(None);
+    if let Some(writer) = balance_events {
+        writer
+            .join()
+            .map_err(|_| "Balance events writer panicked")?
+            .map_err(|e| format!("Unable to write balance events: {}", e))?;
+    }
+
+    // Send a digest of frozen and charged back accounts
+    let digest = tracker.into_inner().unwrap().finish(&accounts);
+    if !digest.is_empty() {
+        notify.send(&digest)?;
+    }
+    Ok(())
+}
+
+/// Serve metrics on an address on their own thread
+#[cfg(feature = "http")]
+fn serve_metrics(address: &str) -> Result<Arc<Metrics>, String> {
+    let listener = std::net::TcpListener::bind(address)
+        .map_err(|e| format!("Unable to listen on {}: {}", address, e))?;
+    let metrics = Arc::new(Metrics::new());
+    let served = metrics.clone();
+    thread::spawn(move || {
+        if let Err(e) = crate::http::serve_metrics(&served, listener) {
+            tracing::error!("Unable to serve metrics: {}", e);
+        }
+    });
+    Ok(metrics)
+}
+
+#[cfg(not(feature = "http"))]
+fn serve_metrics(_: &str) -> Result<Arc<Metrics>, String> {
+    Err("Unable to serve metrics: transactor was built without the `http` feature".into())
+}
+
+/// Apply transactions consumed from Kafka until an error occurs, emitting accounts periodically
+#[cfg(feature = "kafka")]
+fn consume_kafka<F, G>(
+    kafka: &KafkaOptions,
+    accounts: &mut Accounts,
+    on_executed: F,
+    emit: G,
+) -> Result<(), String>
+where
+    F: FnMut(&Record, Result<(), crate::TransactionError>, &Accounts),
+    G: FnMut(&Accounts) -> Result<(), String>,
+{
+    use crate::kafka::{process_kafka_source_with, KafkaSource};
+    let mut source = KafkaSource::connect(&kafka.brokers, &kafka.topic, &kafka.group)
+        .map_err(|e| format!("Unable to connect to Kafka at {}: {}", kafka.brokers, e))?;
+    process_kafka_source_with(&mut source, accounts, kafka.interval, on_executed, emit)
+}
+
+#[cfg(not(feature = "kafka"))]
+fn consume_kafka<F, G>(_: &KafkaOptions, _: &mut Accounts, _: F, _: G) -> Result<(), String> {
+    Err("Unable to consume from Kafka: transactor was built without the `kafka` feature".into())
+}
@@ -0,0 +1,231 @@
+//! JSON Schemas of the JSON that transactor writes
+//!
+//! Integrators can generate clients from these rather than from examples. The schemas are:
+//!
+//! - `account`: an account in JSON output and the HTTP API, including extended output
+//! - `transaction-results`: the response to `POST /transactions`
+//! - `transaction-page`: the response to `GET /accounts/{client}/transactions`
+//! - `error`: the response to a failed HTTP request
+//! - `events`: a digest of events sent to a webhook, in event version 1
+//!
+//! Amounts are written as decimal strings so they are not subject to floating-point rounding.
+
+use serde_json::{json, Value};
+
+/// The names of the available schemas
+pub const SCHEMAS: [&str; 5] = [
+    "account",
+    "transaction-results",
+    "transaction-page",
+    "error",
+    "events",
+];
+
+/// Get every schema as an object keyed by name
+pub fn all() -> Value {
+    Value::Object(
+        SCHEMAS
+            .iter()
+            .map(|name| (name.to_string(), schema(name).unwrap()))
+            .collect(),
+    )
+}
+
+/// Get the JSON Schema with some name
+///
+/// Returns `None` if there is no schema with the name
+pub fn schema(name: &str) -> Option<Value> {
+    let (title, schema) = match name {
+        "account" => ("Account", account()),
+        "transaction-results" => ("Transaction results", transaction_results()),
+        "transaction-page" => ("Transaction page", transaction_page()),
+        "error" => ("Error", error()),
+        "events" => ("Events", events()),
+        _ => return None,
+    };
+    let mut header = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!("urn:transactor:{}", name),
+        "title": title,
+    });
+    if let (Value::Object(header), Value::Object(schema)) = (&mut header, schema) {
+        header.extend(schema);
+    }
+    Some(header)
+}
+
+fn amount() -> Value {
+    json!({
+        "type": "string",
+        "pattern": "^-?[0-9]+(\\.[0-9]+)?$",
+    })
+}
+
+fn client() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": u16::MAX })
+}
+
+fn tx() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": u32::MAX })
+}
+
+/// A whole number of seconds since the Unix epoch
+fn timestamp() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": u64::MAX })
+}
+
+/// An object with some properties, all of which are required unless listed in `optional`
+fn object(properties: Value, optional: &[&str]) -> Value {
+    let required: Vec<&String> = properties
+        .as_object()
+        .into_iter()
+        .flat_map(|properties| properties.keys())
+        .filter(|key| !optional.contains(&key.as_str()))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+fn account() -> Value {
+    let mut pending = amount();
+    pending["description"] = "Funds of deposits that have not settled, which are part of the total but not available. Only in extended output.".into();
+    let mut schema = object(
+        json!({
+            "client": client(),
+            "available": amount(),
+            "held": amount(),
+            "total": amount(),
+            "locked": { "type": "boolean" },
+            "first_tx": {
+                "description": "The first deposit, withdrawal, or transfer. Only in extended output.",
+                "oneOf": [tx(), { "type": "null" }],
+            },
+            "last_tx": {
+                "description": "The most recent deposit, withdrawal, or transfer. Only in extended output.",
+                "oneOf": [tx(), { "type": "null" }],
+            },
+            "pending": pending,
+            "first_timestamp": {
+                "description": "The timestamp of the first deposit, withdrawal, or transfer, if it had one. Only in extended output.",
+                "oneOf": [timestamp(), { "type": "null" }],
+            },
+            "last_timestamp": {
+                "description": "The timestamp of the most recent deposit, withdrawal, or transfer, if it had one. Only in extended output.",
+                "oneOf": [timestamp(), { "type": "null" }],
+            },
+        }),
+        &[
+            "first_tx",
+            "last_tx",
+            "pending",
+            "first_timestamp",
+            "last_timestamp",
+        ],
+    );
+    schema["description"] = "The balances of a client's account".into();
+    schema
+}
+
+fn transaction_results() -> Value {
+    json!({
+        "description": "The result of each transaction in a request, in order",
+        "type": "array",
+        "items": {
+            "oneOf": [
+                object(json!({ "status": { "const": "ok" } }), &[]),
+                object(
+                    json!({
+                        "status": { "const": "error" },
+                        "error": { "type": "string" },
+                        "code": {
+                            "description": "Why the transaction was not applied, such as `insufficient_funds`. Only from protocol version 2.",
+                            "type": "string",
+                        },
+                    }),
+                    &["code"],
+                ),
+            ],
+        },
+    })
+}
+
+fn transaction_page() -> Value {
+    let entry = object(
+        json!({
+            "tx": tx(),
+            "type": {
+                "oneOf": [
+                    { "const": "deposit" },
+                    { "const": "withdrawal" },
+                    { "const": "fee" },
+                ],
+            },
+            "amount": amount(),
+            "timestamp": { "oneOf": [timestamp(), { "type": "null" }] },
+        }),
+        &[],
+    );
+    let mut schema = object(
+        json!({
+            "transactions": { "type": "array", "items": entry },
+            "next_after": {
+                "description": "The `after` of the next page, or null if this is the last page",
+                "oneOf": [tx(), { "type": "null" }],
+            },
+        }),
+        &[],
+    );
+    schema["description"] =
+        "A page of a client's history, the deposits, withdrawals, and fees that can still be disputed, in order of transaction id"
+            .into();
+    schema
+}
+
+fn error() -> Value {
+    let mut schema = object(json!({ "error": { "type": "string" } }), &[]);
+    schema["description"] = "Why a request failed".into();
+    schema
+}
+
+fn events() -> Value {
+    let event = |event_type: &str, properties: Value| {
+        let mut properties = properties;
+        properties["event_type"] = json!({ "const": event_type });
+        properties["event_version"] = json!({ "const": 1 });
+        object(properties, &[])
+    };
+    let mut schema = object(
+        json!({
+            "event_version": { "const": 1 },
+            "events": {
+                "type": "array",
+                "items": {
+                    "oneOf": [
+                        event("deposit", json!({
+                            "client": client(),
+                            "tx": tx(),
+                            "amount": amount(),
+                        })),
+                        event("freeze", json!({
+                            "client": client(),
+                            "available": amount(),
+                            "held": amount(),
+                            "total": amount(),
+                        })),
+                        event("chargeback", json!({
+                            "client": client(),
+                            "tx": tx(),
+                        })),
+                    ],
+                },
+            },
+        }),
+        &[],
+    );
+    schema["description"] = "Events from a run, as sent to a webhook".into();
+    schema
+}
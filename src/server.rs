@@ -0,0 +1,803 @@
+//! Serving transactions over TCP
+//!
+//! Each connection sends lines of transaction input in either format, see
+//! [`records`](crate::records). The format is detected separately for each connection, and
+//! a CSV connection may start with its own header row. For every transaction, the server
+//! writes back a line of `ok` if it was applied, or `error: ` followed by the reason it was
+//! rejected or could not be parsed. Empty lines and the header row get no response.
+//!
+//! A client may start its connection with a handshake, to learn which version of the protocol
+//! and which of its optional features the server supports. The first line is then `hello`, the
+//! newest [`PROTOCOL_VERSION`] the client speaks, and the features it would like to use, such
+//! as `hello 2 batch`. The server answers with the version they both speak and the features
+//! they both support, such as `hello 2 batch`, see [`Handshake`]. Clients that send no
+//! handshake are served as before. Batch frames are accepted whether or not they were agreed
+//! on.
+//!
+//! With the `compression` feature, a client may ask for `zstd`. If it is agreed on, everything
+//! the client sends after its `hello` line, and everything the server sends after its answer,
+//! is a Zstandard stream. The server flushes its stream whenever it flushes responses, so the
+//! client should flush its own stream whenever it waits for responses.
+//!
+//! A client can also send transactions in batch frames, to learn the outcome of each one and
+//! resend only those that failed. A frame is a line of `batch <n>` followed by `n` records.
+//! Each record gets back a line of `ack <i>` if it was applied or `nack <i> <code> <reason>`
+//! if it was not, where `i` is the position of the record in the batch, counting from 0, and
+//! `code` is a machine-readable [`Rejected::code`], such as `insufficient_funds`. Records are
+//! applied as they arrive, so a client that loses its connection partway through a batch can
+//! resend the records it has no `ack` for. Records that were applied before the connection
+//! was lost are then nacked with `duplicate_transaction_id`.
+//!
+//! [`ConnectionLimits`] bound what connections may use, so a misbehaving client cannot exhaust
+//! the server's memory. A line over the size limit gets an `error: ` response, or a `nack` in a
+//! batch, and is skipped, and a connection over the connection limit gets an `error: `
+//! response and is closed. Every record of a batch larger than the in-flight limit is nacked
+//! without being applied.
+//!
+//! Transactions from all connections are applied to the same [`Accounts`], one at a time.
+//! A [`Recorder`] can capture the order in which they were applied, with each connection as
+//! a separate source.
+//!
+//! A running server can be managed over its admin API, see the `control` module. Once it is
+//! drained, it refuses new connections and stops when the open ones have closed.
+//!
+//! With the `http` feature, a server can also be exposed as a JSON API, see the `http` module.
+//! With the `grpc` feature, it can be exposed as a gRPC service, see the `grpc` module. With
+//! the `websocket` feature, it can also push account updates to WebSocket clients, see the
+//! `websocket` module.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::{
+    account::TransactionError,
+    admin::{AdminOp, AdminOpKind, Journal},
+    input::{Parser, ProcessError},
+    metrics::{Metrics, SubmissionRate},
+    record::{Recorder, SourceId},
+    service,
+    transaction::{BalanceChange, ClientId, Transaction, TransactionId},
+    Accounts, ClientTransaction,
+};
+
+/// The number of history entries in a page if a request does not say, see [`Server::history`]
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// The most history entries in a page, see [`Server::history`]
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// A page of a client's history
+#[derive(Debug, Clone)]
+pub struct HistoryPage {
+    /// The entries, in order of transaction id
+    pub entries: Vec<(TransactionId, BalanceChange)>,
+    /// The transaction id to get the next page after, or `None` if this is the last page
+    pub next_after: Option<TransactionId>,
+}
+
+/// Limits on what connections may use
+///
+/// Each limit is unlimited if it is `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    /// The most connections served at once
+    ///
+    /// TCP and WebSocket connections count for as long as they are open, and HTTP requests and
+    /// gRPC calls count while they are handled.
+    pub max_connections: Option<usize>,
+    /// The most transactions a single request may submit before it gets responses
+    ///
+    /// This applies to HTTP requests, WebSocket messages, and TCP batch frames, which are
+    /// rejected whole if they have more, and to concurrent gRPC calls on one connection. Other
+    /// transactions from a TCP connection are not limited, since each gets its response before
+    /// the next one is read.
+    pub max_in_flight: Option<usize>,
+    /// The most bytes in a line or quoted record of a TCP connection, an HTTP request body,
+    /// a WebSocket message, or a gRPC message
+    pub max_request_size: Option<usize>,
+}
+
+/// A request that went over one of the [`ConnectionLimits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum LimitExceeded {
+    #[error("Too many connections, at most {0} are served at once")]
+    Connections(usize),
+    #[error("Too many transactions in one request, at most {0} are allowed")]
+    InFlight(usize),
+    #[error("Request is longer than {0} bytes")]
+    RequestSize(usize),
+}
+
+impl LimitExceeded {
+    /// Get a short, machine-readable code for the limit, such as `too_many_connections`
+    pub const fn code(&self) -> &'static str {
+        match self {
+            LimitExceeded::Connections(_) => "too_many_connections",
+            LimitExceeded::InFlight(_) => "too_many_in_flight",
+            LimitExceeded::RequestSize(_) => "request_too_large",
+        }
+    }
+}
+
+/// Why a server did not apply a transaction
+#[derive(Debug, Error)]
+pub enum Rejected {
+    /// The input is not a valid transaction
+    #[error(transparent)]
+    Input(#[from] ProcessError),
+    /// The engine rejected the transaction
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+    /// The transaction could not be recorded
+    #[error("Unable to record transaction: {0}")]
+    Record(io::Error),
+    /// The request went over a limit
+    #[error(transparent)]
+    Limit(#[from] LimitExceeded),
+}
+
+impl Rejected {
+    /// Get a short, machine-readable code for why the transaction was not applied, such as
+    /// `insufficient_funds` or `invalid_amount`
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejected::Input(e) => e.kind.code(),
+            Rejected::Transaction(e) => e.code(),
+            Rejected::Record(_) => "record_failed",
+            Rejected::Limit(e) => e.code(),
+        }
+    }
+}
+
+/// The newest version of the protocol that a server speaks
+///
+/// Version 1 is lines of transactions answered with `ok` or `error: `. Version 2 adds the
+/// handshake and batch frames, and error codes in the results of `POST /transactions` over HTTP.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Get the optional features of the protocol that a server supports, in the order they are
+/// advertised
+///
+/// `zstd` is only supported with the `compression` feature.
+pub fn protocol_features() -> Vec<&'static str> {
+    let mut features = vec!["batch"];
+    if cfg!(feature = "compression") {
+        features.push("zstd");
+    }
+    features
+}
+
+/// What a client and a server agreed on in a handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    /// The version of the protocol both speak, the older of the two
+    pub version: u32,
+    /// The features that both support, of those the client asked for
+    pub features: Vec<&'static str>,
+}
+
+impl Handshake {
+    /// Agree on a version and features from the rest of a client's `hello` line, such as
+    /// `2 batch`
+    ///
+    /// Features are only agreed on from version 2, and unknown features are left out.
+    pub fn negotiate(hello: &str) -> Result<Self, String> {
+        let mut words = hello.split_whitespace();
+        let version = words.next().unwrap_or_default();
+        let version = (version.parse::<u32>())
+            .ok()
+            .filter(|&version| version > 0)
+            .ok_or_else(|| format!("Invalid protocol version {:?}", version))?
+            .min(PROTOCOL_VERSION);
+        let requested: Vec<&str> = words.collect();
+        let features = protocol_features()
+            .into_iter()
+            .filter(|feature| version >= 2 && requested.contains(feature))
+            .collect();
+        Ok(Handshake { version, features })
+    }
+}
+
+impl fmt::Display for Handshake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hello {}", self.version)?;
+        for feature in &self.features {
+            write!(f, " {}", feature)?;
+        }
+        Ok(())
+    }
+}
+
+/// The position in a batch frame of a connection, see the module documentation
+struct Batch {
+    /// The number of records in the batch
+    size: usize,
+    /// The position of the next record
+    next: usize,
+    /// The in-flight limit if the batch is larger, so its records are not applied
+    over_limit: Option<usize>,
+}
+
+/// Why a server did not serve a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum Refused {
+    #[error(transparent)]
+    Limit(#[from] LimitExceeded),
+    #[error("Server is draining and accepts no new connections")]
+    Draining,
+}
+
+/// One of the connections a server is serving, which stops counting when dropped
+pub(crate) struct Connection<'a> {
+    connections: &'a AtomicUsize,
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The state shared between connections
+struct State {
+    accounts: Accounts,
+    recorder: Option<Recorder<Box<dyn Write + Send>>>,
+    /// Channels that are sent the clients whose accounts change
+    watchers: Vec<Sender<ClientId>>,
+    /// Where admin operations from the admin API are recorded
+    journal: Option<Journal<File>>,
+}
+
+/// A server that applies transactions from many connections to shared accounts
+pub struct Server {
+    state: Mutex<State>,
+    next_source: AtomicU64,
+    metrics: Metrics,
+    limits: ConnectionLimits,
+    /// The number of connections being served
+    connections: AtomicUsize,
+    draining: AtomicBool,
+}
+
+impl Server {
+    /// Create a server that applies transactions to some accounts
+    pub fn new(accounts: Accounts) -> Self {
+        Server {
+            state: Mutex::new(State {
+                accounts,
+                recorder: None,
+                watchers: Vec::new(),
+                journal: None,
+            }),
+            next_source: AtomicU64::new(0),
+            metrics: Metrics::new(),
+            limits: ConnectionLimits::default(),
+            connections: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+        }
+    }
+    /// Create a server that also records every transaction before applying it
+    ///
+    /// A transaction is only acknowledged once it is recorded, so the recorder should commit
+    /// every record on its own.
+    pub fn with_recorder(accounts: Accounts, recorder: Recorder<Box<dyn Write + Send>>) -> Self {
+        Server {
+            state: Mutex::new(State {
+                accounts,
+                recorder: Some(recorder),
+                watchers: Vec::new(),
+                journal: None,
+            }),
+            next_source: AtomicU64::new(0),
+            metrics: Metrics::new(),
+            limits: ConnectionLimits::default(),
+            connections: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+        }
+    }
+    /// Limit what connections may use
+    pub fn set_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+    /// Get the limits on what connections may use
+    pub fn limits(&self) -> ConnectionLimits {
+        self.limits
+    }
+    /// Record the admin operations applied over the admin API in a journal
+    pub fn set_journal(&mut self, journal: Journal<File>) {
+        self.state.get_mut().unwrap().journal = Some(journal);
+    }
+    /// Count a connection as served until the returned guard is dropped
+    ///
+    /// Fails if the server is draining or as many connections as the limit are already being
+    /// served.
+    pub(crate) fn connect(&self) -> Result<Connection<'_>, Refused> {
+        if self.is_draining() {
+            return Err(Refused::Draining);
+        }
+        let max = self.limits.max_connections.unwrap_or(usize::MAX);
+        self.connections
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .map_err(|_| LimitExceeded::Connections(max))?;
+        Ok(Connection {
+            connections: &self.connections,
+        })
+    }
+    /// Accept connections and handle each one on its own thread
+    ///
+    /// This only returns if accepting connections fails. Each connection is recorded as a
+    /// new source. Connections over the connection limit are sent an error and closed.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let mut stream = stream?;
+                let source = self.next_source();
+                scope.spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    let _connection = match self.connect() {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            tracing::warn!(source, ?peer, "Connection refused: {}", e);
+                            let _ = writeln!(stream, "error: {}", e);
+                            return;
+                        }
+                    };
+                    let result = stream
+                        .try_clone()
+                        .and_then(|writer| self.handle(source, stream, writer));
+                    if let Err(e) = result {
+                        tracing::warn!(source, ?peer, "Connection failed: {}", e);
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+    /// Handle the transactions from one source until it ends, writing a response to each one
+    ///
+    /// Responses are flushed whenever no more input is buffered, so a client may send
+    /// transactions one at a time or many at once. A line, or a record of quoted lines, over
+    /// the size limit gets an error response and is skipped. The first line may be a
+    /// handshake, see the module documentation.
+    pub fn handle<R, W>(&self, source: SourceId, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: Read,
+        W: Write,
+    {
+        let mut reader = BufReader::new(reader);
+        let mut parser = Parser::new(None);
+        let mut line = Vec::new();
+        self.read_line(&mut reader, &mut line)?;
+        let first = String::from_utf8_lossy(&line).trim_end().to_owned();
+        if let Some(hello) = first.strip_prefix("hello ") {
+            parser.skip_line(&first);
+            let handshake = Handshake::negotiate(hello);
+            match &handshake {
+                Ok(handshake) => writeln!(writer, "{}", handshake)?,
+                Err(e) => writeln!(writer, "error: {}", e)?,
+            }
+            writer.flush()?;
+            line.clear();
+            // Everything after the handshake is compressed in both directions
+            #[cfg(feature = "compression")]
+            if handshake.is_ok_and(|handshake| handshake.features.contains(&"zstd")) {
+                let reader = BufReader::new(zstd::Decoder::with_buffer(reader)?);
+                let mut writer = zstd::Encoder::new(writer, 0)?;
+                self.handle_lines(source, reader, &mut writer, parser, line)?;
+                return writer.finish().map(drop);
+            }
+        }
+        self.handle_lines(source, reader, writer, parser, line)
+    }
+    /// Handle the lines of a source after its handshake, starting with a line already read
+    fn handle_lines<R, W>(
+        &self,
+        source: SourceId,
+        mut reader: BufReader<R>,
+        mut writer: W,
+        mut parser: Parser,
+        mut line: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        R: Read,
+        W: Write,
+    {
+        let max_size = self.limits.max_request_size;
+        let mut batch = None;
+        loop {
+            if line.is_empty() && self.read_line(&mut reader, &mut line)? == 0 {
+                break;
+            }
+            let result = if let Some(max) =
+                max_size.filter(|&max| parser.pending_len() + line.len() > max)
+            {
+                if line.last() != Some(&b'\n') {
+                    reader.skip_until(b'\n')?;
+                }
+                parser.discard_pending();
+                Some(Err(LimitExceeded::RequestSize(max).into()))
+            } else {
+                let line = std::str::from_utf8(&line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let trimmed = line.strip_suffix('\n').unwrap_or(line);
+                let trimmed = trimmed.strip_suffix('\r').unwrap_or(trimmed);
+                match trimmed.strip_prefix("batch ") {
+                    // A batch can only start between records
+                    Some(size) if batch.is_none() && parser.pending_len() == 0 => {
+                        parser.skip_line(trimmed);
+                        match size.trim().parse::<usize>() {
+                            Ok(size) if size > 0 => {
+                                batch = Some(Batch {
+                                    size,
+                                    next: 0,
+                                    over_limit: self.limits.max_in_flight.filter(|&max| size > max),
+                                });
+                            }
+                            _ => writeln!(writer, "error: Invalid batch size {:?}", size)?,
+                        }
+                        None
+                    }
+                    _ => match batch.as_ref().and_then(|batch| batch.over_limit) {
+                        Some(max) => (parser.parse_line(trimmed.into()))
+                            .map(|_| Err(LimitExceeded::InFlight(max).into())),
+                        None => self.apply_line(source, &mut parser, trimmed.into()),
+                    },
+                }
+            };
+            if let Some(result) = result {
+                respond(&mut writer, &mut batch, result)?;
+            }
+            if reader.buffer().is_empty() {
+                writer.flush()?;
+            }
+            line.clear();
+        }
+        if let Some(e) = parser.finish() {
+            respond(&mut writer, &mut batch, Err(e.into()))?;
+        }
+        writer.flush()
+    }
+    /// Read a line from a source, stopping past the size limit so a long line is never held
+    /// whole
+    fn read_line<R: BufRead>(&self, reader: R, line: &mut Vec<u8>) -> io::Result<usize> {
+        let limit = (self.limits.max_request_size).map_or(u64::MAX, |max| max as u64 + 1);
+        reader.take(limit).read_until(b'\n', line)
+    }
+    /// Get a new source id
+    ///
+    /// Sources are numbered in the order they are created, starting from 0.
+    pub(crate) fn next_source(&self) -> SourceId {
+        self.next_source.fetch_add(1, Ordering::Relaxed)
+    }
+    /// Parse a line of input from a source and apply its transaction, if it completes one
+    pub(crate) fn apply_line(
+        &self,
+        source: SourceId,
+        parser: &mut Parser,
+        line: String,
+    ) -> Option<Result<(), Rejected>> {
+        let record = parser.parse_line(line)?;
+        Some(
+            record
+                .map_err(Rejected::from)
+                .and_then(|record| self.transact(source, record.tx)),
+        )
+    }
+    /// Record and apply a transaction
+    pub(crate) fn transact(&self, source: SourceId, tx: ClientTransaction) -> Result<(), Rejected> {
+        self.metrics.observe_submission(tx.client);
+        let mut state = self.state.lock().unwrap();
+        let State {
+            accounts,
+            recorder,
+            watchers,
+            ..
+        } = &mut *state;
+        if let Some(recorder) = recorder {
+            recorder.record(source, &tx).map_err(Rejected::Record)?;
+        }
+        let changed = match tx.tx {
+            Transaction::Transfer { to, .. } => [Some(tx.client), Some(to)],
+            _ => [Some(tx.client), None],
+        };
+        let result = accounts.transact(tx.clone());
+        self.metrics.observe(&tx, &result);
+        result?;
+        // Watchers that were dropped are removed
+        watchers.retain(|watcher| {
+            changed
+                .into_iter()
+                .flatten()
+                .all(|client| watcher.send(client).is_ok())
+        });
+        Ok(())
+    }
+    /// Get a channel that is sent the client of every account that a transaction changes
+    ///
+    /// A transfer changes the accounts of both clients.
+    #[cfg(feature = "websocket")]
+    pub(crate) fn watch(&self) -> std::sync::mpsc::Receiver<ClientId> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.state.lock().unwrap().watchers.push(sender);
+        receiver
+    }
+    /// Call a function with the accounts as they are between transactions
+    ///
+    /// No transactions are applied while the function runs.
+    pub fn with_accounts<T>(&self, f: impl FnOnce(&Accounts) -> T) -> T {
+        f(&self.state.lock().unwrap().accounts)
+    }
+    /// Get a page of a client's history, see [`Accounts::history`]
+    ///
+    /// `limit` defaults to [`DEFAULT_PAGE_SIZE`] and is capped at [`MAX_PAGE_SIZE`]. Returns
+    /// `None` if the client has no account.
+    pub fn history(
+        &self,
+        client_id: ClientId,
+        after: Option<TransactionId>,
+        limit: Option<usize>,
+    ) -> Option<Result<HistoryPage, String>> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        self.with_accounts(|accounts| {
+            accounts.get(client_id)?;
+            Some(
+                (accounts.history(client_id, after, limit))
+                    .map(|entries| HistoryPage {
+                        // A full page may be followed by more
+                        next_after: (entries.len() == limit)
+                            .then(|| entries.last().map(|(tx_id, _)| *tx_id))
+                            .flatten(),
+                        entries,
+                    })
+                    .map_err(|e| e.to_string()),
+            )
+        })
+    }
+    /// Render the metrics of the transactions applied so far in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        self.with_accounts(|accounts| self.metrics.update_accounts(accounts));
+        self.metrics.render()
+    }
+    /// Get the number of transactions executed, including rejected ones, and the number rejected
+    pub fn transaction_counts(&self) -> (u64, u64) {
+        (self.metrics.transactions(), self.metrics.rejections())
+    }
+    /// Get the clients that have submitted the most transactions over the last minute
+    ///
+    /// Rejected transactions count as submissions, so integrations that submit too many can be
+    /// found and limited.
+    pub fn top_submitters(&self, count: usize) -> Vec<SubmissionRate> {
+        self.metrics.top_submitters(count)
+    }
+    /// Get the number of connections being served
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+    /// Refuse new connections, HTTP requests, and gRPC calls from now on
+    ///
+    /// Connections that are already open are still served. See [`Server::is_drained`].
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+    /// Check whether the server has been drained
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+    /// Check whether the server has been drained and every connection has closed
+    pub fn is_drained(&self) -> bool {
+        self.is_draining() && self.connections() == 0
+    }
+    /// Apply an admin operation, recording it in the journal if there is one
+    ///
+    /// In sandbox mode, the operation is only simulated, see [`AdminOp::apply`].
+    pub(crate) fn apply_admin(&self, op: &AdminOp) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            accounts,
+            watchers,
+            journal,
+            ..
+        } = &mut *state;
+        let result = op.apply(accounts);
+        if let Some(journal) = journal {
+            let recorded = if result.is_ok() && accounts.sandbox() {
+                journal.record_simulated(op)
+            } else {
+                journal.record(op, &result)
+            };
+            recorded.map_err(|e| format!("Unable to write journal: {}", e))?;
+        }
+        result.map_err(|e| e.to_string())?;
+        let changed = match op.kind {
+            AdminOpKind::Merge(into) => [Some(op.client), Some(into)],
+            _ => [Some(op.client), None],
+        };
+        watchers.retain(|watcher| {
+            changed
+                .into_iter()
+                .flatten()
+                .all(|client| watcher.send(client).is_ok())
+        });
+        Ok(())
+    }
+    /// Stop serving and get the accounts
+    pub fn into_accounts(self) -> Accounts {
+        self.state.into_inner().unwrap().accounts
+    }
+}
+
+/// Write the response to a transaction from a connection, in its batch if it is in one
+fn respond<W: Write>(
+    mut writer: W,
+    batch: &mut Option<Batch>,
+    result: Result<(), Rejected>,
+) -> io::Result<()> {
+    let Some(current) = batch else {
+        return match result {
+            Ok(()) => writeln!(writer, "ok"),
+            Err(e) => writeln!(writer, "error: {}", e),
+        };
+    };
+    match result {
+        Ok(()) => writeln!(writer, "ack {}", current.next)?,
+        Err(e) => writeln!(writer, "nack {} {} {}", current.next, e.code(), e)?,
+    }
+    current.next += 1;
+    if current.next == current.size {
+        *batch = None;
+    }
+    Ok(())
+}
+
+/// The addresses a server listens on
+#[derive(Debug, Clone, Default)]
+pub struct Listeners {
+    /// Serve lines of transactions over TCP
+    pub tcp: Option<String>,
+    /// Serve the JSON API, which needs the `http` feature
+    pub http: Option<String>,
+    /// Serve gRPC, which needs the `grpc` feature
+    pub grpc: Option<String>,
+    /// Serve WebSockets, which needs the `websocket` feature
+    pub websocket: Option<String>,
+    /// Serve the admin API, see the `control` module
+    pub admin: Option<String>,
+}
+
+impl Listeners {
+    /// Check whether there are no addresses to serve transactions on
+    pub fn is_empty(&self) -> bool {
+        self.tcp.is_none() && self.http.is_none() && self.grpc.is_none() && self.websocket.is_none()
+    }
+    /// Check that every address can be served with the enabled features
+    pub fn check(&self) -> Result<(), String> {
+        #[cfg(not(feature = "http"))]
+        if self.http.is_some() {
+            return Err(
+                "Unable to serve HTTP: transactor was built without the `http` feature".into(),
+            );
+        }
+        #[cfg(not(feature = "grpc"))]
+        if self.grpc.is_some() {
+            return Err(
+                "Unable to serve gRPC: transactor was built without the `grpc` feature".into(),
+            );
+        }
+        #[cfg(not(feature = "websocket"))]
+        if self.websocket.is_some() {
+            return Err(
+                "Unable to serve WebSockets: transactor was built without the `websocket` feature"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// How often [`listen`] checks whether the server has been drained
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serve on every listener until interrupted or drained
+///
+/// Each listener is served on its own thread so that all can be served at once. The service
+/// manager is notified once the server is ready, and pinged while its watchdog is enabled,
+/// see [`crate::service`]. Once the server is drained and its connections have closed, this
+/// returns without waiting for the listeners.
+pub fn listen(server: Server, listeners: Listeners) -> Result<(), String> {
+    let bind = |address: &str| {
+        TcpListener::bind(address).map_err(|e| format!("Unable to listen on {}: {}", address, e))
+    };
+    let tcp = listeners.tcp.as_deref().map(bind).transpose()?;
+    #[cfg(feature = "http")]
+    let http = listeners.http.as_deref().map(bind).transpose()?;
+    #[cfg(feature = "grpc")]
+    let grpc = listeners.grpc.as_deref().map(bind).transpose()?;
+    #[cfg(feature = "websocket")]
+    let websocket = listeners.websocket.as_deref().map(bind).transpose()?;
+    let admin = listeners.admin.as_deref().map(bind).transpose()?;
+
+    let server = Arc::new(server);
+    if let Err(e) = service::notify("READY=1") {
+        tracing::warn!("Unable to notify the service manager: {}", e);
+    }
+    if let Some(interval) = service::watchdog_interval() {
+        let server = server.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval / 2);
+            // Only ping while transactions can still be applied
+            server.with_accounts(|_| ());
+            if let Err(e) = service::notify("WATCHDOG=1") {
+                tracing::warn!("Unable to ping the service manager watchdog: {}", e);
+            }
+        });
+    }
+    let mut threads = Vec::new();
+    let mut spawn = |serve: Box<dyn FnOnce(Arc<Server>) -> Result<(), String> + Send>| {
+        let server = server.clone();
+        threads.push(thread::spawn(move || serve(server)));
+    };
+    if let Some(listener) = tcp {
+        spawn(Box::new(|server| {
+            server
+                .serve(listener)
+                .map_err(|e| format!("Error accepting connections: {}", e))
+        }));
+    }
+    #[cfg(feature = "http")]
+    if let Some(listener) = http {
+        spawn(Box::new(|server| {
+            server
+                .serve_http(listener)
+                .map_err(|e| format!("Error serving HTTP: {}", e))
+        }));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(listener) = grpc {
+        spawn(Box::new(|server| {
+            server
+                .serve_grpc(listener)
+                .map_err(|e| format!("Error serving gRPC: {}", e))
+        }));
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(listener) = websocket {
+        spawn(Box::new(|server| {
+            server
+                .serve_websocket(listener)
+                .map_err(|e| format!("Error accepting WebSocket connections: {}", e))
+        }));
+    }
+    if let Some(listener) = admin {
+        spawn(Box::new(|server| {
+            server
+                .serve_admin(listener)
+                .map_err(|e| format!("Error accepting admin connections: {}", e))
+        }));
+    }
+    while !threads.is_empty() {
+        if server.is_drained() {
+            tracing::info!("Drained, stopping");
+            return Ok(());
+        }
+        if let Some(i) = threads.iter().position(|thread| thread.is_finished()) {
+            threads.swap_remove(i).join().unwrap()?;
+        } else {
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Integration with service managers
+//!
+//! Under systemd, a unit with `Type=notify` is told when the server is ready with [`notify`],
+//! and a unit with `WatchdogSec` can be pinged at the [`watchdog_interval`]. These do nothing
+//! when the process was not started by systemd.
+//!
+//! With the `windows-service` feature on Windows, `run_windows_service` runs the server
+//! under the Windows service control manager.
+
+use std::{env, ffi::OsStr, io, time::Duration};
+
+/// Send a state, such as `READY=1` or `WATCHDOG=1`, to the service manager
+///
+/// This does nothing unless the service manager set `NOTIFY_SOCKET`, which systemd does for
+/// units with `Type=notify`.
+pub fn notify(state: &str) -> io::Result<()> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => send(&path, state),
+        None => Ok(()),
+    }
+}
+
+/// Send a state to the socket of the service manager
+#[cfg(unix)]
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    // A leading `@` names a socket in the abstract namespace
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+            socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Abstract socket {:?} is not supported", path),
+            ))
+        }
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Send a state to the socket of the service manager
+#[cfg(not(unix))]
+fn send(path: &OsStr, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Unable to notify {:?} on this platform", path),
+    ))
+}
+
+/// Get the interval in which the service manager expects `WATCHDOG=1` pings, if it does
+///
+/// systemd sets this for units with `WatchdogSec`. Pings should be sent at about half the
+/// interval so that a late one does not get the service restarted.
+pub fn watchdog_interval() -> Option<Duration> {
+    // The watchdog may be meant for another process
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
+
+#[cfg(all(windows, feature = "windows-service"))]
+pub use windows::run_windows_service;
+
+#[cfg(all(windows, feature = "windows-service"))]
+mod windows {
+    use std::{
+        ffi::OsString,
+        sync::{Mutex, OnceLock},
+        time::Duration,
+    };
+
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+            ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+        service_dispatcher,
+    };
+
+    /// The name transactor's service is registered with
+    const SERVICE_NAME: &str = "transactor";
+
+    /// The function to run as the service
+    type Run = Box<dyn FnOnce() -> Result<(), String> + Send>;
+
+    /// The function passed to [`run_windows_service`], which the service manager calls into
+    static RUN: Mutex<Option<Run>> = Mutex::new(None);
+
+    /// The handle to report the status of the service with
+    static STATUS: OnceLock<ServiceStatusHandle> = OnceLock::new();
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Run a function as a Windows service
+    ///
+    /// This must be called from a process started by the service control manager. It blocks
+    /// until the function returns or the service is stopped. A server cannot be shut down
+    /// gracefully, so stopping the service ends the process.
+    pub fn run_windows_service(
+        run: impl FnOnce() -> Result<(), String> + Send + 'static,
+    ) -> Result<(), String> {
+        *RUN.lock().unwrap() = Some(Box::new(run));
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .map_err(|e| format!("Unable to start the Windows service: {}", e))
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            tracing::error!("Windows service failed: {}", e);
+        }
+    }
+
+    fn run_service() -> Result<(), String> {
+        let status = service_control_handler::register(SERVICE_NAME, |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                if let Some(status) = STATUS.get() {
+                    let _ = set_status(status, ServiceState::Stopped, ServiceExitCode::NO_ERROR);
+                }
+                std::process::exit(0)
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })
+        .map_err(|e| e.to_string())?;
+        let status = STATUS.get_or_init(|| status);
+        set_status(status, ServiceState::Running, ServiceExitCode::NO_ERROR)?;
+        let run = RUN
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or("The service has already run")?;
+        let result = run();
+        let exit_code = match result {
+            Ok(()) => ServiceExitCode::NO_ERROR,
+            Err(_) => ServiceExitCode::ServiceSpecific(1),
+        };
+        set_status(status, ServiceState::Stopped, exit_code)?;
+        result
+    }
+
+    fn set_status(
+        status: &ServiceStatusHandle,
+        state: ServiceState,
+        exit_code: ServiceExitCode,
+    ) -> Result<(), String> {
+        let controls_accepted = if state == ServiceState::Running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        };
+        status
+            .set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted,
+                exit_code,
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,69 @@
+//! Settings of the engine shared by the commands that execute transactions
+//!
+//! [`Settings`] gathers everything that changes how [`Accounts`] execute transactions, so
+//! that running, replaying, verifying, and serving all set up their accounts the same way.
+
+use std::path::PathBuf;
+
+use crate::{
+    accrual::AccrualSchedule, admin, history::HistorySpill, Accounts, Amount, DisputeLimits,
+    OverdraftLimits, Precision, SettlementPeriod, WithdrawalRetries,
+};
+
+/// How accounts execute transactions
+///
+/// The default settings are those of [`Accounts::default`].
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub precision: Precision,
+    /// Accept admin transactions, see [`Accounts::set_admin_transactions`]
+    ///
+    /// This requires the admin token, see [`admin::authenticate_env`].
+    pub admin: bool,
+    /// The admin token provided by the caller
+    pub token: Option<String>,
+    pub sandbox: bool,
+    pub dispute_limits: DisputeLimits,
+    pub withdrawal_retries: Option<WithdrawalRetries>,
+    pub settlement_period: SettlementPeriod,
+    pub representment_unfreezes: bool,
+    pub fee_overdraft: Amount,
+    pub overdraft_limits: OverdraftLimits,
+    pub accrual_schedule: Option<AccrualSchedule>,
+    pub history_spill: Option<HistorySpill>,
+    /// A snapshot to load the accounts from, see [`Accounts::load_snapshot`]
+    pub load_state: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Check the admin token if admin transactions are accepted
+    pub fn authenticate(&self) -> Result<(), String> {
+        if self.admin {
+            admin::authenticate_env(self.token.as_deref())?;
+        }
+        Ok(())
+    }
+    /// Create accounts with these settings, loading them from the snapshot if there is one
+    pub fn accounts(self) -> Result<Accounts, String> {
+        self.authenticate()?;
+        let mut accounts = Accounts::with_precision(self.precision);
+        accounts.set_admin_transactions(self.admin);
+        accounts.set_sandbox(self.sandbox);
+        accounts.set_dispute_limits(self.dispute_limits);
+        accounts.set_withdrawal_retries(self.withdrawal_retries);
+        accounts.set_settlement_period(self.settlement_period);
+        accounts.set_representment_unfreezes(self.representment_unfreezes);
+        accounts.set_fee_overdraft(self.fee_overdraft);
+        accounts.set_overdraft_limits(self.overdraft_limits);
+        accounts.set_accrual_schedule(self.accrual_schedule);
+        if let Some(spill) = self.history_spill {
+            accounts.set_history_spill(spill);
+        }
+        if let Some(path) = &self.load_state {
+            accounts
+                .load_snapshot(path)
+                .map_err(|e| format!("Unable to load state from {:?}: {}", path, e))?;
+        }
+        Ok(accounts)
+    }
+}
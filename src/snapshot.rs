@@ -0,0 +1,86 @@
+//! Snapshots of engine state
+//!
+//! A snapshot holds the full state of every account, including history, so a long-running
+//! ingest can be stopped and later resumed without replaying every transaction before it.
+//! Engine settings, such as the precision, are not part of a snapshot and must be set
+//! again when it is loaded. Neither is state that only lasts for a run, such as parked
+//! withdrawals or history kept on disk.
+//!
+//! Snapshots are text files. The first line is `snapshot,<version>`, and each account is
+//! a `client,<id>` line followed by the account's state.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{account::Account, transaction::ClientId, Accounts};
+
+/// The version of the snapshot format
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+impl Accounts {
+    /// Save a snapshot of every account to a file
+    ///
+    /// The file is written atomically, so it is never left partially written.
+    pub fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        let temp_path = path.with_extension("tmp");
+        self.write_snapshot(BufWriter::new(File::create(&temp_path)?))?;
+        fs::rename(&temp_path, path)
+    }
+    /// Write a snapshot of every account
+    pub fn write_snapshot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "snapshot,{}", SNAPSHOT_VERSION)?;
+        for (client_id, account) in self.iter() {
+            writeln!(writer, "client,{}", client_id)?;
+            account.save(&mut writer)?;
+        }
+        writer.flush()
+    }
+    /// Replace every account with those in a snapshot file
+    ///
+    /// Engine settings are kept.
+    pub fn load_snapshot(&mut self, path: &Path) -> io::Result<()> {
+        fn invalid(message: String) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        }
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if header != format!("snapshot,{}", SNAPSHOT_VERSION) {
+            return Err(invalid(format!("Unsupported snapshot header {:?}", header)));
+        }
+        let mut accounts = Vec::new();
+        let mut current: Option<(ClientId, String)> = None;
+        for line in lines {
+            let line = line?;
+            if let Some(id) = line.strip_prefix("client,") {
+                let client_id = id
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid client id {:?}", id)))?;
+                accounts.extend(current.replace((client_id, String::new())));
+            } else if let Some((_, state)) = &mut current {
+                state.push_str(&line);
+                state.push('\n');
+            } else {
+                return Err(invalid(format!("Expected a client, found {:?}", line)));
+            }
+        }
+        accounts.extend(current);
+        let mut loaded = Vec::with_capacity(accounts.len());
+        for (client_id, state) in accounts {
+            let account = Account::load(state.as_bytes())
+                .map_err(|e| invalid(format!("Invalid account of client {}: {}", client_id, e)))?;
+            loaded.push((client_id, account));
+        }
+        // Only replace accounts once the whole snapshot is valid
+        let clients: Vec<ClientId> = self.iter().map(|(client_id, _)| client_id).collect();
+        for client_id in clients {
+            self.remove(client_id);
+        }
+        for (client_id, account) in loaded {
+            self.insert(client_id, account);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! Opening input files, URLs, and standard input
+//!
+//! An input path of `-` is standard input. With the `download` feature, a path starting with
+//! `https://` or `http://` is downloaded as it is read, see the `download` module. Compressed
+//! input is decompressed as it is read, see [`decompressed`].
+//!
+//! Avro, Parquet, and Arrow files are read in blocks of records rather than lines, see
+//! [`BatchInput`].
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Seek},
+    path::Path,
+};
+
+use crate::{
+    input::{decompressed, Sha256Reader},
+    priority, Record,
+};
+
+/// How input is opened
+#[derive(Debug, Clone, Default)]
+pub struct InputOptions {
+    /// Advise the operating system that files will be read from start to end
+    pub sequential: bool,
+    /// The SHA-256 digest the input is expected to have, as hexadecimal
+    pub sha256: Option<String>,
+}
+
+/// Open an input file, URL, or stdin if the path is `-`
+///
+/// Compressed input is decompressed as it is read.
+pub fn open(path: &str) -> Result<Box<dyn Read>, String> {
+    decompress(path, open_raw(path, &InputOptions::default())?)
+}
+
+/// Open an input file, URL, or stdin if the path is `-`, without decompressing it
+///
+/// With an expected SHA-256 digest, the whole input is checked before it is returned, so
+/// nothing is processed from input that does not match. Files are read twice, once to check
+/// them. Standard input and URLs cannot be read again, so they are copied to a temporary file
+/// as they are checked.
+pub fn open_raw(path: &str, options: &InputOptions) -> Result<Box<dyn Read>, String> {
+    let Some(expected) = &options.sha256 else {
+        return open_unchecked(path, options);
+    };
+    let mut input = Sha256Reader::new(open_unchecked(path, options)?, expected)?;
+    let unverified = |e: io::Error| format!("Unable to verify {:?}: {}", path, e);
+    if path == "-" || is_url(path) {
+        let mut spool = tempfile::tempfile()
+            .map_err(|e| format!("Unable to create a temporary file: {}", e))?;
+        io::copy(&mut input, &mut spool).map_err(unverified)?;
+        spool.rewind().map_err(unverified)?;
+        return Ok(Box::new(spool));
+    }
+    io::copy(&mut input, &mut io::sink()).map_err(unverified)?;
+    open_unchecked(path, options)
+}
+
+/// Open an input file, URL, or stdin if the path is `-`, without checking its digest
+fn open_unchecked(path: &str, options: &InputOptions) -> Result<Box<dyn Read>, String> {
+    Ok(if path == "-" {
+        Box::new(io::stdin().lock())
+    } else if is_url(path) {
+        download(path)?
+    } else {
+        let file = File::open(path).map_err(|e| format!("Unable to open {:?}: {}", path, e))?;
+        if options.sequential {
+            if let Err(e) = priority::advise_sequential(&file) {
+                tracing::warn!("Unable to advise sequential reads of {:?}: {}", path, e);
+            }
+        }
+        Box::new(file)
+    })
+}
+
+/// Decompress an input if it is compressed
+pub fn decompress(path: &str, input: Box<dyn Read>) -> Result<Box<dyn Read>, String> {
+    decompressed(input).map_err(|e| format!("Unable to read {:?}: {}", path, e))
+}
+
+/// Check whether an input path is a URL to download
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://")
+}
+
+/// Start downloading an input URL
+#[cfg(feature = "download")]
+fn download(url: &str) -> Result<Box<dyn Read>, String> {
+    let download = crate::download::Download::open(url)
+        .map_err(|e| format!("Unable to download {:?}: {}", url, e))?;
+    Ok(Box::new(download))
+}
+
+#[cfg(not(feature = "download"))]
+fn download(_: &str) -> Result<Box<dyn Read>, String> {
+    Err("Unable to download input: transactor was built without the `download` feature".into())
+}
+
+/// Get the size of an input file, or `None` for standard input and URLs
+pub fn input_size(path: &str) -> Option<u64> {
+    if path == "-" || is_url(path) {
+        return None;
+    }
+    fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+/// A format of input that is read in blocks of records rather than lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchInput {
+    Avro,
+    Parquet,
+    ArrowFile,
+    ArrowStream,
+}
+
+impl BatchInput {
+    /// Detect the format of an input file from its extension
+    pub fn detect(path: &str) -> Option<Self> {
+        match Path::new(path).extension()?.to_str()? {
+            "avro" => Some(BatchInput::Avro),
+            "parquet" => Some(BatchInput::Parquet),
+            "arrow" | "feather" => Some(BatchInput::ArrowFile),
+            "arrows" => Some(BatchInput::ArrowStream),
+            _ => None,
+        }
+    }
+    /// Read the transactions in an input file
+    pub fn records(
+        self,
+        path: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<Record, String>>>, String> {
+        Ok(match self {
+            BatchInput::Avro => Box::new(avro_records(path)?),
+            BatchInput::Parquet => Box::new(parquet_records(path)?),
+            BatchInput::ArrowFile => Box::new(arrow_records(path, false)?),
+            BatchInput::ArrowStream => Box::new(arrow_records(path, true)?),
+        })
+    }
+}
+
+impl fmt::Display for BatchInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchInput::Avro => write!(f, "Avro"),
+            BatchInput::Parquet => write!(f, "Parquet"),
+            BatchInput::ArrowFile | BatchInput::ArrowStream => write!(f, "Arrow"),
+        }
+    }
+}
+
+/// Read the transactions in an Avro file
+#[cfg(feature = "avro")]
+fn avro_records(path: &str) -> Result<crate::avro::AvroRecords<io::BufReader<File>>, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {:?}: {}", path, e))?;
+    crate::avro::avro_records(io::BufReader::new(file))
+        .map_err(|e| format!("Unable to read {:?}: {}", path, e))
+}
+
+#[cfg(not(feature = "avro"))]
+fn avro_records(_: &str) -> Result<std::iter::Empty<Result<Record, String>>, String> {
+    Err("Unable to read Avro input: transactor was built without the `avro` feature".into())
+}
+
+/// Read the transactions in a Parquet file
+#[cfg(feature = "parquet")]
+fn parquet_records(path: &str) -> Result<crate::parquet::ParquetRecords, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {:?}: {}", path, e))?;
+    crate::parquet::parquet_records(file)
+        .map_err(|e| format!("Unable to read {:?} as Parquet: {}", path, e))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_records(_: &str) -> Result<std::iter::Empty<Result<Record, String>>, String> {
+    Err("Unable to read Parquet input: transactor was built without the `parquet` feature".into())
+}
+
+/// Read the transactions in an Arrow IPC file or stream
+#[cfg(feature = "arrow")]
+fn arrow_records(
+    path: &str,
+    stream: bool,
+) -> Result<Box<dyn Iterator<Item = Result<Record, String>>>, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {:?}: {}", path, e))?;
+    let invalid = |e| format!("Unable to read {:?} as Arrow: {}", path, e);
+    Ok(if stream {
+        Box::new(crate::arrow::arrow_stream_records(io::BufReader::new(file)).map_err(invalid)?)
+    } else {
+        Box::new(crate::arrow::arrow_file_records(file).map_err(invalid)?)
+    })
+}
+
+#[cfg(not(feature = "arrow"))]
+fn arrow_records(_: &str, _: bool) -> Result<std::iter::Empty<Result<Record, String>>, String> {
+    Err("Unable to read Arrow input: transactor was built without the `arrow` feature".into())
+}
@@ -0,0 +1,73 @@
+//! Storage of accounts in a SQLite database
+//!
+//! This module is enabled by the `sqlite` feature. Each account is stored as a row of the
+//! `accounts` table, holding the account's full state in the same format as archived
+//! accounts. The database uses write-ahead logging, so saved batches survive crashes.
+
+use std::{collections::BTreeSet, io, path::Path};
+
+use rusqlite::{params, Connection};
+
+use crate::{account::Account, store::AccountStore, transaction::ClientId, Accounts};
+
+/// A store of accounts in a SQLite database
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    /// Open the database at a path, creating it if it does not exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        connection
+            .execute_batch(
+                "PRAGMA journal_mode = WAL;
+                CREATE TABLE IF NOT EXISTS accounts (
+                    client INTEGER PRIMARY KEY,
+                    state TEXT NOT NULL
+                );",
+            )
+            .map_err(io::Error::other)?;
+        Ok(SqliteStore { connection })
+    }
+}
+
+impl AccountStore for SqliteStore {
+    fn load(&mut self, accounts: &mut Accounts) -> io::Result<()> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT client, state FROM accounts")
+            .map_err(io::Error::other)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, ClientId>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(io::Error::other)?;
+        for row in rows {
+            let (client_id, state) = row.map_err(io::Error::other)?;
+            accounts.insert(client_id, Account::load(state.as_bytes())?);
+        }
+        Ok(())
+    }
+    fn save(&mut self, accounts: &Accounts, clients: &BTreeSet<ClientId>) -> io::Result<()> {
+        let transaction = self.connection.transaction().map_err(io::Error::other)?;
+        for &client_id in clients {
+            match accounts.get(client_id) {
+                Some(account) => {
+                    let mut state = Vec::new();
+                    account.save(&mut state)?;
+                    let state = String::from_utf8(state).map_err(io::Error::other)?;
+                    transaction.execute(
+                        "INSERT INTO accounts (client, state) VALUES (?1, ?2)
+                        ON CONFLICT (client) DO UPDATE SET state = excluded.state",
+                        params![client_id, state],
+                    )
+                }
+                None => transaction
+                    .execute("DELETE FROM accounts WHERE client = ?1", params![client_id]),
+            }
+            .map_err(io::Error::other)?;
+        }
+        transaction.commit().map_err(io::Error::other)
+    }
+}
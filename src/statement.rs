@@ -0,0 +1,284 @@
+//! Account statements
+//!
+//! A [`Statement`] lists every transaction submitted for a client, whether it was
+//! applied, and the account's balances after each one. Statements can be rendered
+//! as plain text or as an HTML document suitable for sending to a customer.
+//!
+//! Statements may also list the [`Note`]s attached to the account by admin operations.
+//! These are for internal use, so they are only rendered if they were added.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    account::{Account, Accounts, Note},
+    amount::Amount,
+    records,
+    transaction::*,
+};
+
+/// A single line of a [`Statement`]
+#[derive(Debug, Clone)]
+pub struct StatementEntry {
+    /// The line of input the transaction was on
+    pub line_no: usize,
+    /// The transaction
+    pub tx: Transaction,
+    /// When the transaction was made, if the input says
+    pub timestamp: Option<Timestamp>,
+    /// Why the transaction was rejected, if it was
+    pub rejection: Option<String>,
+    /// The available balance after the transaction
+    pub balance: Amount,
+    /// The held balance after the transaction
+    pub held: Amount,
+}
+
+/// A statement of a client's account activity
+#[derive(Debug, Default)]
+pub struct Statement {
+    /// The client the statement is for
+    pub client: ClientId,
+    /// The transactions submitted for the client, in order
+    pub entries: Vec<StatementEntry>,
+    /// The account after all transactions were executed
+    pub account: Account,
+    /// Notes attached to the account by admin operations, oldest first
+    pub notes: Vec<Note>,
+}
+
+impl Statement {
+    /// Generate a statement for a client by executing their transactions from a source
+    ///
+    /// Transfers to the client are included, so the transactions of other clients are
+    /// executed as well.
+    pub fn generate<R>(source: R, client: ClientId) -> Result<Self, String>
+    where
+        R: Read,
+    {
+        let mut accounts = Accounts::default();
+        let mut entries = Vec::new();
+        for record in records(source) {
+            let record = record?;
+            let tx = record.tx.tx;
+            let timestamp = record.tx.timestamp;
+            let involves_client = record.tx.client == client
+                || matches!(tx, Transaction::Transfer { to, .. } if to == client);
+            let result = accounts.transact(record.tx);
+            if !involves_client {
+                continue;
+            }
+            let account = accounts.get(client);
+            entries.push(StatementEntry {
+                line_no: record.line_no,
+                tx,
+                timestamp,
+                rejection: result.err().map(|e| e.to_string()),
+                balance: account.map(Account::balance).unwrap_or_default(),
+                held: account.map(Account::held).unwrap_or_default(),
+            });
+        }
+        let account = accounts.remove(client).unwrap_or_default();
+        Ok(Statement {
+            client,
+            entries,
+            notes: account.notes().to_vec(),
+            account,
+        })
+    }
+    /// Check if any of the transactions have timestamps, in which case they are rendered
+    fn is_timed(&self) -> bool {
+        self.entries.iter().any(|entry| entry.timestamp.is_some())
+    }
+    /// Render the statement as plain text
+    pub fn render_text<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "Statement for client {}", self.client)?;
+        writeln!(writer)?;
+        let timed = self.is_timed();
+        if timed {
+            write!(writer, "{:<25}", "time")?;
+        }
+        writeln!(
+            writer,
+            "{:<12}{:>10}{:>16}{:>16}{:>16}  status",
+            "type", "tx", "amount", "available", "held"
+        )?;
+        for entry in &self.entries {
+            if timed {
+                write!(writer, "{:<25}", time_cell(entry.timestamp))?;
+            }
+            writeln!(
+                writer,
+                "{:<12}{:>10}{:>16}{:>16}{:>16}  {}",
+                entry.tx.type_name(),
+                entry.tx.tx_id(),
+                amount_cell(&entry.tx),
+                entry.balance,
+                entry.held,
+                entry.rejection.as_deref().unwrap_or("applied")
+            )?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "Available: {}", self.account.balance())?;
+        writeln!(writer, "Held:      {}", self.account.held())?;
+        writeln!(writer, "Total:     {}", self.account.total())?;
+        writeln!(
+            writer,
+            "Locked:    {}",
+            if self.account.is_frozen() {
+                "yes"
+            } else {
+                "no"
+            }
+        )?;
+        if !self.notes.is_empty() {
+            writeln!(writer)?;
+            writeln!(writer, "Notes:")?;
+            for note in &self.notes {
+                writeln!(
+                    writer,
+                    "{} {} ({}): {}",
+                    format_timestamp(note.timestamp),
+                    note.operator,
+                    note.reference,
+                    note.text
+                )?;
+            }
+        }
+        Ok(())
+    }
+    /// Render the statement as a standalone HTML document
+    pub fn render_html<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let timed = self.is_timed();
+        let mut rows = String::new();
+        for entry in &self.entries {
+            let (class, status) = match &entry.rejection {
+                Some(reason) => ("rejected", escape_html(reason)),
+                None => ("applied", "Applied".into()),
+            };
+            let time = if timed {
+                format!("<td>{}</td>", time_cell(entry.timestamp))
+            } else {
+                String::new()
+            };
+            rows.push_str(&format!(
+                "      <tr class=\"{}\">{}<td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                class,
+                time,
+                entry.tx.type_name(),
+                entry.tx.tx_id(),
+                amount_cell(&entry.tx),
+                entry.balance,
+                entry.held,
+                status
+            ));
+        }
+        let mut notes = String::new();
+        if !self.notes.is_empty() {
+            notes.push_str("  <h2>Notes</h2>\n  <table>\n");
+            for note in &self.notes {
+                notes.push_str(&format!(
+                    "    <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    format_timestamp(note.timestamp),
+                    escape_html(&note.operator),
+                    escape_html(&note.reference),
+                    escape_html(&note.text)
+                ));
+            }
+            notes.push_str("  </table>\n");
+        }
+        let html = HTML_TEMPLATE
+            .replace("{{client}}", &self.client.to_string())
+            .replace("{{time}}", if timed { "<th>Time</th>" } else { "" })
+            .replace("{{rows}}", &rows)
+            .replace("{{available}}", &self.account.balance().to_string())
+            .replace("{{held}}", &self.account.held().to_string())
+            .replace("{{total}}", &self.account.total().to_string())
+            .replace("{{notes}}", &notes)
+            .replace(
+                "{{locked}}",
+                if self.account.is_frozen() {
+                    "Yes"
+                } else {
+                    "No"
+                },
+            );
+        writer.write_all(html.as_bytes())
+    }
+}
+
+/// Get the amount of a transaction for display, or an empty string if it has none
+fn amount_cell(tx: &Transaction) -> String {
+    tx.amount()
+        .map(|amount| amount.to_string())
+        .unwrap_or_default()
+}
+
+/// Get the time of a transaction for display, or an empty string if it has none
+fn time_cell(timestamp: Option<Timestamp>) -> String {
+    timestamp.map(format_timestamp).unwrap_or_default()
+}
+
+/// Format a time in seconds since the Unix epoch as a UTC date and time
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let secs = timestamp % 86_400;
+    // Convert days since the epoch to a civil date
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Statement for client {{client}}</title>
+  <style>
+    body { font-family: sans-serif; margin: 2em; }
+    table { border-collapse: collapse; }
+    th, td { padding: 0.3em 0.8em; border-bottom: 1px solid #ddd; text-align: right; }
+    th:first-child, td:first-child, td:last-child { text-align: left; }
+    tr.rejected { color: #999; }
+  </style>
+</head>
+<body>
+  <h1>Statement for client {{client}}</h1>
+  <table>
+    <thead>
+      <tr>{{time}}<th>Type</th><th>Transaction</th><th>Amount</th><th>Available</th><th>Held</th><th>Status</th></tr>
+    </thead>
+    <tbody>
+{{rows}}    </tbody>
+  </table>
+  <h2>Summary</h2>
+  <table>
+    <tr><td>Available</td><td>{{available}}</td></tr>
+    <tr><td>Held</td><td>{{held}}</td></tr>
+    <tr><td>Total</td><td>{{total}}</td></tr>
+    <tr><td>Locked</td><td>{{locked}}</td></tr>
+  </table>
+{{notes}}</body>
+</html>
+"#;
@@ -0,0 +1,121 @@
+//! Persistent storage of accounts
+//!
+//! An [`AccountStore`] keeps the full state of accounts, including history, so that it
+//! persists across runs. A [`StoreWriter`] saves the accounts changed by transactions to a
+//! store in batches as they are executed, so a crash loses at most the latest batch.
+//!
+//! With the `sqlite` feature, accounts can be stored in a SQLite database, see
+//! `sqlite::SqliteStore`.
+
+use std::{collections::BTreeSet, io};
+
+use crate::{
+    transaction::{ClientId, Transaction},
+    Accounts, ClientTransaction,
+};
+
+/// The number of transactions whose changes are saved to a store at once
+const BATCH_SIZE: usize = 10_000;
+
+/// A persistent store of accounts
+pub trait AccountStore {
+    /// Load every stored account into `accounts`
+    fn load(&mut self, accounts: &mut Accounts) -> io::Result<()>;
+    /// Save the accounts of some clients all at once
+    ///
+    /// Clients without an account in `accounts`, such as archived ones, are removed from
+    /// the store.
+    fn save(&mut self, accounts: &Accounts, clients: &BTreeSet<ClientId>) -> io::Result<()>;
+}
+
+impl<S: AccountStore + ?Sized> AccountStore for Box<S> {
+    fn load(&mut self, accounts: &mut Accounts) -> io::Result<()> {
+        (**self).load(accounts)
+    }
+    fn save(&mut self, accounts: &Accounts, clients: &BTreeSet<ClientId>) -> io::Result<()> {
+        (**self).save(accounts, clients)
+    }
+}
+
+/// Open a store given as a specification, such as `sqlite:accounts.db`
+pub fn open(spec: &str) -> Result<Box<dyn AccountStore + Send>, String> {
+    let Some(path) = spec.strip_prefix("sqlite:") else {
+        return Err(format!(
+            "Unknown store {:?}. Stores are given as sqlite:<path>",
+            spec
+        ));
+    };
+    #[cfg(feature = "sqlite")]
+    {
+        let store = crate::sqlite::SqliteStore::open(std::path::Path::new(path))
+            .map_err(|e| format!("Unable to open store {:?}: {}", path, e))?;
+        Ok(Box::new(store))
+    }
+    #[cfg(not(feature = "sqlite"))]
+    Err(format!(
+        "Unable to open store {:?}: transactor was built without the `sqlite` feature",
+        path
+    ))
+}
+
+/// Saves the accounts changed by transactions to a store in batches
+pub struct StoreWriter<S> {
+    store: S,
+    changed: BTreeSet<ClientId>,
+    pending: usize,
+    error: Option<io::Error>,
+}
+
+impl<S: AccountStore> StoreWriter<S> {
+    /// Start saving changes to a store
+    pub fn new(store: S) -> Self {
+        StoreWriter {
+            store,
+            changed: BTreeSet::new(),
+            pending: 0,
+            error: None,
+        }
+    }
+    /// Observe the execution of a transaction
+    ///
+    /// Once a batch of transactions has been observed, the accounts they changed are saved.
+    /// If saving fails, nothing more is saved and the error is returned by
+    /// [`StoreWriter::flush`] or [`StoreWriter::finish`].
+    pub fn observe(&mut self, tx: &ClientTransaction, accounts: &Accounts) {
+        if self.error.is_some() {
+            return;
+        }
+        self.changed.insert(tx.client);
+        if let Transaction::Transfer { to, .. } = tx.tx {
+            self.changed.insert(to);
+        }
+        self.pending += 1;
+        if self.pending == BATCH_SIZE {
+            if let Err(e) = self.flush(accounts) {
+                self.error = Some(e);
+            }
+        }
+    }
+    /// Mark an account as changed outside of a transaction, such as by
+    /// [`Accounts::accrue`], so that it is saved with the next batch
+    pub fn mark_changed(&mut self, client: ClientId) {
+        self.changed.insert(client);
+    }
+    /// Save the accounts changed since the last batch
+    ///
+    /// If an earlier batch failed to save, its error is returned instead.
+    pub fn flush(&mut self, accounts: &Accounts) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.store.save(accounts, &self.changed)?;
+        self.changed.clear();
+        self.pending = 0;
+        Ok(())
+    }
+    /// Save the remaining changes and return the store
+    pub fn finish(mut self, accounts: &Accounts) -> io::Result<S> {
+        self.flush(accounts)?;
+        Ok(self.store)
+    }
+}
@@ -0,0 +1,86 @@
+//! Processing transactions from async sources
+//!
+//! With the `async` feature, the engine can be embedded in async services that receive
+//! transactions in real time, such as from sockets or message queues. Each transaction is
+//! applied as soon as it arrives, so the accounts always reflect every transaction received
+//! so far.
+
+use std::{future::poll_fn, pin::Pin};
+
+use futures_core::Stream;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::{
+    input::{Parser, ProcessError},
+    Accounts, ClientTransaction, Record, TransactionError,
+};
+
+/// Apply transactions from a stream to accounts, passing the result of each one to a callback
+///
+/// The callback also receives the accounts as they are after the transaction.
+pub async fn process_transaction_stream<S, F>(
+    mut stream: S,
+    accounts: &mut Accounts,
+    mut on_executed: F,
+) where
+    S: Stream<Item = ClientTransaction> + Unpin,
+    F: FnMut(&ClientTransaction, Result<(), TransactionError>, &Accounts),
+{
+    while let Some(tx) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        let result = accounts.transact(tx.clone());
+        on_executed(&tx, result, accounts);
+    }
+}
+
+/// Apply transactions parsed from an async reader to accounts
+///
+/// This is the async version of [`process_transaction_source`](crate::process_transaction_source).
+pub async fn process_async_transaction_source<R>(
+    source: R,
+    accounts: &mut Accounts,
+) -> Result<(), ProcessError>
+where
+    R: AsyncRead + Unpin,
+{
+    process_async_transaction_source_with(source, accounts, |record, result, _| {
+        if let Err(e) = result {
+            crate::logging::rejected(record, &e);
+        }
+    })
+    .await
+}
+
+/// Apply transactions parsed from an async reader to accounts, passing the result of each one
+/// to a callback
+///
+/// This is the async version of
+/// [`process_transaction_source_with`](crate::process_transaction_source_with).
+pub async fn process_async_transaction_source_with<R, F>(
+    source: R,
+    accounts: &mut Accounts,
+    mut on_executed: F,
+) -> Result<(), ProcessError>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(&Record, Result<(), TransactionError>, &Accounts),
+{
+    let mut lines = BufReader::new(source).lines();
+    let mut parser = Parser::new(None);
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => return Err(parser.read_error(e)),
+        };
+        // Break on parse error
+        if let Some(record) = parser.parse_line(line) {
+            let record = record?;
+            let result = accounts.transact(record.tx.clone());
+            on_executed(&record, result, accounts);
+        }
+    }
+    match parser.finish() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
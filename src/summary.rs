@@ -0,0 +1,105 @@
+//! Summary statistics of a run
+//!
+//! A [`ProcessingSummary`] observes the result of every transaction and counts what was done.
+//! It is returned by [`process_transaction_source`](crate::process_transaction_source).
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::{
+    account::TransactionError,
+    amount::Amount,
+    transaction::{ChangeKind, ClientId, ClientTransaction, Transaction, TRANSACTION_TYPES},
+};
+
+/// Counts of the transactions applied in a run
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessingSummary {
+    /// The number of transactions of each type, by the name it has in input
+    pub by_type: BTreeMap<&'static str, u64>,
+    /// The number of transactions that were applied
+    pub accepted: u64,
+    /// The number of transactions that were rejected
+    pub rejected: u64,
+    /// The total amount of applied deposits
+    ///
+    /// This stops growing if it would overflow.
+    pub deposited: Amount,
+    /// The total amount of applied withdrawals
+    ///
+    /// This stops growing if it would overflow.
+    pub withdrawn: Amount,
+    /// The total amount of applied fees
+    ///
+    /// This stops growing if it would overflow.
+    pub fees: Amount,
+    /// The clients whose accounts were changed by an applied transaction
+    pub clients: BTreeSet<ClientId>,
+}
+
+impl ProcessingSummary {
+    /// Observe the execution of a transaction
+    pub fn observe(&mut self, tx: &ClientTransaction, result: &Result<(), TransactionError>) {
+        *self.by_type.entry(tx.tx.type_name()).or_default() += 1;
+        if result.is_err() {
+            self.rejected += 1;
+            return;
+        }
+        self.accepted += 1;
+        self.clients.insert(tx.client);
+        match tx.tx {
+            Transaction::Change { change, .. } => {
+                let total = match change.kind {
+                    ChangeKind::Deposit => &mut self.deposited,
+                    ChangeKind::Withdrawal => &mut self.withdrawn,
+                    ChangeKind::Fee => &mut self.fees,
+                };
+                *total = total.checked_add(change.amount).unwrap_or(*total);
+            }
+            Transaction::Transfer { to, .. } => {
+                self.clients.insert(to);
+            }
+            Transaction::Dispute { .. }
+            | Transaction::Resolution { .. }
+            | Transaction::Unfreeze(_)
+            | Transaction::Representment(_) => {}
+        }
+    }
+    /// Get the total number of transactions
+    pub fn transactions(&self) -> u64 {
+        self.accepted + self.rejected
+    }
+}
+
+/// Amounts are written with the formatter's precision, if it has one
+impl fmt::Display for ProcessingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Transactions: {} ({} accepted, {} rejected)",
+            self.transactions(),
+            self.accepted,
+            self.rejected
+        )?;
+        for name in TRANSACTION_TYPES {
+            if let Some(count) = self.by_type.get(name) {
+                writeln!(f, "  {}: {}", name, count)?;
+            }
+        }
+        match f.precision() {
+            Some(places) => {
+                writeln!(f, "Deposited: {:.*}", places, self.deposited)?;
+                writeln!(f, "Withdrawn: {:.*}", places, self.withdrawn)?;
+                writeln!(f, "Fees: {:.*}", places, self.fees)?;
+            }
+            None => {
+                writeln!(f, "Deposited: {}", self.deposited)?;
+                writeln!(f, "Withdrawn: {}", self.withdrawn)?;
+                writeln!(f, "Fees: {}", self.fees)?;
+            }
+        }
+        writeln!(f, "Accounts touched: {}", self.clients.len())
+    }
+}
@@ -0,0 +1,41 @@
+//! Export of tracing spans over OTLP
+//!
+//! Processing is instrumented with [`tracing`] spans. This module, enabled by the `otel`
+//! feature, exports those spans to an OpenTelemetry collector.
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+
+use crate::logging::BoxedLayer;
+
+/// Keeps spans being exported until dropped, at which point remaining spans are flushed
+pub struct TelemetryGuard {
+    provider: SdkTracerProvider,
+}
+
+/// Start exporting spans to an OTLP/HTTP endpoint, such as `http://localhost:4318/v1/traces`
+///
+/// Spans are exported once the returned layer is installed, such as with
+/// [`logging::init`](crate::logging::init).
+pub fn init(endpoint: &str) -> Result<(BoxedLayer, TelemetryGuard), String> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| format!("Unable to create OTLP exporter: {}", e))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("transactor").build())
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("transactor"));
+    Ok((Box::new(layer), TelemetryGuard { provider }))
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Error flushing spans: {}", e);
+        }
+    }
+}
@@ -1,8 +1,21 @@
 use crate::{
-    account::{Account, Accounts},
-    amount::Amount,
-    process_transaction_source,
-    transaction::{ResolutionKind, Transaction},
+    account::{Account, Accounts, SettlementPeriod, TransactionError},
+    admin,
+    alert::{AlertMonitor, AlertRule},
+    amount::{Amount, Precision},
+    archive::Archive,
+    client_map::ClientMap,
+    filter::{self, Filter},
+    history::{HistoryBackend, HistorySpill, HistoryStore},
+    input::{self, ProcessError, ProcessErrorKind},
+    notify::{self, DigestTracker},
+    output::{self, OutputFormat},
+    process_transaction_source, process_transaction_source_with,
+    reconcile::{self, DisputeLedger},
+    record::{self, Recorder},
+    statement::Statement,
+    transaction::{ClientTransaction, ResolutionKind, Transaction},
+    trend, try_process_records_with, try_process_transaction_source_with,
 };
 
 #[test]
@@ -51,7 +64,7 @@ fn withdrawal() {
 #[test]
 fn resolve() {
     let mut account = account_with_100();
-    account.transact(Transaction::Dispute(0)).unwrap();
+    account.transact(Transaction::dispute(0)).unwrap();
     assert_eq!(account.balance(), 0.0);
     assert_eq!(account.held(), 100.0);
     account
@@ -65,7 +78,7 @@ fn resolve() {
 #[test]
 fn chargeback() {
     let mut account = account_with_100();
-    account.transact(Transaction::Dispute(0)).unwrap();
+    account.transact(Transaction::dispute(0)).unwrap();
     assert_eq!(account.balance(), 0.0);
     assert_eq!(account.held(), 100.0);
     account
@@ -76,10 +89,307 @@ fn chargeback() {
     assert!(account.is_frozen());
 }
 
+/// An account that had 100 deposited and then 40 withdrawn with transaction id 1
+fn account_with_withdrawal() -> Account {
+    let mut account = account_with_100();
+    account
+        .transact(Transaction::withdrawal(1, Amount::from_f64(40.0).unwrap()))
+        .unwrap();
+    account
+}
+
+#[test]
+fn dispute_withdrawal() {
+    let mut account = account_with_withdrawal();
+    account.transact(Transaction::dispute(1)).unwrap();
+    assert_eq!(account.balance(), 60.0);
+    assert_eq!(account.held(), 0.0);
+    assert_eq!(account.pending_credit(), 40.0);
+    assert_eq!(account.total(), 60.0);
+}
+
+#[test]
+fn resolve_withdrawal() {
+    let mut account = account_with_withdrawal();
+    account.transact(Transaction::dispute(1)).unwrap();
+    account
+        .transact(Transaction::resolution(ResolutionKind::Resolve, 1))
+        .unwrap();
+    assert_eq!(account.balance(), 100.0);
+    assert_eq!(account.pending_credit(), 0.0);
+    assert!(!account.is_frozen());
+    // The withdrawal was reversed, so it cannot be disputed again
+    account.transact(Transaction::dispute(1)).unwrap_err();
+}
+
+#[test]
+fn chargeback_withdrawal() {
+    let mut account = account_with_withdrawal();
+    account.transact(Transaction::dispute(1)).unwrap();
+    account
+        .transact(Transaction::resolution(ResolutionKind::Chargeback, 1))
+        .unwrap();
+    assert_eq!(account.balance(), 60.0);
+    assert_eq!(account.pending_credit(), 0.0);
+    assert!(!account.is_frozen());
+    account
+        .transact(Transaction::resolution(ResolutionKind::Chargeback, 1))
+        .unwrap_err();
+    account.transact(Transaction::dispute(1)).unwrap_err();
+}
+
+#[test]
+fn partial_disputes() {
+    let mut accounts = Accounts::default();
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,100").unwrap();
+    transact("dispute,1,1,30").unwrap();
+    // Only part of the dispute is charged back, and the rest is released
+    let err = transact("chargeback,1,1,40").unwrap_err();
+    assert_eq!(err.code(), "excessive_dispute_amount");
+    transact("chargeback,1,1,20").unwrap();
+    assert_eq!(accounts[1].balance(), 80.0);
+    assert_eq!(accounts[1].held(), 0.0);
+    assert!(accounts[1].is_frozen());
+    // What was not charged back can still be disputed
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    assert!(matches!(
+        transact("dispute,1,1,81"),
+        Err(TransactionError::ExcessiveDisputeAmount { tx_id: 1, .. })
+    ));
+    transact("dispute,1,1").unwrap();
+    assert_eq!(accounts[1].held(), 80.0);
+
+    // Partial disputes are kept in snapshots
+    let mut saved = Vec::new();
+    accounts[1].save(&mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap();
+    assert!(saved.contains("\ndisputed,1\ncharged-back,1,20.0000\npartly-reversed,1,20.0000\n"));
+    let mut loaded = Account::load(saved.as_bytes()).unwrap();
+    loaded
+        .transact(Transaction::resolution(ResolutionKind::Chargeback, 1))
+        .unwrap();
+    assert_eq!(loaded.total(), 0.0);
+    loaded.transact(Transaction::dispute(1)).unwrap_err();
+
+    // Resolving part of a withdrawal returns that part
+    let mut account = account_with_withdrawal();
+    let partial = ClientTransaction::dispute(0, 1)
+        .partial(Amount::from_f64(15.0).unwrap())
+        .unwrap();
+    assert_eq!(partial.to_string(), "dispute,0,1,15.0000");
+    account.transact(partial.tx).unwrap();
+    assert_eq!(account.pending_credit(), 15.0);
+    account
+        .transact(Transaction::resolution(ResolutionKind::Resolve, 1))
+        .unwrap();
+    assert_eq!(account.balance(), 75.0);
+    account.transact(Transaction::dispute(1)).unwrap();
+    assert_eq!(account.pending_credit(), 25.0);
+}
+
+#[test]
+fn representment() {
+    let mut accounts = Accounts::default();
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,100").unwrap();
+    transact("deposit,1,2,50").unwrap();
+    assert_eq!(
+        transact("representment,1,1").unwrap_err().code(),
+        "invalid_representment"
+    );
+    for tx in [
+        "dispute,1,1,30",
+        "chargeback,1,1",
+        "dispute,1,2",
+        "chargeback,1,2",
+    ] {
+        transact(tx).unwrap();
+    }
+    transact("chargeback_reversal,1,1").unwrap();
+    // Each chargeback is only reversed once
+    transact("representment,1,1").unwrap_err();
+    assert_eq!(accounts[1].balance(), 100.0);
+    assert!(accounts[1].is_frozen());
+
+    // With the option, the account is unfrozen once none of its chargebacks stand
+    accounts.set_representment_unfreezes(true);
+    let mut saved = Vec::new();
+    accounts[1].save(&mut saved).unwrap();
+    assert!(String::from_utf8(saved)
+        .unwrap()
+        .contains("\ncharged-back,2,50.0000\n"));
+    accounts
+        .transact("representment,1,2".parse().unwrap())
+        .unwrap();
+    assert_eq!(accounts[1].balance(), 150.0);
+    assert!(!accounts[1].is_frozen());
+}
+
+#[test]
+fn fees() {
+    let mut accounts = Accounts::default();
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,10").unwrap();
+    transact("dispute,1,1").unwrap();
+    transact("chargeback,1,1").unwrap();
+    transact("deposit,1,2,3").unwrap();
+    // Fees are charged to frozen accounts, but need the funds by default
+    transact("fee,1,3,2").unwrap();
+    assert_eq!(
+        transact("fee,1,4,2").unwrap_err().code(),
+        "insufficient_funds"
+    );
+    accounts.set_fee_overdraft("5".parse().unwrap());
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("fee,1,4,2").unwrap();
+    assert_eq!(
+        transact("fee,1,5,5").unwrap_err().code(),
+        "insufficient_funds"
+    );
+    assert_eq!(accounts[1].balance(), -1.0);
+
+    // Fees are kept apart from withdrawals, and can be disputed like them
+    let mut saved = Vec::new();
+    accounts[1].save(&mut saved).unwrap();
+    assert!(String::from_utf8(saved)
+        .unwrap()
+        .contains("\nhistory,4,fee,2.0000\n"));
+    accounts.transact("dispute,1,4".parse().unwrap()).unwrap();
+    accounts.transact("resolve,1,4".parse().unwrap()).unwrap();
+    assert_eq!(accounts[1].balance(), 1.0);
+}
+
+#[test]
+fn overdraft_limits() {
+    use crate::OverdraftLimits;
+
+    let mut limits = OverdraftLimits {
+        default: "10".parse().unwrap(),
+        ..OverdraftLimits::default()
+    };
+    limits
+        .read_clients("client,limit\n2,0\n".as_bytes())
+        .unwrap();
+    assert!(limits
+        .clone()
+        .read_clients("client,limit\n2,1\n".as_bytes())
+        .is_err());
+    let mut accounts = Accounts::default();
+    accounts.set_overdraft_limits(limits);
+    accounts.set_fee_overdraft("1".parse().unwrap());
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,5").unwrap();
+    transact("deposit,2,2,5").unwrap();
+    assert_eq!(
+        transact("withdrawal,1,3,16").unwrap_err().code(),
+        "insufficient_funds"
+    );
+    transact("withdrawal,1,4,15").unwrap();
+    // Fees may go past the overdraft limit by the fee overdraft
+    transact("fee,1,5,1").unwrap();
+    assert_eq!(
+        transact("withdrawal,2,6,6").unwrap_err().code(),
+        "insufficient_funds"
+    );
+    transact("fee,2,7,6").unwrap();
+    assert_eq!(accounts[1].balance(), -11.0);
+    assert_eq!(accounts[2].balance(), -1.0);
+}
+
+#[test]
+fn accrual_schedule() {
+    use std::num::NonZeroU64;
+
+    use crate::accrual::{AccrualPolicy, AccrualSchedule, FundsTreatment};
+
+    const DAY: u64 = 24 * 60 * 60;
+    let mut accounts = Accounts::default();
+    accounts.set_accrual_schedule(Some(AccrualSchedule {
+        policy: AccrualPolicy {
+            interest_rate: "0.1".parse().unwrap(),
+            held: FundsTreatment::Penalty("0.5".parse().unwrap()),
+            ..AccrualPolicy::default()
+        },
+        period: NonZeroU64::new(DAY).unwrap(),
+    }));
+    let mut transact = |tx: String| accounts.transact(tx.parse().unwrap());
+    transact(format!("deposit,1,1,100,,{}", DAY / 2)).unwrap();
+    transact(format!("deposit,2,2,10,,{}", DAY / 2)).unwrap();
+    transact(format!("dispute,2,2,,,{}", DAY - 1)).unwrap();
+    // Two periods end before the next transaction, and interest compounds
+    transact(format!("deposit,1,3,1,,{}", 3 * DAY - 1)).unwrap();
+    assert_eq!(accounts[1].balance(), 122.0);
+    // Penalties on held funds are taken, even if they overdraw the account
+    assert_eq!(accounts[2].balance(), -10.0);
+
+    // Accruals are not transactions, so they are not activity and cannot be disputed
+    assert_eq!(accounts[1].activity().unwrap().last_tx, 3);
+    let mut saved = Vec::new();
+    accounts[1].save(&mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap();
+    assert_eq!(saved.matches("\nhistory,").count(), 2);
+    assert!(matches!(
+        accounts.transact(format!("dispute,1,{},,", u32::MAX - 1).parse().unwrap()),
+        Err(TransactionError::InvalidDispute(_))
+    ));
+}
+
+#[test]
+fn transfer() {
+    let input = "type,client,tx,amount,to
+        deposit,1,1,100,
+        deposit,3,2,10,
+        dispute,3,2,,
+        chargeback,3,2,,
+        transfer,1,3,30,2
+        transfer,1,4,80,2
+        transfer,2,5,10,2
+        transfer,1,6,5,3
+        transfer,2,3,1,1
+        transfer,2,7,5,4
+        dispute,2,7,,
+        transfer,1,8,,2";
+    let mut accounts = Accounts::default();
+    let mut errors = Vec::new();
+    process_transaction_source_with(input.as_bytes(), &mut accounts, |_, result, _| {
+        errors.push(result.err().map(|e| e.to_string()))
+    })
+    .unwrap_err();
+    let applied: Vec<bool> = errors.iter().map(Option::is_none).collect();
+    assert_eq!(
+        applied,
+        [true, true, true, true, true, false, false, false, false, true, false]
+    );
+    assert_eq!(
+        errors[7].as_deref(),
+        Some("Destination account of client 3 is frozen")
+    );
+    assert_eq!(accounts[1].balance(), 70.0);
+    assert_eq!(accounts[2].balance(), 25.0);
+    assert_eq!(accounts[3].total(), 0.0);
+    assert_eq!(accounts[4].balance(), 5.0);
+    assert_eq!(accounts[4].activity().unwrap().first_tx, 7);
+    // Transfers are written back out with their destination
+    let tx = ClientTransaction {
+        client: 1,
+        tx: Transaction::transfer(3, 2, Amount::from_f64(30.0).unwrap()),
+        timestamp: None,
+    };
+    assert_eq!(tx.to_string(), "transfer,1,3,30.0000,2");
+    assert_eq!(
+        tx.to_string()
+            .parse::<ClientTransaction>()
+            .unwrap()
+            .to_string(),
+        tx.to_string()
+    );
+}
+
 #[test]
 fn double_chargeback() {
     let mut account = account_with_100();
-    account.transact(Transaction::Dispute(0)).unwrap();
+    account.transact(Transaction::dispute(0)).unwrap();
     assert_eq!(account.balance(), 0.0);
     assert_eq!(account.held(), 100.0);
     account
@@ -113,3 +423,4399 @@ fn amount_reliability() {
     i += delta;
     assert_eq!(i, 0.9);
 }
+
+#[test]
+fn error_codes() {
+    use std::error::Error;
+
+    use crate::{account::TransactionError, transaction::TransactionParseError};
+
+    let mut account = Account::default();
+    let err = account
+        .transact(Transaction::withdrawal(1, Amount::from_f64(5.0).unwrap()))
+        .unwrap_err();
+    assert_eq!(err.code(), "insufficient_funds");
+    assert_eq!(err.number(), 2);
+    assert_eq!(
+        err.to_string(),
+        "Attempted to withdraw 5.0000 from an account with 0.0000 available"
+    );
+    let err = "deposit,1,1,abc"
+        .parse::<crate::ClientTransaction>()
+        .unwrap_err();
+    assert_eq!(err.code(), "invalid_amount");
+    assert_eq!(err.number(), 108);
+    let err = TransactionError::History(std::io::Error::other("disk full"));
+    assert_eq!(err.source().unwrap().to_string(), "disk full");
+    assert!(matches!(
+        "chargeback".parse::<crate::ClientTransaction>(),
+        Err(TransactionParseError::MissingClientId)
+    ));
+}
+
+#[test]
+fn cancellation() {
+    use std::io::{self, Read};
+
+    use crate::{process_transaction_source_with_cancel, CancellationToken};
+
+    /// Input that is read one line at a time and cancels a token after some lines
+    struct Lines {
+        lines: Vec<&'static str>,
+        token: CancellationToken,
+        cancel_after: usize,
+    }
+
+    impl Read for Lines {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.cancel_after == 0 {
+                self.token.cancel();
+            }
+            self.cancel_after = self.cancel_after.saturating_sub(1);
+            if self.lines.is_empty() {
+                return Ok(0);
+            }
+            let line = self.lines.remove(0);
+            buf[..line.len()].copy_from_slice(line.as_bytes());
+            Ok(line.len())
+        }
+    }
+
+    let lines = vec![
+        "type,client,tx,amount\n",
+        "deposit,1,1,10\n",
+        "deposit,1,2,10\n",
+        "deposit,1,3,10\n",
+        "deposit,1,4,10\n",
+    ];
+    let token = CancellationToken::new();
+    let mut accounts = Accounts::default();
+    let input = Lines {
+        lines: lines.clone(),
+        token: token.clone(),
+        cancel_after: 3,
+    };
+    let processed = process_transaction_source_with_cancel(input, &mut accounts, &token).unwrap();
+    assert!(processed.cancelled);
+    let last_line = processed.last_line.unwrap();
+    assert!(last_line < 5);
+    assert_eq!(processed.summary.accepted as usize, last_line - 1);
+    assert_eq!(accounts[1].total(), 10.0 * (last_line - 1) as f64);
+
+    let token = CancellationToken::new();
+    let mut accounts = Accounts::default();
+    let input = Lines {
+        lines,
+        token: CancellationToken::new(),
+        cancel_after: 0,
+    };
+    let processed = process_transaction_source_with_cancel(input, &mut accounts, &token).unwrap();
+    assert!(!processed.cancelled);
+    assert_eq!(processed.last_line, Some(5));
+    assert_eq!(accounts[1].total(), 40.0);
+}
+
+#[test]
+fn non_positive_amounts() {
+    use crate::{account::TransactionError, transaction::TransactionParseError};
+
+    for amount in ["0", "-50.0", "0.000000001"] {
+        let result = format!("deposit,1,1,{}", amount).parse::<crate::ClientTransaction>();
+        assert!(matches!(
+            result,
+            Err(TransactionParseError::NonPositiveAmount(_))
+        ));
+    }
+    let mut account = account_with_100();
+    for amount in [0.0, -50.0] {
+        let amount = Amount::from_f64(amount).unwrap();
+        let result = account.transact(Transaction::deposit(1, amount));
+        assert!(matches!(
+            result,
+            Err(TransactionError::NonPositiveAmount(_))
+        ));
+        let result = account.transact(Transaction::withdrawal(1, amount));
+        assert!(matches!(
+            result,
+            Err(TransactionError::NonPositiveAmount(_))
+        ));
+    }
+    assert_eq!(account.balance(), 100.0);
+}
+
+#[test]
+fn overflow() {
+    use crate::{
+        account::TransactionError,
+        transaction::{ClientTransaction, TransactionParseError},
+    };
+
+    let max = Amount::from_units(i128::MAX);
+    let mut account = Account::default();
+    account.transact(Transaction::deposit(0, max)).unwrap();
+    let result = account.transact(Transaction::deposit(1, Amount::from_f64(1.0).unwrap()));
+    assert!(matches!(result, Err(TransactionError::Overflow)));
+    assert_eq!(account.balance(), max);
+    // The failed deposit is not recorded
+    account.transact(Transaction::dispute(1)).unwrap_err();
+
+    // Holding funds cannot overflow the held balance either
+    let mut account = Account::default();
+    account.transact(Transaction::deposit(0, max)).unwrap();
+    account.transact(Transaction::dispute(0)).unwrap();
+    let result = account.transact(Transaction::deposit(2, Amount::from_f64(1.0).unwrap()));
+    assert!(matches!(result, Err(TransactionError::Overflow)));
+    assert_eq!(account.held(), max);
+    assert_eq!(account.total(), max);
+
+    // The largest amount is about 1.7 × 10^30
+    let largest = "1701411834604692317316873037158.84105727";
+    assert_eq!(largest.parse::<Amount>().unwrap(), max);
+    // At the default precision, the largest balance is rounded down to 4 decimal places
+    let mut accounts = Accounts::default();
+    let deposit = "deposit,1,1,1701411834604692317316873037158.841"
+        .parse()
+        .unwrap();
+    accounts.transact(deposit).unwrap();
+    assert!(matches!(
+        accounts.transact("deposit,1,2,0.0001".parse().unwrap()),
+        Err(TransactionError::Overflow)
+    ));
+    assert_eq!(
+        accounts[1].total().to_string(),
+        "1701411834604692317316873037158.8410"
+    );
+    // Amounts too large to represent are told apart from malformed ones
+    let err = "deposit,1,3,1701411834604692317316873037158.84105728"
+        .parse::<ClientTransaction>()
+        .unwrap_err();
+    assert!(matches!(err, TransactionParseError::AmountOutOfRange(_)));
+    assert_eq!(
+        err.to_string(),
+        "Amount \"1701411834604692317316873037158.84105728\" is out of range"
+    );
+    assert_eq!(err.code(), "amount_out_of_range");
+    assert!(matches!(
+        "deposit,1,3,1.2.3".parse::<ClientTransaction>(),
+        Err(TransactionParseError::InvalidAmount(_))
+    ));
+    // Amounts beyond the range of an i64 of units are valid
+    accounts
+        .transact("deposit,2,4,100000000000".parse().unwrap())
+        .unwrap();
+    accounts
+        .transact("deposit,2,5,922337203685477.5807".parse().unwrap())
+        .unwrap();
+    assert_eq!(accounts[2].total().to_string(), "922437203685477.5807");
+}
+
+#[test]
+fn amount_parsing() {
+    let units = |s: &str| s.parse::<Amount>().map(Amount::units);
+    assert_eq!(units("12.3456"), Ok(1_234_560_000));
+    assert_eq!(units("-0.5"), Ok(-50_000_000));
+    assert_eq!(units("+7"), Ok(700_000_000));
+    assert_eq!(units(".25"), Ok(25_000_000));
+    assert_eq!(units("3."), Ok(300_000_000));
+    assert_eq!(units("30.12345"), Ok(3_012_345_000));
+    assert_eq!(units("30.123456785"), Ok(3_012_345_679));
+    assert_eq!(units("-30.123456784"), Ok(-3_012_345_678));
+    // Values too large for an f64 to represent exactly are still exact
+    assert_eq!(units("92233720368.54775807"), Ok(i64::MAX as i128));
+    assert_eq!(
+        units("1701411834604692317316873037158.84105727"),
+        Ok(i128::MAX)
+    );
+    assert_eq!(
+        units("1701411834604692317316873037158.84105728"),
+        Err("Amount \"1701411834604692317316873037158.84105728\" is out of range".into())
+    );
+    assert!(units("1701411834604692317316873037159").is_err());
+    assert_eq!(units("007.50"), Ok(750_000_000));
+    for invalid in ["", "-", ".", "1e5", "1.2.3", " 1", "1,5", "NaN", "inf"] {
+        assert!(units(invalid).is_err(), "{:?} should be invalid", invalid);
+    }
+}
+
+#[test]
+fn amount_display() {
+    let display = |units| Amount::from_units(units).to_string();
+    assert_eq!(display(10_000_000_000), "100.0000");
+    assert_eq!(display(-50_000), "-0.0005");
+    assert_eq!(display(-5), "-0.00000005");
+    assert_eq!(display(0), "0.0000");
+    assert_eq!(
+        display(i128::MAX),
+        "1701411834604692317316873037158.84105727"
+    );
+    assert_eq!(
+        display(i128::MIN),
+        "-1701411834604692317316873037158.84105728"
+    );
+    let amount = |s: &str| s.parse::<Amount>().unwrap();
+    assert_eq!(format!("{:>10}", amount("1.5")), "    1.5000");
+    assert_eq!(format!("{:.2}", amount("12.345")), "12.35");
+    assert_eq!(format!("{:.2}", amount("-12.345")), "-12.35");
+    assert_eq!(format!("{:.0}", amount("2.5")), "3");
+    assert_eq!(format!("{:.10}", amount("1.5")), "1.5000000000");
+    assert_eq!(format!("{:>+8.1}", amount("1.25")), "    +1.3");
+    // Displayed amounts parse back exactly
+    for units in [1, -1, 123_456_789, i64::MAX as i128, i128::MAX] {
+        assert_eq!(display(units).parse::<Amount>().unwrap().units(), units);
+    }
+}
+
+#[test]
+fn precision() {
+    let amount = |s: &str| s.parse::<Amount>().unwrap();
+    let cents = Precision::new(2).unwrap();
+    assert_eq!(amount("1.005").round(cents), amount("1.01"));
+    assert_eq!(amount("-1.005").round(cents), amount("-1.01"));
+    assert_eq!(amount("1.00499999").round(cents), amount("1"));
+    assert_eq!(amount("2.5").round(Precision::new(0).unwrap()), amount("3"));
+    assert_eq!(
+        amount("0.12345678").round(Precision::new(8).unwrap()),
+        amount("0.12345678")
+    );
+    assert!(Precision::new(9).is_none());
+    assert!("9".parse::<Precision>().is_err());
+
+    // Accounts round transaction amounts to their precision and output amounts with it
+    let process = |decimal_places| {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1.005\n\
+            deposit,1,2,0.004\n\
+            withdrawal,1,3,0.12345678\n";
+        let mut accounts = Accounts::with_precision(Precision::new(decimal_places).unwrap());
+        process_transaction_source(input.as_bytes(), &mut accounts).unwrap();
+        let mut output = Vec::new();
+        output::write_accounts(&accounts, OutputFormat::Csv, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    // The second deposit rounds to zero with 2 decimal places, so it is rejected
+    assert!(process(2).ends_with("\n1,0.89,0.00,0.89,false\n"));
+    assert!(process(4).ends_with("\n1,0.8855,0.0000,0.8855,false\n"));
+    assert!(process(8).ends_with("\n1,0.88554322,0.00000000,0.88554322,false\n"));
+
+    // Rounding keeps the rest of a change, including its timestamp
+    use crate::transaction::{BalanceChange, ChangeKind};
+    let deposit = Transaction::Change {
+        tx_id: 1,
+        change: BalanceChange {
+            kind: ChangeKind::Deposit,
+            amount: amount("1.005"),
+            timestamp: Some(60),
+        },
+    };
+    let Transaction::Change { change, .. } = deposit.round(cents) else {
+        unreachable!()
+    };
+    assert_eq!(
+        (change.amount, change.timestamp),
+        (amount("1.01"), Some(60))
+    );
+    // So the dispute window applies to it
+    let mut accounts = Accounts::with_precision(cents);
+    accounts.set_dispute_limits(crate::DisputeLimits {
+        window: Some(100),
+        ..Default::default()
+    });
+    let deposit = ClientTransaction {
+        client: 1,
+        tx: deposit,
+        timestamp: None,
+    };
+    accounts.transact(deposit).unwrap();
+    assert!(matches!(
+        accounts.transact("dispute,1,1,,,161".parse().unwrap()),
+        Err(TransactionError::DisputeWindowExpired(1))
+    ));
+}
+
+fn filtered(filter: &Filter) -> String {
+    let mut output = Vec::new();
+    filter::filter(
+        include_bytes!("../test.csv").as_slice(),
+        filter,
+        &mut output,
+    )
+    .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn filter_by_client() {
+    let output = filtered(&Filter {
+        clients: vec![3],
+        ..Default::default()
+    });
+    assert_eq!(
+        output,
+        "type,client,tx,amount,to,timestamp\n\
+        deposit,3,3,50.0000\n\
+        deposit,3,6,20.0000\n\
+        deposit,3,7,40.0000\n\
+        dispute,3,6,\n\
+        dispute,3,7,\n\
+        resolve,3,6,\n\
+        chargeback,3,7,\n"
+    );
+}
+
+#[test]
+fn filter_keeps_references_consistent() {
+    // Disputes of deposits outside the range are dropped
+    let output = filtered(&Filter {
+        range: Some(filter::parse_range("7..").unwrap()),
+        ..Default::default()
+    });
+    assert_eq!(
+        output,
+        "type,client,tx,amount,to,timestamp\n\
+        deposit,3,7,40.0000\n\
+        dispute,3,7,\n\
+        chargeback,3,7,\n\
+        deposit,4,8,100.0000\n\
+        deposit,4,9,20.6000\n\
+        dispute,4,9,\n\
+        chargeback,4,8,\n"
+    );
+    // Disputes are dropped entirely if their deposits are filtered out by type
+    let output = filtered(&Filter {
+        types: vec!["dispute".into()],
+        ..Default::default()
+    });
+    assert_eq!(output, "type,client,tx,amount,to,timestamp\n");
+}
+
+#[test]
+fn extract_client() {
+    let input = "\
+        type,client,tx,amount\n\
+        deposit,1,1,10\n\
+        deposit,2,2,10\n\
+        withdrawal,1,3,5\n\
+        dispute,2,1,\n\
+        dispute,1,2,\n\
+        dispute,1,1,\n\
+        resolve,1,1,\n";
+    let mut output = Vec::new();
+    filter::extract(input.as_bytes(), 1, &mut output).unwrap();
+    // Disputes only refer to transactions of the client that submitted them
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "type,client,tx,amount,to,timestamp\n\
+        deposit,1,1,10.0000\n\
+        withdrawal,1,3,5.0000\n\
+        dispute,1,1,\n\
+        resolve,1,1,\n"
+    );
+}
+
+#[test]
+fn extract_transfers() {
+    let input = "\
+        type,client,tx,amount,to,timestamp\n\
+        deposit,2,1,10,,100\n\
+        transfer,2,2,4,1,200\n\
+        transfer,2,3,50,1,300\n\
+        dispute,1,2,\n\
+        transfer,1,4,1,3,400\n\
+        deposit,1,5,2\n\
+        dispute,1,5,\n";
+    let mut output = Vec::new();
+    filter::extract(input.as_bytes(), 1, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    // The transfer that succeeded becomes a deposit, and the one that failed is dropped
+    assert_eq!(
+        output,
+        "type,client,tx,amount,to,timestamp\n\
+        deposit,1,2,4.0000,,200\n\
+        transfer,1,4,1.0000,3,400\n\
+        deposit,1,5,2.0000\n\
+        dispute,1,5,\n"
+    );
+    // Replaying the output gives the client the same balances
+    let replay = |input: &str| {
+        let mut accounts = Accounts::default();
+        for record in crate::records(input.as_bytes()) {
+            let _ = accounts.transact(record.unwrap().tx);
+        }
+        let account = accounts.get(1).unwrap().clone();
+        (account.balance(), account.held(), account.total())
+    };
+    assert_eq!(replay(&output), replay(input));
+    let (available, held, total) = replay(input);
+    assert_eq!(available, 3.0);
+    assert_eq!(held, 2.0);
+    assert_eq!(total, 5.0);
+}
+
+#[test]
+fn split_by_client() {
+    // More clients than files are kept open at once, so files are closed and reopened
+    let mut input = String::from("type,client,tx,amount\n");
+    for round in 0..3 {
+        for client in 0..100 {
+            let tx_id = round * 100 + client;
+            input += &format!("deposit,{},{},1\n", client, tx_id);
+            input += &format!("dispute,{},{},\n", client, tx_id);
+        }
+    }
+    // Disputes of other clients' transactions are dropped
+    input += "dispute,1,0,\n";
+    let dir = std::env::temp_dir().join(format!("transactor-split-{}", std::process::id()));
+    filter::split_by_client(input.as_bytes(), &dir).unwrap();
+    for client in 0..100 {
+        let output = std::fs::read_to_string(dir.join(format!("client_{}.csv", client))).unwrap();
+        let mut expected = String::from("type,client,tx,amount,to,timestamp\n");
+        for round in 0..3 {
+            let tx_id = round * 100 + client;
+            expected += &format!(
+                "deposit,{},{},1.0000\ndispute,{},{},\n",
+                client, tx_id, client, tx_id
+            );
+        }
+        assert_eq!(output, expected);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn record_and_replay() {
+    use std::{sync::Mutex, thread};
+
+    use crate::transaction::ClientTransaction;
+
+    // Apply transactions from several threads at once, as a server would for several connections
+    let state = Mutex::new((Accounts::default(), Recorder::new(Vec::new()).unwrap()));
+    thread::scope(|scope| {
+        for source in 0..4u64 {
+            let state = &state;
+            scope.spawn(move || {
+                for i in 0..50u32 {
+                    let tx_id = source as u32 * 100 + i;
+                    let tx = ClientTransaction {
+                        client: (i % 3) as u16,
+                        tx: if i % 2 == 0 {
+                            Transaction::deposit(tx_id, Amount::from_f64(2.0).unwrap())
+                        } else {
+                            Transaction::withdrawal(tx_id, Amount::from_f64(3.0).unwrap())
+                        },
+                        timestamp: None,
+                    };
+                    let (accounts, recorder) = &mut *state.lock().unwrap();
+                    recorder.record(source, &tx).unwrap();
+                    let _ = accounts.transact(tx);
+                }
+            });
+        }
+    });
+    let (accounts, recorder) = state.into_inner().unwrap();
+
+    // Replaying the recording reproduces the exact final state
+    let mut replayed = Accounts::default();
+    record::replay(recorder.into_inner().unwrap().as_slice(), &mut replayed).unwrap();
+    for (client_id, account) in accounts.iter() {
+        assert_eq!(replayed[client_id].balance(), account.balance());
+        assert_eq!(replayed[client_id].held(), account.held());
+    }
+}
+
+#[test]
+fn group_commit_recovery() {
+    use std::time::Duration;
+
+    use crate::{record::GroupCommit, transaction::ClientTransaction};
+
+    let txs: Vec<ClientTransaction> = crate::records(include_bytes!("../test.csv").as_slice())
+        .map(|record| record.unwrap().tx)
+        .collect();
+    let report = |accounts: &Accounts| {
+        let mut output = Vec::new();
+        output::write_accounts(accounts, OutputFormat::Csv, &mut output).unwrap();
+        output
+    };
+    let group_commit = GroupCommit {
+        max_records: 4,
+        max_delay: Duration::from_secs(3600),
+    };
+    let mut recorder = Recorder::with_group_commit(Vec::new(), group_commit).unwrap();
+    let mut accounts = Accounts::default();
+    for (i, tx) in txs.iter().enumerate() {
+        recorder.record(0, tx).unwrap();
+        let _ = accounts.transact(tx.clone());
+        // Records are committed in groups of 4
+        let committed = recorder.committed() as usize;
+        assert_eq!(committed, (i + 1) / 4 * 4);
+        // Recovering from what was written at any point gives exactly the acknowledged state
+        let mut recovered = Accounts::default();
+        record::replay(recorder.get_ref().as_slice(), &mut recovered).unwrap();
+        let mut acknowledged = Accounts::default();
+        for tx in &txs[..committed] {
+            let _ = acknowledged.transact(tx.clone());
+        }
+        assert_eq!(report(&recovered), report(&acknowledged));
+    }
+    // Finishing the recording commits the rest
+    let mut recovered = Accounts::default();
+    record::replay(recorder.into_inner().unwrap().as_slice(), &mut recovered).unwrap();
+    assert_eq!(report(&recovered), report(&accounts));
+
+    // Records are also committed once they have waited long enough
+    let group_commit = GroupCommit {
+        max_records: 100,
+        max_delay: Duration::ZERO,
+    };
+    let mut recorder = Recorder::with_group_commit(Vec::new(), group_commit).unwrap();
+    recorder.record(0, &txs[0]).unwrap();
+    assert_eq!(recorder.committed(), 1);
+}
+
+#[test]
+fn server_connections() {
+    use std::fs::File;
+
+    use crate::server::Server;
+
+    let path = std::env::temp_dir().join(format!("transactor-server-{}.csv", std::process::id()));
+    let recorder = Recorder::new(Box::new(File::create(&path).unwrap()) as _).unwrap();
+    let server = Server::with_recorder(Accounts::default(), recorder);
+    let respond = |source, input: &str| {
+        let mut output = Vec::new();
+        server
+            .handle(source, input.as_bytes(), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(
+        respond(
+            0,
+            "type,client,tx,amount\r\ndeposit,1,1,5\r\n\r\nwithdrawal,1,2,7\r\n"
+        ),
+        "ok\nerror: Attempted to withdraw 7.0000 from an account with 5.0000 available\n"
+    );
+    // Each connection detects its own format
+    assert_eq!(
+        respond(
+            1,
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":3,\"amount\":\"2\"}\n{\"type\":\"nope\"}\n"
+        ),
+        "ok\nerror: Invalid transaction on line 2: Missing client id\n"
+    );
+    assert_eq!(
+        respond(2, "withdrawal,1,4,7\ndeposit,1,5,\"1"),
+        "ok\nerror: Unclosed quote on line 2\n"
+    );
+    let accounts = server.into_accounts();
+    assert_eq!(accounts[1].total(), Amount::default());
+
+    // The recording reproduces the state of the server
+    let mut replayed = Accounts::default();
+    record::replay(File::open(&path).unwrap(), &mut replayed).unwrap();
+    assert_eq!(replayed[1].total(), Amount::default());
+    assert_eq!(replayed[1].activity(), accounts[1].activity());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn close() {
+    let mut account = account_with_100();
+    account.transact(Transaction::dispute(0)).unwrap();
+    account.close().unwrap_err();
+    account
+        .transact(Transaction::resolution(ResolutionKind::Resolve, 0))
+        .unwrap();
+    account.close().unwrap();
+    assert!(account.is_closed());
+    account
+        .transact(Transaction::deposit(1, Amount::from_f64(1.0).unwrap()))
+        .unwrap_err();
+    assert_eq!(account.total(), 100.0);
+}
+
+#[test]
+fn archive_and_unarchive() {
+    let dir = std::env::temp_dir().join(format!("transactor-archive-{}", std::process::id()));
+    let archive = Archive::open(&dir).unwrap();
+
+    let mut accounts = Accounts::default();
+    process_transaction_source(include_bytes!("../test.csv").as_slice(), &mut accounts).unwrap();
+    accounts.close(1).unwrap();
+    accounts.close(4).unwrap_err();
+
+    // Only the closed account is archived
+    assert_eq!(archive.archive_closed(&mut accounts).unwrap(), [1]);
+    assert!(accounts.get(1).is_none());
+    assert!(archive.contains(1));
+    assert!(!archive.contains(4));
+
+    // Unarchiving restores the account's state and history
+    assert!(archive.unarchive(&mut accounts, 1).unwrap());
+    assert!(!archive.contains(1));
+    assert!(!accounts[1].is_closed());
+    assert_eq!(accounts[1].total(), 18.3);
+    accounts.transact("dispute,1,1".parse().unwrap()).unwrap();
+    assert_eq!(accounts[1].held(), 18.3);
+    assert!(!archive.unarchive(&mut accounts, 1).unwrap());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn statement() {
+    let statement = Statement::generate(include_bytes!("../test.csv").as_slice(), 2).unwrap();
+    assert_eq!(statement.entries.len(), 3);
+    assert!(statement.entries[1].rejection.is_none());
+    assert!(statement.entries[2].rejection.is_some());
+    assert_eq!(statement.entries[2].balance, 10.1235);
+    assert_eq!(statement.account.total(), 10.1235);
+
+    let mut html = Vec::new();
+    statement.render_html(&mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<h1>Statement for client 2</h1>"));
+    assert_eq!(html.matches("<tr class=\"rejected\">").count(), 1);
+}
+
+#[test]
+fn csv_header_and_quoting() {
+    let input = "\
+        client,amount,\"type\",tx\n\
+        \n\
+        1,\"1,000.5\",deposit,1\n\
+        1,\" 20 \",\"deposit\",2\n\
+        1,,dispute,2\n";
+    let records: Vec<_> = crate::records(input.as_bytes()).collect();
+    // Amounts with embedded commas are a single field, so they are rejected rather than misparsed
+    assert!(records[0]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("line 3"));
+    let records: Vec<_> = records.into_iter().skip(1).map(Result::unwrap).collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].line_no, 4);
+    assert_eq!(records[0].tx.to_string(), "deposit,1,2,20.0000");
+    assert_eq!(records[1].tx.to_string(), "dispute,1,2,");
+    assert_eq!(records[0].line, "1,\" 20 \",\"deposit\",2");
+}
+
+#[test]
+fn frozen_digest() {
+    let mut accounts = Accounts::default();
+    let mut tracker = DigestTracker::new(&accounts);
+    process_transaction_source_with(
+        include_bytes!("../test.csv").as_slice(),
+        &mut accounts,
+        |record, result, _| tracker.observe(&record.tx, &result),
+    )
+    .unwrap();
+    let digest = tracker.finish(&accounts);
+    assert_eq!(digest.entries.len(), 1);
+    assert_eq!(digest.entries[0].client, 3);
+    assert!(digest.entries[0].newly_frozen);
+    assert_eq!(digest.entries[0].chargebacks, [7]);
+    assert_eq!(
+        digest.to_json(1).unwrap(),
+        r#"{"event_version":1,"events":[{"event_type":"chargeback","event_version":1,"client":3,"tx":7},{"event_type":"freeze","event_version":1,"client":3,"available":"70.0000","held":"0.0000","total":"70.0000"}]}"#
+    );
+    assert!(digest.to_json(0).is_none());
+    assert_eq!(notify::negotiate_event_version(&[]), Some(1));
+    assert_eq!(notify::negotiate_event_version(&[1, 2]), Some(1));
+    assert_eq!(notify::negotiate_event_version(&[2]), None);
+}
+
+#[test]
+fn json_schemas() {
+    use serde_json::{json, Value};
+
+    use crate::{notify::Event, schema};
+
+    /// Check the parts of JSON Schema used by transactor's schemas
+    fn conforms(value: &Value, schema: &Value) -> bool {
+        let schema = schema.as_object().unwrap();
+        if let Some(expected) = schema.get("const") {
+            return value == expected;
+        }
+        if let Some(Value::Array(options)) = schema.get("oneOf") {
+            return options
+                .iter()
+                .filter(|option| conforms(value, option))
+                .count()
+                == 1;
+        }
+        match (schema["type"].as_str().unwrap(), value) {
+            ("object", Value::Object(object)) => {
+                let properties = schema["properties"].as_object().unwrap();
+                let required = schema["required"].as_array().unwrap();
+                object
+                    .iter()
+                    .all(|(key, value)| properties.get(key).is_some_and(|p| conforms(value, p)))
+                    && required
+                        .iter()
+                        .all(|key| object.contains_key(key.as_str().unwrap()))
+            }
+            ("array", Value::Array(items)) => {
+                items.iter().all(|item| conforms(item, &schema["items"]))
+            }
+            ("string", Value::String(s)) => {
+                !schema.contains_key("pattern") || s.parse::<Amount>().is_ok()
+            }
+            ("integer", Value::Number(n)) => n.as_u64().is_some_and(|n| {
+                n >= schema["minimum"].as_u64().unwrap() && n <= schema["maximum"].as_u64().unwrap()
+            }),
+            ("boolean", Value::Bool(_)) | ("null", Value::Null) => true,
+            _ => false,
+        }
+    }
+
+    for name in schema::SCHEMAS {
+        let schema = schema::schema(name).unwrap();
+        assert_eq!(schema["$id"], format!("urn:transactor:{}", name));
+    }
+    assert!(schema::schema("nope").is_none());
+    let account = schema::schema("account").unwrap();
+
+    let mut accounts = Accounts::default();
+    process_transaction_source(include_bytes!("../test.csv").as_slice(), &mut accounts).unwrap();
+    accounts.insert(9, Account::default());
+    for extended in [false, true] {
+        let mut output = Vec::new();
+        output::write_matching_accounts(
+            &accounts,
+            &"client >= 0".parse().unwrap(),
+            OutputFormat::JsonLines,
+            extended,
+            &mut output,
+        )
+        .unwrap();
+        for line in String::from_utf8(output).unwrap().lines() {
+            let value = serde_json::from_str(line).unwrap();
+            assert!(conforms(&value, &account), "{}", line);
+        }
+    }
+    assert!(!conforms(
+        &json!({ "client": 1, "available": "1" }),
+        &account
+    ));
+    assert!(!conforms(
+        &json!({ "client": 1, "available": "x", "held": "0", "total": "1", "locked": false }),
+        &account
+    ));
+
+    let mut tracker = DigestTracker::new(&accounts);
+    process_transaction_source_with(
+        "deposit,1,20,5\ndispute,1,20\nchargeback,1,20".as_bytes(),
+        &mut accounts,
+        |record, result, _| tracker.observe(&record.tx, &result),
+    )
+    .unwrap();
+    let events: Value =
+        serde_json::from_str(&tracker.finish(&accounts).to_json(1).unwrap()).unwrap();
+    let mut deposit = Event::Deposit {
+        client: 1,
+        tx: 20,
+        amount: Amount::from_f64(5.0).unwrap(),
+    }
+    .payload(1)
+    .unwrap();
+    let mut all_events = events.clone();
+    all_events["events"]
+        .as_array_mut()
+        .unwrap()
+        .push(deposit.clone());
+    assert!(conforms(&all_events, &schema::schema("events").unwrap()));
+    deposit["event_type"] = "freeze".into();
+    all_events["events"].as_array_mut().unwrap().push(deposit);
+    assert!(!conforms(&all_events, &schema::schema("events").unwrap()));
+
+    let results = json!([
+        { "status": "ok" },
+        { "status": "error", "error": "Insufficient funds" },
+        { "status": "error", "error": "Insufficient funds", "code": "insufficient_funds" },
+    ]);
+    assert!(conforms(
+        &results,
+        &schema::schema("transaction-results").unwrap()
+    ));
+    assert!(!conforms(
+        &json!([{ "status": "error" }]),
+        &schema::schema("transaction-results").unwrap()
+    ));
+    assert!(conforms(
+        &json!({ "error": "Not found" }),
+        &schema::schema("error").unwrap()
+    ));
+    let page = json!({
+        "transactions": [
+            { "tx": 1, "type": "deposit", "amount": "5.0000", "timestamp": null },
+            { "tx": 2, "type": "fee", "amount": "1.0000", "timestamp": 60 },
+        ],
+        "next_after": 2,
+    });
+    assert!(conforms(
+        &page,
+        &schema::schema("transaction-page").unwrap()
+    ));
+    assert!(!conforms(
+        &json!({ "transactions": [{ "tx": 1, "type": "dispute", "amount": "5", "timestamp": null }], "next_after": null }),
+        &schema::schema("transaction-page").unwrap()
+    ));
+}
+
+#[test]
+fn json_lines() {
+    let input = r#"
+{"type":"deposit","client":1,"tx":5,"amount":"3.21"}
+
+{"type":"withdrawal","client":1,"tx":6,"amount":1}
+{"type":"dispute","client":1,"tx":5}
+{"type":"deposit","client":1,"tx":7}
+"#;
+    let records: Vec<_> = crate::records(input.as_bytes()).collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[0].as_ref().unwrap().line_no, 2);
+    assert_eq!(
+        records[1].as_ref().unwrap().tx.to_string(),
+        "withdrawal,1,6,1.0000"
+    );
+    assert_eq!(records[2].as_ref().unwrap().tx.to_string(), "dispute,1,5,");
+    assert!(records[3]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("line 6: Missing amount"));
+}
+
+#[test]
+fn json_output() {
+    let mut accounts = Accounts::default();
+    accounts
+        .transact("deposit,1,1,20.6".parse().unwrap())
+        .unwrap();
+    accounts.transact("dispute,1,1".parse().unwrap()).unwrap();
+    let mut output = Vec::new();
+    output::write_accounts(&accounts, OutputFormat::JsonLines, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "{\"client\":1,\"available\":\"0.0000\",\"held\":\"20.6000\",\"total\":\"20.6000\",\"locked\":false}\n"
+    );
+}
+
+#[test]
+fn unfreeze() {
+    use crate::account::TransactionError;
+
+    let mut accounts = Accounts::default();
+    for tx in [
+        "deposit,1,1,50",
+        "deposit,1,2,20",
+        "dispute,1,2",
+        "chargeback,1,2",
+    ] {
+        accounts.transact(tx.parse().unwrap()).unwrap();
+    }
+    assert!(accounts[1].is_frozen());
+    let unfreeze = "unfreeze,1,3".parse::<crate::ClientTransaction>().unwrap();
+    assert!(matches!(
+        accounts.transact(unfreeze.clone()),
+        Err(TransactionError::AdminRequired)
+    ));
+    assert!(accounts[1].is_frozen());
+    accounts.set_admin_transactions(true);
+    accounts.transact(unfreeze).unwrap();
+    assert!(!accounts[1].is_frozen());
+    accounts
+        .transact("withdrawal,1,4,10".parse().unwrap())
+        .unwrap();
+    assert_eq!(accounts[1].balance(), 40.0);
+    // `unlock` is accepted as another name for `unfreeze`
+    let unlock = "unlock,1,5".parse::<crate::ClientTransaction>().unwrap();
+    assert_eq!(unlock.to_string(), "unfreeze,1,5,");
+
+    // A lone account has no admin setting, so it rejects unfreezes
+    let mut account = Account::default();
+    let amount = Amount::from_f64(5.0).unwrap();
+    account.transact(Transaction::deposit(1, amount)).unwrap();
+    account.transact(Transaction::dispute(1)).unwrap();
+    let chargeback = Transaction::resolution(ResolutionKind::Chargeback, 1);
+    account.transact(chargeback).unwrap();
+    assert!(account.is_frozen());
+    assert!(matches!(
+        account.transact(Transaction::Unfreeze(2)),
+        Err(TransactionError::AdminRequired)
+    ));
+    assert!(account.is_frozen());
+}
+
+#[test]
+fn admin_ops() {
+    let mut accounts = Accounts::default();
+    for tx in [
+        "deposit,1,1,50",
+        "deposit,1,2,20.0000",
+        "dispute,1,2",
+        "chargeback,1,2",
+        "deposit,2,3,10",
+        "dispute,2,3",
+    ] {
+        accounts.transact(tx.parse().unwrap()).unwrap();
+    }
+    let ops = admin::read_ops(
+        "op,client,operator,reference,tx,amount
+unfreeze,1,alice,INC-1,,
+adjust,1,alice,INC-1,,-5.5
+release-hold,2,bob,INC-2,3,
+adjust,2,bob,INC-2,,-100
+close,2,bob,INC-2,,"
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(ops.len(), 5);
+    let mut journal = Vec::new();
+    let (applied, rejected) =
+        admin::apply_ops(&ops, &mut accounts, &mut admin::Journal::new(&mut journal)).unwrap();
+    assert_eq!((applied, rejected), (4, 1));
+    assert!(!accounts[1].is_frozen());
+    assert_eq!(accounts[1].balance(), Amount::from_f64(44.5).unwrap());
+    assert_eq!(accounts[2].balance(), Amount::from_f64(10.0).unwrap());
+    assert!(accounts[2].is_closed());
+    let journal = String::from_utf8(journal).unwrap();
+    let lines: Vec<_> = journal.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[1].ends_with(",alice,INC-1,adjust,1,,-5.5000,applied,,"));
+    assert!(lines[3].contains(",bob,INC-2,adjust,2,,-100.0000,rejected: "));
+
+    // Notes are attached to accounts and recorded in the journal
+    let ops = admin::read_ops(
+        "op,client,operator,reference,note
+note,1,carol,INC-3,\"Verified ID, unfrozen\"
+close,2,carol,INC-4,Never attached
+note,2,carol,INC-4,"
+            .as_bytes(),
+    );
+    let err = ops.unwrap_err();
+    assert_eq!(err, "Invalid admin operation 3: Missing note");
+    let ops = admin::read_ops(
+        "op,client,operator,reference,amount,note
+note,1,carol,INC-3,,\"Verified ID, unfrozen\"
+adjust,2,carol,INC-4,-100,Never attached"
+            .as_bytes(),
+    )
+    .unwrap();
+    let mut journal = Vec::new();
+    admin::apply_ops(&ops, &mut accounts, &mut admin::Journal::new(&mut journal)).unwrap();
+    let notes = accounts[1].notes();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(
+        (notes[0].operator.as_str(), notes[0].reference.as_str()),
+        ("carol", "INC-3")
+    );
+    assert_eq!(notes[0].text, "Verified ID, unfrozen");
+    assert!(accounts[2].notes().is_empty());
+    let journal = [
+        b"timestamp,operator,reference,op,client,tx,amount,result,note,into\n".as_slice(),
+        &journal,
+    ]
+    .concat();
+    assert_eq!(admin::read_notes(journal.as_slice(), 1).unwrap(), notes);
+    assert!(admin::read_notes(journal.as_slice(), 2).unwrap().is_empty());
+
+    // Operator and reference are mandatory
+    let err = admin::read_ops("op,client,operator,reference\nclose,1,,INC-3".as_bytes());
+    assert_eq!(
+        err.unwrap_err(),
+        "Invalid admin operation 1: Missing operator"
+    );
+    let err = admin::read_ops("op,client,operator\nclose,1,alice".as_bytes());
+    assert!(err.unwrap_err().contains("\"reference\" column"));
+
+    // Closed accounts cannot be frozen
+    let ops = admin::read_ops(
+        "op,client,operator,reference\nfreeze,1,alice,INC-5\nfreeze,2,alice,INC-5".as_bytes(),
+    )
+    .unwrap();
+    let mut journal = Vec::new();
+    let (applied, rejected) =
+        admin::apply_ops(&ops, &mut accounts, &mut admin::Journal::new(&mut journal)).unwrap();
+    assert_eq!((applied, rejected), (1, 1));
+    assert!(accounts[1].is_frozen());
+    assert!(!accounts[2].is_frozen());
+
+    assert!(admin::authenticate("secret", "secret"));
+    assert!(!admin::authenticate("secreT", "secret"));
+    assert!(!admin::authenticate("secret2", "secret"));
+}
+
+#[test]
+fn merge_accounts() {
+    let mut accounts = Accounts::default();
+    for tx in [
+        "deposit,7,1,10",
+        "deposit,12,2,5",
+        "withdrawal,12,3,1",
+        "deposit,12,4,2",
+        "dispute,12,4",
+        "deposit,3,5,1",
+    ] {
+        accounts.transact(tx.parse().unwrap()).unwrap();
+    }
+    let ops = admin::read_ops(
+        "op,client,operator,reference,into,note
+merge-accounts,12,alice,INC-7,7,Same customer
+merge-accounts,3,alice,INC-8,3,
+merge-accounts,12,alice,INC-9,3,"
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(ops[0].to_string(), "merge-accounts client 12 into client 7");
+    let mut journal = Vec::new();
+    let (applied, rejected) =
+        admin::apply_ops(&ops, &mut accounts, &mut admin::Journal::new(&mut journal)).unwrap();
+    assert_eq!((applied, rejected), (1, 2));
+    assert_eq!(accounts[7].balance(), Amount::from_f64(14.0).unwrap());
+    assert_eq!(accounts[7].held(), Amount::from_f64(2.0).unwrap());
+    assert_eq!(accounts[7].notes()[0].text, "Same customer");
+    assert!(accounts[12].is_closed());
+    assert_eq!(accounts[12].total(), Amount::default());
+    let journal = String::from_utf8(journal).unwrap();
+    let lines: Vec<_> = journal.lines().collect();
+    assert!(lines[0].ends_with(",merge-accounts,12,,,applied,Same customer,7"));
+    assert!(lines[1].contains(",rejected: Cannot merge an account into itself,"));
+    assert!(lines[2].contains(",rejected: Account is closed,"));
+    let journal = [
+        b"timestamp,operator,reference,op,client,tx,amount,result,note,into\n".as_slice(),
+        journal.as_bytes(),
+    ]
+    .concat();
+    assert_eq!(admin::read_notes(journal.as_slice(), 7).unwrap().len(), 1);
+    assert!(admin::read_notes(journal.as_slice(), 12)
+        .unwrap()
+        .is_empty());
+
+    // The merged dispute is resolved on the account merged into
+    accounts.transact("resolve,7,4".parse().unwrap()).unwrap();
+    assert_eq!(accounts[7].balance(), Amount::from_f64(16.0).unwrap());
+    assert!(accounts
+        .transact("deposit,12,6,1".parse().unwrap())
+        .is_err());
+
+    // Accounts that used the same transaction id cannot be merged
+    let mut accounts = Accounts::default();
+    accounts.set_admin_transactions(true);
+    accounts
+        .transact("deposit,1,1,10".parse().unwrap())
+        .unwrap();
+    accounts
+        .transact("deposit,2,1,10".parse().unwrap())
+        .unwrap();
+    let op = admin::AdminOp {
+        client: 2,
+        kind: admin::AdminOpKind::Merge(1),
+        operator: "bob".into(),
+        reference: "INC-10".into(),
+        note: None,
+    };
+    let err = op.apply(&mut accounts).unwrap_err();
+    assert_eq!(err.code(), "merge_conflict");
+    assert_eq!(accounts[1].balance(), Amount::from_f64(10.0).unwrap());
+    assert_eq!(accounts[2].balance(), Amount::from_f64(10.0).unwrap());
+}
+
+#[test]
+fn sandbox_unfreeze() {
+    use crate::Simulated;
+
+    let mut accounts = Accounts::default();
+    accounts.set_admin_transactions(true);
+    process_transaction_source(
+        "deposit,1,1,50\ndispute,1,1\nchargeback,1,1".as_bytes(),
+        &mut accounts,
+    )
+    .unwrap();
+    assert!(accounts[1].is_frozen());
+    // The unfreeze is checked but not applied
+    accounts.set_sandbox(true);
+    accounts.transact("unfreeze,1,2".parse().unwrap()).unwrap();
+    assert!(accounts[1].is_frozen());
+    assert_eq!(accounts.simulated(), [Simulated::Unfreeze { client: 1 }]);
+    assert_eq!(
+        accounts.simulated()[0].to_string(),
+        "would have unfrozen the account of client 1"
+    );
+}
+
+#[test]
+fn sandbox() {
+    use crate::Simulated;
+
+    let mut accounts = Accounts::default();
+    accounts.set_sandbox(true);
+    let mut tracker = DigestTracker::new(&accounts);
+    process_transaction_source_with(
+        "deposit,1,1,50
+dispute,1,1
+chargeback,1,1
+chargeback,1,2
+deposit,2,3,10"
+            .as_bytes(),
+        &mut accounts,
+        |record, result, _| tracker.observe(&record.tx, &result),
+    )
+    .unwrap();
+    // The chargeback is checked but not applied
+    assert!(!accounts[1].is_frozen());
+    assert_eq!(accounts[1].held(), Amount::from_f64(50.0).unwrap());
+    assert!(tracker.finish(&accounts).entries.is_empty());
+    accounts.close(2).unwrap();
+    assert!(!accounts[2].is_closed());
+
+    let ops = admin::read_ops(
+        "op,client,operator,reference,tx,amount,note
+adjust,2,alice,INC-1,,-5,Refund
+adjust,2,alice,INC-2,,-100,
+close,2,alice,INC-3,,,"
+            .as_bytes(),
+    )
+    .unwrap();
+    let mut journal = Vec::new();
+    let (applied, rejected) =
+        admin::apply_ops(&ops, &mut accounts, &mut admin::Journal::new(&mut journal)).unwrap();
+    assert_eq!((applied, rejected), (2, 1));
+    assert_eq!(accounts[2].balance(), Amount::from_f64(10.0).unwrap());
+    assert!(accounts[2].notes().is_empty());
+    let journal = String::from_utf8(journal).unwrap();
+    assert!(journal.contains(",alice,INC-1,adjust,2,,-5.0000,simulated,Refund"));
+    assert!(journal.contains(",alice,INC-2,adjust,2,,-100.0000,rejected: "));
+
+    assert_eq!(
+        accounts.simulated(),
+        [
+            Simulated::Chargeback { client: 1, tx: 1 },
+            Simulated::Close { client: 2 },
+            Simulated::Admin {
+                client: 2,
+                op: "adjust client 2 by -5.0000".into(),
+                reference: "INC-1".into(),
+            },
+            Simulated::Admin {
+                client: 2,
+                op: "close client 2".into(),
+                reference: "INC-3".into(),
+            },
+        ]
+    );
+    assert_eq!(
+        accounts.simulated()[0].to_string(),
+        "would have charged back transaction 1 of client 1"
+    );
+}
+
+#[test]
+fn alerts() {
+    let rules: Vec<AlertRule> = ["available<0", "held>5.0000"]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+    assert_eq!(rules[1].to_string(), "held>5.0000");
+    assert!("pending<0".parse::<AlertRule>().is_err());
+    let mut monitor = AlertMonitor::new(rules);
+    let mut raised = Vec::new();
+    process_transaction_source_with(
+        "deposit,1,1,10
+withdrawal,1,2,8
+dispute,1,1
+deposit,1,3,1
+resolve,1,1
+dispute,1,1"
+            .as_bytes(),
+        &mut Accounts::default(),
+        |record, _, accounts| {
+            let account = accounts.get(record.tx.client).unwrap();
+            for alert in monitor.check(record.tx.client, account) {
+                raised.push((record.line_no, alert.to_string()));
+            }
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        raised,
+        [
+            (
+                3,
+                "Client 1 available balance -8.0000 is below 0.0000".into()
+            ),
+            (3, "Client 1 held balance 10.0000 is above 5.0000".into()),
+            (
+                6,
+                "Client 1 available balance -7.0000 is below 0.0000".into()
+            ),
+            (6, "Client 1 held balance 10.0000 is above 5.0000".into()),
+        ]
+    );
+}
+
+#[test]
+fn report_query() {
+    use crate::query::Query;
+
+    let mut accounts = Accounts::default();
+    process_transaction_source(
+        "deposit,1,1,10
+deposit,2,2,5
+dispute,2,2
+deposit,3,3,7
+dispute,3,3
+chargeback,3,3
+deposit,4,4,1"
+            .as_bytes(),
+        &mut accounts,
+    )
+    .unwrap();
+    let matching = |query: &str| {
+        let query: Query = query.parse().unwrap();
+        let mut output = Vec::new();
+        output::write_matching_accounts(&accounts, &query, OutputFormat::Csv, false, &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    assert_eq!(
+        matching("held > 0 && !locked"),
+        "client,available,held,total,locked\n2,0.0000,5.0000,5.0000,false\n"
+    );
+    assert_eq!(
+        matching("locked || available>=10"),
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n3,0.0000,0.0000,0.0000,true\n"
+    );
+    assert_eq!(
+        matching("!(client < 4 && total != 10)"),
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n4,1.0000,0.0000,1.0000,false\n"
+    );
+    for invalid in [
+        "",
+        "held",
+        "pending > 0",
+        "held > x",
+        "(locked",
+        "locked locked",
+        "held # 0",
+    ] {
+        assert!(invalid.parse::<Query>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn output_shards() {
+    use crate::output::Shard;
+
+    let mut accounts = Accounts::default();
+    process_transaction_source(
+        "deposit,1,1,10
+deposit,16384,2,5
+deposit,40000,3,7
+deposit,65535,4,1"
+            .as_bytes(),
+        &mut accounts,
+    )
+    .unwrap();
+    let shard = |index: usize| {
+        let mut output = Vec::new();
+        let shard = Shard { index, count: 4 };
+        output::write_shard(
+            &accounts,
+            shard,
+            None,
+            OutputFormat::Csv,
+            false,
+            None,
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let clients: Vec<String> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().into())
+            .collect();
+        clients.join(" ")
+    };
+    assert_eq!(shard(0), "1");
+    assert_eq!(shard(1), "16384");
+    assert_eq!(shard(2), "40000");
+    assert_eq!(shard(3), "65535");
+    assert_eq!(Shard { index: 1, count: 4 }.clients(), 16384..=32767);
+    assert_eq!(Shard { index: 2, count: 3 }.clients(), 43691..=65535);
+    assert_eq!(Shard::of(43690, 3).index, 1);
+    assert_eq!(Shard::of(43691, 3).index, 2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let mut accounts = Accounts::default();
+    process_transaction_source(include_bytes!("../test.csv").as_slice(), &mut accounts).unwrap();
+    accounts
+        .transact("deposit,5,100,123456789.0123".parse().unwrap())
+        .unwrap();
+    let json = serde_json::to_string(&accounts).unwrap();
+    assert!(json.contains("\"123456789.0123\""));
+    let round_tripped: Accounts = serde_json::from_str(&json).unwrap();
+    for (client, account) in accounts.iter() {
+        let other = &round_tripped[client];
+        assert_eq!(account.balance(), other.balance());
+        assert_eq!(account.held(), other.held());
+        assert_eq!(account.is_frozen(), other.is_frozen());
+    }
+    assert_eq!(round_tripped.iter().count(), accounts.iter().count());
+
+    let tx: crate::ClientTransaction = "withdrawal,1,2,0.0001".parse().unwrap();
+    let json = serde_json::to_string(&tx).unwrap();
+    let tx: crate::ClientTransaction = serde_json::from_str(&json).unwrap();
+    assert_eq!(tx.to_string(), "withdrawal,1,2,0.0001");
+    for (amount, exact) in [
+        ("\"-12.5000\"", -12.5),
+        ("\"0.0001\"", 0.0001),
+        ("\"7.0000\"", 7.0),
+    ] {
+        let parsed: Amount = serde_json::from_str(amount).unwrap();
+        assert_eq!(parsed, Amount::from_f64(exact).unwrap());
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), amount);
+    }
+    for invalid in ["\"1e5\"", "\"\"", "\"-\"", "1.5"] {
+        assert!(serde_json::from_str::<Amount>(invalid).is_err());
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_processing() {
+    use std::{
+        future::Future,
+        pin::{pin, Pin},
+        task::{Context, Poll, Waker},
+    };
+
+    // In-memory sources are always ready, so futures can be polled to completion
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    struct Transactions(std::vec::IntoIter<ClientTransaction>);
+    impl futures_core::Stream for Transactions {
+        type Item = ClientTransaction;
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<Self::Item>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    // Async sources give the same result as blocking ones
+    let input = include_bytes!("../test.csv");
+    let mut expected = Accounts::default();
+    let mut expected_lines = Vec::new();
+    process_transaction_source_with(input.as_slice(), &mut expected, |record, _, _| {
+        expected_lines.push(record.line_no)
+    })
+    .unwrap();
+    let mut accounts = Accounts::default();
+    let mut lines = Vec::new();
+    block_on(crate::process_async_transaction_source_with(
+        input.as_slice(),
+        &mut accounts,
+        |record, _, _| lines.push(record.line_no),
+    ))
+    .unwrap();
+    assert_eq!(lines, expected_lines);
+    for (client, account) in expected.iter() {
+        assert_eq!(accounts[client].balance(), account.balance());
+        assert_eq!(accounts[client].held(), account.held());
+    }
+    let err = block_on(crate::process_async_transaction_source(
+        "deposit,1,1,\"2".as_bytes(),
+        &mut Accounts::default(),
+    ));
+    assert_eq!(err.unwrap_err().to_string(), "Unclosed quote on line 1");
+
+    let txs =
+        ["deposit,1,1,5", "withdrawal,1,2,10", "withdrawal,1,3,2"].map(|tx| tx.parse().unwrap());
+    let mut accounts = Accounts::default();
+    let mut results = Vec::new();
+    block_on(crate::process_transaction_stream(
+        Transactions(Vec::from(txs).into_iter()),
+        &mut accounts,
+        |tx, result, accounts| {
+            results.push((tx.to_string(), result.is_ok(), accounts[1].balance()))
+        },
+    ));
+    assert_eq!(
+        results,
+        [
+            (
+                "deposit,1,1,5.0000".into(),
+                true,
+                Amount::from_f64(5.0).unwrap()
+            ),
+            (
+                "withdrawal,1,2,10.0000".into(),
+                false,
+                Amount::from_f64(5.0).unwrap()
+            ),
+            (
+                "withdrawal,1,3,2.0000".into(),
+                true,
+                Amount::from_f64(3.0).unwrap()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn transaction_builder() {
+    let deposit = ClientTransaction::deposit(1, 7).amount(" 12.3456").unwrap();
+    assert_eq!(deposit.to_string(), "deposit,1,7,12.3456");
+    let transfer = ClientTransaction::transfer(1, 8, 2)
+        .amount_value(Amount::from_f64(1.5).unwrap())
+        .unwrap();
+    assert_eq!(transfer.to_string(), "transfer,1,8,1.5000,2");
+    assert_eq!(
+        ClientTransaction::chargeback(3, 7).to_string(),
+        "chargeback,3,7,"
+    );
+    // Amounts are validated as they are in input
+    for amount in ["0", "-5", "abc", ""] {
+        let built = ClientTransaction::withdrawal(1, 9).amount(amount);
+        let parsed = format!("withdrawal,1,9,{}", amount).parse::<ClientTransaction>();
+        assert_eq!(
+            built.unwrap_err().to_string(),
+            parsed.unwrap_err().to_string()
+        );
+    }
+    assert!(ClientTransaction::deposit(1, 10)
+        .amount_value(Amount::default())
+        .is_err());
+}
+
+#[test]
+fn ledger_entries() {
+    use crate::LedgerEntry;
+
+    for line in [
+        "deposit,1,7,12.3456",
+        "transfer,1,8,1.5000,2",
+        "dispute,1,7,",
+        "unfreeze,1,7,",
+    ] {
+        let tx: ClientTransaction = line.parse().unwrap();
+        let entry = LedgerEntry::from(tx);
+        let tx = ClientTransaction::try_from(entry).unwrap();
+        assert_eq!(tx.to_string(), line);
+    }
+    let mut entry = LedgerEntry::from(ClientTransaction::resolve(3, 4));
+    assert_eq!((entry.kind.as_str(), entry.amount), ("resolve", None));
+    entry.currency = Some("EUR".into());
+    entry.metadata.insert("source".into(), "bank".into());
+    assert!(ClientTransaction::try_from(entry.clone()).is_ok());
+    // Entries are validated as input is
+    entry.kind = "withdrawal".into();
+    assert!(matches!(
+        ClientTransaction::try_from(entry.clone()),
+        Err(crate::transaction::TransactionParseError::MissingAmount)
+    ));
+    entry.amount = Some(Amount::default());
+    assert!(ClientTransaction::try_from(entry.clone()).is_err());
+    entry.kind = "refund".into();
+    assert!(ClientTransaction::try_from(entry).is_err());
+}
+
+#[test]
+fn processing_summary() {
+    let input = "\
+type,client,tx,amount,to
+deposit,1,1,5
+deposit,2,2,10.5
+withdrawal,1,3,10
+withdrawal,2,4,0.5
+transfer,2,5,1,3
+dispute,1,1
+";
+    let mut accounts = Accounts::default();
+    let summary = process_transaction_source(input.as_bytes(), &mut accounts).unwrap();
+    assert_eq!(summary.transactions(), 6);
+    assert_eq!((summary.accepted, summary.rejected), (5, 1));
+    assert_eq!(summary.by_type["deposit"], 2);
+    assert_eq!(summary.by_type["withdrawal"], 2);
+    assert_eq!(summary.by_type["transfer"], 1);
+    assert_eq!(summary.deposited, 15.5);
+    assert_eq!(summary.withdrawn, 0.5);
+    assert_eq!(summary.clients.len(), 3);
+    assert!(format!("{:.2}", summary).contains("Deposited: 15.50\n"));
+}
+
+#[test]
+fn stop_on_rejection() {
+    let input = "deposit,1,1,5\nwithdrawal,1,2,10\ndeposit,1,3,5\n";
+    let mut accounts = Accounts::default();
+    let mut executed = 0;
+    let err = try_process_transaction_source_with(
+        input.as_bytes(),
+        &mut accounts,
+        |record, result, _| {
+            executed += 1;
+            result.map_err(|e| ProcessError::rejected(record, e))
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.line_no, Some(2));
+    assert_eq!(err.line.as_deref(), Some("withdrawal,1,2,10"));
+    assert!(matches!(
+        err.kind,
+        ProcessErrorKind::Rejected(TransactionError::InsufficientFunds { .. })
+    ));
+
+    // Parse errors point at the raw line and its offset in bytes
+    let input = "deposit,1,1,5\r\nwithdrawal,1,2,abc\r\n";
+    let err = process_transaction_source(input.as_bytes(), &mut Accounts::default()).unwrap_err();
+    assert_eq!(err.line_no, Some(2));
+    assert_eq!(err.offset, Some(15));
+    assert_eq!(err.line.as_deref(), Some("withdrawal,1,2,abc"));
+    assert!(matches!(
+        err.kind,
+        ProcessErrorKind::Parse(crate::TransactionParseError::InvalidAmount(_))
+    ));
+    assert_eq!(
+        err.to_string(),
+        "Invalid transaction on line 2: Invalid amount \"abc\""
+    );
+    assert_eq!(executed, 2);
+    assert_eq!(accounts[1].total(), 5.0);
+}
+
+#[test]
+fn malformed_rows() {
+    // Quoted rows are split by the csv crate rather than on commas
+    let input = "\
+type,client,tx,amount
+deposit,1,1,5
+\"deposit\",1,2,abc
+\"withdrawal\",1,\"3
+4\",1
+deposit,1
+\"deposit,1,5,1
+";
+    let errors: Vec<ProcessError> = crate::records(input.as_bytes())
+        .filter_map(Result::err)
+        .collect();
+    let located: Vec<_> = (errors.iter())
+        .map(|e| (e.line_no, e.offset, e.line.as_deref()))
+        .collect();
+    assert_eq!(
+        located,
+        [
+            (Some(3), Some(36), Some("\"deposit\",1,2,abc")),
+            (Some(4), Some(54), Some("\"withdrawal\",1,\"3\n4\",1")),
+            (Some(6), Some(77), Some("deposit,1")),
+            (Some(7), Some(87), Some("\"deposit,1,5,1")),
+        ]
+    );
+    assert!(matches!(
+        errors[0].kind,
+        ProcessErrorKind::Parse(crate::TransactionParseError::InvalidAmount(_))
+    ));
+    assert!(matches!(errors[1].kind, ProcessErrorKind::Parse(_)));
+    assert!(matches!(errors[3].kind, ProcessErrorKind::UnclosedQuote));
+    assert_eq!(errors[3].to_string(), "Unclosed quote on line 7");
+
+    // Escaped quotes in a quoted field that spans lines, and quotes in the middle of a
+    // field, are read the same way the csv crate reads them
+    let input = "\
+type,client,tx,amount
+deposit,1,1,\"5\"\"
+\"\"\"
+deposit,1,2,5\"
+deposit,1,3,5
+";
+    let results: Vec<_> = crate::records(input.as_bytes()).collect();
+    assert_eq!(results.len(), 3);
+    let err = results[0].as_ref().unwrap_err();
+    assert_eq!(
+        (err.line_no, err.line.as_deref()),
+        (Some(2), Some("deposit,1,1,\"5\"\"\n\"\"\""))
+    );
+    let mut expected = csv::StringRecord::new();
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader("deposit,1,1,\"5\"\"\n\"\"\"".as_bytes())
+        .read_record(&mut expected)
+        .unwrap();
+    assert_eq!(&expected[3], "5\"\n\"");
+    assert!(matches!(
+        &err.kind,
+        ProcessErrorKind::Parse(crate::TransactionParseError::InvalidAmount(amount))
+            if amount == &expected[3]
+    ));
+    let err = results[1].as_ref().unwrap_err();
+    assert_eq!(
+        (err.line_no, err.line.as_deref()),
+        (Some(4), Some("deposit,1,2,5\""))
+    );
+    assert_eq!(results[2].as_ref().unwrap().tx.tx.tx_id(), 3);
+
+    // Malformed JSON lines
+    let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":5}\n{\"type\":\n";
+    let err = crate::records(input.as_bytes())
+        .find_map(Result::err)
+        .unwrap();
+    assert_eq!(err.line_no, Some(2));
+    assert_eq!(err.offset, Some(48));
+    assert_eq!(err.line.as_deref(), Some("{\"type\":"));
+    assert!(matches!(err.kind, ProcessErrorKind::Record(_)));
+}
+
+#[test]
+fn parallel_processing() {
+    // Deposits, withdrawals, disputes, and transfers across many clients
+    let mut input = String::from("type,client,tx,amount,to\n");
+    for i in 0..2000u32 {
+        let client = i % 37;
+        let line = match i % 7 {
+            0..=2 => format!("deposit,{},{},{}.5\n", client, i, i % 50 + 1),
+            3 => format!("withdrawal,{},{},{}\n", client, i, i % 30 + 1),
+            4 => format!("transfer,{},{},{},{}\n", client, i, i % 20 + 1, i % 11),
+            5 => format!("dispute,{},{}\n", client, i - 5),
+            _ => format!("chargeback,{},{}\n", client, i - 6),
+        };
+        input.push_str(&line);
+    }
+    let mut expected = Accounts::default();
+    process_transaction_source_with(input.as_bytes(), &mut expected, |_, _, _| {}).unwrap();
+    for threads in [1, 4] {
+        let mut accounts = Accounts::default();
+        let executed = std::sync::atomic::AtomicUsize::new(0);
+        crate::process_transaction_source_parallel(
+            input.as_bytes(),
+            &mut accounts,
+            threads.try_into().unwrap(),
+            |_, _, _| {
+                executed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            },
+        )
+        .unwrap();
+        assert_eq!(executed.into_inner(), 2000);
+        let mut out = Vec::new();
+        output::write_accounts(&accounts, OutputFormat::Csv, &mut out).unwrap();
+        let mut expected_out = Vec::new();
+        output::write_accounts(&expected, OutputFormat::Csv, &mut expected_out).unwrap();
+        assert_eq!(String::from_utf8(out), String::from_utf8(expected_out));
+    }
+    let err = crate::process_transaction_source_parallel(
+        "deposit,1,1,5\nfoo".as_bytes(),
+        &mut Accounts::default(),
+        2.try_into().unwrap(),
+        |_, _, _| {},
+    );
+    assert!(err
+        .unwrap_err()
+        .starts_with("Invalid transaction on line 2"));
+
+    // Accruals cannot be posted by shards
+    let mut accounts = Accounts::default();
+    accounts.set_accrual_schedule(Some(crate::accrual::AccrualSchedule {
+        policy: crate::accrual::AccrualPolicy::default(),
+        period: std::num::NonZeroU64::new(86_400).unwrap(),
+    }));
+    let err = crate::process_transaction_source_parallel(
+        "deposit,1,1,5".as_bytes(),
+        &mut accounts,
+        2.try_into().unwrap(),
+        |_, _, _| {},
+    );
+    assert!(err.unwrap_err().contains("accrual schedule"));
+    assert!(accounts.get(1).is_none());
+
+    // Accounts moved between shards for transfers are not scheduled to settle again
+    let mut input = String::new();
+    for tx in 1..=100u32 {
+        input.push_str(&format!("deposit,{},{},5\n", tx % 2, tx));
+        input.push_str(&format!(
+            "transfer,{},{},1,{}\n",
+            tx % 2,
+            tx + 1000,
+            1 - tx % 2
+        ));
+    }
+    let mut accounts = Accounts::default();
+    accounts.set_settlement_period(SettlementPeriod {
+        transactions: Some(1_000_000),
+        ..Default::default()
+    });
+    crate::process_transaction_source_parallel(
+        input.as_bytes(),
+        &mut accounts,
+        2.try_into().unwrap(),
+        |_, _, _| {},
+    )
+    .unwrap();
+    assert_eq!(accounts.scheduled_settlements(), 100);
+}
+
+#[test]
+fn parallel_progress() {
+    // One hot client and a few cold ones, with transfers between them
+    let mut input = String::new();
+    for i in 0..5000u32 {
+        let client = if i % 2 == 0 { 1 } else { i % 9 + 2 };
+        if i % 10 == 5 {
+            input.push_str(&format!("transfer,{},{},1,{}\n", client, i, i % 9 + 2));
+        } else {
+            input.push_str(&format!("deposit,{},{},2\n", client, i));
+        }
+    }
+    let mut expected = Accounts::default();
+    process_transaction_source_with(input.as_bytes(), &mut expected, |_, _, _| {}).unwrap();
+    let mut accounts = Accounts::default();
+    let mut reports = Vec::new();
+    crate::parallel::process_transaction_source_parallel_with_progress(
+        input.as_bytes(),
+        &mut accounts,
+        3.try_into().unwrap(),
+        std::time::Duration::ZERO,
+        |progress| reports.push(progress.to_vec()),
+        |_, _, _| {},
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    output::write_accounts(&accounts, OutputFormat::Csv, &mut out).unwrap();
+    let mut expected_out = Vec::new();
+    output::write_accounts(&expected, OutputFormat::Csv, &mut expected_out).unwrap();
+    assert_eq!(String::from_utf8(out), String::from_utf8(expected_out));
+    let last = reports.last().unwrap();
+    assert_eq!(last.len(), 3);
+    assert_eq!(last.iter().map(|shard| shard.applied).sum::<u64>(), 5000);
+    assert!(last.iter().all(|shard| shard.queued == 0));
+    assert_eq!(last.iter().map(|shard| shard.clients).sum::<usize>(), 10);
+}
+
+#[test]
+fn dispute_limits() {
+    let mut accounts = Accounts::default();
+    accounts.set_dispute_limits(crate::DisputeLimits {
+        max_open: Some(2),
+        max_per_run: Some(3),
+        window: None,
+    });
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    for tx in 1..=5 {
+        for client in [1, 2] {
+            transact(&format!("deposit,{},{},1", client, tx)).unwrap();
+        }
+    }
+    transact("dispute,1,1").unwrap();
+    transact("dispute,1,2").unwrap();
+    assert!(matches!(
+        transact("dispute,1,3"),
+        Err(TransactionError::OpenDisputeLimit(2))
+    ));
+    // Limits are per client
+    transact("dispute,2,1").unwrap();
+    // Rejected disputes do not count towards the run limit
+    assert!(transact("dispute,1,9").is_err());
+    transact("resolve,1,1").unwrap();
+    transact("dispute,1,3").unwrap();
+    assert!(matches!(
+        transact("dispute,1,4"),
+        Err(TransactionError::DisputeRunLimit(3))
+    ));
+    assert_eq!(accounts[1].held(), Amount::from_f64(2.0).unwrap());
+
+    // Disputes more than the window past the disputed transaction are rejected
+    let day = 24 * 60 * 60;
+    let mut accounts = Accounts::default();
+    accounts.set_dispute_limits(crate::DisputeLimits {
+        window: Some(90 * day),
+        ..Default::default()
+    });
+    let mut transact = |tx: String| accounts.transact(tx.parse().unwrap());
+    transact(format!("deposit,1,1,1,,{}", day)).unwrap();
+    transact(format!("deposit,1,2,1,,{}", 50 * day)).unwrap();
+    transact("deposit,1,3,1".into()).unwrap();
+    let err = transact(format!("dispute,1,1,,,{}", 100 * day)).unwrap_err();
+    assert!(matches!(err, TransactionError::DisputeWindowExpired(1)));
+    assert_eq!(err.code(), "dispute_window_expired");
+    transact(format!("dispute,1,2,,,{}", 100 * day)).unwrap();
+    transact(format!("dispute,1,3,,,{}", 1000 * day)).unwrap();
+    transact("dispute,1,1".into()).unwrap();
+}
+
+#[test]
+fn test_clock() {
+    use std::sync::Arc;
+
+    use crate::clock::TestClock;
+
+    // Untimestamped transactions are stamped with the clock's time
+    let day = 24 * 60 * 60;
+    let clock = TestClock::new(day);
+    let mut accounts = Accounts::default();
+    accounts.set_clock(Some(Arc::new(clock.clone())));
+    accounts.set_dispute_limits(crate::DisputeLimits {
+        window: Some(90 * day),
+        ..Default::default()
+    });
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,1").unwrap();
+    transact("deposit,1,2,1").unwrap();
+    clock.advance(60 * day);
+    transact("dispute,1,1").unwrap();
+    clock.advance(60 * day);
+    assert!(matches!(
+        transact("dispute,1,2"),
+        Err(TransactionError::DisputeWindowExpired(2))
+    ));
+    // Timestamps in the input take precedence
+    transact(&format!("dispute,1,2,,,{}", 2 * day)).unwrap();
+    assert_eq!(accounts.now(), 121 * day);
+}
+
+#[test]
+fn dispute_window_boundary() {
+    use std::sync::Arc;
+
+    use crate::clock::TestClock;
+
+    let day = 24 * 60 * 60;
+    let window = 90 * day;
+    let clock = TestClock::new(day);
+    let mut accounts = Accounts::default();
+    accounts.set_clock(Some(Arc::new(clock.clone())));
+    accounts.set_dispute_limits(crate::DisputeLimits {
+        window: Some(window),
+        ..Default::default()
+    });
+    accounts.transact("deposit,1,1,1".parse().unwrap()).unwrap();
+    accounts.transact("deposit,1,2,1".parse().unwrap()).unwrap();
+    // Just inside the window
+    clock.advance(window);
+    accounts.transact("dispute,1,1".parse().unwrap()).unwrap();
+    // Just outside the window
+    clock.advance(1);
+    assert!(matches!(
+        accounts.transact("dispute,1,2".parse().unwrap()),
+        Err(TransactionError::DisputeWindowExpired(2))
+    ));
+    assert_eq!(accounts[1].held(), Amount::from_f64(1.0).unwrap());
+
+    // Transactions without timestamps are not limited, in either direction
+    accounts.set_clock(None);
+    accounts.transact("deposit,1,3,1".parse().unwrap()).unwrap();
+    accounts
+        .transact("deposit,1,4,1,,0".parse().unwrap())
+        .unwrap();
+    let later = format!("dispute,1,3,,,{}", 1000 * day);
+    accounts.transact(later.parse().unwrap()).unwrap();
+    accounts.transact("dispute,1,4".parse().unwrap()).unwrap();
+    accounts.transact("dispute,1,2".parse().unwrap()).unwrap();
+    assert_eq!(accounts[1].held(), Amount::from_f64(4.0).unwrap());
+}
+
+#[test]
+fn balance_events() {
+    use std::sync::mpsc;
+
+    use crate::events::{BalanceEventKind::*, Balances};
+
+    let (sender, receiver) = mpsc::channel();
+    let mut accounts = Accounts::default();
+    accounts.set_balance_events(Some(sender));
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,10").unwrap();
+    transact("transfer,1,2,4,2").unwrap();
+    transact("dispute,1,1,3").unwrap();
+    transact("chargeback,1,1").unwrap();
+    // Rejected transactions and those that do not change balances send nothing
+    transact("withdrawal,1,3,100").unwrap_err();
+    transact("dispute,2,9").unwrap_err();
+    let events: Vec<_> = receiver.try_iter().collect();
+    let kinds: Vec<_> = events
+        .iter()
+        .map(|event| (event.kind, event.client))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (FundsDeposited, 1),
+            (TransferSent, 1),
+            (TransferReceived, 2),
+            (FundsHeld, 1),
+            (ChargebackApplied, 1)
+        ]
+    );
+    let amount = |n: f64| Amount::from_f64(n).unwrap();
+    assert_eq!(events[3].tx, Some(1));
+    assert_eq!(
+        events[3].before,
+        Balances {
+            available: amount(6.0),
+            held: amount(0.0),
+            total: amount(6.0),
+            pending_credit: amount(0.0),
+            locked: false,
+        }
+    );
+    assert_eq!(
+        events[4].after,
+        Balances {
+            available: amount(3.0),
+            held: amount(0.0),
+            total: amount(3.0),
+            pending_credit: amount(0.0),
+            locked: true,
+        }
+    );
+    let mut json = Vec::new();
+    events[0].write_json(&mut json).unwrap();
+    assert!(String::from_utf8(json)
+        .unwrap()
+        .starts_with(r#"{"event":"funds_deposited","client":1,"tx":1,"#));
+
+    // A dispute of a withdrawal makes a pending credit rather than holding funds
+    let (sender, receiver) = mpsc::channel();
+    let mut accounts = Accounts::default();
+    accounts.set_balance_events(Some(sender));
+    for tx in [
+        "deposit,1,1,10",
+        "withdrawal,1,2,4",
+        "dispute,1,2",
+        "dispute,1,1,1",
+    ] {
+        accounts.transact(tx.parse().unwrap()).unwrap();
+    }
+    let events: Vec<_> = receiver.try_iter().skip(2).collect();
+    assert_eq!(events[0].kind, CreditPending);
+    assert_eq!(events[0].tx, Some(2));
+    assert_eq!(events[0].before.pending_credit, 0.0);
+    assert_eq!(events[0].after.pending_credit, 4.0);
+    assert_eq!(events[0].after.held, 0.0);
+    assert_eq!(events[1].kind, FundsHeld);
+    assert_eq!(events.len(), 2);
+
+    // A lone account sends no events
+    let mut account = Account::default();
+    account
+        .transact(Transaction::deposit(1, amount(1.0)))
+        .unwrap();
+    assert!(receiver.try_iter().next().is_none());
+
+    // In parallel, the events of each client are still in order
+    let mut input = String::new();
+    for tx in 1..=200u32 {
+        input.push_str(&format!("deposit,{},{},1\n", tx % 7, tx));
+    }
+    let (sender, receiver) = mpsc::channel();
+    let mut accounts = Accounts::default();
+    accounts.set_balance_events(Some(sender));
+    crate::parallel::process_transaction_source_parallel(
+        input.as_bytes(),
+        &mut accounts,
+        std::num::NonZeroUsize::new(4).unwrap(),
+        |_, result, _| result.unwrap(),
+    )
+    .unwrap();
+    accounts.set_balance_events(None);
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events.len(), 200);
+    for client in 0..7 {
+        let txs: Vec<_> = (events.iter())
+            .filter(|event| event.client == client)
+            .map(|event| event.tx.unwrap())
+            .collect();
+        assert!(txs.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(txs.len(), (1..=200).filter(|tx| tx % 7 == client).count());
+    }
+}
+
+#[test]
+fn withdrawal_retries() {
+    let mut accounts = Accounts::default();
+    accounts.set_withdrawal_retries(Some(crate::WithdrawalRetries {
+        max_retries: 2,
+        window: Some(10),
+    }));
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,5").unwrap();
+    assert!(matches!(
+        transact("withdrawal,1,2,8"),
+        Err(TransactionError::WithdrawalParked { .. })
+    ));
+    assert!(transact("withdrawal,1,3,20").is_err());
+    assert!(transact("withdrawal,2,4,1").is_err());
+    // Only deposits to the same account retry parked withdrawals
+    transact("deposit,2,5,100").unwrap();
+    transact("deposit,1,6,4").unwrap();
+    transact("deposit,1,7,1").unwrap();
+    assert_eq!(accounts[1].balance(), Amount::from_f64(2.0).unwrap());
+    assert_eq!(accounts[2].balance(), Amount::from_f64(99.0).unwrap());
+    assert_eq!(accounts.unsettled_withdrawals(), [(1, 3)]);
+    // Withdrawals are not retried outside the window
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    assert!(transact("withdrawal,2,20,200").is_err());
+    transact("deposit,2,31,200").unwrap();
+    assert_eq!(accounts[2].balance(), Amount::from_f64(299.0).unwrap());
+    assert_eq!(accounts.unsettled_withdrawals(), [(1, 3), (2, 20)]);
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let path = std::env::temp_dir().join(format!("transactor-snapshot-{}", std::process::id()));
+    let input = include_bytes!("../test.csv");
+    let mut expected = Accounts::default();
+    process_transaction_source_with(input.as_slice(), &mut expected, |_, _, _| {}).unwrap();
+
+    // Resuming from a snapshot gives the same result as processing everything at once
+    let split = input
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b == b'\n')
+        .nth(8)
+        .unwrap()
+        .0
+        + 1;
+    let mut accounts = Accounts::default();
+    process_transaction_source_with(&input[..split], &mut accounts, |_, _, _| {}).unwrap();
+    accounts.save_snapshot(&path).unwrap();
+    let mut resumed = Accounts::default();
+    resumed
+        .transact("deposit,9,100,1".parse().unwrap())
+        .unwrap();
+    resumed.load_snapshot(&path).unwrap();
+    assert!(resumed.get(9).is_none());
+    process_transaction_source_with(&input[split..], &mut resumed, |_, _, _| {}).unwrap();
+    let mut out = Vec::new();
+    output::write_accounts(&resumed, OutputFormat::Csv, &mut out).unwrap();
+    let mut expected_out = Vec::new();
+    output::write_accounts(&expected, OutputFormat::Csv, &mut expected_out).unwrap();
+    assert_eq!(String::from_utf8(out), String::from_utf8(expected_out));
+
+    // Invalid snapshots leave accounts unchanged
+    std::fs::write(&path, "snapshot,1\nclient,1\naccount,1\n").unwrap();
+    assert!(resumed.load_snapshot(&path).is_err());
+    std::fs::write(&path, "client,1\n").unwrap();
+    assert!(resumed.load_snapshot(&path).is_err());
+    assert_eq!(resumed[4].held(), 20.6);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn store_writer() {
+    use std::collections::BTreeSet;
+
+    use crate::store::{AccountStore, StoreWriter};
+
+    // Records the balances of saved accounts
+    #[derive(Default)]
+    struct MemoryStore(Vec<(u16, Option<Amount>)>);
+    impl AccountStore for MemoryStore {
+        fn load(&mut self, _: &mut Accounts) -> std::io::Result<()> {
+            Ok(())
+        }
+        fn save(&mut self, accounts: &Accounts, clients: &BTreeSet<u16>) -> std::io::Result<()> {
+            for &client in clients {
+                let balance = accounts.get(client).map(|account| account.balance());
+                self.0.push((client, balance));
+            }
+            Ok(())
+        }
+    }
+
+    let mut accounts = Accounts::default();
+    let mut writer = StoreWriter::new(MemoryStore::default());
+    for tx in ["deposit,1,1,5", "deposit,2,2,3", "transfer,2,3,1,4"] {
+        let tx: ClientTransaction = tx.parse().unwrap();
+        accounts.transact(tx.clone()).unwrap();
+        writer.observe(&tx, &accounts);
+    }
+    let store = writer.finish(&accounts).unwrap();
+    assert_eq!(
+        store.0,
+        [
+            (1, Some(Amount::from_f64(5.0).unwrap())),
+            (2, Some(Amount::from_f64(2.0).unwrap())),
+            (4, Some(Amount::from_f64(1.0).unwrap())),
+        ]
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_store() {
+    use crate::{
+        sqlite::SqliteStore,
+        store::{AccountStore, StoreWriter},
+    };
+
+    let path = std::env::temp_dir().join(format!("transactor-store-{}.db", std::process::id()));
+    let mut expected = Accounts::default();
+    let mut writer = StoreWriter::new(SqliteStore::open(&path).unwrap());
+    process_transaction_source_with(
+        include_bytes!("../test.csv").as_slice(),
+        &mut expected,
+        |record, _, accounts| writer.observe(&record.tx, accounts),
+    )
+    .unwrap();
+    drop(writer.finish(&expected).unwrap());
+
+    // Accounts persist across runs, including their history
+    let mut accounts = Accounts::default();
+    SqliteStore::open(&path)
+        .unwrap()
+        .load(&mut accounts)
+        .unwrap();
+    for (client, account) in expected.iter() {
+        assert_eq!(accounts[client].balance(), account.balance());
+        assert_eq!(accounts[client].held(), account.held());
+        assert_eq!(accounts[client].is_frozen(), account.is_frozen());
+    }
+    accounts.transact("resolve,4,9".parse().unwrap()).unwrap();
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[test]
+fn history_spill() {
+    let path = std::env::temp_dir().join(format!("transactor-history-{}", std::process::id()));
+    check_history_spill(std::sync::Arc::new(HistoryStore::create(&path).unwrap()));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn server_limits() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::server::{ConnectionLimits, Server};
+
+    let mut server = Server::new(Accounts::default());
+    server.set_limits(ConnectionLimits {
+        max_request_size: Some(20),
+        ..Default::default()
+    });
+    let mut output = Vec::new();
+    let input =
+        "deposit,1,1,5\ndeposit,1,2,5.000000000000\ndeposit,1,3,\"5\n\n\n\n\n\n\"\ndeposit,1,4,5\n";
+    server.handle(0, input.as_bytes(), &mut output).unwrap();
+    // Long lines and long quoted records are skipped, and the connection carries on
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "ok\n\
+        error: Request is longer than 20 bytes\n\
+        error: Request is longer than 20 bytes\n\
+        ok\n"
+    );
+    assert_eq!(server.with_accounts(|accounts| accounts[1].total()), 10.0);
+
+    // Connections over the limit are refused
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut server = Server::new(Accounts::default());
+    server.set_limits(ConnectionLimits {
+        max_connections: Some(1),
+        ..Default::default()
+    });
+    // The server runs until the tests end
+    let server: &'static Server = Box::leak(Box::new(server));
+    std::thread::spawn(move || server.serve(listener));
+    let mut first = BufReader::new(TcpStream::connect(address).unwrap());
+    let mut response = String::new();
+    writeln!(first.get_mut(), "deposit,1,1,5").unwrap();
+    first.read_line(&mut response).unwrap();
+    assert_eq!(response, "ok\n");
+    let mut second = BufReader::new(TcpStream::connect(address).unwrap());
+    response.clear();
+    second.read_line(&mut response).unwrap();
+    assert_eq!(
+        response,
+        "error: Too many connections, at most 1 are served at once\n"
+    );
+    response.clear();
+    assert_eq!(second.read_line(&mut response).unwrap(), 0);
+    // Once the first connection closes, another may connect
+    drop(first);
+    let mut third = loop {
+        let mut stream = BufReader::new(TcpStream::connect(address).unwrap());
+        writeln!(stream.get_mut(), "deposit,1,2,5").unwrap();
+        response.clear();
+        stream.read_line(&mut response).unwrap();
+        if response == "ok\n" {
+            break stream;
+        }
+    };
+    writeln!(third.get_mut(), "withdrawal,1,3,10").unwrap();
+    response.clear();
+    third.read_line(&mut response).unwrap();
+    assert_eq!(response, "ok\n");
+}
+
+#[test]
+fn server_batches() {
+    use crate::server::{ConnectionLimits, Server};
+
+    let mut server = Server::new(Accounts::default());
+    server.set_limits(ConnectionLimits {
+        max_in_flight: Some(3),
+        ..Default::default()
+    });
+    let mut output = Vec::new();
+    let input = "deposit,1,1,5\n\
+        batch 3\n\
+        deposit,1,2,5\n\
+        withdrawal,1,3,20\n\
+        deposit,1,2,5\n\
+        withdrawal,1,4,1\n\
+        batch x\n\
+        batch 2\n\
+        deposit,1,5,\"1\n\
+        \"\n\
+        batch 1\n\
+        batch 4\n\
+        deposit,1,6,1\n\
+        deposit,1,7,1\n\
+        deposit,1,8,1\n\
+        deposit,1,9,1\n\
+        batch 2\n\
+        deposit,1,10,1\n\
+        deposit,1,11,\"1\n";
+    server.handle(0, input.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output.lines().collect::<Vec<_>>(),
+        [
+            "ok",
+            "ack 0",
+            "nack 1 insufficient_funds Attempted to withdraw 20.0000 from an account with 10.0000 available",
+            "nack 2 duplicate_transaction_id Transaction id 2 has already been used",
+            "ok",
+            "error: Invalid batch size \"x\"",
+            "ack 0",
+            "nack 1 missing_client_id Invalid transaction on line 11: Missing client id",
+            "nack 0 too_many_in_flight Too many transactions in one request, at most 3 are allowed",
+            "nack 1 too_many_in_flight Too many transactions in one request, at most 3 are allowed",
+            "nack 2 too_many_in_flight Too many transactions in one request, at most 3 are allowed",
+            "nack 3 too_many_in_flight Too many transactions in one request, at most 3 are allowed",
+            "ack 0",
+            "nack 1 unclosed_quote Unclosed quote on line 19",
+        ]
+    );
+    // Only the acknowledged transactions were applied
+    assert_eq!(server.with_accounts(|accounts| accounts[1].total()), 11.0);
+}
+
+#[test]
+fn server_handshake() {
+    use crate::server::{Handshake, Server};
+
+    let server = Server::new(Accounts::default());
+    let handle = |input: &str| {
+        let mut output = Vec::new();
+        server.handle(0, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    // Unknown features are left out, and newer clients speak the server's version
+    assert_eq!(
+        handle("hello 2 binary batch\ndeposit,1,1,5\n"),
+        "hello 2 batch\nok\n"
+    );
+    assert_eq!(handle("hello 9 batch\n"), "hello 2 batch\n");
+    assert_eq!(handle("hello 1 batch\n"), "hello 1\n");
+    assert_eq!(
+        handle("hello x\ndeposit,1,2,5\n"),
+        "error: Invalid protocol version \"x\"\nok\n"
+    );
+    // Clients without a handshake are served as before, and a handshake must come first
+    assert_eq!(
+        handle("deposit,1,3,5\nhello 2\n"),
+        "ok\nerror: Invalid transaction on line 2: Missing client id\n"
+    );
+    assert_eq!(
+        Handshake::negotiate("2 batch batch"),
+        Ok(Handshake {
+            version: 2,
+            features: vec!["batch"]
+        })
+    );
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_connections() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::server::Server;
+
+    let server = Server::new(Accounts::default());
+    let mut input = b"hello 2 zstd batch\n".to_vec();
+    input.extend(
+        zstd::encode_all("deposit,1,1,5\nbatch 1\nwithdrawal,1,2,9\n".as_bytes(), 0).unwrap(),
+    );
+    let mut output = Vec::new();
+    server.handle(0, input.as_slice(), &mut output).unwrap();
+    let answer = b"hello 2 batch zstd\n";
+    assert!(output.starts_with(answer));
+    assert_eq!(
+        zstd::decode_all(&output[answer.len()..]).unwrap(),
+        b"ok\nnack 0 insufficient_funds Attempted to withdraw 9.0000 from an account with 5.0000 available\n"
+    );
+
+    // Responses arrive as soon as a client flushes what it has sent
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // The server runs until the tests end
+    let server: &'static Server = Box::leak(Box::new(server));
+    std::thread::spawn(move || server.serve(listener));
+    let stream = TcpStream::connect(address).unwrap();
+    let mut reader = BufReader::new(&stream);
+    writeln!(&stream, "hello 2 zstd").unwrap();
+    let mut response = String::new();
+    reader.read_line(&mut response).unwrap();
+    assert_eq!(response, "hello 2 zstd\n");
+    let mut writer = zstd::Encoder::new(&stream, 0).unwrap();
+    let mut reader = BufReader::new(zstd::Decoder::with_buffer(reader).unwrap());
+    for (tx, expected) in [("deposit,2,3,5", "ok\n"), ("deposit,2,3,5", "error: ")] {
+        writeln!(writer, "{}", tx).unwrap();
+        writer.flush().unwrap();
+        response.clear();
+        reader.read_line(&mut response).unwrap();
+        assert!(response.starts_with(expected), "{}", response);
+    }
+}
+
+#[test]
+fn admin_api() {
+    use std::{
+        io::{BufRead, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{
+        admin::{AdminOp, AdminOpKind, TOKEN_VAR},
+        control::{self, Command},
+        server::Server,
+    };
+
+    std::env::set_var(TOKEN_VAR, "secret");
+    let server = Server::new(Accounts::default());
+    let mut output = Vec::new();
+    server
+        .handle(0, "deposit,1,1,5\n".as_bytes(), &mut output)
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    // The server runs until the tests end
+    let server: &'static Server = Box::leak(Box::new(server));
+    std::thread::spawn(move || server.serve_admin(listener));
+    let request = |command: &Command| control::request(&address, "secret", command);
+
+    let freeze = |kind| {
+        Command::Op(AdminOp {
+            client: 1,
+            kind,
+            operator: "alice".into(),
+            reference: "INC-1".into(),
+            note: Some("Suspected fraud".into()),
+        })
+    };
+    assert_eq!(
+        control::request(&address, "wrong", &Command::Stats).unwrap_err(),
+        "Invalid admin token"
+    );
+    request(&freeze(AdminOpKind::Freeze)).unwrap();
+    let response = request(&Command::Inspect(1)).unwrap();
+    assert_eq!(response["account"]["locked"], true);
+    assert_eq!(response["account"]["closed"], false);
+    assert_eq!(response["account"]["notes"][0]["text"], "Suspected fraud");
+    assert_eq!(
+        request(&Command::Inspect(9)).unwrap_err(),
+        "Client 9 has no account"
+    );
+    let mut output = Vec::new();
+    server
+        .handle(1, "withdrawal,1,2,1\n".as_bytes(), &mut output)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "error: Account is frozen\n"
+    );
+    request(&freeze(AdminOpKind::Unfreeze)).unwrap();
+    let response = request(&Command::Stats).unwrap();
+    assert_eq!(
+        response.to_string(),
+        r#"{"status":"ok","stats":{"accounts":1,"frozen":0,"closed":0,"held":"0.0000","transactions":2,"rejected":1,"connections":0,"draining":false}}"#
+    );
+
+    // Requests are checked before anything is applied
+    let mut output = Vec::new();
+    let input = "{\"token\":\"secret\",\"command\":\"freeze\",\"client\":1}\n\
+        {\"token\":\"secret\",\"command\":\"close\",\"client\":1}\n\
+        not json\n";
+    server.handle_admin(input.as_bytes(), &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], r#"{"status":"error","error":"Missing operator"}"#);
+    assert_eq!(
+        lines[1],
+        r#"{"status":"error","error":"Invalid command \"close\""}"#
+    );
+    assert!(lines[2].starts_with(r#"{"status":"error","error":"Invalid request: "#));
+    assert!(!server.with_accounts(|accounts| accounts[1].is_frozen()));
+
+    // Once drained, new connections are refused but the admin API is still served
+    request(&Command::Drain).unwrap();
+    assert!(server.is_drained());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let tcp = listener.local_addr().unwrap();
+    std::thread::spawn(move || server.serve(listener));
+    let mut response = String::new();
+    BufReader::new(TcpStream::connect(tcp).unwrap())
+        .read_line(&mut response)
+        .unwrap();
+    assert_eq!(
+        response,
+        "error: Server is draining and accepts no new connections\n"
+    );
+    assert_eq!(request(&Command::Stats).unwrap()["stats"]["draining"], true);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_api() {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::server::Server;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // The server runs until the tests end
+    let server: &'static Server = Box::leak(Box::new(Server::new(Accounts::default())));
+    std::thread::spawn(move || server.serve_http(listener));
+    let send = |method: &str, path: &str, headers: &str, body: &str| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].to_owned();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_owned();
+        (status, body)
+    };
+    let request = |method: &str, path: &str, body: &str| send(method, path, "", body);
+
+    let (status, body) = request(
+        "POST",
+        "/transactions",
+        "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,7\ndeposit,2,3,1",
+    );
+    assert_eq!(status, "200");
+    assert_eq!(
+        body,
+        r#"[{"status":"ok"},{"status":"error","error":"Attempted to withdraw 7.0000 from an account with 5.0000 available"},{"status":"ok"}]"#
+    );
+    assert_eq!(
+        request("GET", "/accounts/1", ""),
+        (
+            "200".into(),
+            r#"{"client":1,"available":"5.0000","held":"0.0000","total":"5.0000","locked":false}"#
+                .into()
+        )
+    );
+    let (status, body) = request("GET", "/accounts", "");
+    assert_eq!(status, "200");
+    assert!(body.starts_with(r#"[{"client":1,"#) && body.contains(r#"{"client":2,"#));
+    assert_eq!(request("GET", "/accounts/3", "").0, "404");
+    assert_eq!(request("GET", "/accounts/x", "").0, "400");
+    // History is paged through in order of transaction id
+    request("POST", "/transactions", "deposit,1,4,1,,60\ndeposit,1,5,2");
+    assert_eq!(
+        request("GET", "/accounts/1/transactions?limit=2", ""),
+        (
+            "200".into(),
+            r#"{"transactions":[{"tx":1,"type":"deposit","amount":"5.0000","timestamp":null},{"tx":4,"type":"deposit","amount":"1.0000","timestamp":60}],"next_after":4}"#
+                .into()
+        )
+    );
+    let (status, body) = request("GET", "/accounts/1/transactions?after=4&limit=2", "");
+    assert_eq!(status, "200");
+    assert!(body.contains(r#""next_after":null"#) && body.contains(r#""tx":5"#));
+    assert_eq!(
+        request("GET", "/accounts/1/transactions?after=x", "").0,
+        "400"
+    );
+    assert_eq!(request("GET", "/accounts/3/transactions", "").0, "404");
+    assert_eq!(request("POST", "/accounts/1/transactions", "").0, "405");
+    assert_eq!(request("DELETE", "/accounts", "").0, "405");
+    assert_eq!(request("GET", "/nope", "").0, "404");
+    let (status, body) = request("GET", "/metrics", "");
+    assert_eq!(status, "200");
+    assert!(body.contains("transactor_transactions_total 5\n"));
+    assert!(body.contains("transactor_accounts 2\n"));
+    server.with_accounts(|accounts| assert_eq!(accounts.iter().count(), 2));
+
+    // Requests that speak version 2 get error codes
+    assert_eq!(
+        request("GET", "/protocol", ""),
+        (
+            "200".into(),
+            serde_json::json!({ "version": 2, "features": crate::server::protocol_features() })
+                .to_string()
+        )
+    );
+    assert_eq!(
+        send("POST", "/transactions", "Transactor-Protocol: 3\r\n", "withdrawal,2,6,9"),
+        (
+            "200".into(),
+            r#"[{"status":"error","error":"Attempted to withdraw 9.0000 from an account with 1.0000 available","code":"insufficient_funds"}]"#
+                .into()
+        )
+    );
+    let (status, body) = send("GET", "/accounts", "Transactor-Protocol: 0\r\n", "");
+    assert_eq!(
+        (status.as_str(), body.as_str()),
+        ("400", r#"{"error":"Invalid protocol version \"0\""}"#)
+    );
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_limits() {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::server::{ConnectionLimits, Server};
+
+    let serve = |limits| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = Server::new(Accounts::default());
+        server.set_limits(limits);
+        // The server runs until the tests end
+        let server: &'static Server = Box::leak(Box::new(server));
+        std::thread::spawn(move || server.serve_http(listener));
+        (server, address)
+    };
+    let post = |address, body: &str| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /transactions HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].to_owned();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_owned();
+        (status, body)
+    };
+
+    // Requests over the limits apply none of their transactions
+    let (server, address) = serve(ConnectionLimits {
+        max_connections: None,
+        max_in_flight: Some(2),
+        max_request_size: Some(50),
+    });
+    assert_eq!(
+        post(address, "deposit,1,1,5\ndeposit,1,2,5\n"),
+        ("200".into(), r#"[{"status":"ok"},{"status":"ok"}]"#.into())
+    );
+    assert_eq!(
+        post(address, "deposit,1,3,5\ndeposit,1,4,5\ndeposit,1,5,5\n"),
+        (
+            "413".into(),
+            r#"{"error":"Too many transactions in one request, at most 2 are allowed"}"#.into()
+        )
+    );
+    assert_eq!(
+        post(address, &format!("deposit,1,6,{}\n", "5".repeat(50))),
+        (
+            "413".into(),
+            r#"{"error":"Request is longer than 50 bytes"}"#.into()
+        )
+    );
+    server.with_accounts(|accounts| assert_eq!(accounts[1].total(), 10.0));
+
+    let (_, address) = serve(ConnectionLimits {
+        max_connections: Some(0),
+        ..Default::default()
+    });
+    assert_eq!(
+        post(address, "deposit,1,1,5\n"),
+        (
+            "503".into(),
+            r#"{"error":"Too many connections, at most 0 are served at once"}"#.into()
+        )
+    );
+}
+
+#[test]
+fn metrics() {
+    let metrics = crate::metrics::Metrics::new();
+    let mut accounts = Accounts::default();
+    for line in [
+        "deposit,1,1,5",
+        "deposit,2,2,3",
+        "withdrawal,1,3,9",
+        "withdrawal,2,4,9",
+        "dispute,1,1",
+        "chargeback,1,1",
+        "withdrawal,1,5,1",
+        "dispute,2,2",
+    ] {
+        let tx: ClientTransaction = line.parse().unwrap();
+        let result = accounts.transact(tx.clone());
+        metrics.observe(&tx, &result);
+    }
+    metrics.update_accounts(&accounts);
+    let rendered = metrics.render();
+    for expected in [
+        "# TYPE transactor_transactions_total counter\ntransactor_transactions_total 8\n",
+        "transactor_rejections_total{reason=\"insufficient_funds\"} 2\n",
+        "transactor_rejections_total{reason=\"account_frozen\"} 1\n",
+        "transactor_chargebacks_total 1\n",
+        "# TYPE transactor_accounts gauge\ntransactor_accounts 2\n",
+        "transactor_held_funds 3.0000\n",
+    ] {
+        assert!(
+            rendered.contains(expected),
+            "{:?} not in {}",
+            expected,
+            rendered
+        );
+    }
+}
+
+#[test]
+fn submission_rates() {
+    use std::time::{Duration, Instant};
+
+    let metrics = crate::metrics::Metrics::new();
+    let start = Instant::now();
+    for (client, count) in [(1, 30), (2, 10), (3, 20)] {
+        for _ in 0..count {
+            metrics.observe_submission_at(client, start);
+        }
+    }
+    let top = metrics.top_submitters_at(2, start);
+    assert_eq!(top.len(), 2);
+    assert_eq!(
+        (top[0].client, top[0].per_minute, top[0].total),
+        (1, 30.0, 30)
+    );
+    assert_eq!(
+        (top[1].client, top[1].per_minute, top[1].total),
+        (3, 20.0, 20)
+    );
+
+    // Half of the previous window overlaps the last minute
+    let later = start + Duration::from_secs(90);
+    for _ in 0..25 {
+        metrics.observe_submission_at(2, later);
+    }
+    let top = metrics.top_submitters_at(3, later);
+    assert_eq!(
+        (top[0].client, top[0].per_minute, top[0].total),
+        (2, 30.0, 35)
+    );
+    assert_eq!((top[1].client, top[1].per_minute), (1, 15.0));
+
+    // Submissions older than two windows no longer count
+    let top = metrics.top_submitters_at(1, start + Duration::from_secs(300));
+    assert_eq!(
+        (top[0].client, top[0].per_minute, top[0].total),
+        (2, 0.0, 35)
+    );
+}
+
+#[test]
+fn audit_log() {
+    use crate::audit::{verify, AuditLog, Verification};
+
+    let input = "\
+type,client,tx,amount,to,timestamp
+deposit,1,1,5,,1700000000
+withdrawal,1,2,9
+deposit,2,3,2
+transfer,1,4,1.5,2,1700000060
+dispute,1,1";
+    let mut out = Vec::new();
+    let mut log = AuditLog::new(&mut out);
+    let mut accounts = Accounts::default();
+    process_transaction_source_with(
+        input.as_bytes(),
+        &mut accounts,
+        |record, result, accounts| log.observe(&record.tx, &result, accounts),
+    )
+    .unwrap();
+    log.flush().unwrap();
+    drop(log);
+    let out = String::from_utf8(out).unwrap();
+    // Compare entries without their hashes
+    let entries: Vec<&str> = out
+        .lines()
+        .map(|line| line.rsplit_once(',').unwrap().0)
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            "deposit,1,1,5.0000,,1700000000,5.0000,0.0000,5.0000,false",
+            "deposit,2,3,2.0000,,,2.0000,0.0000,2.0000,false",
+            "transfer,1,4,1.5000,2,1700000060,3.5000,0.0000,3.5000,false",
+            "dispute,1,1,,,,-1.5000,5.0000,3.5000,false",
+        ]
+    );
+
+    // The log can be verified against the input it was produced from
+    let header = "type,client,tx,amount,to,timestamp,available,held,total,locked,hash\n";
+    let verify = |log: &str, input: &str| {
+        verify(
+            format!("{}{}", header, log).as_bytes(),
+            input.as_bytes(),
+            &mut Accounts::default(),
+        )
+        .unwrap()
+    };
+    assert_eq!(verify(&out, input), Verification::Verified { entries: 4 });
+    let mut lines: Vec<&str> = out.lines().collect();
+    let changed = lines[1].replace("2.0000", "3.0000");
+    lines[1] = &changed;
+    assert_eq!(
+        verify(&lines.join("\n"), input),
+        Verification::Mismatch { entry: 2 }
+    );
+    let without_last = &input[..input.rfind('\n').unwrap()];
+    assert_eq!(
+        verify(&out, without_last),
+        Verification::Inserted { entry: 4 }
+    );
+    assert_eq!(
+        verify(&out, &format!("{}\nresolve,1,1", input)),
+        Verification::Omitted { entry: 5 }
+    );
+
+    // Tampered logs are rejected
+    let lines: Vec<&str> = out.lines().collect();
+    // A forged hash breaks the chain, even if the entry's columns are unchanged
+    let forged = format!(
+        "{},{}",
+        lines[0].rsplit_once(',').unwrap().0,
+        "0".repeat(64)
+    );
+    let tampered = [forged.as_str(), lines[1], lines[2], lines[3]].join("\n");
+    assert_eq!(
+        verify(&tampered, input),
+        Verification::Mismatch { entry: 1 }
+    );
+    // Reordered entries
+    let reordered = [lines[1], lines[0], lines[2], lines[3]].join("\n");
+    assert_eq!(
+        verify(&reordered, input),
+        Verification::Mismatch { entry: 1 }
+    );
+    let reordered = [lines[0], lines[1], lines[3], lines[2]].join("\n");
+    assert_eq!(
+        verify(&reordered, input),
+        Verification::Mismatch { entry: 3 }
+    );
+    // Truncated after an entry
+    let truncated = lines[..3].join("\n");
+    assert_eq!(
+        verify(&truncated, input),
+        Verification::Omitted { entry: 4 }
+    );
+    assert!(verify(&truncated, input).check().is_err());
+    assert_eq!(verify("", input), Verification::Omitted { entry: 1 });
+    // Truncated within an entry's hash
+    let truncated = &out.trim_end()[..out.trim_end().len() - 10];
+    assert_eq!(
+        verify(truncated, input),
+        Verification::Mismatch { entry: 4 }
+    );
+    // Truncated before an entry's hash
+    let truncated = &out[..out.rfind(",false,").unwrap()];
+    assert!(crate::audit::verify(
+        format!("{}{}", header, truncated).as_bytes(),
+        input.as_bytes(),
+        &mut Accounts::default(),
+    )
+    .unwrap_err()
+    .contains("Entry on line 5 has 9 columns instead of 11"));
+    // Truncated before the header
+    assert!(crate::audit::verify(
+        &header.as_bytes()[..20],
+        input.as_bytes(),
+        &mut Accounts::default(),
+    )
+    .unwrap_err()
+    .contains("Expected a header row"));
+}
+
+#[test]
+fn rejects_report() {
+    use crate::rejects::Rejects;
+
+    let input =
+        "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,9\ndeposit,1,1,2\nresolve,1,1";
+    let mut out = Vec::new();
+    let mut rejects = Rejects::new(&mut out);
+    let mut accounts = Accounts::default();
+    process_transaction_source_with(input.as_bytes(), &mut accounts, |record, result, _| {
+        rejects.observe(record, &result)
+    })
+    .unwrap();
+    rejects.flush().unwrap();
+    drop(rejects);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\
+line_no,line,reason,error
+3,\"withdrawal,1,2,9\",insufficient_funds,Attempted to withdraw 9.0000 from an account with 5.0000 available
+4,\"deposit,1,1,2\",duplicate_transaction_id,Transaction id 1 has already been used
+5,\"resolve,1,1\",undisputed_resolution,A transaction with id 1 was never disputed
+"
+    );
+}
+
+#[test]
+fn duplicates_report() {
+    use crate::duplicates::Duplicates;
+
+    let input = "\
+type,client,tx,amount,to
+deposit,1,1,5
+transfer,1,2,1,2
+deposit,1,1,5
+withdrawal,1,1,2
+deposit,2,2,3
+deposit,2,3,1";
+    let mut out = Vec::new();
+    let mut duplicates = Duplicates::new(&mut out);
+    let mut accounts = Accounts::default();
+    process_transaction_source_with(input.as_bytes(), &mut accounts, |record, result, _| {
+        duplicates.observe(record, &result)
+    })
+    .unwrap();
+    assert_eq!(duplicates.count(), 3);
+    assert_eq!(duplicates.matched(), 1);
+    duplicates.flush().unwrap();
+    drop(duplicates);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\
+line_no,line,client,tx,first_line_no,matched
+4,\"deposit,1,1,5\",1,1,2,true
+5,\"withdrawal,1,1,2\",1,1,2,false
+6,\"deposit,2,2,3\",2,2,3,false
+"
+    );
+}
+
+#[test]
+fn memory_estimate() {
+    use crate::estimate::estimate;
+
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=1000 {
+        input.push_str(&format!("deposit,{},{},1\n", tx % 10, tx));
+    }
+    input.push_str("dispute,1,1,\n");
+
+    let full = estimate(input.as_bytes(), input.len() as u64, None, 10_000);
+    assert!(full.complete);
+    assert_eq!(
+        (full.sampled, full.clients, full.history, full.disputes),
+        (1001, 10, 1000, 1)
+    );
+
+    let partial = estimate(input.as_bytes(), input.len() as u64, None, 100);
+    assert!(!partial.complete);
+    assert_eq!(partial.sampled, 100);
+    assert_eq!(partial.clients, 10);
+    assert!((900..=1100).contains(&partial.transactions));
+    assert!(partial.peak_bytes.abs_diff(full.peak_bytes) < full.peak_bytes / 5);
+
+    let limited = estimate(input.as_bytes(), input.len() as u64, Some(100), 10_000);
+    assert_eq!(limited.history, 100);
+    assert!(limited.peak_bytes < full.peak_bytes);
+}
+
+#[test]
+fn demo_dataset() {
+    use crate::demo;
+
+    let txs = |seed| -> Vec<String> {
+        demo::transactions(seed)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    };
+    assert_eq!(txs(3), txs(3));
+    assert_ne!(txs(3), txs(4));
+    for seed in 0..20 {
+        let accounts = demo::accounts(seed);
+        let accounts: Vec<&Account> = accounts.iter().map(|(_, account)| account).collect();
+        assert_eq!(accounts.len(), 7);
+        assert_eq!(accounts.iter().filter(|a| a.is_frozen()).count(), 1);
+        assert_eq!(
+            accounts
+                .iter()
+                .filter(|a| a.held() > Amount::default())
+                .count(),
+            1
+        );
+        assert_eq!(
+            accounts
+                .iter()
+                .filter(|a| a.pending_credit() > Amount::default())
+                .count(),
+            1
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn sequential_read_advice() {
+    use std::{fs::File, io::Write};
+
+    use crate::priority;
+
+    let path = std::env::temp_dir().join(format!("transactor-advice-{}", std::process::id()));
+    File::create(&path)
+        .unwrap()
+        .write_all(b"type,client,tx,amount\n")
+        .unwrap();
+    priority::advise_sequential(&File::open(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn sha256_reader() {
+    use std::io::Read;
+
+    use crate::input::Sha256Reader;
+
+    // The SHA-256 digest of "test"
+    let digest = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+    let mut read = String::new();
+    Sha256Reader::new(b"test".as_slice(), digest)
+        .unwrap()
+        .read_to_string(&mut read)
+        .unwrap();
+    assert_eq!(read, "test");
+    let error = Sha256Reader::new(b"tset".as_slice(), digest)
+        .unwrap()
+        .read_to_string(&mut String::new())
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(Sha256Reader::new(b"test".as_slice(), "9f86d0").is_err());
+}
+
+#[test]
+fn sha256_before_processing() {
+    use std::io::Read;
+
+    use crate::source::{self, InputOptions};
+
+    let path = std::env::temp_dir().join(format!("transactor-sha256-{}.csv", std::process::id()));
+    std::fs::write(&path, "test").unwrap();
+    let path = path.to_str().unwrap();
+    let options = |sha256: &str| InputOptions {
+        sha256: Some(sha256.into()),
+        ..InputOptions::default()
+    };
+    // A mismatch is reported when the input is opened, before any of it is read
+    let mismatch = source::open_raw(path, &options(&"0".repeat(64))).err();
+    let mut read = String::new();
+    source::open_raw(
+        path,
+        &options("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"),
+    )
+    .unwrap()
+    .read_to_string(&mut read)
+    .unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(mismatch.unwrap().contains("SHA-256 digest is 9f86d0"));
+    assert_eq!(read, "test");
+}
+
+#[cfg(unix)]
+#[test]
+fn systemd_notify() {
+    use std::{os::unix::net::UnixDatagram, time::Duration};
+
+    use crate::service;
+
+    let path = std::env::temp_dir().join(format!("transactor-notify-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    std::env::set_var("NOTIFY_SOCKET", &path);
+    service::notify("READY=1").unwrap();
+    let mut buf = [0; 16];
+    let len = socket.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"READY=1");
+    std::env::remove_var("NOTIFY_SOCKET");
+    std::fs::remove_file(&path).unwrap();
+    // Without a socket, notifying does nothing
+    service::notify("READY=1").unwrap();
+
+    std::env::set_var("WATCHDOG_USEC", "3000000");
+    std::env::set_var("WATCHDOG_PID", std::process::id().to_string());
+    assert_eq!(service::watchdog_interval(), Some(Duration::from_secs(3)));
+    std::env::set_var("WATCHDOG_PID", "1");
+    assert_eq!(service::watchdog_interval(), None);
+    std::env::remove_var("WATCHDOG_USEC");
+    std::env::remove_var("WATCHDOG_PID");
+    assert_eq!(service::watchdog_interval(), None);
+}
+
+#[cfg(feature = "grpc")]
+#[test]
+fn grpc_service() {
+    use std::{net::TcpListener, sync::Arc};
+
+    use tonic::Code;
+
+    use crate::{
+        grpc::proto::{
+            transactor_client::TransactorClient, GetAccountRequest, ListTransactionsRequest,
+            SubmitTransactionRequest, TransactionType,
+        },
+        server::Server,
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // The server runs until the tests end
+    let server = Arc::new(Server::new(Accounts::default()));
+    std::thread::spawn({
+        let server = server.clone();
+        move || server.serve_grpc(listener)
+    });
+    let tx = |r#type: TransactionType, client, tx, amount: &str| SubmitTransactionRequest {
+        r#type: r#type as i32,
+        client,
+        tx,
+        amount: amount.into(),
+        to: None,
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let mut client = TransactorClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        client
+            .submit_transaction(tx(TransactionType::Deposit, 1, 1, "5"))
+            .await
+            .unwrap();
+        let status = client
+            .submit_transaction(tx(TransactionType::Withdrawal, 1, 2, "7"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status.message(),
+            "Attempted to withdraw 7.0000 from an account with 5.0000 available"
+        );
+        let status = client
+            .submit_transaction(tx(TransactionType::Deposit, 1, 3, ""))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let account = client
+            .get_account(GetAccountRequest { client: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            (account.client, account.available.as_str(), account.locked),
+            (1, "5.0000", false)
+        );
+        let status = client
+            .get_account(GetAccountRequest { client: 2 })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        for tx_id in [4, 5] {
+            client
+                .submit_transaction(tx(TransactionType::Withdrawal, 1, tx_id, "1"))
+                .await
+                .unwrap();
+        }
+        let request = |after| ListTransactionsRequest {
+            client: 1,
+            after,
+            limit: Some(2),
+        };
+        let page = client
+            .list_transactions(request(None))
+            .await
+            .unwrap()
+            .into_inner();
+        let tx_ids: Vec<_> = page.transactions.iter().map(|entry| entry.tx).collect();
+        assert_eq!((tx_ids, page.next_after), (vec![1, 4], Some(4)));
+        assert_eq!(page.transactions[1].r#type(), TransactionType::Withdrawal);
+        assert_eq!(page.transactions[1].amount, "1.0000");
+        let page = client
+            .list_transactions(request(Some(4)))
+            .await
+            .unwrap()
+            .into_inner();
+        let tx_ids: Vec<_> = page.transactions.iter().map(|entry| entry.tx).collect();
+        assert_eq!((tx_ids, page.next_after), (vec![5], None));
+        let status = client
+            .list_transactions(ListTransactionsRequest {
+                client: 2,
+                after: None,
+                limit: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    });
+    server.with_accounts(|accounts| assert_eq!(accounts.iter().count(), 1));
+
+    // Calls over the connection limit are refused
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut server = Server::new(Accounts::default());
+    server.set_limits(crate::server::ConnectionLimits {
+        max_connections: Some(0),
+        ..Default::default()
+    });
+    let server = Arc::new(server);
+    std::thread::spawn(move || server.serve_grpc(listener));
+    runtime.block_on(async {
+        let mut client = TransactorClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        let status = client
+            .submit_transaction(tx(TransactionType::Deposit, 1, 1, "5"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    });
+}
+
+#[cfg(feature = "websocket")]
+#[test]
+fn websocket_pushes() {
+    use std::net::TcpListener;
+
+    use tungstenite::Message;
+
+    use crate::server::Server;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    // The server runs until the tests end
+    let server: &'static Server = Box::leak(Box::new(Server::new(Accounts::default())));
+    std::thread::spawn(move || server.serve_websocket(listener));
+    let (mut dashboard, _) = tungstenite::connect(&url).unwrap();
+    let (mut ingest, _) = tungstenite::connect(&url).unwrap();
+    let receive = |socket: &mut tungstenite::WebSocket<_>| loop {
+        if let Message::Text(text) = socket.read().unwrap() {
+            break text.to_string();
+        }
+    };
+
+    dashboard
+        .send(Message::text(r#"{"subscribe":[1]}"#))
+        .unwrap();
+    assert_eq!(receive(&mut dashboard), r#"{"status":"ok"}"#);
+    ingest
+        .send(Message::text(
+            "deposit,2,1,3\ndeposit,1,2,5\nwithdrawal,1,3,9",
+        ))
+        .unwrap();
+    assert_eq!(receive(&mut ingest), r#"{"status":"ok"}"#);
+    assert_eq!(receive(&mut ingest), r#"{"status":"ok"}"#);
+    assert_eq!(
+        receive(&mut ingest),
+        r#"{"status":"error","error":"Attempted to withdraw 9.0000 from an account with 5.0000 available"}"#
+    );
+    assert_eq!(
+        receive(&mut dashboard),
+        r#"{"account":{"client":1,"available":"5.0000","held":"0.0000","total":"5.0000","locked":false}}"#
+    );
+    ingest
+        .send(Message::text(r#"{"subscribe":"all"}"#))
+        .unwrap();
+    assert!(receive(&mut ingest).starts_with(r#"{"status":"error","error":"Invalid subscription"#));
+
+    // Messages over the limits are rejected
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let mut server = Server::new(Accounts::default());
+    server.set_limits(crate::server::ConnectionLimits {
+        max_connections: Some(1),
+        max_in_flight: Some(1),
+        max_request_size: Some(100),
+    });
+    let server: &'static Server = Box::leak(Box::new(server));
+    std::thread::spawn(move || server.serve_websocket(listener));
+    let (mut first, _) = tungstenite::connect(&url).unwrap();
+    first
+        .send(Message::text("deposit,1,1,5\ndeposit,1,2,5"))
+        .unwrap();
+    assert_eq!(
+        receive(&mut first),
+        r#"{"status":"error","error":"Too many transactions in one request, at most 1 are allowed"}"#
+    );
+    let (mut second, _) = tungstenite::connect(&url).unwrap();
+    assert_eq!(
+        receive(&mut second),
+        r#"{"status":"error","error":"Too many connections, at most 1 are served at once"}"#
+    );
+    first.send(Message::text("x".repeat(101))).unwrap();
+    assert_eq!(
+        receive(&mut first),
+        r#"{"status":"error","error":"Request is longer than 100 bytes"}"#
+    );
+    server.with_accounts(|accounts| assert_eq!(accounts.iter().count(), 0));
+}
+
+#[cfg(feature = "kafka")]
+#[test]
+fn kafka_messages() {
+    use crate::kafka::parse_message;
+
+    // Each message is parsed on its own, with or without a header
+    let records =
+        parse_message(b"type,client,tx,amount\ndeposit,1,1,5.0\n\nwithdrawal,1,2,2.0").unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1].line_no, 4);
+    let records = parse_message(b"deposit,2,3,1.0\n").unwrap();
+    assert_eq!(records[0].tx.client, 2);
+    let records = parse_message(br#"{"type":"deposit","client":3,"tx":4,"amount":"1"}"#).unwrap();
+    assert_eq!(records[0].tx.client, 3);
+    assert!(parse_message(b"").unwrap().is_empty());
+
+    assert!(parse_message(b"deposit,1,5,\"1.0").is_err());
+    assert!(parse_message(b"deposit,1,5,x").is_err());
+    assert!(parse_message(&[0xff]).is_err());
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_history_spill() {
+    let path = std::env::temp_dir().join(format!("transactor-sled-{}", std::process::id()));
+    check_history_spill(std::sync::Arc::new(
+        crate::history::SledHistoryStore::create(&path).unwrap(),
+    ));
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+/// Check that accounts with history spilled to a store behave like accounts without
+fn check_history_spill(store: std::sync::Arc<dyn HistoryBackend>) {
+    let mut accounts = Accounts::default();
+    accounts.set_history_spill(HistorySpill {
+        store: store.clone(),
+        limit: 2,
+    });
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    for tx in 1..=6 {
+        transact(&format!("deposit,{},{},{}", tx % 2 + 1, tx, tx)).unwrap();
+    }
+    // Older entries are on disk
+    assert!(store.contains(2, 1).unwrap());
+    assert!(!store.contains(1, 1).unwrap());
+    assert!(!store.contains(1, 6).unwrap());
+    // Spilled ids are still used
+    assert!(matches!(
+        transact("deposit,2,1,5"),
+        Err(TransactionError::DuplicateTransactionId(1))
+    ));
+    assert!(transact("transfer,1,3,1,2").is_err());
+    // Spilled entries can be disputed and charged back
+    transact("dispute,2,1").unwrap();
+    assert!(!store.contains(2, 1).unwrap());
+    for tx in 7..=10 {
+        transact(&format!("deposit,1,{},1", tx)).unwrap();
+    }
+    transact("chargeback,2,1").unwrap();
+    transact("dispute,1,2").unwrap();
+    transact("resolve,1,2").unwrap();
+    assert!(transact("dispute,2,1").is_err());
+
+    let mut expected = Accounts::default();
+    for tx in [
+        "deposit,2,1,1",
+        "deposit,1,2,2",
+        "deposit,2,3,3",
+        "deposit,1,4,4",
+        "deposit,2,5,5",
+        "deposit,1,6,6",
+        "dispute,2,1",
+        "deposit,1,7,1",
+        "deposit,1,8,1",
+        "deposit,1,9,1",
+        "deposit,1,10,1",
+        "chargeback,2,1",
+    ] {
+        expected.transact(tx.parse().unwrap()).unwrap();
+    }
+    for (client, account) in expected.iter() {
+        assert_eq!(accounts[client].balance(), account.balance());
+        assert_eq!(accounts[client].held(), account.held());
+        assert_eq!(accounts[client].is_frozen(), account.is_frozen());
+    }
+    // Pages of history include spilled entries, in order of transaction id
+    let pages = |accounts: &Accounts, client| {
+        let (mut tx_ids, mut after) = (Vec::new(), None);
+        loop {
+            let page = accounts.history(client, after, 3).unwrap();
+            tx_ids.extend(page.iter().map(|(tx_id, _)| *tx_id));
+            match page.last() {
+                Some((tx_id, _)) if page.len() == 3 => after = Some(*tx_id),
+                _ => return tx_ids,
+            }
+        }
+    };
+    assert!(store.contains(1, 4).unwrap());
+    assert_eq!(pages(&accounts, 1), [2, 4, 6, 7, 8, 9, 10]);
+    assert_eq!(pages(&accounts, 1), pages(&expected, 1));
+    assert_eq!(pages(&accounts, 2), pages(&expected, 2));
+}
+
+#[test]
+fn history_spill_collision() {
+    let path = std::env::temp_dir().join(format!(
+        "transactor-history-collision-{}",
+        std::process::id()
+    ));
+    let store = std::sync::Arc::new(HistoryStore::create(&path).unwrap());
+    let mut accounts = Accounts::default();
+    accounts.set_history_spill(HistorySpill {
+        store: store.clone(),
+        limit: 1,
+    });
+    let mut transact = |tx: &str| accounts.transact(tx.parse().unwrap());
+    transact("deposit,1,1,10").unwrap();
+    transact("deposit,2,1,20").unwrap();
+    // Client 1's entry has the slot, so client 2's entry waits in memory
+    transact("deposit,1,2,30").unwrap();
+    assert!(store.contains(1, 1).unwrap());
+    assert!(!store.contains(2, 1).unwrap());
+    // Once the slot is free, client 2's entry is spilled
+    transact("dispute,1,1").unwrap();
+    transact("deposit,1,3,40").unwrap();
+    assert!(store.contains(2, 1).unwrap());
+    transact("dispute,2,1").unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(accounts[2].held(), 20.0);
+}
+
+#[test]
+fn activity() {
+    let mut accounts = Accounts::default();
+    process_transaction_source(include_bytes!("../test.csv").as_slice(), &mut accounts).unwrap();
+    // The failed withdrawal with id 5 is not activity
+    let activity = accounts[2].activity().unwrap();
+    assert_eq!((activity.first_tx, activity.last_tx), (2, 4));
+    let activity = accounts[3].activity().unwrap();
+    assert_eq!((activity.first_tx, activity.last_tx), (3, 7));
+
+    let mut output = Vec::new();
+    let mut accounts = Accounts::default();
+    accounts
+        .transact("dispute,1,1".parse().unwrap())
+        .unwrap_err();
+    output::write_extended_accounts(&accounts, OutputFormat::Csv, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked,first_tx,last_tx,pending,first_timestamp,last_timestamp\n\
+         1,0.0000,0.0000,0.0000,false,,,0.0000,,\n"
+    );
+
+    // Timestamps of the first and last activity are reported when present
+    let mut accounts = Accounts::default();
+    for tx in [
+        "deposit,1,1,5",
+        "deposit,1,2,5,,1700000000",
+        "withdrawal,1,3,1,,1700000060",
+        "dispute,1,2,,,1700000120",
+    ] {
+        accounts.transact(tx.parse().unwrap()).unwrap();
+    }
+    let activity = accounts[1].activity().unwrap();
+    assert_eq!((activity.first_tx, activity.last_tx), (1, 3));
+    assert_eq!(
+        (activity.first_timestamp, activity.last_timestamp),
+        (None, Some(1_700_000_060))
+    );
+    let mut saved = Vec::new();
+    accounts[1].save(&mut saved).unwrap();
+    let loaded = Account::load(saved.as_slice()).unwrap();
+    assert_eq!(loaded.activity(), accounts[1].activity());
+    let mut output = Vec::new();
+    output::write_extended_accounts(&accounts, OutputFormat::Csv, &mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .ends_with(",1,3,0.0000,,1700000060\n"));
+}
+
+#[test]
+fn deterministic_output() {
+    // Transactions for different clients are independent, so the same per-client
+    // transactions in any interleaving must give the same report
+    let input = std::str::from_utf8(include_bytes!("../test.csv")).unwrap();
+    let mut lines: Vec<_> = input.lines().skip(1).collect();
+    let report = |lines: &[&str]| {
+        let mut accounts = Accounts::default();
+        process_transaction_source(lines.join("\n").as_bytes(), &mut accounts).unwrap();
+        let mut output = Vec::new();
+        output::write_accounts(&accounts, OutputFormat::Csv, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    let expected = report(&lines);
+    assert_eq!(
+        expected,
+        "client,available,held,total,locked\n\
+        1,18.3000,0.0000,18.3000,false\n\
+        2,10.1235,0.0000,10.1235,false\n\
+        3,70.0000,0.0000,70.0000,true\n\
+        4,100.0000,20.6000,120.6000,false\n"
+    );
+    let client = |line: &&str| line.split(',').nth(1).unwrap().trim().to_string();
+    lines.sort_by_key(|line| std::cmp::Reverse(client(line)));
+    assert_eq!(report(&lines), expected);
+
+    // Saved account state does not depend on hash order either
+    let mut account = Account::default();
+    for tx_id in (0..50).rev() {
+        account
+            .transact(Transaction::deposit(tx_id, Amount::from_f64(1.0).unwrap()))
+            .unwrap();
+        account.transact(Transaction::dispute(tx_id)).unwrap();
+    }
+    let mut saved = Vec::new();
+    account.save(&mut saved).unwrap();
+    let mut resaved = Vec::new();
+    Account::load(saved.as_slice())
+        .unwrap()
+        .save(&mut resaved)
+        .unwrap();
+    assert_eq!(saved, resaved);
+    assert!(String::from_utf8(saved)
+        .unwrap()
+        .lines()
+        .nth(1)
+        .unwrap()
+        .starts_with("history,0,"));
+}
+
+#[test]
+fn chargeback_cross_check() {
+    let input = "type,client,tx,amount
+        deposit,1,1,10
+        deposit,1,2,5
+        deposit,2,3,7
+        deposit,2,4,3
+        dispute,1,1,
+        chargeback,1,1,
+        dispute,1,2,
+        resolve,1,2,
+        dispute,2,3,
+        dispute,2,4,
+        chargeback,2,4,
+        resolve,2,9,";
+    let report = "client,tx,amount,reason
+        1,1,10,fraud
+        1,2,5,fraud
+        3,9,1,fraud
+        1,1,10,fraud";
+    let mut ledger = DisputeLedger::default();
+    process_transaction_source_with(
+        input.as_bytes(),
+        &mut Accounts::default(),
+        |record, result, _| ledger.observe(&record.tx, &result),
+    )
+    .unwrap();
+    let report = reconcile::read_chargebacks(report.as_bytes()).unwrap();
+    let check = reconcile::cross_check(&ledger, &report);
+    assert_eq!(check.matched, 1);
+    let mut output = Vec::new();
+    check.write_csv(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "discrepancy,client,tx,status
+unrecorded_chargeback,1,2,resolved
+unacknowledged_dispute,2,3,open
+unacknowledged_dispute,2,4,charged_back
+unrecorded_chargeback,3,9,none
+"
+    );
+    assert!(reconcile::read_chargebacks("client\n1\n".as_bytes()).is_err());
+}
+
+#[test]
+fn trend() {
+    let dir = std::env::temp_dir().join(format!("transactor-trend-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let reports = [
+        (
+            "2024-03-02.json",
+            r#"[{"client":5,"available":"7.5","held":"0","total":"7.5","locked":false}]"#,
+        ),
+        (
+            "2024-03-01.csv",
+            "client,available,held,total,locked\n5,10.0000,0.0000,10.0000,false\n",
+        ),
+        (
+            "2024-03-03.jsonl",
+            r#"{"client":6,"available":"1","held":"0","total":"1","locked":false}"#,
+        ),
+        (
+            "2024-03-04.csv",
+            "client,available,held,total,locked,first_tx,last_tx\n5,2.5,5,7.5,true,1,9\n",
+        ),
+    ];
+    for (name, report) in reports {
+        std::fs::write(dir.join(name), report).unwrap();
+    }
+    let points = trend::trend(&dir, 5).unwrap();
+    let mut output = Vec::new();
+    trend::write_trend(&points, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "run,available,held,total,locked,change
+2024-03-01,10.0000,0.0000,10.0000,false,
+2024-03-02,7.5000,0.0000,7.5000,false,-2.5000
+2024-03-04,2.5000,5.0000,7.5000,true,0.0000
+"
+    );
+    std::fs::write(dir.join("2024-03-05.csv"), "client,available\n5,1\n").unwrap();
+    assert!(trend::trend(&dir, 5).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_vectors() {
+    use crate::vectors::{self, VECTORS};
+
+    for vector in VECTORS {
+        let output = vectors::run(vector).unwrap();
+        assert_eq!(output, vector.expected, "{}", vector.name);
+    }
+    // Other implementations may order accounts and format amounts differently
+    let expected = "client,available,held,total,locked\n1,5.5000,0.0000,5.5000,false\n2,1.0000,0.0000,1.0000,false\n";
+    vectors::compare(
+        expected,
+        "client,locked,available,held,total\n2,false,1,0,1\n1,false,5.50,0,5.5\n",
+    )
+    .unwrap();
+    let err = vectors::compare(
+        expected,
+        "client,available,held,total,locked\n1,5.5,0,5.5,true\n",
+    );
+    assert_eq!(
+        err.unwrap_err(),
+        "Client 1 has available 5.5000, held 0.0000, total 5.5000, locked true, \
+        expected available 5.5000, held 0.0000, total 5.5000, locked false"
+    );
+    vectors::compare(
+        expected,
+        "client,available,held,total,locked\n1,5.5,0,5.5,false\n",
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn structured_logging() {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::logging::LogFormat;
+
+    /// A writer that appends to a shared buffer
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert_eq!(LogFormat::default().to_string(), "text");
+    assert!("xml".parse::<LogFormat>().is_err());
+
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(move || writer.clone())
+        .finish();
+    let input = "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,10\n";
+    tracing::subscriber::with_default(subscriber, || {
+        process_transaction_source(input.as_bytes(), &mut Accounts::default()).unwrap();
+    });
+    let logged = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = logged.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", logged);
+    for expected in [
+        "\"level\":\"WARN\"",
+        "\"line\":3,\"client\":1,\"tx\":2,\"reason\":\"insufficient_funds\"",
+        "\"span\":{\"client\":1,\"line\":3,\"tx\":2,\"name\":\"transaction\"}",
+    ] {
+        assert!(
+            lines[0].contains(expected),
+            "{:?} not in {}",
+            expected,
+            lines[0]
+        );
+    }
+}
+
+#[test]
+fn accrual() {
+    use crate::accrual::{AccrualPolicy, FundsTreatment};
+
+    let input = "\
+type,client,tx,amount
+deposit,1,1,100
+deposit,1,2,50
+dispute,1,2
+deposit,2,3,200
+deposit,3,5,80
+dispute,3,5
+chargeback,3,5
+deposit,4,6,10
+";
+    let mut accounts = Accounts::default();
+    process_transaction_source(input.as_bytes(), &mut accounts).unwrap();
+    accounts.close(4).unwrap();
+
+    assert_eq!(
+        "penalty:0.01".parse::<FundsTreatment>().unwrap(),
+        FundsTreatment::Penalty("0.01".parse().unwrap())
+    );
+    assert!("penalty:-0.01".parse::<FundsTreatment>().is_err());
+    assert!("bonus".parse::<FundsTreatment>().is_err());
+
+    let policy = AccrualPolicy {
+        interest_rate: "0.05".parse().unwrap(),
+        held: "penalty:0.01".parse().unwrap(),
+        frozen: FundsTreatment::Excluded,
+    };
+    let accrued = accounts
+        .accrue(|_, account| policy.accrual(account))
+        .unwrap();
+    assert_eq!(
+        accrued,
+        [(1, "4.5".parse().unwrap()), (2, "10".parse().unwrap())]
+    );
+    assert_eq!(accounts[1].balance(), 104.5);
+    assert_eq!(accounts[1].held(), 50.0);
+    assert_eq!(accounts[2].balance(), 210.0);
+    assert_eq!(accounts[3].total(), 0.0);
+    assert_eq!(accounts[4].total(), 10.0);
+
+    // Accruals are rounded to the accounts' precision
+    let accrued = accounts
+        .accrue(|_, account| {
+            account
+                .balance()
+                .checked_mul("0.00001".parse().unwrap())
+                .ok_or(TransactionError::Overflow)
+        })
+        .unwrap();
+    assert_eq!(
+        accrued,
+        [
+            (1, "0.001".parse().unwrap()),
+            (2, "0.0021".parse().unwrap())
+        ]
+    );
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_input() {
+    use std::{fs::File, sync::Arc};
+
+    use ::parquet::arrow::ArrowWriter;
+    use arrow_array::{
+        ArrayRef, Decimal128Array, RecordBatch, StringArray, UInt16Array, UInt32Array,
+    };
+
+    use crate::{parquet::parquet_records, try_process_records_with};
+
+    let path =
+        std::env::temp_dir().join(format!("transactor-parquet-{}.parquet", std::process::id()));
+    let amounts = Decimal128Array::from(vec![Some(5_0000), Some(2_5000), None, Some(1_0000)])
+        .with_precision_and_scale(10, 4)
+        .unwrap();
+    let batch = RecordBatch::try_from_iter([
+        (
+            "type",
+            Arc::new(StringArray::from(vec![
+                "deposit",
+                "withdrawal",
+                "dispute",
+                "transfer",
+            ])) as ArrayRef,
+        ),
+        ("client", Arc::new(UInt16Array::from(vec![1, 1, 1, 1]))),
+        ("tx", Arc::new(UInt32Array::from(vec![1, 2, 1, 3]))),
+        ("amount", Arc::new(amounts)),
+        (
+            "to",
+            Arc::new(UInt16Array::from(vec![None, None, None, Some(2)])),
+        ),
+    ])
+    .unwrap();
+    let mut writer =
+        ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let mut accounts = Accounts::default();
+    let mut lines = Vec::new();
+    try_process_records_with(
+        parquet_records(File::open(&path).unwrap()).unwrap(),
+        &mut accounts,
+        |record, result, _| {
+            lines.push((record.line_no, record.line.clone(), result.is_ok()));
+            Ok(())
+        },
+    )
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(lines[0], (1, "deposit,1,1,5.0000,".into(), true));
+    assert_eq!(lines[2], (3, "dispute,1,1,,".into(), true));
+    // The deposit is held by the dispute, so the transfer has no funds
+    assert!(!lines[3].2);
+    assert_eq!(accounts[1].held(), 5.0);
+    assert_eq!(accounts[1].balance(), -2.5);
+}
+
+#[test]
+fn settlement_period() {
+    let mut accounts = Accounts::default();
+    accounts.set_settlement_period(SettlementPeriod {
+        transactions: Some(10),
+        ..SettlementPeriod::default()
+    });
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("deposit,1,1,100").unwrap();
+    // Pending funds cannot be withdrawn
+    assert!(matches!(
+        transact("withdrawal,1,2,50"),
+        Err(TransactionError::InsufficientFunds { .. })
+    ));
+    // Rejected transactions and ids out of sequence do not settle deposits
+    transact("withdrawal,2,1000,1").unwrap_err();
+    transact("deposit,2,1001,1").unwrap();
+    transact("deposit,1,3,20").unwrap();
+    transact("dispute,1,3").unwrap();
+    assert_eq!(accounts[1].pending(), 100.0);
+    assert_eq!(accounts[1].held(), 20.0);
+    assert_eq!(accounts[1].total(), 120.0);
+
+    // Any client's transaction moves the clock
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("deposit,2,11,1").unwrap();
+    transact("withdrawal,1,12,50").unwrap();
+    assert_eq!(accounts[1].balance(), 50.0);
+    assert_eq!(accounts[1].pending(), 0.0);
+
+    // A resolved deposit that has not settled is pending again
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("resolve,1,3").unwrap();
+    assert_eq!(accounts[1].pending(), 20.0);
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("deposit,2,13,1").unwrap();
+    transact("withdrawal,1,14,70").unwrap();
+    assert_eq!(accounts[1].total(), 0.0);
+
+    // Pending deposits are saved in snapshots and settle after loading
+    let path = std::env::temp_dir().join(format!(
+        "transactor-settlement-{}.snapshot",
+        std::process::id()
+    ));
+    accounts
+        .transact("deposit,3,20,5".parse().unwrap())
+        .unwrap();
+    accounts.save_snapshot(&path).unwrap();
+    let mut loaded = Accounts::default();
+    loaded.set_settlement_period(accounts.settlement_period());
+    loaded.load_snapshot(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded[3].pending(), 5.0);
+    let mut transact = |line: &str| loaded.transact(line.parse().unwrap());
+    transact("withdrawal,3,29,5").unwrap_err();
+    transact("deposit,4,30,1").unwrap();
+    transact("withdrawal,3,31,5").unwrap();
+    assert_eq!(loaded[3].total(), 0.0);
+}
+
+#[test]
+fn settlement_clock() {
+    use std::sync::Arc;
+
+    use crate::clock::TestClock;
+
+    const DAY: u64 = 24 * 60 * 60;
+    let clock = TestClock::new(DAY);
+    let mut accounts = Accounts::default();
+    accounts.set_clock(Some(Arc::new(clock.clone())));
+    accounts.set_settlement_period(SettlementPeriod {
+        time: Some(2 * DAY),
+        transactions: Some(10),
+    });
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("deposit,1,1,100").unwrap();
+    // Stamped deposits do not settle by transaction ids
+    transact("deposit,2,50,1").unwrap();
+    clock.advance(2 * DAY - 1);
+    transact("deposit,2,51,1").unwrap();
+    assert_eq!(accounts[1].pending(), 100.0);
+    // Rejected transactions do not settle deposits
+    clock.advance(1);
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("withdrawal,1,52,100").unwrap_err();
+    assert_eq!(accounts[1].pending(), 100.0);
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("deposit,2,53,1").unwrap();
+    assert_eq!(accounts[1].pending(), 0.0);
+    let mut transact = |line: &str| accounts.transact(line.parse().unwrap());
+    transact("withdrawal,1,54,100").unwrap();
+    // Timestamps in the input take precedence over the clock
+    transact(&format!("deposit,1,55,10,,{}", 5 * DAY)).unwrap();
+    transact("deposit,2,56,1").unwrap();
+    assert_eq!(accounts[1].pending(), 10.0);
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow_io() {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, ArrayRef, RecordBatch, StringArray, UInt16Array};
+    use arrow_ipc::{reader::StreamReader, writer::StreamWriter};
+
+    use crate::{arrow::arrow_stream_records, try_process_records_with};
+
+    let batch = RecordBatch::try_from_iter([
+        (
+            "type",
+            Arc::new(StringArray::from(vec!["deposit", "deposit", "withdrawal"])) as ArrayRef,
+        ),
+        ("client", Arc::new(UInt16Array::from(vec![1, 2, 1]))),
+        ("tx", Arc::new(UInt16Array::from(vec![1, 2, 3]))),
+        (
+            "amount",
+            Arc::new(StringArray::from(vec!["10.5", "3", "20"])),
+        ),
+    ])
+    .unwrap();
+    let mut input = Vec::new();
+    let mut writer = StreamWriter::try_new(&mut input, &batch.schema()).unwrap();
+    writer.write(&batch).unwrap();
+    writer.finish().unwrap();
+    drop(writer);
+
+    let mut accounts = Accounts::default();
+    let mut results = Vec::new();
+    try_process_records_with(
+        arrow_stream_records(input.as_slice()).unwrap(),
+        &mut accounts,
+        |record, result, _| {
+            results.push((record.line_no, result.is_ok()));
+            Ok(())
+        },
+    )
+    .unwrap();
+    assert_eq!(results, [(1, true), (2, true), (3, false)]);
+
+    let mut output = Vec::new();
+    output::write_extended_accounts(&accounts, OutputFormat::Arrow, &mut output).unwrap();
+    let batches: Vec<_> = StreamReader::try_new(output.as_slice(), None)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    assert_eq!(batch.num_rows(), 2);
+    let available = batch
+        .column_by_name("available")
+        .unwrap()
+        .as_primitive::<arrow_array::types::Decimal128Type>();
+    // Amounts are exact, with the accounts' 4 decimal places
+    assert_eq!(available.value(0), 10_5000);
+    assert_eq!(available.scale(), 4);
+    assert_eq!(batch.column_by_name("first_tx").unwrap().null_count(), 0);
+}
+
+#[cfg(feature = "avro")]
+#[test]
+fn avro_input() {
+    use avro_schema::{
+        file::{Block, CompressedBlock},
+        schema::{Field, Record as AvroRecord, Schema},
+        write::{self, encode::zigzag_encode},
+    };
+
+    use crate::{avro::avro_records, try_process_records_with};
+
+    // The type is a plain string and there is no destination field
+    let schema = AvroRecord::new(
+        "Transaction",
+        vec![
+            Field::new("type", Schema::String(None)),
+            Field::new("client", Schema::Int(None)),
+            Field::new("tx", Schema::Long(None)),
+            Field::new(
+                "amount",
+                Schema::Union(vec![Schema::Null, Schema::String(None)]),
+            ),
+        ],
+    );
+    let rows: [(&str, i64, i64, Option<&str>); 3] = [
+        ("deposit", 1, 1, Some("5.25")),
+        ("dispute", 1, 1, None),
+        ("refund", 1, 2, Some("1")),
+    ];
+    let mut data = Vec::new();
+    let string = |s: &str, data: &mut Vec<u8>| {
+        zigzag_encode(s.len() as i64, data).unwrap();
+        data.extend(s.as_bytes());
+    };
+    for (ty, client, tx, amount) in rows {
+        string(ty, &mut data);
+        zigzag_encode(client, &mut data).unwrap();
+        zigzag_encode(tx, &mut data).unwrap();
+        match amount {
+            Some(amount) => {
+                zigzag_encode(1, &mut data).unwrap();
+                string(amount, &mut data);
+            }
+            None => zigzag_encode(0, &mut data).unwrap(),
+        }
+    }
+    let mut file = Vec::new();
+    write::write_metadata(&mut file, schema, None).unwrap();
+    let mut block = Block::new(rows.len(), data);
+    let mut compressed = CompressedBlock::default();
+    write::compress(&mut block, &mut compressed, None).unwrap();
+    write::write_block(&mut file, &compressed).unwrap();
+
+    let records: Vec<_> = avro_records(file.as_slice()).unwrap().collect();
+    assert_eq!(records.len(), 3);
+    // The unknown transaction type does not match the bundled schema
+    assert_eq!(
+        records[2].as_ref().unwrap_err(),
+        "Invalid transaction on row 3: Record does not match the transaction schema"
+    );
+
+    let mut accounts = Accounts::default();
+    let mut lines = Vec::new();
+    let result = try_process_records_with(records, &mut accounts, |record, result, _| {
+        lines.push((record.line_no, record.line.clone(), result.is_ok()));
+        Ok(())
+    });
+    assert!(result.is_err());
+    assert_eq!(lines[0], (1, "deposit,1,1,5.25,".into(), true));
+    assert_eq!(lines[1], (2, "dispute,1,1,,".into(), true));
+    assert_eq!(accounts[1].held(), 5.25);
+}
+
+#[test]
+fn state_diff() {
+    use crate::diff::{diff_snapshots, StateDifference, DEFAULT_IGNORED};
+
+    let snapshot = |lines: &[&str]| {
+        let mut accounts = Accounts::default();
+        for line in lines {
+            let _ = accounts.transact(line.parse().unwrap());
+        }
+        let mut snapshot = Vec::new();
+        accounts.write_snapshot(&mut snapshot).unwrap();
+        String::from_utf8(snapshot).unwrap()
+    };
+    let ignored: Vec<String> = DEFAULT_IGNORED.iter().map(|s| s.to_string()).collect();
+    let blue = snapshot(&["deposit,1,1,5", "deposit,2,2,3", "dispute,2,2"]);
+    let green = snapshot(&["deposit,1,1,5", "deposit,2,2,3", "dispute,2,2"]);
+    assert_eq!(
+        diff_snapshots(blue.as_bytes(), green.as_bytes(), &ignored),
+        Ok(Vec::new())
+    );
+
+    // The new deployment missed the last transactions
+    let green = snapshot(&["deposit,1,1,5", "deposit,2,2,3", "deposit,3,3,1"]);
+    let differences = diff_snapshots(blue.as_bytes(), green.as_bytes(), &ignored).unwrap();
+    let difference =
+        |client, field: &str, left: Option<&str>, right: Option<&str>| StateDifference {
+            client,
+            field: field.into(),
+            left: left.map(Into::into),
+            right: right.map(Into::into),
+        };
+    assert!(differences.contains(&difference(2, "balance", Some("0.0000"), Some("3.0000"))));
+    assert!(differences.contains(&difference(2, "disputed[2]", Some("true"), None)));
+    assert!(differences.contains(&difference(3, "history[3].amount", None, Some("1.0000"))));
+    assert!(differences.iter().all(|difference| difference.client != 1));
+
+    // Note timestamps are ignored by default
+    let blue = "snapshot,1\nclient,1\naccount,1,0,false,false\nnote,100,ops,ref,text\n";
+    let green = "snapshot,1\nclient,1\naccount,1,0,false,false\nnote,200,ops,ref,text\n";
+    assert_eq!(
+        diff_snapshots(blue.as_bytes(), green.as_bytes(), &ignored),
+        Ok(Vec::new())
+    );
+    assert_eq!(
+        diff_snapshots(blue.as_bytes(), green.as_bytes(), &[]),
+        Ok(vec![difference(
+            1,
+            "notes[0].timestamp",
+            Some("100"),
+            Some("200")
+        )])
+    );
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_input() {
+    use std::io::{Read, Write};
+
+    use crate::input::decompressed;
+
+    let input = "type,client,tx,amount\ndeposit,1,1,5\nwithdrawal,1,2,2\n";
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(input.as_bytes()).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let zstd = zstd::encode_all(input.as_bytes(), 0).unwrap();
+    for compressed in [gzip, zstd, input.as_bytes().to_vec()] {
+        let mut decoded = String::new();
+        decompressed(compressed.as_slice())
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, input);
+        let mut accounts = Accounts::default();
+        process_transaction_source(decompressed(compressed.as_slice()).unwrap(), &mut accounts)
+            .unwrap();
+        assert_eq!(accounts[1].total(), 3.0);
+    }
+}
+
+/// Check every sequence of a few transactions on one account against a model of its states
+///
+/// After every step, the engine must accept or reject the transaction as the model does, for
+/// the same reason, and the balances must match. A rejected transaction must not change the
+/// account at all.
+#[test]
+fn dispute_state_machine() {
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Status {
+        Open,
+        Disputed,
+        Reversed,
+    }
+
+    /// The expected state of the account
+    #[derive(Debug, Default)]
+    struct Model {
+        available: i64,
+        held: i64,
+        pending_credit: i64,
+        frozen: bool,
+        /// Whether each transaction is a deposit, its amount, and its status
+        txs: HashMap<u32, (bool, i64, Status)>,
+    }
+
+    impl Model {
+        /// Apply a transaction, or get the code of the reason it is rejected
+        fn apply(&mut self, line: &str) -> Result<(), &'static str> {
+            let fields: Vec<&str> = line.split(',').collect();
+            let tx: u32 = fields[2].parse().unwrap();
+            let amount: i64 = fields.get(3).map_or(0, |amount| amount.parse().unwrap());
+            let known = self.txs.get(&tx).copied();
+            match fields[0] {
+                "deposit" | "withdrawal" if known.is_some() => {
+                    return Err("duplicate_transaction_id")
+                }
+                "deposit" => {
+                    self.available += amount;
+                    self.txs.insert(tx, (true, amount, Status::Open));
+                }
+                "withdrawal" if self.frozen => return Err("account_frozen"),
+                "withdrawal" if self.available < amount => return Err("insufficient_funds"),
+                "withdrawal" => {
+                    self.available -= amount;
+                    self.txs.insert(tx, (false, amount, Status::Open));
+                }
+                "dispute" => match known {
+                    Some((_, _, Status::Disputed)) => return Err("already_disputed"),
+                    Some((deposit, amount, Status::Open)) => {
+                        if deposit {
+                            self.available -= amount;
+                            self.held += amount;
+                        } else {
+                            self.pending_credit += amount;
+                        }
+                        self.txs.insert(tx, (deposit, amount, Status::Disputed));
+                    }
+                    _ => return Err("invalid_dispute"),
+                },
+                kind => {
+                    let Some((deposit, amount, Status::Disputed)) = known else {
+                        return Err("undisputed_resolution");
+                    };
+                    let status = match (deposit, kind) {
+                        (true, "resolve") => {
+                            self.available += amount;
+                            self.held -= amount;
+                            Status::Open
+                        }
+                        (true, _) => {
+                            self.held -= amount;
+                            self.frozen = true;
+                            Status::Reversed
+                        }
+                        (false, "resolve") => {
+                            self.available += amount;
+                            self.pending_credit -= amount;
+                            Status::Reversed
+                        }
+                        (false, _) => {
+                            self.pending_credit -= amount;
+                            Status::Reversed
+                        }
+                    };
+                    self.txs.insert(tx, (deposit, amount, status));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    const STEPS: [&str; 10] = [
+        "deposit,1,1,10",
+        "deposit,1,2,4",
+        "withdrawal,1,3,12",
+        "withdrawal,1,4,2",
+        "dispute,1,1",
+        "resolve,1,1",
+        "chargeback,1,1",
+        "dispute,1,3",
+        "resolve,1,3",
+        "chargeback,1,3",
+    ];
+    const DEPTH: u32 = 5;
+
+    // An account that does not exist yet is in the same state as an empty one
+    let state = |accounts: &Accounts| {
+        let mut state = Vec::new();
+        let account = accounts.get(1).cloned().unwrap_or_default();
+        account.save(&mut state).unwrap();
+        String::from_utf8(state).unwrap()
+    };
+    for sequence in 0..STEPS.len().pow(DEPTH) {
+        let mut accounts = Accounts::default();
+        let mut model = Model::default();
+        let mut applied = Vec::new();
+        let mut rest = sequence;
+        for _ in 0..DEPTH {
+            let line = STEPS[rest % STEPS.len()];
+            rest /= STEPS.len();
+            applied.push(line);
+
+            let before = state(&accounts);
+            let result = accounts.transact(line.parse().unwrap());
+            let expected = model.apply(line);
+            assert_eq!(
+                result.as_ref().map(|_| ()).map_err(|e| e.code()),
+                expected,
+                "after {:?}",
+                applied
+            );
+            if result.is_err() {
+                assert_eq!(state(&accounts), before, "rejected {:?}", applied);
+            }
+            let Some(account) = accounts.get(1) else {
+                assert_eq!(model.available, 0);
+                continue;
+            };
+            assert_eq!(account.balance(), model.available as f64, "{:?}", applied);
+            assert_eq!(account.held(), model.held as f64, "{:?}", applied);
+            assert_eq!(
+                account.pending_credit(),
+                model.pending_credit as f64,
+                "{:?}",
+                applied
+            );
+            assert_eq!(account.is_frozen(), model.frozen, "{:?}", applied);
+            assert_eq!(account.total(), account.balance() + account.held());
+            assert!(account.held() >= 0.0 && account.pending_credit() >= 0.0);
+        }
+    }
+}
+
+#[test]
+fn client_map() {
+    let mut clients = ClientMap::read("reference,client\nACME-042,7\n".as_bytes()).unwrap();
+    let input = "\
+type,client,tx,amount,to
+deposit,ACME-042,1,10,
+deposit,beta_co,2,5,
+transfer,ACME-042,3,4,beta_co
+deposit,bad reference,4,1,
+";
+    // Processing stops at the invalid reference
+    let mut accounts = Accounts::default();
+    let result = try_process_records_with(
+        input::records_with_clients(input.as_bytes(), &mut clients),
+        &mut accounts,
+        |record, result, _| result.map_err(|e| ProcessError::rejected(record, e)),
+    );
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Invalid transaction on line 5: Invalid client reference \"bad reference\""
+    );
+
+    // New references are assigned the next free id
+    assert_eq!(clients.get("ACME-042"), Some(7));
+    assert_eq!(clients.get("beta_co"), Some(8));
+    assert_eq!(clients.reference(8), Some("beta_co"));
+    assert_eq!(accounts[7].total(), 6.0);
+    assert_eq!(accounts[8].total(), 9.0);
+
+    // Output has the references, and the map can be read back
+    let mut output = Vec::new();
+    output::write_accounts_with_clients(
+        &accounts,
+        None,
+        OutputFormat::Csv,
+        false,
+        &clients,
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n\
+         ACME-042,6.0000,0.0000,6.0000,false\n\
+         beta_co,9.0000,0.0000,9.0000,false\n"
+    );
+    let mut saved = Vec::new();
+    clients.write(&mut saved).unwrap();
+    assert_eq!(ClientMap::read(saved.as_slice()).unwrap(), clients);
+
+    // Every reference and id may only be mapped once
+    assert!(ClientMap::read("reference,client\na,1\nb,1\n".as_bytes()).is_err());
+    assert!(ClientMap::read("reference,client\na,1\na,2\n".as_bytes()).is_err());
+}
+
+#[test]
+fn timestamps() {
+    let input = "\
+type,client,tx,amount,to,timestamp
+deposit,1,1,5,,1700000000
+withdrawal,1,2,1,,1700000060
+dispute,1,1
+deposit,1,3,1,,soon";
+    let records: Vec<_> = crate::records(input.as_bytes()).collect();
+    assert!(records[3]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("Invalid timestamp \"soon\""));
+    let txs: Vec<ClientTransaction> = records
+        .into_iter()
+        .take(3)
+        .map(|record| record.unwrap().tx)
+        .collect();
+    let json = r#"{"type":"withdrawal","client":1,"tx":2,"amount":"1","timestamp":1700000060}"#;
+    let record = crate::records(json.as_bytes()).next().unwrap().unwrap();
+    assert_eq!(record.tx.timestamp, Some(1_700_000_060));
+    assert_eq!(txs[0].timestamp, Some(1_700_000_000));
+    assert_eq!(txs[1].timestamp, Some(1_700_000_060));
+    assert_eq!(txs[2].timestamp, None);
+
+    // Transactions are written back out with their timestamps
+    assert_eq!(txs[0].to_string(), "deposit,1,1,5.0000,,1700000000");
+    let dispute = ClientTransaction {
+        timestamp: Some(1_700_000_120),
+        ..txs[2].clone()
+    };
+    assert_eq!(dispute.to_string(), "dispute,1,1,,,1700000120");
+    assert_eq!(
+        "dispute,1,1,,,1700000120"
+            .parse::<ClientTransaction>()
+            .unwrap()
+            .timestamp,
+        Some(1_700_000_120)
+    );
+
+    // Timestamps are kept in the history, including on disk
+    let mut accounts = Accounts::default();
+    let path = std::env::temp_dir().join(format!("transactor-timestamps-{}", std::process::id()));
+    accounts.set_history_spill(HistorySpill {
+        store: std::sync::Arc::new(HistoryStore::create(&path).unwrap()),
+        limit: 1,
+    });
+    for tx in &txs[..2] {
+        accounts.transact(tx.clone()).unwrap();
+    }
+    let mut saved = Vec::new();
+    accounts[1].save(&mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap();
+    assert!(saved.contains("history,2,withdrawal,1.0000,1700000060\n"));
+    // The dispute moves the spilled deposit back into memory
+    accounts.transact(txs[2].clone()).unwrap();
+    let mut saved = Vec::new();
+    accounts[1].save(&mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap();
+    assert!(saved.contains("history,1,deposit,5.0000,1700000000\n"));
+    let loaded = Account::load(saved.as_bytes()).unwrap();
+    let mut resaved = Vec::new();
+    loaded.save(&mut resaved).unwrap();
+    assert_eq!(String::from_utf8(resaved).unwrap(), saved);
+    std::fs::remove_file(path).unwrap();
+}
@@ -1,62 +1,113 @@
 //! Transaction types
 
-use std::{error::Error, fmt, str::FromStr};
+use std::{fmt, str::FromStr};
 
-use crate::amount::Amount;
+use thiserror::Error;
+
+use crate::amount::{Amount, ParseAmountError, Precision};
 
 pub type ClientId = u16;
 pub type TransactionId = u32;
+/// A time in seconds since the Unix epoch
+pub type Timestamp = u64;
 
-/// A client-specific transaction to be executed on [`Accounts`]
+/// A client-specific transaction to be executed on [`Accounts`](crate::Accounts)
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientTransaction {
     pub client: ClientId,
     pub tx: Transaction,
+    /// When the transaction was made, if the input says
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timestamp: Option<Timestamp>,
 }
 
-/// A transaction type for a standard deposit or withdrawal
+/// A transaction type for a standard deposit or withdrawal, or a fee
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChangeKind {
     Deposit,
     Withdrawal,
+    /// A fee charged to the account, which may overdraw it by up to
+    /// [`Accounts::fee_overdraft`](crate::Accounts::fee_overdraft)
+    Fee,
 }
 
 /// A change to a balance
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BalanceChange {
     pub kind: ChangeKind,
     pub amount: Amount,
+    /// When the change was made, if known
+    ///
+    /// [`Accounts`](crate::Accounts) record the timestamp of the [`ClientTransaction`] that
+    /// made the change in the account's history.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timestamp: Option<Timestamp>,
 }
 
 /// A transaction type for resolving disputes
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ResolutionKind {
-    /// Resolve a dispute by reverting it. Funds held by the dispute become available again.
-    ///
-    /// Does nothing if the referenced transaction id does not exist or is not a deposit
+    /// Resolve a dispute by reverting it. Funds held by a disputed deposit become available
+    /// again, and the funds of a disputed withdrawal are returned.
     Resolve,
-    /// Charge back a disputed amount. Funds held by the dispute are removed.
-    ///
-    /// Does nothing if the referenced transaction id does not exist or is not a deposit
+    /// Charge back a disputed amount. Funds held by a disputed deposit are removed and the
+    /// account is frozen. A disputed withdrawal becomes final.
     Chargeback,
 }
 
-/// A transaction to be executed on an [`Account`]
+/// A transaction to be executed on an [`Account`](crate::Account)
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Transaction {
     /// A deposit or withdrawal into an account
     Change {
         tx_id: TransactionId,
         change: BalanceChange,
     },
-    /// Initiate a dispute on some transaction. Disputed funds go into holding.
+    /// Initiate a dispute on some transaction
     ///
-    /// Currently, only deposits can be disputed
-    Dispute(TransactionId),
+    /// Funds of a disputed deposit go into holding. Funds of a disputed withdrawal become
+    /// a pending credit.
+    Dispute {
+        tx_id: TransactionId,
+        /// The amount to dispute, if only part of the transaction is disputed
+        ///
+        /// By default, all of the transaction that has not been reversed is disputed.
+        #[cfg_attr(feature = "serde", serde(default))]
+        amount: Option<Amount>,
+    },
     /// A resolution to a dispute
     Resolution {
         kind: ResolutionKind,
         tx_id: TransactionId,
+        /// The amount to charge back, if only part of the disputed amount is charged back
+        ///
+        /// The rest of the dispute is resolved. Resolutions always resolve the whole dispute.
+        #[cfg_attr(feature = "serde", serde(default))]
+        amount: Option<Amount>,
+    },
+    /// Unfreeze the client's account, such as after a chargeback has been investigated
+    ///
+    /// This is an admin transaction, so [`Accounts`](crate::Accounts) only accept it when
+    /// admin transactions are enabled.
+    Unfreeze(TransactionId),
+    /// Reverse the chargebacks of a deposit, such as when the merchant's representment is
+    /// accepted
+    ///
+    /// The charged back funds are credited to the account again. Each chargeback can only be
+    /// reversed once.
+    Representment(TransactionId),
+    /// A transfer of funds from the submitting client's account to another client's account
+    ///
+    /// Transfers happen within the engine, so they cannot be disputed.
+    Transfer {
+        tx_id: TransactionId,
+        to: ClientId,
+        amount: Amount,
     },
 }
 
@@ -64,7 +115,11 @@ impl Transaction {
     pub const fn change(tx_id: TransactionId, kind: ChangeKind, amount: Amount) -> Transaction {
         Transaction::Change {
             tx_id,
-            change: BalanceChange { kind, amount },
+            change: BalanceChange {
+                kind,
+                amount,
+                timestamp: None,
+            },
         }
     }
     pub const fn deposit(tx_id: TransactionId, amount: Amount) -> Transaction {
@@ -73,82 +128,283 @@ impl Transaction {
     pub const fn withdrawal(tx_id: TransactionId, amount: Amount) -> Transaction {
         Transaction::change(tx_id, ChangeKind::Withdrawal, amount)
     }
+    pub const fn fee(tx_id: TransactionId, amount: Amount) -> Transaction {
+        Transaction::change(tx_id, ChangeKind::Fee, amount)
+    }
+    pub const fn dispute(tx_id: TransactionId) -> Transaction {
+        Transaction::Dispute {
+            tx_id,
+            amount: None,
+        }
+    }
     pub const fn resolution(kind: ResolutionKind, tx_id: TransactionId) -> Transaction {
-        Transaction::Resolution { kind, tx_id }
+        Transaction::Resolution {
+            kind,
+            tx_id,
+            amount: None,
+        }
+    }
+    pub const fn transfer(tx_id: TransactionId, to: ClientId, amount: Amount) -> Transaction {
+        Transaction::Transfer { tx_id, to, amount }
+    }
+    /// Get the id of the transaction, or of the transaction it refers to
+    pub const fn tx_id(&self) -> TransactionId {
+        match self {
+            Transaction::Change { tx_id, .. }
+            | Transaction::Dispute { tx_id, .. }
+            | Transaction::Resolution { tx_id, .. }
+            | Transaction::Unfreeze(tx_id)
+            | Transaction::Representment(tx_id)
+            | Transaction::Transfer { tx_id, .. } => *tx_id,
+        }
+    }
+    /// Round the transaction's amount, if it has one, to a precision
+    pub fn round(self, precision: Precision) -> Transaction {
+        match self {
+            Transaction::Change { tx_id, change } => Transaction::Change {
+                tx_id,
+                change: BalanceChange {
+                    amount: change.amount.round(precision),
+                    ..change
+                },
+            },
+            Transaction::Transfer { tx_id, to, amount } => {
+                Transaction::transfer(tx_id, to, amount.round(precision))
+            }
+            Transaction::Dispute { tx_id, amount } => Transaction::Dispute {
+                tx_id,
+                amount: amount.map(|amount| amount.round(precision)),
+            },
+            Transaction::Resolution {
+                kind,
+                tx_id,
+                amount,
+            } => Transaction::Resolution {
+                kind,
+                tx_id,
+                amount: amount.map(|amount| amount.round(precision)),
+            },
+            tx => tx,
+        }
+    }
+    /// Get the transaction's amount, if it has one
+    ///
+    /// Disputes and chargebacks only have an amount if they are partial.
+    pub const fn amount(&self) -> Option<Amount> {
+        match self {
+            Transaction::Change { change, .. } => Some(change.amount),
+            Transaction::Transfer { amount, .. } => Some(*amount),
+            Transaction::Dispute { amount, .. } | Transaction::Resolution { amount, .. } => *amount,
+            Transaction::Unfreeze(_) | Transaction::Representment(_) => None,
+        }
+    }
+    /// Get the name of the transaction's type as it appears in input
+    pub const fn type_name(&self) -> &'static str {
+        match self {
+            Transaction::Change { change, .. } => match change.kind {
+                ChangeKind::Deposit => "deposit",
+                ChangeKind::Withdrawal => "withdrawal",
+                ChangeKind::Fee => "fee",
+            },
+            Transaction::Dispute { .. } => "dispute",
+            Transaction::Resolution { kind, .. } => match kind {
+                ResolutionKind::Resolve => "resolve",
+                ResolutionKind::Chargeback => "chargeback",
+            },
+            Transaction::Unfreeze(_) => "unfreeze",
+            Transaction::Representment(_) => "representment",
+            Transaction::Transfer { .. } => "transfer",
+        }
     }
 }
 
+/// The names of all transaction types as they appear in input
+pub const TRANSACTION_TYPES: [&str; 9] = [
+    "deposit",
+    "withdrawal",
+    "fee",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "transfer",
+    "unfreeze",
+    "representment",
+];
+
 /// An error that can occur when attempting to parse a `ClientTransaction` from a comma-separated string
-#[derive(Debug)]
+///
+/// Like [`TransactionError`](crate::TransactionError), each kind of error has a stable
+/// [`code`](TransactionParseError::code) and [`number`](TransactionParseError::number).
+#[derive(Debug, Error)]
 pub enum TransactionParseError {
+    #[error("Missing transaction type")]
     MissingTransactionType,
+    #[error("Invalid transaction type {0:?}")]
     InvalidTransactionType(String),
+    #[error("Missing client id")]
     MissingClientId,
+    #[error("Invalid client id {0:?}")]
     InvalidClientId(String),
+    #[error("Missing transaction id")]
     MissingTransactionId,
+    #[error("Invalid transaction id {0:?}")]
     InvalidTransactionId(String),
+    #[error("Missing amount")]
     MissingAmount,
+    #[error("Invalid amount {0:?}")]
     InvalidAmount(String),
+    #[error("Amount {0:?} must be greater than zero")]
+    NonPositiveAmount(String),
+    #[error("Missing destination client")]
+    MissingDestination,
+    #[error("Invalid destination client {0:?}")]
+    InvalidDestination(String),
+    #[error("Invalid timestamp {0:?}")]
+    InvalidTimestamp(String),
+    #[error("Amount {0:?} is out of range")]
+    AmountOutOfRange(String),
 }
 
-impl fmt::Display for TransactionParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl TransactionParseError {
+    /// Get a short, machine-readable code for the kind of error, such as `invalid_amount`
+    pub const fn code(&self) -> &'static str {
         match self {
-            TransactionParseError::MissingTransactionType => write!(f, "Missing transaction type"),
-            TransactionParseError::InvalidTransactionType(s) => {
-                write!(f, "Invalid transaction type {:?}", s)
-            }
-            TransactionParseError::MissingClientId => write!(f, "Missing client id"),
-            TransactionParseError::InvalidClientId(s) => write!(f, "Invalid client id {:?}", s),
-            TransactionParseError::MissingTransactionId => write!(f, "Missing transaction id"),
-            TransactionParseError::InvalidTransactionId(s) => {
-                write!(f, "Invalid transaction id {:?}", s)
-            }
-            TransactionParseError::MissingAmount => write!(f, "Missing amount"),
-            TransactionParseError::InvalidAmount(s) => write!(f, "Invalid amount {:?}", s),
+            TransactionParseError::MissingTransactionType => "missing_transaction_type",
+            TransactionParseError::InvalidTransactionType(_) => "invalid_transaction_type",
+            TransactionParseError::MissingClientId => "missing_client_id",
+            TransactionParseError::InvalidClientId(_) => "invalid_client_id",
+            TransactionParseError::MissingTransactionId => "missing_transaction_id",
+            TransactionParseError::InvalidTransactionId(_) => "invalid_transaction_id",
+            TransactionParseError::MissingAmount => "missing_amount",
+            TransactionParseError::InvalidAmount(_) => "invalid_amount",
+            TransactionParseError::NonPositiveAmount(_) => "non_positive_amount",
+            TransactionParseError::MissingDestination => "missing_destination",
+            TransactionParseError::InvalidDestination(_) => "invalid_destination",
+            TransactionParseError::InvalidTimestamp(_) => "invalid_timestamp",
+            TransactionParseError::AmountOutOfRange(_) => "amount_out_of_range",
+        }
+    }
+    /// Get a numeric code for the kind of error, between 100 and 199
+    ///
+    /// Numbers are never reused for a different kind of error, even if a kind is removed.
+    pub const fn number(&self) -> u16 {
+        match self {
+            TransactionParseError::MissingTransactionType => 101,
+            TransactionParseError::InvalidTransactionType(_) => 102,
+            TransactionParseError::MissingClientId => 103,
+            TransactionParseError::InvalidClientId(_) => 104,
+            TransactionParseError::MissingTransactionId => 105,
+            TransactionParseError::InvalidTransactionId(_) => 106,
+            TransactionParseError::MissingAmount => 107,
+            TransactionParseError::InvalidAmount(_) => 108,
+            TransactionParseError::NonPositiveAmount(_) => 109,
+            TransactionParseError::MissingDestination => 110,
+            TransactionParseError::InvalidDestination(_) => 111,
+            TransactionParseError::InvalidTimestamp(_) => 112,
+            TransactionParseError::AmountOutOfRange(_) => 113,
         }
     }
 }
 
-impl Error for TransactionParseError {}
+impl fmt::Display for ClientTransaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},",
+            self.tx.type_name(),
+            self.client,
+            self.tx.tx_id()
+        )?;
+        if let Some(amount) = self.tx.amount() {
+            write!(f, "{}", amount)?;
+        }
+        if let Transaction::Transfer { to, .. } = self.tx {
+            write!(f, ",{}", to)?;
+        }
+        // The timestamp is the sixth column, so the `to` column may need to be filled
+        if let Some(timestamp) = self.timestamp {
+            match self.tx {
+                Transaction::Transfer { .. } => write!(f, ",{}", timestamp)?,
+                _ => write!(f, ",,{}", timestamp)?,
+            }
+        }
+        Ok(())
+    }
+}
 
-impl FromStr for ClientTransaction {
-    type Err = TransactionParseError;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split(',').map(str::trim);
+impl ClientTransaction {
+    /// Parse a transaction from its individual fields
+    ///
+    /// `amount` is only required for deposits, withdrawals, fees, and transfers, and is optional
+    /// for disputes and chargebacks of only part of a transaction. `to`, the destination
+    /// client, is only required for transfers.
+    pub fn from_fields(
+        tx_type: Option<&str>,
+        client_id: Option<&str>,
+        tx_id: Option<&str>,
+        amount: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<Self, TransactionParseError> {
         // Get the transaction type string
-        let tx_type = parts
-            .next()
+        let tx_type = tx_type
+            .map(str::trim)
             .ok_or(TransactionParseError::MissingTransactionType)?;
         // Parse client id
-        let client_id = parts.next().ok_or(TransactionParseError::MissingClientId)?;
+        let client_id = client_id
+            .map(str::trim)
+            .ok_or(TransactionParseError::MissingClientId)?;
         let client_id = client_id
             .parse::<ClientId>()
             .map_err(|_| TransactionParseError::InvalidClientId(client_id.into()))?;
         // Parse transaction id
-        let tx_id = parts
-            .next()
+        let tx_id = tx_id
+            .map(str::trim)
             .ok_or(TransactionParseError::MissingTransactionId)?;
         let tx_id = tx_id
             .parse::<TransactionId>()
             .map_err(|_| TransactionParseError::InvalidTransactionId(tx_id.into()))?;
+        // Closure for getting the amount of a partial dispute or chargeback
+        let partial = || -> Result<Option<Amount>, TransactionParseError> {
+            amount
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(parse_amount)
+                .transpose()
+        };
         // Closure for getting the amount
-        let mut amount = || -> Result<Amount, Self::Err> {
-            let amount_str = parts.next().ok_or(TransactionParseError::MissingAmount)?;
-            let amount = amount_str
-                .parse::<f64>()
-                .map_err(|_| TransactionParseError::InvalidAmount(amount_str.into()))?;
-            Amount::from_f64(amount)
-                .filter(|amount| amount >= &0.0)
-                .ok_or_else(|| TransactionParseError::InvalidAmount(amount_str.into()))
+        let amount = || -> Result<Amount, TransactionParseError> {
+            parse_amount(
+                amount
+                    .map(str::trim)
+                    .ok_or(TransactionParseError::MissingAmount)?,
+            )
+        };
+        // Closure for getting the destination client
+        let to = || -> Result<ClientId, TransactionParseError> {
+            let to = to
+                .map(str::trim)
+                .ok_or(TransactionParseError::MissingDestination)?;
+            to.parse::<ClientId>()
+                .map_err(|_| TransactionParseError::InvalidDestination(to.into()))
         };
         // Make transaction
         let tx = match tx_type {
             "deposit" => Transaction::deposit(tx_id, amount()?),
             "withdrawal" => Transaction::withdrawal(tx_id, amount()?),
-            "dispute" => Transaction::Dispute(tx_id),
+            "fee" => Transaction::fee(tx_id, amount()?),
+            "dispute" => Transaction::Dispute {
+                tx_id,
+                amount: partial()?,
+            },
             "resolve" => Transaction::resolution(ResolutionKind::Resolve, tx_id),
-            "chargeback" => Transaction::resolution(ResolutionKind::Chargeback, tx_id),
+            "chargeback" => Transaction::Resolution {
+                kind: ResolutionKind::Chargeback,
+                tx_id,
+                amount: partial()?,
+            },
+            "transfer" => Transaction::transfer(tx_id, to()?, amount()?),
+            "unfreeze" | "unlock" => Transaction::Unfreeze(tx_id),
+            "representment" | "chargeback_reversal" => Transaction::Representment(tx_id),
             _ => {
                 return Err(TransactionParseError::InvalidTransactionType(
                     tx_type.into(),
@@ -158,6 +414,196 @@ impl FromStr for ClientTransaction {
         Ok(ClientTransaction {
             client: client_id,
             tx,
+            timestamp: None,
         })
     }
+    /// Set when the transaction was made from a field of input
+    ///
+    /// The timestamp is a whole number of seconds since the Unix epoch. If the field is
+    /// missing or empty, the transaction is unchanged.
+    pub fn with_timestamp(
+        mut self,
+        timestamp: Option<&str>,
+    ) -> Result<Self, TransactionParseError> {
+        let Some(timestamp) = timestamp.map(str::trim).filter(|s| !s.is_empty()) else {
+            return Ok(self);
+        };
+        let timestamp = timestamp
+            .parse::<Timestamp>()
+            .map_err(|_| TransactionParseError::InvalidTimestamp(timestamp.into()))?;
+        self.timestamp = Some(timestamp);
+        Ok(self)
+    }
+}
+
+/// Parse a positive amount
+fn parse_amount(s: &str) -> Result<Amount, TransactionParseError> {
+    let amount = Amount::parse(s).map_err(|e| match e {
+        ParseAmountError::Invalid => TransactionParseError::InvalidAmount(s.into()),
+        ParseAmountError::OutOfRange => TransactionParseError::AmountOutOfRange(s.into()),
+    })?;
+    if amount <= Amount::default() {
+        return Err(TransactionParseError::NonPositiveAmount(s.into()));
+    }
+    Ok(amount)
+}
+
+/// Constructors that validate transactions as they are validated when parsed
+///
+/// Deposits, withdrawals, fees, and transfers need an amount, so their constructors return a
+/// [`NeedsAmount`] to finish the transaction with, such as
+/// `ClientTransaction::deposit(1, 7).amount("12.3456")?`.
+impl ClientTransaction {
+    /// Start a deposit into a client's account
+    pub const fn deposit(client: ClientId, tx_id: TransactionId) -> NeedsAmount {
+        NeedsAmount {
+            client,
+            tx_id,
+            kind: AmountKind::Change(ChangeKind::Deposit),
+        }
+    }
+    /// Start a withdrawal from a client's account
+    pub const fn withdrawal(client: ClientId, tx_id: TransactionId) -> NeedsAmount {
+        NeedsAmount {
+            client,
+            tx_id,
+            kind: AmountKind::Change(ChangeKind::Withdrawal),
+        }
+    }
+    /// Start a fee charged to a client's account
+    pub const fn fee(client: ClientId, tx_id: TransactionId) -> NeedsAmount {
+        NeedsAmount {
+            client,
+            tx_id,
+            kind: AmountKind::Change(ChangeKind::Fee),
+        }
+    }
+    /// Start a transfer from a client's account to another client's account
+    pub const fn transfer(client: ClientId, tx_id: TransactionId, to: ClientId) -> NeedsAmount {
+        NeedsAmount {
+            client,
+            tx_id,
+            kind: AmountKind::Transfer(to),
+        }
+    }
+    /// Dispute a transaction of a client
+    pub const fn dispute(client: ClientId, tx_id: TransactionId) -> ClientTransaction {
+        ClientTransaction {
+            client,
+            tx: Transaction::dispute(tx_id),
+            timestamp: None,
+        }
+    }
+    /// Resolve a client's dispute
+    pub const fn resolve(client: ClientId, tx_id: TransactionId) -> ClientTransaction {
+        ClientTransaction {
+            client,
+            tx: Transaction::resolution(ResolutionKind::Resolve, tx_id),
+            timestamp: None,
+        }
+    }
+    /// Charge back a client's dispute
+    pub const fn chargeback(client: ClientId, tx_id: TransactionId) -> ClientTransaction {
+        ClientTransaction {
+            client,
+            tx: Transaction::resolution(ResolutionKind::Chargeback, tx_id),
+            timestamp: None,
+        }
+    }
+    /// Limit a dispute or chargeback to part of the transaction it refers to
+    ///
+    /// The amount must be positive. Other kinds of transactions are returned unchanged.
+    pub fn partial(mut self, amount: Amount) -> Result<ClientTransaction, TransactionParseError> {
+        if amount <= Amount::default() {
+            return Err(TransactionParseError::NonPositiveAmount(amount.to_string()));
+        }
+        if let Transaction::Dispute {
+            amount: partial, ..
+        }
+        | Transaction::Resolution {
+            kind: ResolutionKind::Chargeback,
+            amount: partial,
+            ..
+        } = &mut self.tx
+        {
+            *partial = Some(amount);
+        }
+        Ok(self)
+    }
+    /// Unfreeze a client's account
+    pub const fn unfreeze(client: ClientId, tx_id: TransactionId) -> ClientTransaction {
+        ClientTransaction {
+            client,
+            tx: Transaction::Unfreeze(tx_id),
+            timestamp: None,
+        }
+    }
+    /// Reverse the chargebacks of a client's deposit
+    pub const fn representment(client: ClientId, tx_id: TransactionId) -> ClientTransaction {
+        ClientTransaction {
+            client,
+            tx: Transaction::Representment(tx_id),
+            timestamp: None,
+        }
+    }
+}
+
+/// A deposit, withdrawal, or transfer that still needs an amount
+#[derive(Debug, Clone, Copy)]
+#[must_use = "the transaction is not constructed until it is given an amount"]
+pub struct NeedsAmount {
+    client: ClientId,
+    tx_id: TransactionId,
+    kind: AmountKind,
+}
+
+/// The kind of transaction a [`NeedsAmount`] constructs
+#[derive(Debug, Clone, Copy)]
+enum AmountKind {
+    Change(ChangeKind),
+    Transfer(ClientId),
+}
+
+impl NeedsAmount {
+    /// Finish the transaction with an amount parsed from a string
+    ///
+    /// The amount must be a valid positive decimal, as in input.
+    pub fn amount(self, amount: &str) -> Result<ClientTransaction, TransactionParseError> {
+        Ok(self.build(parse_amount(amount.trim())?))
+    }
+    /// Finish the transaction with an amount
+    ///
+    /// The amount must be positive.
+    pub fn amount_value(self, amount: Amount) -> Result<ClientTransaction, TransactionParseError> {
+        if amount <= Amount::default() {
+            return Err(TransactionParseError::NonPositiveAmount(amount.to_string()));
+        }
+        Ok(self.build(amount))
+    }
+    fn build(self, amount: Amount) -> ClientTransaction {
+        let tx = match self.kind {
+            AmountKind::Change(kind) => Transaction::change(self.tx_id, kind, amount),
+            AmountKind::Transfer(to) => Transaction::transfer(self.tx_id, to, amount),
+        };
+        ClientTransaction {
+            client: self.client,
+            tx,
+            timestamp: None,
+        }
+    }
+}
+
+impl FromStr for ClientTransaction {
+    type Err = TransactionParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        ClientTransaction::from_fields(
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next().filter(|s| !s.is_empty()),
+        )?
+        .with_timestamp(parts.next())
+    }
 }
@@ -0,0 +1,163 @@
+//! Trends of an account across runs
+//!
+//! Each run of transactor can write its account data to a report. Given a directory of
+//! such reports, [`trend`] collects one client's balances from each of them into a time
+//! series. Reports may be in any [`OutputFormat`](crate::output::OutputFormat) other than Arrow. They are
+//! ordered by file name, so names should start with a sortable date such as `2024-03-01`.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use csv::{ReaderBuilder, Trim};
+use serde_json::Value;
+
+use crate::{amount::Amount, transaction::ClientId};
+
+/// A client's account data from a single run
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrendPoint {
+    /// The run the data is from, named by the stem of its report's file name
+    pub run: String,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// Collect a client's account data from every report in a directory, in order of file name
+///
+/// Runs whose report does not include the client are skipped. Hidden files are ignored.
+pub fn trend(dir: &Path, client: ClientId) -> Result<Vec<TrendPoint>, String> {
+    let mut paths = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("Unable to read {:?}: {}", dir, e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Unable to read {:?}: {}", dir, e))?;
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if path.is_file() && !hidden {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut points = Vec::new();
+    for path in paths {
+        let report =
+            fs::read_to_string(&path).map_err(|e| format!("Unable to read {:?}: {}", path, e))?;
+        let run = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if let Some(point) = read_report(report.as_bytes(), client, run)
+            .map_err(|e| format!("Invalid report {:?}: {}", path, e))?
+        {
+            points.push(point);
+        }
+    }
+    Ok(points)
+}
+
+/// Read a client's account data from a single report
+///
+/// The report's format is detected from its first non-whitespace character.
+pub fn read_report<R: Read>(
+    mut source: R,
+    client: ClientId,
+    run: String,
+) -> Result<Option<TrendPoint>, String> {
+    let mut report = String::new();
+    source
+        .read_to_string(&mut report)
+        .map_err(|e| e.to_string())?;
+    let rows: Vec<[String; 5]> = match report.trim_start().chars().next() {
+        None => Vec::new(),
+        Some('[') => {
+            let accounts: Vec<Value> = serde_json::from_str(&report).map_err(|e| e.to_string())?;
+            accounts.iter().map(json_row).collect()
+        }
+        Some('{') => report
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                Ok(json_row(
+                    &serde_json::from_str(line).map_err(|e| e.to_string())?,
+                ))
+            })
+            .collect::<Result<_, String>>()?,
+        Some(_) => csv_rows(&report)?,
+    };
+    let client_str = client.to_string();
+    let Some([_, available, held, total, locked]) =
+        rows.into_iter().find(|row| row[0] == client_str)
+    else {
+        return Ok(None);
+    };
+    let amount = |s: String| s.parse::<Amount>();
+    Ok(Some(TrendPoint {
+        run,
+        available: amount(available)?,
+        held: amount(held)?,
+        total: amount(total)?,
+        locked: locked
+            .parse()
+            .map_err(|_| format!("Invalid locked value {:?}", locked))?,
+    }))
+}
+
+/// Get the fields of an account object from a JSON report
+fn json_row(account: &Value) -> [String; 5] {
+    ["client", "available", "held", "total", "locked"].map(|name| match &account[name] {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    })
+}
+
+/// Get the fields of every account in a CSV report
+fn csv_rows(report: &str) -> Result<Vec<[String; 5]>, String> {
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(report.as_bytes());
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let columns = ["client", "available", "held", "total", "locked"].map(|name| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| format!("Missing a {:?} column", name))
+    });
+    let mut cols = [0; 5];
+    for (col, column) in cols.iter_mut().zip(columns) {
+        *col = column?;
+    }
+    reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|e| e.to_string())?;
+            Ok(cols.map(|col| record.get(col).unwrap_or_default().to_string()))
+        })
+        .collect()
+}
+
+/// Write a trend as CSV with the columns `run, available, held, total, locked, change`
+///
+/// `change` is the difference in the total balance since the previous run.
+pub fn write_trend<W: Write>(points: &[TrendPoint], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "run,available,held,total,locked,change")?;
+    let mut previous: Option<Amount> = None;
+    for point in points {
+        write!(
+            writer,
+            "{},{},{},{},{},",
+            point.run, point.available, point.held, point.total, point.locked
+        )?;
+        if let Some(change) = previous.and_then(|previous| point.total.checked_sub(previous)) {
+            write!(writer, "{}", change)?;
+        }
+        writeln!(writer)?;
+        previous = Some(point.total);
+    }
+    writer.flush()
+}
@@ -0,0 +1,485 @@
+//! Canonical test vectors
+//!
+//! These vectors pin down transactor's semantics, especially around disputes, so that other
+//! implementations can check that they behave the same way. Each [`Vector`] is an input file
+//! and the account data transactor outputs for it in the default CSV format. A few rules
+//! apply throughout:
+//!
+//! - Rejected transactions change nothing, and processing continues with the next one.
+//! - Any transaction creates the account of the client it is submitted with, even if it is
+//!   rejected.
+//! - Transaction ids are scoped to the client, so a client can only dispute their own
+//!   deposits and withdrawals.
+//!
+//! [`export`] writes the vectors to files, and [`check_command`] runs another
+//! implementation against them.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde_json::json;
+
+use crate::{
+    amount::Amount,
+    output::{self, OutputFormat},
+    process_transaction_source_with,
+    transaction::ClientId,
+    Accounts,
+};
+
+/// An input file and the account data it must produce
+#[derive(Debug, Clone, Copy)]
+pub struct Vector {
+    /// A unique name, usable as a file name
+    pub name: &'static str,
+    /// The rule the vector checks
+    pub description: &'static str,
+    /// The transactions to process, as CSV with a header row
+    pub input: &'static str,
+    /// The expected account data, as CSV with a header row
+    pub expected: &'static str,
+}
+
+/// Every test vector
+pub const VECTORS: &[Vector] = &[
+    Vector {
+        name: "deposit_and_withdrawal",
+        description: "Deposits add to available funds and withdrawals remove them",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+withdrawal,1,2,4.5\n\
+deposit,2,3,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,5.5000,0.0000,5.5000,false\n\
+2,1.0000,0.0000,1.0000,false\n",
+    },
+    Vector {
+        name: "insufficient_funds",
+        description: "A withdrawal of more than the available funds is rejected and changes nothing",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+withdrawal,1,2,10.0001\n\
+withdrawal,1,3,10\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,0.0000,0.0000,0.0000,false\n",
+    },
+    Vector {
+        name: "duplicate_transaction_id",
+        description: "A deposit or withdrawal reusing a transaction id of the same client is rejected",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+deposit,1,1,5\n\
+withdrawal,1,1,5\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "amount_rounding",
+        description: "Amounts are rounded half away from zero to 4 decimal places, and a deposit that rounds to zero is rejected",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,1.00005\n\
+deposit,1,2,1.00004\n\
+deposit,2,3,0.00004\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,2.0001,0.0000,2.0001,false\n\
+2,0.0000,0.0000,0.0000,false\n",
+    },
+    Vector {
+        name: "dispute_deposit",
+        description: "Disputing a deposit moves its funds from available to held",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+deposit,1,2,5\n\
+dispute,1,2\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,5.0000,15.0000,false\n",
+    },
+    Vector {
+        name: "dispute_deposit_after_withdrawal",
+        description: "Disputing a deposit whose funds were already withdrawn makes available funds negative",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+withdrawal,1,2,8\n\
+dispute,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,-8.0000,10.0000,2.0000,false\n",
+    },
+    Vector {
+        name: "resolve_deposit",
+        description: "Resolving a disputed deposit makes its held funds available again",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+dispute,1,1\n\
+resolve,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "chargeback_deposit",
+        description: "Charging back a disputed deposit removes its held funds and locks the account",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+deposit,1,2,3\n\
+dispute,1,1\n\
+chargeback,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,3.0000,0.0000,3.0000,true\n",
+    },
+    Vector {
+        name: "locked_account",
+        description: "A locked account still accepts deposits but rejects withdrawals",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+deposit,1,2,3\n\
+dispute,1,2\n\
+chargeback,1,2\n\
+deposit,1,3,5\n\
+withdrawal,1,4,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,15.0000,0.0000,15.0000,true\n",
+    },
+    Vector {
+        name: "dispute_unknown_transaction",
+        description: "Disputes, resolutions, and chargebacks of unknown transactions are ignored",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+dispute,1,9\n\
+resolve,1,9\n\
+chargeback,1,9\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "dispute_other_client",
+        description: "A client cannot dispute another client's transaction. The disputing client's account is still created",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+dispute,2,1\n\
+chargeback,2,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n\
+2,0.0000,0.0000,0.0000,false\n",
+    },
+    Vector {
+        name: "resolve_undisputed",
+        description: "Resolutions and chargebacks of undisputed transactions are ignored",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+resolve,1,1\n\
+chargeback,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "repeated_dispute",
+        description: "Disputing a transaction that is already disputed is ignored",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+dispute,1,1\n\
+dispute,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,0.0000,10.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "redispute_after_resolve",
+        description: "A resolved deposit can be disputed again",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+dispute,1,1\n\
+resolve,1,1\n\
+dispute,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,0.0000,10.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "repeated_chargeback",
+        description: "A charged back deposit cannot be disputed or charged back again",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+deposit,1,2,5\n\
+dispute,1,1\n\
+chargeback,1,1\n\
+dispute,1,1\n\
+chargeback,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,5.0000,0.0000,5.0000,true\n",
+    },
+    Vector {
+        name: "dispute_withdrawal",
+        description: "Disputing a withdrawal leaves the balances unchanged until it is settled",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+withdrawal,1,2,4\n\
+dispute,1,2\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,6.0000,0.0000,6.0000,false\n",
+    },
+    Vector {
+        name: "resolve_withdrawal",
+        description: "Resolving a disputed withdrawal returns its funds",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+withdrawal,1,2,4\n\
+dispute,1,2\n\
+resolve,1,2\n\
+dispute,1,2\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "chargeback_withdrawal",
+        description: "Charging back a disputed withdrawal makes it final without locking the account",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+withdrawal,1,2,4\n\
+dispute,1,2\n\
+chargeback,1,2\n\
+withdrawal,1,3,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,5.0000,0.0000,5.0000,false\n",
+    },
+    Vector {
+        name: "rejected_transaction_not_disputable",
+        description: "Rejected deposits and withdrawals cannot be disputed",
+        input: "\
+type,client,tx,amount\n\
+deposit,1,1,10\n\
+withdrawal,1,2,20\n\
+dispute,1,2\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n",
+    },
+    Vector {
+        name: "transfer",
+        description: "Transfers move funds between clients and cannot be disputed",
+        input: "\
+type,client,tx,amount,to\n\
+deposit,1,1,10\n\
+transfer,1,2,4,2\n\
+dispute,1,2\n\
+dispute,2,2\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,6.0000,0.0000,6.0000,false\n\
+2,4.0000,0.0000,4.0000,false\n",
+    },
+    Vector {
+        name: "transfer_to_locked_account",
+        description: "Transfers to or from locked accounts are rejected",
+        input: "\
+type,client,tx,amount,to\n\
+deposit,1,1,10\n\
+deposit,2,2,5\n\
+deposit,2,5,5\n\
+dispute,2,2\n\
+chargeback,2,2\n\
+transfer,1,3,4,2\n\
+transfer,2,4,1,1\n",
+        expected: "\
+client,available,held,total,locked\n\
+1,10.0000,0.0000,10.0000,false\n\
+2,5.0000,0.0000,5.0000,true\n",
+    },
+];
+
+/// Run a vector against this implementation, returning its account data
+pub fn run(vector: &Vector) -> Result<String, String> {
+    let mut accounts = Accounts::default();
+    process_transaction_source_with(vector.input.as_bytes(), &mut accounts, |_, _, _| {})?;
+    let mut output = Vec::new();
+    output::write_accounts(&accounts, OutputFormat::Csv, &mut output).map_err(|e| e.to_string())?;
+    String::from_utf8(output).map_err(|e| e.to_string())
+}
+
+/// Run a vector against another implementation
+///
+/// The command is given the vector's input on stdin and must write account data as CSV
+/// to stdout. Its output is compared with [`compare`].
+pub fn check_command(vector: &Vector, command: &mut Command) -> Result<(), String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Unable to run command: {}", e))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(vector.input.as_bytes())
+        .map_err(|e| format!("Unable to write input: {}", e))?;
+    drop(stdin);
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Unable to run command: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("Command failed with {}", output.status));
+    }
+    compare(vector.expected, &String::from_utf8_lossy(&output.stdout))
+}
+
+/// Check every vector against this implementation, or against a command if one is given,
+/// writing a line with the outcome of each
+///
+/// Returns the number of vectors that failed.
+pub fn check_all<W: Write>(command: Option<(&str, &[String])>, mut writer: W) -> io::Result<usize> {
+    let mut failed = 0;
+    for vector in VECTORS {
+        let result = match command {
+            Some((program, args)) => check_command(vector, Command::new(program).args(args)),
+            None => run(vector).and_then(|output| compare(vector.expected, &output)),
+        };
+        match result {
+            Ok(()) => writeln!(writer, "ok      {}", vector.name)?,
+            Err(e) => {
+                writeln!(writer, "FAILED  {}: {}", vector.name, e)?;
+                failed += 1;
+            }
+        }
+    }
+    Ok(failed)
+}
+
+/// Compare account data with the expected account data
+///
+/// Accounts may be in any order, and amounts may be written with any number of decimal
+/// places.
+pub fn compare(expected: &str, actual: &str) -> Result<(), String> {
+    let expected =
+        parse_accounts(expected).map_err(|e| format!("Invalid expected account data: {}", e))?;
+    let actual = parse_accounts(actual)?;
+    for (client, expected) in &expected {
+        match actual.get(client) {
+            Some(actual) if actual == expected => {}
+            Some(actual) => {
+                return Err(format!(
+                    "Client {} has {}, expected {}",
+                    client,
+                    describe(actual),
+                    describe(expected)
+                ))
+            }
+            None => return Err(format!("Missing client {}", client)),
+        }
+    }
+    if let Some(client) = actual.keys().find(|client| !expected.contains_key(client)) {
+        return Err(format!("Unexpected client {}", client));
+    }
+    Ok(())
+}
+
+/// The available, held, and total balances of an account and whether it is locked
+type AccountRow = (Amount, Amount, Amount, bool);
+
+fn describe((available, held, total, locked): &AccountRow) -> String {
+    format!(
+        "available {}, held {}, total {}, locked {}",
+        available, held, total, locked
+    )
+}
+
+/// Parse account data written as CSV with a header row
+fn parse_accounts(data: &str) -> Result<BTreeMap<ClientId, AccountRow>, String> {
+    let mut lines = data.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or("Missing header row")?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|&column| column == name)
+            .ok_or_else(|| format!("Missing a {:?} column", name))
+    };
+    let columns = [
+        column("client")?,
+        column("available")?,
+        column("held")?,
+        column("total")?,
+        column("locked")?,
+    ];
+    let mut accounts = BTreeMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [client, available, held, total, locked] =
+            columns.map(|i| fields.get(i).copied().unwrap_or_default());
+        let invalid = || format!("Invalid account {:?}", line);
+        let amount = |s: &str| s.parse::<Amount>().map_err(|_| invalid());
+        let client = client.parse::<ClientId>().map_err(|_| invalid())?;
+        let row = (
+            amount(available)?,
+            amount(held)?,
+            amount(total)?,
+            locked.parse().map_err(|_| invalid())?,
+        );
+        if accounts.insert(client, row).is_some() {
+            return Err(format!("Client {} appears more than once", client));
+        }
+    }
+    Ok(accounts)
+}
+
+/// Write every vector to a directory
+///
+/// Each vector is written as `<name>.input.csv` and `<name>.expected.csv`. An index of all
+/// vectors is written to `vectors.json`.
+pub fn export(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut index = Vec::new();
+    for vector in VECTORS {
+        let input = format!("{}.input.csv", vector.name);
+        let expected = format!("{}.expected.csv", vector.name);
+        fs::write(dir.join(&input), vector.input)?;
+        fs::write(dir.join(&expected), vector.expected)?;
+        index.push(json!({
+            "name": vector.name,
+            "description": vector.description,
+            "input": input,
+            "expected": expected,
+        }));
+    }
+    let index = serde_json::to_string_pretty(&index)?;
+    fs::write(dir.join("vectors.json"), index + "\n")
+}
@@ -0,0 +1,199 @@
+//! Streaming transactions and account updates over WebSockets
+//!
+//! This module is enabled by the `websocket` feature. [`Server::serve_websocket`] accepts
+//! WebSocket connections, each of which may send text messages of two kinds:
+//!
+//! - Lines of transaction input in either format, as sent to a TCP connection. Every
+//!   transaction gets a response of `{"status":"ok"}` or `{"status":"error","error":"..."}`.
+//! - A subscription to the accounts of some clients, such as `{"subscribe":[1,2]}`, which
+//!   replaces any earlier subscription. The response is `{"status":"ok"}`, followed by the
+//!   current account of every subscribed client that has one.
+//!
+//! Whenever a transaction from any source or an admin operation changes the account of a
+//! subscribed client, the connection is pushed the account as `{"account":{...}}`, as in JSON
+//! output. This includes when the account is frozen.
+//!
+//! Connections over the [`ConnectionLimits`](crate::server::ConnectionLimits) are sent an error
+//! and closed if there are too many connections or a message is too long. A message with too
+//! many transactions gets a single error, and none of its transactions are applied. Once the
+//! server is drained, new connections are sent an error and closed.
+//!
+//! Each connection is recorded as a new source.
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, ErrorKind},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Receiver,
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use tungstenite::{protocol::WebSocketConfig, HandshakeError, Message, WebSocket};
+
+use crate::{
+    input::Parser,
+    output::account_json,
+    record::SourceId,
+    server::{LimitExceeded, Rejected, Server},
+    transaction::ClientId,
+};
+
+/// How long a connection waits for a message before pushing account updates
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl Server {
+    /// Accept WebSocket connections and handle each one on its own thread
+    ///
+    /// This only returns if accepting connections fails.
+    pub fn serve_websocket(&self, listener: TcpListener) -> io::Result<()> {
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let source = self.next_source();
+                scope.spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    if let Err(e) = self.handle_websocket(source, stream) {
+                        tracing::warn!(source, ?peer, "WebSocket connection failed: {}", e);
+                    }
+                });
+            }
+            Ok(())
+        })
+    }
+    /// Handle the messages of one connection until it is closed
+    fn handle_websocket(&self, source: SourceId, stream: TcpStream) -> tungstenite::Result<()> {
+        let max_size = self.limits().max_request_size;
+        let config = WebSocketConfig::default()
+            .max_message_size(max_size)
+            .max_frame_size(max_size);
+        let mut socket =
+            tungstenite::accept_with_config(stream, Some(config)).map_err(|e| match e {
+                HandshakeError::Failure(e) => e,
+                HandshakeError::Interrupted(_) => unreachable!("the stream is blocking"),
+            })?;
+        let _connection = match self.connect() {
+            Ok(connection) => connection,
+            Err(e) => return refuse(&mut socket, e),
+        };
+        let changes = self.watch();
+        socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut subscribed = BTreeSet::new();
+        loop {
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    for response in self.websocket_message(source, &mut subscribed, &text) {
+                        socket.send(Message::text(response.to_string()))?;
+                    }
+                }
+                Ok(Message::Close(_)) => {}
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(tungstenite::Error::Capacity(_)) => {
+                    let max = max_size.expect("messages are only too long with a limit");
+                    return refuse(&mut socket, LimitExceeded::RequestSize(max));
+                }
+                Err(e) => return Err(e),
+            }
+            self.push_changes(&changes, &subscribed, &mut socket)?;
+        }
+    }
+    /// Handle a text message, getting the responses to send
+    fn websocket_message(
+        &self,
+        source: SourceId,
+        subscribed: &mut BTreeSet<ClientId>,
+        text: &str,
+    ) -> Vec<Value> {
+        if let Ok(Value::Object(object)) = serde_json::from_str::<Value>(text) {
+            if let Some(clients) = object.get("subscribe") {
+                return match serde_json::from_value::<BTreeSet<ClientId>>(clients.clone()) {
+                    Ok(clients) => {
+                        *subscribed = clients;
+                        let mut responses = vec![json!({ "status": "ok" })];
+                        self.with_accounts(|accounts| {
+                            let places = accounts.precision().decimal_places() as usize;
+                            responses.extend(subscribed.iter().filter_map(|&client_id| {
+                                let account = accounts.get(client_id)?;
+                                Some(json!({
+                                    "account": account_json(client_id, account, places, false)
+                                }))
+                            }));
+                        });
+                        responses
+                    }
+                    Err(e) => vec![error(format!("Invalid subscription: {}", e))],
+                };
+            }
+        }
+        // The whole message is parsed first, so one with too many transactions applies none
+        let mut parser = Parser::new(None);
+        let mut records: Vec<_> = (text.lines())
+            .filter_map(|line| parser.parse_line(line.into()))
+            .collect();
+        records.extend(parser.finish().map(Err));
+        if let Some(max) = self
+            .limits()
+            .max_in_flight
+            .filter(|&max| records.len() > max)
+        {
+            return vec![error(LimitExceeded::InFlight(max).to_string())];
+        }
+        records
+            .into_iter()
+            .map(|record| {
+                match (record.map_err(Rejected::from))
+                    .and_then(|record| self.transact(source, record.tx))
+                {
+                    Ok(()) => json!({ "status": "ok" }),
+                    Err(e) => error(e.to_string()),
+                }
+            })
+            .collect()
+    }
+    /// Push the accounts of subscribed clients that have changed
+    fn push_changes(
+        &self,
+        changes: &Receiver<ClientId>,
+        subscribed: &BTreeSet<ClientId>,
+        socket: &mut WebSocket<TcpStream>,
+    ) -> tungstenite::Result<()> {
+        let changed: BTreeSet<ClientId> = changes
+            .try_iter()
+            .filter(|client_id| subscribed.contains(client_id))
+            .collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let pushes: Vec<Value> = self.with_accounts(|accounts| {
+            let places = accounts.precision().decimal_places() as usize;
+            changed
+                .into_iter()
+                .filter_map(|client_id| {
+                    let account = accounts.get(client_id)?;
+                    Some(json!({ "account": account_json(client_id, account, places, false) }))
+                })
+                .collect()
+        });
+        for push in pushes {
+            socket.send(Message::text(push.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Send an error for refusing a connection or going over a limit and close the connection
+fn refuse(socket: &mut WebSocket<TcpStream>, e: impl fmt::Display) -> tungstenite::Result<()> {
+    socket.send(Message::text(error(e.to_string()).to_string()))?;
+    socket.close(None)?;
+    socket.flush()
+}
+
+/// Get the response to a failed message or transaction
+fn error(message: String) -> Value {
+    json!({ "status": "error", "error": message })
+}